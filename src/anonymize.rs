@@ -0,0 +1,391 @@
+// Rewrite the lines of a chunk so it can be attached to a bug report. Only
+// bytes inside lines change and every replacement has the length of what
+// it replaces, so timestamps, labels, entry and block counts, line and
+// uncompressed block sizes all stay as they were (unless whole lines are
+// replaced by a --placeholder). Blocks are compressed again with the
+// encoding of the input. The structured metadata of format v4 chunks is
+// kept as it is. `lf chunk scrub` is the same command.
+
+use std::path::Path;
+
+use clap::{Parser, ValueEnum};
+use integer_encoding::VarInt;
+use num_traits::FromPrimitive;
+use regex::bytes::{Captures, Regex};
+use ring::digest::{digest, SHA256};
+
+use crate::{
+    common::{format_bytes, gray, green},
+    confirm::confirm,
+    encode::{compress, ChunkEncoding},
+    error::DecodeError,
+    repair::parse_raw_meta,
+    ty::{decompress_bytes, EncType},
+};
+
+/// hash or mask sensitive parts of the lines of a chunk file, keeping
+/// everything else byte for byte
+#[derive(Parser, Debug)]
+pub struct Anonymize {
+    /// input chunk file
+    input: String,
+
+    /// output chunk file
+    output: String,
+
+    /// logfmt or json field whose value is replaced by a hash of the same
+    /// length, equal values keep hashing to equal values
+    #[clap(long)]
+    hash_field: Vec<String>,
+
+    /// regex whose matches are replaced by as many '*'
+    #[clap(long)]
+    mask_regex: Vec<String>,
+
+    /// replace whole lines instead: hash gives every line a hash of its
+    /// length (equal lines, equal hashes), redact as many '*'
+    #[clap(long, value_enum, conflicts_with_all = ["hash_field", "mask_regex"])]
+    lines: Option<LineMode>,
+
+    /// with --lines redact, write this text instead of every line, which
+    /// then loses its length
+    #[clap(long, value_name = "TEXT")]
+    placeholder: Option<String>,
+
+    /// salt of the hashes, so they can't be reversed by guessing values
+    #[clap(long, default_value = "")]
+    salt: String,
+
+    /// overwrite the output file if it already exists without asking
+    #[clap(short, long, alias = "force")]
+    yes: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum LineMode {
+    Hash,
+    Redact,
+}
+
+#[derive(Default)]
+struct Rules {
+    // one per hashed field, the value is in the first matching group
+    fields: Vec<Regex>,
+    masks: Vec<Regex>,
+    salt: String,
+    lines: Option<LineMode>,
+    placeholder: Option<String>,
+}
+
+fn field_regex(name: &str) -> anyhow::Result<Regex> {
+    let name = regex::escape(name);
+    // "name": "value" | name="value" | name=value
+    Ok(Regex::new(&format!(
+        r#""{name}"\s*:\s*"((?:[^"\\]|\\.)*)"|(?:^|[\s,{{]){name}=(?:"((?:[^"\\]|\\.)*)"|([^\s"]\S*))"#
+    ))?)
+}
+
+impl Rules {
+    // hex of salted sha256 chained until `len` bytes are there
+    fn hash(&self, value: &[u8], len: usize) -> Vec<u8> {
+        let mut input = self.salt.as_bytes().to_vec();
+        input.extend_from_slice(value);
+        let mut out = Vec::with_capacity(len + 64);
+        while out.len() < len {
+            let sum = digest(&SHA256, &input);
+            out.extend(sum.as_ref().iter().flat_map(|b| format!("{b:02x}").into_bytes()));
+            input = sum.as_ref().to_vec();
+        }
+        out.truncate(len);
+        out
+    }
+
+    /// The line replacing `line`, and the number of replacements made.
+    fn rewrite(&self, line: &[u8]) -> (Vec<u8>, usize) {
+        match (self.lines, self.placeholder.as_ref()) {
+            (Some(LineMode::Hash), _) => (self.hash(line, line.len()), 1),
+            (Some(LineMode::Redact), Some(text)) => (text.as_bytes().to_vec(), 1),
+            (Some(LineMode::Redact), None) => (vec![b'*'; line.len()], 1),
+            (None, _) => {
+                let mut line = line.to_vec();
+                let replaced = self.apply(&mut line);
+                (line, replaced)
+            }
+        }
+    }
+
+    /// Rewrite a line in place, returns the number of replacements.
+    fn apply(&self, line: &mut [u8]) -> usize {
+        let mut replaced = 0;
+        for re in self.fields.iter() {
+            let spans: Vec<_> = re
+                .captures_iter(line)
+                .filter_map(|c: Captures| c.iter().skip(1).flatten().next().map(|m| m.range()))
+                .collect();
+            for span in spans {
+                let hashed = self.hash(&line[span.clone()], span.len());
+                line[span].copy_from_slice(&hashed);
+                replaced += 1;
+            }
+        }
+        for re in self.masks.iter() {
+            let spans: Vec<_> = re.find_iter(line).map(|m| m.range()).collect();
+            for span in spans {
+                line[span].fill(b'*');
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    entries: usize,
+    lines: usize,
+    replacements: usize,
+}
+
+// rewrite the raw entries of a block (varint ts, uvarint len, line, then
+// with `metadata` the uvarint length of the structured metadata refs and
+// the refs, which are copied through)
+fn anonymize_block(
+    raw: &[u8],
+    entries: usize,
+    metadata: bool,
+    rules: &Rules,
+    stats: &mut Stats,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut pos = 0;
+    for _ in 0..entries {
+        let (_, n) = i64::decode_var(&raw[pos..]).ok_or(DecodeError::Truncated("entry timestamp"))?;
+        out.extend_from_slice(&raw[pos..pos + n]);
+        pos += n;
+        let (len, n) = u64::decode_var(&raw[pos..]).ok_or(DecodeError::Truncated("entry length"))?;
+        pos += n;
+        let line = raw
+            .get(pos..pos + len as usize)
+            .ok_or(DecodeError::Truncated("entry line"))?;
+        let (line, replaced) = rules.rewrite(line);
+        if replaced > 0 {
+            stats.lines += 1;
+            stats.replacements += replaced;
+        }
+        out.extend_from_slice(&(line.len() as u64).encode_var_vec());
+        out.extend_from_slice(&line);
+        stats.entries += 1;
+        pos += len as usize;
+        if metadata {
+            let (len, n) = u64::decode_var(&raw[pos..]).ok_or(DecodeError::Truncated("structured metadata length"))?;
+            let refs = raw
+                .get(pos..pos + n + len as usize)
+                .ok_or(DecodeError::Truncated("structured metadata"))?;
+            out.extend_from_slice(refs);
+            pos += refs.len();
+        }
+    }
+    if pos != raw.len() {
+        return Err(DecodeError::Corrupt(format!("{} bytes left after the entries of a block", raw.len() - pos)).into());
+    }
+    Ok(out)
+}
+
+fn anonymize_chunk(bs: &[u8], rules: &Rules, stats: &mut Stats) -> anyhow::Result<Vec<u8>> {
+    let head_len = be_u32(bs, 0).ok_or(DecodeError::Truncated("head length"))? as usize;
+    if head_len < 4 || head_len + 4 > bs.len() {
+        return Err(DecodeError::InvalidHead(format!("invalid head length: {head_len}")).into());
+    }
+    let chunk = &bs[head_len + 4..];
+    if chunk.len() < 14 {
+        return Err(DecodeError::Truncated("chunk data").into());
+    }
+    let format = chunk[4];
+    let header_len = if format > 1 { 6 } else { 5 };
+    let enc = if format > 1 { chunk[5] } else { EncType::EncGZIP as u8 };
+    let enc = EncType::from_u8(enc).ok_or_else(|| DecodeError::Unsupported(format!("encoding {enc}")))?;
+    let codec = ChunkEncoding::try_from(&enc)?;
+    let meta_offset = u64::from_be_bytes(chunk[chunk.len() - 8..].try_into()?) as usize;
+    let meta = parse_raw_meta(chunk, meta_offset, format)
+        .ok_or_else(|| DecodeError::Corrupt("unable to parse block metas".to_string()))?;
+
+    let mut out = chunk[..header_len].to_vec();
+    if format >= 4 {
+        // the symbols (and their crc) stay where they are, right after the header
+        if chunk.len() < header_len + 32 {
+            return Err(DecodeError::Truncated("chunk trailer").into());
+        }
+        let trailer = &chunk[chunk.len() - 32..];
+        let symbols_len = u64::from_be_bytes(trailer[..8].try_into()?) as usize;
+        let symbols_offset = u64::from_be_bytes(trailer[8..16].try_into()?) as usize;
+        if symbols_offset != header_len {
+            return Err(DecodeError::Corrupt(format!("symbols at {symbols_offset}, not after the header")).into());
+        }
+        let symbols = chunk
+            .get(symbols_offset..symbols_offset + symbols_len + 4)
+            .ok_or(DecodeError::Truncated("symbols"))?;
+        out.extend_from_slice(symbols);
+    }
+    let mut metas = (meta.blocks.len() as u64).encode_var_vec();
+    for b in meta.blocks.iter() {
+        let data = chunk
+            .get(b.offset..b.offset + b.len)
+            .ok_or_else(|| DecodeError::Corrupt(format!("block at {} out of bounds", b.offset)))?;
+        let raw = decompress_bytes(data, &enc).map_err(DecodeError::from)?;
+        let raw = anonymize_block(&raw, b.entries, format >= 4, rules, stats)?;
+        let compressed = compress(&raw, codec)?;
+        let offset = out.len();
+        out.extend_from_slice(&compressed);
+        out.extend_from_slice(&crc32c::crc32c(&compressed).to_be_bytes());
+
+        metas.extend_from_slice(&(b.entries as u64).encode_var_vec());
+        metas.extend_from_slice(&b.mint.encode_var_vec());
+        metas.extend_from_slice(&b.maxt.encode_var_vec());
+        metas.extend_from_slice(&(offset as u64).encode_var_vec());
+        if format >= 3 {
+            metas.extend_from_slice(&(raw.len() as u64).encode_var_vec());
+        }
+        metas.extend_from_slice(&(compressed.len() as u64).encode_var_vec());
+    }
+    let new_meta_offset = out.len();
+    out.extend_from_slice(&metas);
+    out.extend_from_slice(&crc32c::crc32c(&metas).to_be_bytes());
+    if format >= 4 {
+        // symbols length and offset, metas length and offset, lengths without the crc
+        out.extend_from_slice(&chunk[chunk.len() - 32..chunk.len() - 16]);
+        out.extend_from_slice(&(metas.len() as u64).to_be_bytes());
+    }
+    out.extend_from_slice(&(new_meta_offset as u64).to_be_bytes());
+
+    // the head is kept as is, it only holds labels and the time range
+    let mut result = bs[..head_len].to_vec();
+    result.extend_from_slice(&(out.len() as u32).to_be_bytes());
+    result.extend_from_slice(&out);
+    Ok(result)
+}
+
+fn be_u32(bs: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bs.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+pub fn anonymize(a: Anonymize) -> anyhow::Result<()> {
+    if a.placeholder.is_some() && a.lines != Some(LineMode::Redact) {
+        return Err(anyhow::format_err!("--placeholder goes with --lines redact"));
+    }
+    if a.hash_field.is_empty() && a.mask_regex.is_empty() && a.lines.is_none() {
+        return Err(anyhow::format_err!("nothing to do, give --hash-field, --mask-regex or --lines"));
+    }
+    if Path::new(&a.output) == Path::new(&a.input) {
+        return Err(anyhow::format_err!("refuse to overwrite the input file"));
+    }
+    let rules = Rules {
+        fields: a.hash_field.iter().map(|f| field_regex(f)).collect::<anyhow::Result<_>>()?,
+        masks: a
+            .mask_regex
+            .iter()
+            .map(|r| Regex::new(r).map_err(|e| anyhow::format_err!("--mask-regex {r}: {e}")))
+            .collect::<anyhow::Result<_>>()?,
+        salt: a.salt.clone(),
+        lines: a.lines,
+        placeholder: a.placeholder.clone(),
+    };
+    let bs = std::fs::read(&a.input)?;
+    let mut stats = Stats::default();
+    let out = anonymize_chunk(&bs, &rules, &mut stats)?;
+    if let Ok(existing) = std::fs::metadata(&a.output) {
+        confirm(
+            &format!("overwrite {}", a.output),
+            &[format!(
+                "{} ({}) is replaced by the anonymized chunk ({}, {} lines rewritten)",
+                a.output,
+                format_bytes(existing.len()),
+                format_bytes(out.len() as u64),
+                stats.lines
+            )],
+            a.yes,
+        )?;
+    }
+    std::fs::write(&a.output, &out)?;
+    println!(
+        "{}",
+        gray(&format!(
+            "{} entries, {} lines rewritten, {} replacements",
+            stats.entries, stats.lines, stats.replacements
+        ))
+    );
+    println!("{}", green(&format!("written to {}", a.output)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_line() {
+        let rules = Rules {
+            fields: vec![field_regex("email").unwrap()],
+            masks: vec![Regex::new(r"\d{16}").unwrap()],
+            salt: "s".to_string(),
+            ..Default::default()
+        };
+        let mut a = br#"level=info email=jo@x.io card=4111111111111111 msg="a""#.to_vec();
+        let mut b = br#"{"email": "jo@x.io", "myemail": "keep@x.io"}"#.to_vec();
+        let (a0, b0) = (a.clone(), b.clone());
+        assert_eq!(rules.apply(&mut a), 2);
+        assert_eq!(rules.apply(&mut b), 1);
+        let (a, b) = (String::from_utf8(a).unwrap(), String::from_utf8(b).unwrap());
+        assert_eq!((a.len(), b.len()), (a0.len(), b0.len()));
+        assert!(a.contains("card=****************") && !a.contains("jo@x.io"));
+        // same value, same hash
+        let hashed = &a["level=info email=".len().."level=info email=jo@x.io".len()];
+        assert!(b.contains(&format!(r#""email": "{hashed}""#)) && b.contains("keep@x.io"));
+    }
+
+    #[test]
+    fn test_scrub_lines() {
+        let line = b"user=jo@x.io failed";
+        let hash = Rules {
+            lines: Some(LineMode::Hash),
+            ..Default::default()
+        };
+        let (hashed, n) = hash.rewrite(line);
+        assert_eq!((hashed.len(), n), (line.len(), 1));
+        assert_eq!(hash.rewrite(line).0, hashed);
+        assert_ne!(hash.rewrite(b"user=al@x.io failed").0, hashed);
+
+        let redact = Rules {
+            lines: Some(LineMode::Redact),
+            placeholder: Some("<redacted>".to_string()),
+            ..Default::default()
+        };
+        let mut stats = Stats::default();
+        let mut raw = 5i64.encode_var_vec();
+        raw.extend_from_slice(&(line.len() as u64).encode_var_vec());
+        raw.extend_from_slice(line);
+        let out = anonymize_block(&raw, 1, false, &redact, &mut stats).unwrap();
+        assert_eq!(out, [&[10u8, 10][..], b"<redacted>"].concat());
+        assert_eq!((stats.entries, stats.lines), (1, 1));
+    }
+
+    #[test]
+    fn test_anonymize_v4() -> anyhow::Result<()> {
+        use std::io::Cursor;
+
+        use crate::{repair::test::chunk_v4, ty::Chunk};
+
+        let rules = Rules {
+            lines: Some(LineMode::Redact),
+            ..Default::default()
+        };
+        let mut stats = Stats::default();
+        let out = anonymize_chunk(&chunk_v4()?, &rules, &mut stats)?;
+        assert_eq!((stats.entries, stats.lines), (2, 2));
+        let chunk = Chunk::from_reader(&mut Cursor::new(out))?;
+        let entries = &chunk.data.blocks[0].entries;
+        assert_eq!(entries[0].line, "***");
+        assert_eq!(entries[0].structured_metadata, [("trace_id".to_string(), "abc".to_string())]);
+        assert!(entries[1].line == "***" && entries[1].structured_metadata.is_empty());
+        Ok(())
+    }
+}
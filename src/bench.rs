@@ -0,0 +1,154 @@
+use std::{
+    io::{BufRead, BufReader, Cursor},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use binread::BinReaderExt;
+use clap::{Parser, ValueEnum};
+
+use crate::{
+    common::{format_bytes, gray, green, yellow},
+    encode::{encode_memchunk, ChunkEncoding, EncodeEntry},
+    error::DecodeError,
+    ty::ChunkData,
+};
+
+/// compare chunk encodings on real data
+#[derive(Parser, Debug)]
+pub struct Bench {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// encode the same entries with every block encoding and report
+    /// size, encode and decode time per codec
+    Encode(EncodeCommand),
+}
+
+#[derive(Parser, Debug)]
+struct EncodeCommand {
+    /// ndjson file of {"ts": <nanoseconds>, "line": "..."} objects, - for stdin
+    #[clap(short, long)]
+    input: PathBuf,
+
+    /// uncompressed size at which a block is cut
+    #[clap(long, default_value = "262144")]
+    block_size: usize,
+
+    /// times every codec is run, the fastest run is reported
+    #[clap(long, default_value = "3")]
+    rounds: usize,
+}
+
+pub fn run(b: Bench) -> anyhow::Result<()> {
+    match b.cmd {
+        SubCommand::Encode(e) => bench_encode(e),
+    }
+}
+
+fn read_entries(input: &PathBuf) -> anyhow::Result<Vec<EncodeEntry>> {
+    let reader: Box<dyn BufRead> = if input.as_os_str() == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(std::fs::File::open(input)?))
+    };
+    let mut entries = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let obj: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| anyhow::format_err!("line {}: {e}", i + 1))?;
+        let ts = match &obj["ts"] {
+            serde_json::Value::Number(n) => n.as_i64(),
+            serde_json::Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow::format_err!("line {}: missing or invalid ts", i + 1))?;
+        let Some(line) = obj["line"].as_str() else {
+            return Err(anyhow::format_err!("line {}: missing line", i + 1));
+        };
+        entries.push(EncodeEntry {
+            ts,
+            line: line.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+// fastest of `rounds` runs of `f` and its last result
+fn fastest<T, F: FnMut() -> anyhow::Result<T>>(rounds: usize, mut f: F) -> anyhow::Result<(Duration, T)> {
+    let mut best = Duration::MAX;
+    let mut last = None;
+    for _ in 0..rounds.max(1) {
+        let start = Instant::now();
+        let r = f()?;
+        best = best.min(start.elapsed());
+        last = Some(r);
+    }
+    Ok((best, last.unwrap()))
+}
+
+fn bench_encode(e: EncodeCommand) -> anyhow::Result<()> {
+    let entries = read_entries(&e.input)?;
+    if entries.is_empty() {
+        return Err(anyhow::format_err!("no entries in {}", e.input.display()));
+    }
+    let raw: u64 = entries.iter().map(|e| e.line.len() as u64).sum();
+    println!(
+        "{}",
+        gray(&format!(
+            "{} entries, {} of lines, block size {}, best of {} rounds",
+            entries.len(),
+            format_bytes(raw),
+            format_bytes(e.block_size as u64),
+            e.rounds.max(1)
+        ))
+    );
+    println!(
+        "{:<8} {:>12} {:>7} {:>12} {:>12} {:>10}",
+        "codec", "size", "ratio", "encode", "decode", "decode/s"
+    );
+    for enc in ChunkEncoding::value_variants() {
+        // the chunk head is the same for every codec, only the memchunk
+        // (blocks and their metas) is measured
+        let (encode, chunk) = fastest(e.rounds, || encode_memchunk(&entries, *enc, e.block_size))?;
+        // ChunkData reads the length prefix of the storage envelope
+        let mut framed = (chunk.len() as u32).to_be_bytes().to_vec();
+        framed.extend_from_slice(&chunk);
+        let decoded = fastest(e.rounds, || {
+            let data: ChunkData = Cursor::new(&framed).read_le().map_err(DecodeError::from)?;
+            Ok(data.blocks.iter().map(|b| b.entries.len()).sum::<usize>())
+        });
+        let (decode, throughput) = match decoded {
+            Ok((d, n)) if n == entries.len() => (
+                format!("{d:.3?}"),
+                format!("{}/s", format_bytes((raw as f64 / d.as_secs_f64().max(1e-9)) as u64)),
+            ),
+            Ok((_, n)) => {
+                return Err(anyhow::format_err!(
+                    "{enc:?}: decoded {n} entries, expected {}",
+                    entries.len()
+                ))
+            }
+            Err(err) => match err.downcast_ref::<DecodeError>() {
+                Some(DecodeError::Unsupported(_)) => ("-".to_string(), yellow("unsupported")),
+                _ => return Err(err.context(format!("decoding {enc:?} chunk"))),
+            },
+        };
+        println!(
+            "{} {:>12} {:>7} {:>12} {:>12} {:>10}",
+            green(&format!("{:<8}", format!("{enc:?}").to_lowercase())),
+            format_bytes(chunk.len() as u64),
+            format!("{:.2}", raw as f64 / chunk.len() as f64),
+            format!("{encode:.3?}"),
+            decode,
+            throughput
+        );
+    }
+    Ok(())
+}
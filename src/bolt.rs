@@ -1,33 +1,56 @@
 use std::{
     cmp::{max, min},
-    collections::HashSet,
-    str::from_utf8,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::Read,
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::{from_utf8, FromStr},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
 use anyhow::Result;
 use base64::{encode_config, STANDARD_NO_PAD};
-use chrono::{Local, NaiveDateTime};
-use clap::Parser;
-use nut::DBBuilder;
+use chrono::NaiveDateTime;
+use clap::{Parser, ValueEnum};
+use flate2::read::GzDecoder;
+use nut::{DBBuilder, DB};
+use regex::Regex;
 use ring::digest::{digest, SHA256};
+use serde::Serialize;
 
 use crate::{
-    common::{blue, gray, green, yellow, KeyValue, TimeRangeOpts, red},
+    common::{blue, format_bytes, format_millis, gray, green, parse_duration, yellow, ChunkRef, KeyValue, TimeRangeOpts, red},
+    decode::csv_field,
+    encode::fingerprint,
+    error::IndexError,
+    grep::{collect_files, grep_chunk_bytes},
+    platform::{display_path, native_path},
+    proxy::format_labels,
     query::get_duration,
+    s3::{parse_s3_url, S3Client, S3Opts},
+    store::fs_chunk_path,
 };
 
 /// boltdb inspection (based on loki v2.6.1)
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Bolt {
+    #[clap(subcommand)]
+    cmd: Option<BoltCommand>,
+
     #[command(flatten)]
     time_range: TimeRangeOpts,
 
-    /// query label string
+    /// query label matchers, like a=1, b=~"x|y.*", c!=2 or d!~"z.*"
     #[arg(short, long, num_args=1..)]
-    query: Vec<KeyValue>,
+    query: Vec<LabelMatcher>,
 
-    /// boltdb file
-    file: String,
+    /// boltdb file, or a directory of tables (index_{n}/ directories or
+    /// files) where those of the time range are queried
+    #[arg(required = true)]
+    file: Option<String>,
 
     /// tenant name
     #[arg(short, long, default_value = "fake")]
@@ -40,24 +63,459 @@ pub struct Bolt {
     /// disable broad queries
     #[arg(long)]
     disable_broad_queries: bool,
+
+    /// index table period of the schema, like 168h for the weekly tables
+    /// of clusters that never moved to daily ones
+    #[arg(long, default_value = "24h", value_parser = parse_duration)]
+    period: Duration,
+
+    /// schema of the index, decides the form of the chunk keys
+    #[arg(long, value_enum, default_value = "v11")]
+    schema: Schema,
+
+    /// also write the chunk refs found to this file, csv when it ends
+    /// with .csv, json otherwise
+    #[arg(long)]
+    refs_out: Option<PathBuf>,
+
+    /// fetch the chunks found from --store and print their lines of the
+    /// time range
+    #[arg(long, requires = "store")]
+    fetch: bool,
+
+    /// chunks directory of a filesystem object store, or s3://bucket/prefix
+    #[arg(long)]
+    store: Option<String>,
+
+    #[command(flatten)]
+    s3: S3Opts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Schema {
+    V11,
+    V12,
+    V13,
+}
+
+// loki's v12Entries (used by v13 as well) is v11Entries with another chunk
+// key, so only the chunk ids in the range values tell the schemas apart:
+// v11 `tenant/fp:from:through:checksum`, from v12 on
+// `tenant/fp/from:through:checksum`
+impl Schema {
+    fn name(&self) -> String {
+        self.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default()
+    }
+
+    /// `{shard}:{tenant}:d{day}:logs:{name}`, label name (and value) to
+    /// series ids
+    fn label_hash(&self, shard: u32, bucket: &Bucket, name: &str) -> String {
+        format!("{:02}:{}:logs:{}", shard, bucket.hash_key, name)
+    }
+
+    /// `{tenant}:d{day}:{series id}`, series id to chunk ids
+    fn series_hash(&self, bucket: &Bucket, series_id: &str) -> String {
+        format!("{}:{}", bucket.hash_key, series_id)
+    }
+
+    /// The object key of `r`.
+    pub fn chunk_key(&self, r: &ChunkRef) -> String {
+        let sep = match self {
+            Schema::V11 => ':',
+            Schema::V12 | Schema::V13 => '/',
+        };
+        format!("{}/{:x}{sep}{:x}:{:x}:{:x}", r.user_id, r.fingerprint, r.from, r.to, r.checksum)
+    }
+
+    /// `parse_chunk_time_range_value`, with the chunk ids checked against
+    /// the schema.
+    fn parse_range_value(&self, range_value: &str) -> Result<String> {
+        let id = parse_chunk_time_range_value(range_value)?;
+        // only chunk ids have a '/'
+        let Some((_, rest)) = id.split_once('/') else {
+            return Ok(id);
+        };
+        let found = match rest.contains('/') {
+            true => "v12",
+            false => "v11",
+        };
+        let expected = match self {
+            Schema::V11 => "v11",
+            Schema::V12 | Schema::V13 => "v12",
+        };
+        if found != expected {
+            return Err(IndexError::InvalidKey(format!(
+                "chunk id {id} has the {found} form, not the one of {} (see --schema)",
+                self.name()
+            ))
+            .into());
+        }
+        Ok(id)
+    }
+}
+
+const DAY: Duration = Duration::from_secs(86400);
+
+#[derive(Debug, Clone)]
+enum MatchType {
+    Equal,
+    NotEqual,
+    Regex(Regex),
+    NotRegex(Regex),
+}
+
+/// A label matcher of `lf bolt -q`: `name=value`, `name!=value`,
+/// `name=~regex` or `name!~regex`, regexes anchored at both ends as in loki.
+#[derive(Debug, Clone)]
+pub struct LabelMatcher {
+    key: String,
+    value: String,
+    op: MatchType,
+}
+
+impl FromStr for LabelMatcher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // the first operator, two character ones before =
+        let found = s.char_indices().find_map(|(i, _)| {
+            ["!=", "!~", "=~", "="].into_iter().find(|op| s[i..].starts_with(op)).map(|op| (i, op))
+        });
+        let Some((i, op)) = found else {
+            return Err(anyhow::format_err!("invalid format, expect something like A=B, A!=B, A=~B or A!~B"));
+        };
+        let (key, value) = (&s[..i], &s[i + op.len()..]);
+        let regex = || Regex::new(&format!("^(?:{value})$"));
+        let op = match op {
+            "=" => MatchType::Equal,
+            "!=" => MatchType::NotEqual,
+            "=~" => MatchType::Regex(regex()?),
+            _ => MatchType::NotRegex(regex()?),
+        };
+        Ok(LabelMatcher {
+            key: key.to_string(),
+            value: value.to_string(),
+            op,
+        })
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            MatchType::Equal => "=",
+            MatchType::NotEqual => "!=",
+            MatchType::Regex(_) => "=~",
+            MatchType::NotRegex(_) => "!~",
+        };
+        write!(f, "{}{op}{:?}", self.key, self.value)
+    }
+}
+
+impl LabelMatcher {
+    /// Whether a series with label value `value` matches.
+    fn matches(&self, value: &str) -> bool {
+        match &self.op {
+            MatchType::Equal => self.value == value,
+            MatchType::NotEqual => self.value != value,
+            MatchType::Regex(re) => re.is_match(value),
+            MatchType::NotRegex(re) => !re.is_match(value),
+        }
+    }
+
+    fn is_negative(&self) -> bool {
+        matches!(self.op, MatchType::NotEqual | MatchType::NotRegex(_))
+    }
+
+    /// Whether the series with label value `value` are taken out by this
+    /// (negative) matcher.
+    fn excludes(&self, value: &str) -> bool {
+        match &self.op {
+            MatchType::NotEqual => self.value == value,
+            MatchType::NotRegex(re) => re.is_match(value),
+            MatchType::Equal | MatchType::Regex(_) => false,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+enum BoltCommand {
+    /// explore the index interactively (labels, values, series, chunks)
+    Repl(Repl),
+
+    /// series created/deleted/persisting between consecutive days, per tenant
+    Churn(Churn),
+
+    /// distinct label sets sharing a stream fingerprint, per tenant
+    Collisions(Collisions),
+
+    /// every series of the index with its labels and fingerprint, per tenant
+    Series(SeriesList),
+
+    /// label names as loki's /loki/api/v1/labels answers, from the index alone
+    Labels(LabelsApi),
+
+    /// values of a label as loki's /loki/api/v1/label/<name>/values answers
+    LabelValues(LabelValuesApi),
+
+    /// every key and value of a bucket, hash and range values split apart
+    Dump(Dump),
+
+    /// keys, series, label names, chunks and their time span per table
+    Stats(Stats),
+
+    /// delete requests of the compactor's delete_requests file
+    Deletes(Deletes),
+
+    /// distinct values and series of each label name, per tenant
+    Cardinality(Cardinality),
+
+    /// series and chunks the compactor's retention would delete by now
+    Retention(Retention),
 }
 
 pub fn inspect(b: Bolt) -> Result<()> {
+    match b.cmd {
+        Some(BoltCommand::Repl(r)) => return repl(r),
+        Some(BoltCommand::Churn(c)) => return churn(c),
+        Some(BoltCommand::Collisions(c)) => return collisions(c),
+        Some(BoltCommand::Series(s)) => return series(s),
+        Some(BoltCommand::Labels(l)) => return label_api(&l, None),
+        Some(BoltCommand::LabelValues(lv)) => return label_api(&lv.index, Some(&lv.label)),
+        Some(BoltCommand::Dump(d)) => return dump(d),
+        Some(BoltCommand::Stats(s)) => return stats(s),
+        Some(BoltCommand::Deletes(d)) => return deletes(d),
+        Some(BoltCommand::Cardinality(c)) => return cardinality(c),
+        Some(BoltCommand::Retention(r)) => return retention(r),
+        None => {}
+    }
     println!("To simplify things, we assume a few things:");
-    println!("  1. schema is 24 hour, making bucket size 86400000, also v11 is used");
     println!(
-        "  2. we only consider MatchEqual exprs, so query only accepts something like a=1 b=2"
+        "  1. schema {} is used, index tables span --period ({}) and hash buckets are a day (86400000)",
+        b.schema.name(),
+        humantime::format_duration(b.period)
+    );
+    println!(
+        "  2. query accepts matchers like a=1 b=~2|3 c!=4 d!~5, at least one of them a = or =~ one"
     );
     println!("{}", yellow("we now begin\n"));
 
-    let (buckets, (start, end)) = get_buckets(&b);
-    let mut series_ids = HashSet::default();
-    let db = DBBuilder::new(b.file.clone()).read_only(true).build()?;
+    let (buckets, (start, end)) = get_buckets(&b)?;
+    let files = index_files(&native_path(b.file.clone().unwrap_or_default()), &buckets)?;
+    let mut found = vec![];
+    for file in files.iter() {
+        if files.len() > 1 {
+            println!("\n{}", blue(&format!("== {} ==", display_path(file))));
+        }
+        // a file of a directory only holds the days of its table
+        let file_buckets: Vec<_> = match (files.len(), table_of(file)) {
+            (1, _) | (_, None) => buckets.clone(),
+            (_, Some(t)) => buckets.iter().filter(|b| b.table_name == t).cloned().collect(),
+        };
+        for r in query_file(&b, &file_buckets, file)? {
+            let chunk_ref = ChunkRef::parse_external_key(&r)?;
+            if chunk_ref.to < start.timestamp_millis() || chunk_ref.from > end.timestamp_millis() {
+                continue;
+            }
+            found.push((chunk_ref, file));
+        }
+    }
+    let chunk_refs = dedup_refs(found);
+    println!("final result:\n{:?}", chunk_refs.iter().map(|r| &r.0).collect::<Vec<_>>());
+    println!("len: {}", chunk_refs.len());
+    println!("\n{}", gray("object keys:"));
+    for (r, found_in) in chunk_refs.iter() {
+        match files.len() {
+            1 => println!("{}", b.schema.chunk_key(r)),
+            _ => {
+                let found_in: Vec<_> = found_in.iter().map(|f| display_path(f)).collect();
+                println!("{} {}", b.schema.chunk_key(r), gray(&found_in.join(", ")));
+            }
+        }
+    }
+    if let Some(out) = b.refs_out.as_ref() {
+        write_refs(&native_path(out), b.schema, chunk_refs.iter().map(|r| &r.0))?;
+        println!("{}", gray(&format!("{} chunk refs written to {}", chunk_refs.len(), display_path(out))));
+    }
+    if b.fetch {
+        let mut keys: Vec<_> = chunk_refs.iter().map(|(r, _)| (r.from, b.schema.chunk_key(r))).collect();
+        keys.sort();
+        keys.dedup();
+        let source = ChunkSource::new(b.store.as_deref().unwrap_or_default(), &b.s3)?;
+        fetch_lines(&source, keys.into_iter().map(|k| k.1), (start.timestamp_nanos(), end.timestamp_nanos()))?;
+    }
+    Ok(())
+}
+
+// ingesters each upload a file of the same table, the refs they share are
+// kept once, in the order first found, with the files they were found in
+fn dedup_refs(found: Vec<(ChunkRef, &PathBuf)>) -> Vec<(ChunkRef, Vec<&PathBuf>)> {
+    let mut chunk_refs: Vec<(ChunkRef, Vec<&PathBuf>)> = vec![];
+    let mut seen: HashMap<_, usize> = HashMap::new();
+    for (r, file) in found {
+        let id = (r.user_id.clone(), r.fingerprint, r.from, r.to, r.checksum);
+        match seen.get(&id) {
+            Some(&i) => {
+                if !chunk_refs[i].1.contains(&file) {
+                    chunk_refs[i].1.push(file);
+                }
+            }
+            None => {
+                seen.insert(id, chunk_refs.len());
+                chunk_refs.push((r, vec![file]));
+            }
+        }
+    }
+    chunk_refs
+}
+
+#[derive(Debug, Serialize)]
+struct RefRecord<'a> {
+    tenant: &'a str,
+    // hex, as in the keys (and too large for the numbers of some json readers)
+    fingerprint: String,
+    from: i64,
+    to: i64,
+    checksum: u32,
+    key: String,
+}
+
+impl<'a> RefRecord<'a> {
+    fn new(schema: Schema, r: &'a ChunkRef) -> Self {
+        RefRecord {
+            tenant: &r.user_id,
+            fingerprint: format!("{:x}", r.fingerprint),
+            from: r.from,
+            to: r.to,
+            checksum: r.checksum,
+            key: schema.chunk_key(r),
+        }
+    }
+}
+
+// the chunk refs as a json array, or csv rows after a header row when
+// `path` ends with .csv
+fn write_refs<'a>(path: &Path, schema: Schema, refs: impl Iterator<Item = &'a ChunkRef>) -> Result<()> {
+    use std::io::Write;
+
+    let mut w = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let records: Vec<_> = refs.map(|r| RefRecord::new(schema, r)).collect();
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+        writeln!(w, "tenant,fingerprint,from,to,checksum,key")?;
+        for r in records.iter() {
+            let (tenant, key) = (csv_field(r.tenant, ','), csv_field(&r.key, ','));
+            writeln!(w, "{tenant},{},{},{},{},{key}", r.fingerprint, r.from, r.to, r.checksum)?;
+        }
+    } else {
+        serde_json::to_writer_pretty(&mut w, &records)?;
+        writeln!(w)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+// where the chunks of the index are
+enum ChunkSource {
+    Fs(PathBuf),
+    S3(S3Client, String),
+}
+
+impl ChunkSource {
+    fn new(store: &str, s3: &S3Opts) -> Result<Self> {
+        if !store.starts_with("s3://") {
+            return Ok(ChunkSource::Fs(native_path(store)));
+        }
+        let (bucket, mut prefix) = parse_s3_url(store)?;
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Ok(ChunkSource::S3(S3Client::new(s3, &bucket)?, prefix))
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            ChunkSource::Fs(root) => Ok(std::fs::read(fs_chunk_path(root, key)?)?),
+            ChunkSource::S3(client, prefix) => client.get(&format!("{prefix}{key}")),
+        }
+    }
+}
+
+// the lines of the chunks `keys` within `range` (nanoseconds), chunk by
+// chunk; a chunk that can't be had is reported and skipped
+fn fetch_lines(source: &ChunkSource, keys: impl Iterator<Item = String>, range: (i64, i64)) -> Result<()> {
+    println!("\n{}", gray("fetching chunks..."));
+    let all = Regex::new("")?;
+    let (mut chunks, mut lines, mut failed) = (0, 0, 0);
+    for key in keys {
+        let matched = match source.get(&key).and_then(|bs| grep_chunk_bytes(&bs, &all, Some(range), &[], None)) {
+            Ok(m) => m,
+            Err(err) => {
+                eprintln!("{} {}", yellow(&key), red(&err.to_string()));
+                failed += 1;
+                continue;
+            }
+        };
+        chunks += 1;
+        lines += matched.lines.len();
+        println!("{} {}", blue(&key), green(&matched.labels));
+        for e in matched.lines {
+            let date_str = e.time.format("%Y-%m-%d %H:%M:%S").to_string();
+            println!("  {} {} {}", gray(&date_str), blue("|"), e.line);
+        }
+    }
+    let summary = format!("{lines} lines in {chunks} chunks");
+    match failed {
+        0 => println!("{}", gray(&summary)),
+        n => println!("{}", yellow(&format!("{summary}, {n} chunks not fetched"))),
+    }
+    Ok(())
+}
+
+// the table of an index file: `index_{n}` of its name or of the directory
+// holding it, as in the boltdb-shipper cache and object store layouts
+fn table_of(path: &Path) -> Option<String> {
+    path.components().rev().find_map(|c| {
+        let rest = c.as_os_str().to_str()?.strip_prefix("index_")?;
+        let n: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        (!n.is_empty()).then(|| format!("index_{n}"))
+    })
+}
+
+// `path` itself, or the files under it of the tables of `buckets`
+fn index_files(path: &Path, buckets: &[Bucket]) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = vec![];
+    collect_files(path, &mut files)?;
+    let tables: HashSet<&str> = buckets.iter().map(|b| b.table_name.as_str()).collect();
+    let total = files.len();
+    files.retain(|f| table_of(f).is_some_and(|t| tables.contains(t.as_str())));
+    println!("{}", gray(&format!("{} of {total} files under {} are of the time range", files.len(), display_path(path))));
+    if files.is_empty() {
+        return Err(IndexError::NotFound(format!("no index_* files of the time range under {}", display_path(path))).into());
+    }
+    Ok(files)
+}
+
+// the chunk ids of the series matching the query in one index file
+fn query_file(b: &Bolt, buckets: &[Bucket], file: &Path) -> Result<Vec<String>> {
+    // != and !~ matchers not excluding "" only take series out, see
+    // select_series
+    if !b.query.is_empty() && b.query.iter().all(|m| m.is_negative() && m.matches("")) {
+        return Err(anyhow::format_err!("the query needs a = or =~ matcher, != and !~ ones only take series out"));
+    }
+    let mut found = vec![];
+    let db = open_index(file)?;
     let tx = db.begin_tx()?;
-    let bucket = tx.bucket(b"index")?;
-    for kv in b.query.iter() {
-        println!("{:?}", kv);
-        let queries = calc_queries(b.shard, &buckets, kv);
+    // compacted files have a bucket per tenant instead
+    let bucket = match tx.bucket(b"index") {
+        Ok(bucket) => bucket,
+        Err(_) => tx.bucket(b.tenant.as_bytes())?,
+    };
+    for m in b.query.iter() {
+        println!("{}", m);
+        let queries = calc_queries(b.schema, b.shard, buckets, m);
 
         println!("\n{}", gray("getting entries (query pages)..."));
         let entries = get_entries_from_queries(b.disable_broad_queries, &bucket, queries)?;
@@ -70,7 +528,7 @@ pub fn inspect(b: Bolt) -> Result<()> {
         println!("\n{}", gray("parsing index entries"));
         let batch_result: Vec<_> = entries
             .iter()
-            .map(|e| parse_chunk_time_range_value(&e.range_value))
+            .map(|e| b.schema.parse_range_value(&e.range_value))
             .collect::<anyhow::Result<_>>()?;
 
         print!("{}", gray("len of batch result: "));
@@ -78,20 +536,24 @@ pub fn inspect(b: Bolt) -> Result<()> {
         print!("{}", gray("after dedup: "));
         let unique_set: HashSet<String> = batch_result.into_iter().collect();
         println!("{}", unique_set.len());
-        println!("batch series ids for {:?}: {:?}", kv, unique_set);
 
-        if series_ids.is_empty() {
-            series_ids = unique_set;
+        let excluded: HashSet<String> = entries
+            .iter()
+            .filter(|e| m.excludes(&e.value))
+            .map(|e| b.schema.parse_range_value(&e.range_value))
+            .collect::<anyhow::Result<_>>()?;
+        if m.is_negative() {
+            println!("excluded series ids for {}: {:?}", m, excluded);
         } else {
-            let t = series_ids.intersection(&unique_set).collect::<HashSet<_>>();
-            series_ids = t.into_iter().cloned().collect();
+            println!("batch series ids for {}: {:?}", m, unique_set);
         }
+        found.push((m, unique_set, excluded));
     }
-    let result: Vec<_> = series_ids.into_iter().collect();
+    let result: Vec<_> = select_series(found).into_iter().collect();
     println!("{}", red(&format!("final series_ids: {:?}", result)));
 
     println!("\n{}", gray("make new queries based on series id (v10)"));
-    let queries = calc_queries_for_serires(&buckets, result);
+    let queries = calc_queries_for_serires(b.schema, buckets, result);
     print!("{}", gray("len: "));
     println!("{}", queries.len());
     println!("{:?}", queries);
@@ -105,58 +567,64 @@ pub fn inspect(b: Bolt) -> Result<()> {
 
     let result: Vec<_> = entries
         .iter()
-        .map(|e| parse_chunk_time_range_value(&e.range_value))
+        .map(|e| b.schema.parse_range_value(&e.range_value))
         .collect::<anyhow::Result<_>>()?;
     println!("got chunk-ids:\n{:?}", result);
     println!("len: {}", result.len());
 
-    let mut chunk_refs = vec![];
-    for r in result {
-        let mut rsp = r.split("/");
-        let tenant_id = rsp.next().unwrap();
-        let segs = rsp.next().unwrap();
-        let parts = segs.split(":").collect::<Vec<_>>();
-        let fingerprint = u64::from_str_radix(parts[0], 16)?;
-        let from = i64::from_str_radix(parts[1], 16)?;
-        let to = i64::from_str_radix(parts[2], 16)?;
-        let checksum = u32::from_str_radix(parts[3], 16)?;
-        if to < start.timestamp_millis() || from > end.timestamp_millis() {
+    Ok(result)
+}
+
+// the series ids of a query, from the series having the label of each
+// matcher and those of them with a value the matcher excludes. Series
+// lacking the label of a != or !~ matcher match it (the value is "" then),
+// so those matchers take their excluded series out of what the others
+// select, unless they exclude "" too (like a!= or a!~), then they select the
+// series with the label minus the excluded ones.
+fn select_series(found: Vec<(&LabelMatcher, HashSet<String>, HashSet<String>)>) -> HashSet<String> {
+    let mut series_ids: Option<HashSet<String>> = None;
+    let mut excluded_ids = HashSet::new();
+    for (m, with_label, excluded) in found {
+        if m.is_negative() && m.matches("") {
+            excluded_ids.extend(excluded);
             continue;
         }
-        chunk_refs.push(ChunkRef {
-            user_id: tenant_id.to_string(),
-            fingerprint,
-            from,
-            to,
-            checksum,
+        let selected: HashSet<_> = with_label.difference(&excluded).cloned().collect();
+        // an empty set after a matcher stays empty
+        series_ids = Some(match series_ids {
+            None => selected,
+            Some(ids) => ids.intersection(&selected).cloned().collect(),
         });
     }
-    println!("final result:\n{:?}", chunk_refs);
-    println!("len: {}", chunk_refs.len());
-    Ok(())
+    series_ids.unwrap_or_default().difference(&excluded_ids).cloned().collect()
 }
 
-// only do match_equal
-fn filter_entries(entries: &Vec<Entry>, query: &Query) -> Vec<Entry> {
-    entries.into_iter().filter(|x| {
-        if query.range_value_prefix.len() > 0 && !x.range_value.starts_with(&query.range_value_prefix) {
+// values of regex matchers are checked here, on the entries of the broad
+// label name query; != and !~ queries keep every value, the exclusion is
+// done on the series ids
+fn filter_entries(entries: &[Entry], query: &Query) -> Vec<Entry> {
+    entries.iter().filter(|x| {
+        if !query.range_value_prefix.is_empty() && !x.range_value.starts_with(&query.range_value_prefix) {
             return false;
         }
         // I compared with loki's implementation, this can only filter out "some" chunk
         // if the time starts with 00000000 this won't be able to filter out any chunk
         // we need additional filter for time range
         // TODO: pkg/storage/chunk/chunk.go
-        if query.range_value_start.len() > 0 && query.range_value_start > x.range_value {
+        if !query.range_value_start.is_empty() && query.range_value_start > x.range_value {
             return false;
         }
-        if query.value_equal.len() > 0 && query.value_equal != x.value {
+        if !query.value_equal.is_empty() && query.value_equal != x.value {
             return false;
         }
-        return true;
+        if query.value_regex.as_ref().is_some_and(|re| !re.is_match(&x.value)) {
+            return false;
+        }
+        true
     }).cloned().collect()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(dead_code)]
 struct Bucket {
     from: u32,
@@ -174,6 +642,7 @@ struct Query {
     range_value_prefix: String,
     range_value_start: String,
     value_equal: String,
+    value_regex: Option<Regex>,
 }
 
 #[derive(Debug, Clone)]
@@ -185,30 +654,9 @@ struct Entry {
     value: String,
 }
 
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-struct ChunkRef {
-    user_id: String,
-    fingerprint: u64,
-    from: i64,
-    to: i64,
-    checksum: u32,
-}
-
-fn get_buckets(b: &Bolt) -> (Vec<Bucket>, (NaiveDateTime, NaiveDateTime)) {
+fn get_buckets(b: &Bolt) -> Result<(Vec<Bucket>, (NaiveDateTime, NaiveDateTime))> {
     println!("{}", gray("calculating start/end..."));
-    let (start, end) = match get_duration(&b.time_range) {
-        Ok(k) => {
-            println!("determined given time range: ");
-            k
-        }
-        Err(_) => {
-            println!("failed to determined given time range, using default (past 1 hour): ");
-            let end = Local::now().naive_utc();
-            let start = end.checked_sub_signed(chrono::Duration::hours(1)).unwrap();
-            (start, end)
-        }
-    };
+    let (start, end) = get_duration(&b.time_range)?;
 
     println!(
         "start: {}, end: {}",
@@ -217,6 +665,18 @@ fn get_buckets(b: &Bolt) -> (Vec<Bucket>, (NaiveDateTime, NaiveDateTime)) {
     );
 
     println!("\n{}", gray("preparing 'Buckets'..."));
+    let buckets = make_buckets(&b.tenant, b.period, start, end)?;
+    println!("{:#?}", buckets);
+    Ok((buckets, (start, end)))
+}
+
+// loki's dailyBuckets: whatever the table period, the hash keys of schema
+// v9 on are per day, the table is the one holding the start of the day
+fn make_buckets(tenant: &str, period: Duration, start: NaiveDateTime, end: NaiveDateTime) -> Result<Vec<Bucket>> {
+    let period = period.as_secs() as i64;
+    if period == 0 {
+        return Err(anyhow::format_err!("--period must be at least a second"));
+    }
     let mut buckets = vec![];
     let from_day = start.timestamp() / 86400;
     let to_day = end.timestamp() / 86400;
@@ -226,33 +686,43 @@ fn get_buckets(b: &Bolt) -> (Vec<Bucket>, (NaiveDateTime, NaiveDateTime)) {
         buckets.push(Bucket {
             from: relative_from as u32,
             through: relative_through as u32,
-            table_name: format!("index_{}", d),
-            hash_key: format!("{}:d{}", b.tenant, d),
+            table_name: format!("index_{}", d * 86400 / period),
+            hash_key: format!("{}:d{}", tenant, d),
             bucket_size: 86_400_000,
         });
     }
-    println!("{:#?}", buckets);
-    (buckets, (start, end))
+    Ok(buckets)
 }
 
-fn calc_queries(shard: u32, buckets: &Vec<Bucket>, kv: &KeyValue) -> Vec<Query> {
+// loki's GetReadQueriesForMetricLabelValue for equality, otherwise
+// GetReadQueriesForMetricLabel (every value of the label) with the regex
+// applied to the values found, or nothing for != and !~
+fn calc_queries(schema: Schema, shard: u32, buckets: &[Bucket], m: &LabelMatcher) -> Vec<Query> {
     let mut queries = vec![];
     for bucket in buckets.iter() {
         println!(
             "{}, {}",
-            blue(&format!("{:?}", kv)),
+            blue(&m.to_string()),
             yellow(&format!("{:?}", bucket))
         );
-        let hash_val = digest(&SHA256, kv.value.as_ref());
-        let mut hash_val_encoded = encode_config(hash_val, STANDARD_NO_PAD);
-        hash_val_encoded.push_str("\x00");
+        let (range_value_prefix, value_equal, value_regex) = match &m.op {
+            MatchType::Equal => {
+                let hash_val = digest(&SHA256, m.value.as_ref());
+                let mut hash_val_encoded = encode_config(hash_val, STANDARD_NO_PAD);
+                hash_val_encoded.push('\x00');
+                (hash_val_encoded, m.value.clone(), None)
+            }
+            MatchType::Regex(re) => (String::default(), String::default(), Some(re.clone())),
+            MatchType::NotEqual | MatchType::NotRegex(_) => (String::default(), String::default(), None),
+        };
         for i in 0..shard {
             queries.push(Query {
                 table_name: bucket.table_name.clone(),
-                hash_value: format!("{:02}:{}:logs:{}", i, bucket.hash_key, kv.key),
-                range_value_prefix: hash_val_encoded.clone(),
+                hash_value: schema.label_hash(i, bucket, &m.key),
+                range_value_prefix: range_value_prefix.clone(),
                 range_value_start: String::default(),
-                value_equal: kv.value.clone(),
+                value_equal: value_equal.clone(),
+                value_regex: value_regex.clone(),
             });
         }
     }
@@ -268,27 +738,23 @@ fn calc_queries(shard: u32, buckets: &Vec<Bucket>, kv: &KeyValue) -> Vec<Query>
 // Orig implementation is at: pkg/storage/stores/series/index/schema_util.go
 // Note: this is just a partial implementation, which only targets for schema
 // version v11 and only returns chunk_id.
-fn parse_chunk_time_range_value(range_value: &String) -> anyhow::Result<String> {
+pub(crate) fn parse_chunk_time_range_value(range_value: &str) -> anyhow::Result<String> {
     let components = range_value.split("\x00").collect::<Vec<_>>();
     if components.len() != 5 {
-        return Err(anyhow::format_err!(
+        return Err(IndexError::InvalidKey(format!(
             "components lens: {}, should be 5",
             components.len()
-        ));
+        ))
+        .into());
     }
     match components[3] {
-        "3" => {
-            return Ok(components[2].to_string());
-        }
-        "8" => {
-            return Ok(components[1].to_string());
-        }
-        other => {
-            return Err(anyhow::format_err!(
-                "components[3] has unexpected value: {}",
-                other
-            ));
-        }
+        "3" => Ok(components[2].to_string()),
+        "8" => Ok(components[1].to_string()),
+        other => Err(IndexError::InvalidKey(format!(
+            "components[3] has unexpected value: {}",
+            other
+        ))
+        .into()),
     }
 }
 
@@ -299,6 +765,7 @@ fn do_broad_queries(bucket: &nut::Bucket, queries: Vec<Query>) -> anyhow::Result
         range_value_prefix: String::default(),
         range_value_start: q.range_value_start,
         value_equal: q.value_equal,
+        value_regex: q.value_regex,
     }).collect();
     query_pages(bucket, queries)
 }
@@ -327,9 +794,9 @@ fn query_pages(
     let mut entries = vec![];
     for query in queries {
         let prefix_len = query.hash_value.len() + 1;
-        let start = if query.range_value_prefix.len() > 0 {
+        let start = if !query.range_value_prefix.is_empty() {
             query.hash_value.clone() + "\x00" + &query.range_value_prefix
-        } else if query.range_value_start.len() > 0 {
+        } else if !query.range_value_start.is_empty() {
             // query.hash_value + "\x00" + &query.range_value_start
             // original code appends range_value_start here
             // but doesn't actually use it in iterator to filter
@@ -340,31 +807,29 @@ fn query_pages(
         let mut sub_entries = vec![];
         bucket.for_each(Box::new(|key, value| -> Result<(), String> {
             if key.starts_with(start.as_bytes()) {
-                if value.is_none() {
-                    return Ok(());
-                } else {
-                    if query.value_equal.len() > 0 {
-                        if value.unwrap() != query.value_equal.as_bytes() {
-                            return Ok(())
-                        }
-                    }
+                let value = match value {
+                    Some(v) => v,
+                    None => return Ok(()),
+                };
+                if !query.value_equal.is_empty() && value != query.value_equal.as_bytes() {
+                    return Ok(())
                 }
                 let range_value = from_utf8(&key[prefix_len..]).unwrap().to_string();
                 sub_entries.push(Entry {
                     table_name: query.table_name.clone(),
                     hash_value: start.clone(),
                     range_value,
-                    value: from_utf8(value.unwrap()).unwrap().to_string(),
+                    value: from_utf8(value).unwrap().to_string(),
                 });
             }
             Ok(())
         }))?;
         entries.extend(filter_entries(&sub_entries, &query));
     }
-    return Ok(entries);
+    Ok(entries)
 }
 
-fn calc_queries_for_serires(buckets: &Vec<Bucket>, series_ids: Vec<String>) -> Vec<Query> {
+fn calc_queries_for_serires(schema: Schema, buckets: &[Bucket], series_ids: Vec<String>) -> Vec<Query> {
     println!("\n{}", gray("make Query for series id"));
     let mut queries = vec![];
     for bucket in buckets {
@@ -372,10 +837,11 @@ fn calc_queries_for_serires(buckets: &Vec<Bucket>, series_ids: Vec<String>) -> V
             let encode_from_bytes = encode_time(bucket.from);
             Query {
                 table_name: bucket.table_name.clone(),
-                hash_value: format!("{}:{}", bucket.hash_key, id),
+                hash_value: schema.series_hash(bucket, id),
                 range_value_prefix: String::default(),
                 range_value_start: encode_from_bytes,
                 value_equal: String::default(),
+                value_regex: None,
             }
         }))
     }
@@ -392,3 +858,1469 @@ fn encode_time(from: u32) -> String {
         (from & 0x000000ff)
     )
 }
+
+#[derive(Parser, Debug)]
+struct Repl {
+    /// boltdb file
+    file: String,
+
+    /// tenant name
+    #[arg(short, long, default_value = "fake")]
+    tenant: String,
+
+    /// row shard
+    #[arg(short, long, default_value = "16")]
+    shard: u32,
+
+    /// Restrict to the days of this time range, by default every day
+    /// found in the file is used
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+}
+
+const REPL_HELP: &str = "commands:
+  labels                  label names
+  values <name>           values of a label
+  series <k=v> [k=v...]   series ids matching all the given label pairs
+  chunks <series-id>      chunks of a series
+  days                    days (index buckets) being looked at
+  tenant [name]           show or switch tenant
+  help, quit";
+
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// An open index file. Gzipped ones, as boltdb-shipper uploads them, are
+// decompressed to a temporary file first, removed once the db is closed.
+pub(crate) struct IndexDb {
+    db: DB,
+    _temp: Option<TempFile>,
+}
+
+impl Deref for IndexDb {
+    type Target = DB;
+
+    fn deref(&self) -> &DB {
+        &self.db
+    }
+}
+
+pub(crate) fn open_index(path: &Path) -> Result<IndexDb> {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+    let mut magic = [0; 2];
+    let gzipped = std::fs::File::open(path)?.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+    if !gzipped {
+        let db = DBBuilder::new(path).read_only(true).build()?;
+        return Ok(IndexDb { db, _temp: None });
+    }
+    // create_new so that a file (or symlink) someone else put at the
+    // name is never followed nor truncated, another name is tried instead
+    let (temp, mut out) = loop {
+        let n = SEQ.fetch_add(1, Ordering::Relaxed);
+        let name = std::env::temp_dir().join(format!("lf-index-{}-{n}", std::process::id()));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&name) {
+            Ok(f) => break (TempFile(name), f),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    std::io::copy(&mut GzDecoder::new(std::fs::File::open(path)?), &mut out)
+        .map_err(|e| anyhow::format_err!("{} does not gunzip: {e}", display_path(path)))?;
+    let db = DBBuilder::new(&temp.0).read_only(true).build()?;
+    Ok(IndexDb { db, _temp: Some(temp) })
+}
+
+// calls `f` with the key (minus `prefix`) and value of every item whose
+// key starts with `prefix`, stops early when `f` returns false
+pub(crate) fn scan_prefix<F: FnMut(&[u8], &[u8]) -> bool>(bucket: &nut::Bucket, prefix: &[u8], mut f: F) -> Result<()> {
+    let cursor = bucket.cursor()?;
+    let mut item = cursor.seek(prefix)?;
+    while let Some(key) = item.key {
+        if !key.starts_with(prefix) {
+            break;
+        }
+        if !f(&key[prefix.len()..], item.value.unwrap_or_default()) {
+            break;
+        }
+        item = cursor.next()?;
+    }
+    Ok(())
+}
+
+// skip scan over `{shard}:{tenant}:d{day}:` keys collecting the days
+fn discover_days(bucket: &nut::Bucket, tenant: &str, shard: u32) -> Result<Vec<i64>> {
+    let mut days = std::collections::BTreeSet::new();
+    for i in 0..shard {
+        let prefix = format!("{:02}:{}:d", i, tenant);
+        let cursor = bucket.cursor()?;
+        let mut item = cursor.seek(prefix.as_bytes())?;
+        while let Some(key) = item.key {
+            let Some(rest) = key.strip_prefix(prefix.as_bytes()) else {
+                break;
+            };
+            let day = from_utf8(rest)?.split(':').next().unwrap_or_default();
+            let Ok(d) = day.parse::<i64>() else {
+                break;
+            };
+            days.insert(d);
+            // ';' sorts right after ':', jumping over the rest of the day
+            item = cursor.seek(format!("{prefix}{d};").as_bytes())?;
+        }
+    }
+    Ok(days.into_iter().collect())
+}
+
+struct ReplState {
+    tenant: String,
+    shard: u32,
+    days: Vec<i64>,
+    // days come from the file rather than from a time range
+    discover: bool,
+    cache: std::collections::HashMap<String, Vec<String>>,
+}
+
+impl ReplState {
+    // the days of the time range, every day of the tenant in the file
+    // without one
+    fn new(bucket: &nut::Bucket, tenant: &str, shard: u32, t: &TimeRangeOpts) -> Result<Self> {
+        let discover = t.is_empty();
+        let days = if discover {
+            discover_days(bucket, tenant, shard)?
+        } else {
+            let (start, end) = get_duration(t)?;
+            make_buckets(tenant, DAY, start, end)?
+                .iter()
+                .filter_map(|b| b.table_name.strip_prefix("index_")?.parse().ok())
+                .collect()
+        };
+        Ok(ReplState {
+            tenant: tenant.to_string(),
+            shard,
+            days,
+            discover,
+            cache: Default::default(),
+        })
+    }
+
+    fn labels(&self, bucket: &nut::Bucket) -> Result<Vec<String>> {
+        let mut names = std::collections::BTreeSet::new();
+        for d in self.days.iter() {
+            for i in 0..self.shard {
+                let prefix = format!("{:02}:{}:d{}:logs:", i, self.tenant, d);
+                scan_prefix(bucket, prefix.as_bytes(), |k, _| {
+                    if let Some(name) = k.split(|c| *c == 0).next() {
+                        names.insert(String::from_utf8_lossy(name).to_string());
+                    }
+                    true
+                })?;
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    fn values(&self, bucket: &nut::Bucket, name: &str) -> Result<Vec<String>> {
+        let mut values = std::collections::BTreeSet::new();
+        for d in self.days.iter() {
+            for i in 0..self.shard {
+                let prefix = format!("{:02}:{}:d{}:logs:{}\x00", i, self.tenant, d, name);
+                scan_prefix(bucket, prefix.as_bytes(), |_, v| {
+                    values.insert(String::from_utf8_lossy(v).to_string());
+                    true
+                })?;
+            }
+        }
+        Ok(values.into_iter().collect())
+    }
+
+    fn series(&self, bucket: &nut::Bucket, matchers: &[KeyValue]) -> Result<Vec<String>> {
+        let mut result: Option<HashSet<String>> = None;
+        for kv in matchers {
+            let hash_val = digest(&SHA256, kv.value.as_ref());
+            let encoded = encode_config(hash_val, STANDARD_NO_PAD);
+            let mut ids = HashSet::new();
+            for d in self.days.iter() {
+                for i in 0..self.shard {
+                    let prefix = format!("{:02}:{}:d{}:logs:{}\x00{}\x00", i, self.tenant, d, kv.key, encoded);
+                    let mut err = None;
+                    scan_prefix(bucket, prefix.as_bytes(), |k, v| {
+                        if v != kv.value.as_bytes() {
+                            return true;
+                        }
+                        let range_value = format!("{}\x00{}", encoded, String::from_utf8_lossy(k));
+                        match parse_chunk_time_range_value(&range_value) {
+                            Ok(id) => {
+                                ids.insert(id);
+                                true
+                            }
+                            Err(e) => {
+                                err = Some(e);
+                                false
+                            }
+                        }
+                    })?;
+                    if let Some(e) = err {
+                        return Err(e);
+                    }
+                }
+            }
+            result = Some(match result {
+                None => ids,
+                Some(r) => r.intersection(&ids).cloned().collect(),
+            });
+        }
+        let mut ids: Vec<_> = result.unwrap_or_default().into_iter().collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn chunks(&self, bucket: &nut::Bucket, series_id: &str) -> Result<Vec<String>> {
+        let mut chunks = vec![];
+        for d in self.days.iter() {
+            let prefix = format!("{}:d{}:{}\x00", self.tenant, d, series_id);
+            let mut err = None;
+            scan_prefix(bucket, prefix.as_bytes(), |k, _| {
+                match parse_chunk_time_range_value(&String::from_utf8_lossy(k)) {
+                    Ok(id) => {
+                        chunks.push(id);
+                        true
+                    }
+                    Err(e) => {
+                        err = Some(e);
+                        false
+                    }
+                }
+            })?;
+            if let Some(e) = err {
+                return Err(e);
+            }
+        }
+        let mut lines = vec![];
+        for c in chunks {
+            let line = match ChunkRef::parse_external_key(&c) {
+                Ok(r) => format!(
+                    "{c} {}",
+                    gray(&format!("{} - {}", format_millis(r.from), format_millis(r.to)))
+                ),
+                Err(_) => c,
+            };
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+
+    fn run(&mut self, bucket: &nut::Bucket, line: &str) -> Result<Option<Vec<String>>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((cmd, args)) = words.split_first() else {
+            return Ok(None);
+        };
+        // lookups are answered from the cache once done
+        if let Some(lines) = self.cache.get(&words.join(" ")) {
+            return Ok(Some(lines.clone()));
+        }
+        let lines = match (*cmd, args) {
+            ("labels", []) => self.labels(bucket)?,
+            ("values", [name]) => self.values(bucket, name)?,
+            ("series", matchers) if !matchers.is_empty() => {
+                let matchers = matchers
+                    .iter()
+                    .map(|m| m.parse::<KeyValue>())
+                    .collect::<Result<Vec<_>>>()?;
+                self.series(bucket, &matchers)?
+            }
+            ("chunks", [id]) => self.chunks(bucket, id)?,
+            ("days", []) => {
+                return Ok(Some(
+                    self.days
+                        .iter()
+                        .map(|d| {
+                            let date = NaiveDateTime::from_timestamp_opt(d * 86400, 0).unwrap_or_default();
+                            format!("{d} {}", gray(&date.date().to_string()))
+                        })
+                        .collect(),
+                ))
+            }
+            ("tenant", []) => return Ok(Some(vec![self.tenant.clone()])),
+            ("tenant", [t]) => {
+                self.tenant = t.to_string();
+                self.cache.clear();
+                if self.discover {
+                    self.days = discover_days(bucket, &self.tenant, self.shard)?;
+                }
+                return Ok(Some(vec![format!("tenant is now {t}")]));
+            }
+            _ => return Err(anyhow::format_err!("invalid command, try help")),
+        };
+        self.cache.insert(words.join(" "), lines.clone());
+        Ok(Some(lines))
+    }
+}
+
+fn repl(r: Repl) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    let db = open_index(&native_path(&r.file))?;
+    // the transaction stays open for the whole session
+    let tx = db.begin_tx()?;
+    let bucket = tx.bucket(b"index")?;
+
+    let mut state = ReplState::new(&bucket, &r.tenant, r.shard, &r.time_range)?;
+    println!("{}", gray(&format!("{} opened, {} days for tenant {}, type help for commands", r.file, state.days.len(), state.tenant)));
+
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("{} ", blue("bolt>"));
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            break;
+        };
+        let line = line?;
+        match line.trim() {
+            "quit" | "exit" | "q" => break,
+            "help" | "?" => {
+                println!("{REPL_HELP}");
+                continue;
+            }
+            _ => {}
+        }
+        match state.run(&bucket, &line) {
+            Ok(Some(out)) => {
+                for l in out.iter() {
+                    println!("{l}");
+                }
+                println!("{}", gray(&format!("({} rows)", out.len())));
+            }
+            Ok(None) => {}
+            Err(err) => println!("{}", red(&err.to_string())),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct LabelsApi {
+    /// boltdb file
+    file: String,
+
+    /// tenant name
+    #[arg(short, long, default_value = "fake")]
+    tenant: String,
+
+    /// row shard
+    #[arg(short, long, default_value = "16")]
+    shard: u32,
+
+    /// Restrict to the days of this time range, by default every day
+    /// found in the file is used
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+}
+
+#[derive(Parser, Debug)]
+struct LabelValuesApi {
+    #[command(flatten)]
+    index: LabelsApi,
+
+    /// label name
+    label: String,
+}
+
+// the json of loki's label apis, for when the cluster is gone but its
+// index files are not
+fn label_api(l: &LabelsApi, label: Option<&str>) -> Result<()> {
+    let db = open_index(&native_path(&l.file))?;
+    let tx = db.begin_tx()?;
+    let bucket = tx.bucket(b"index")?;
+    let state = ReplState::new(&bucket, &l.tenant, l.shard, &l.time_range)?;
+    let data = match label {
+        None => state.labels(&bucket)?,
+        Some(name) => state.values(&bucket, name)?,
+    };
+    let obj = serde_json::json!({ "status": "success", "data": data });
+    println!("{}", serde_json::to_string_pretty(&obj)?);
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Dump {
+    /// boltdb file
+    file: String,
+
+    /// bucket to dump, the tenant of compacted files
+    #[arg(short, long, default_value = "index")]
+    bucket: String,
+
+    /// json: a record per line, tsv: hash value, value, then the range
+    /// value parts
+    #[arg(short, long, value_enum, default_value = "json")]
+    format: DumpFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum DumpFormat {
+    Json,
+    Tsv,
+}
+
+// printable text as is, anything else base64 encoded
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+enum Part {
+    Text(String),
+    Binary { base64: String },
+}
+
+impl Part {
+    fn new(b: &[u8]) -> Self {
+        match from_utf8(b) {
+            Ok(s) if !s.chars().any(char::is_control) && !s.starts_with("base64:") => Part::Text(s.to_string()),
+            _ => Part::Binary { base64: base64::encode(b) },
+        }
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Part::Text(s) => write!(f, "{s}"),
+            Part::Binary { base64 } => write!(f, "base64:{base64}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct DumpRecord {
+    hash_value: Part,
+    // the NUL separated parts, the last one of loki's keys is empty
+    range_value: Vec<Part>,
+    value: Part,
+}
+
+// keys are `{hash value}\0{range value}`, range values are parts ended
+// by NULs
+fn dump_record(key: &[u8], value: &[u8]) -> DumpRecord {
+    let (hash, range) = match key.iter().position(|b| *b == 0) {
+        Some(i) => (&key[..i], Some(&key[i + 1..])),
+        None => (key, None),
+    };
+    DumpRecord {
+        hash_value: Part::new(hash),
+        range_value: range.map(|r| r.split(|b| *b == 0).map(Part::new).collect()).unwrap_or_default(),
+        value: Part::new(value),
+    }
+}
+
+fn dump(d: Dump) -> Result<()> {
+    use std::io::Write;
+
+    let db = open_index(&native_path(&d.file))?;
+    let tx = db.begin_tx()?;
+    let Ok(bucket) = tx.bucket(d.bucket.as_bytes()) else {
+        let names: Vec<_> = tx.buckets().iter().map(|b| String::from_utf8_lossy(b).to_string()).collect();
+        return Err(IndexError::NotFound(format!("no bucket {}, the file has {}", d.bucket, names.join(", "))).into());
+    };
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut err = None;
+    scan_prefix(&bucket, b"", |k, v| {
+        let record = dump_record(k, v);
+        let written = match d.format {
+            DumpFormat::Json => serde_json::to_writer(&mut out, &record).map_err(anyhow::Error::from),
+            DumpFormat::Tsv => {
+                let range: Vec<_> = record.range_value.iter().map(|p| format!("\t{p}")).collect();
+                write!(out, "{}\t{}{}", record.hash_value, record.value, range.concat()).map_err(anyhow::Error::from)
+            }
+        }
+        .and_then(|_| Ok(writeln!(out)?));
+        match written {
+            Ok(()) => true,
+            Err(e) => {
+                err = Some(e);
+                false
+            }
+        }
+    })?;
+    if let Some(e) = err {
+        return Err(e);
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Stats {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct TableStats {
+    keys: usize,
+    series: HashSet<String>,
+    labels: HashSet<String>,
+    chunks: usize,
+    // of the chunks, milliseconds
+    mint: Option<i64>,
+    maxt: Option<i64>,
+}
+
+impl TableStats {
+    fn add(&mut self, key: &[u8]) {
+        self.keys += 1;
+        let mut parts = key.splitn(2, |b| *b == 0);
+        let (Some(Ok(hash)), Some(Ok(range_value))) = (parts.next().map(from_utf8), parts.next().map(from_utf8)) else {
+            return;
+        };
+        if let Some((tenant, _, id)) = parse_series_hash(hash) {
+            self.series.insert(format!("{tenant}/{id}"));
+            let Ok(r) = parse_chunk_time_range_value(range_value).and_then(|c| ChunkRef::parse_external_key(&c)) else {
+                return;
+            };
+            self.chunks += 1;
+            self.mint = Some(self.mint.map_or(r.from, |t| t.min(r.from)));
+            self.maxt = Some(self.maxt.map_or(r.to, |t| t.max(r.to)));
+        } else if let Some((_, name)) = parse_label_hash(hash) {
+            self.labels.insert(name.to_string());
+        }
+    }
+}
+
+fn stats(s: Stats) -> Result<()> {
+    let mut files = vec![];
+    for p in s.paths.iter() {
+        collect_files(&native_path(p), &mut files)?;
+    }
+    println!(
+        "{:<12} {:>10} {:>8} {:>7} {:>8} {:<23} {:<23} {:>10}  file",
+        "table", "keys", "series", "labels", "chunks", "earliest", "latest", "size"
+    );
+    for file in files.iter() {
+        let db = match open_index(file) {
+            Ok(db) => db,
+            Err(err) => {
+                println!("{} {}", yellow(&display_path(file)), gray(&format!("skipped: {err}")));
+                continue;
+            }
+        };
+        let tx = db.begin_tx()?;
+        let mut t = TableStats::default();
+        for name in tx.buckets() {
+            let bucket = tx.bucket(&name)?;
+            scan_prefix(&bucket, b"", |k, _| {
+                t.add(k);
+                true
+            })?;
+        }
+        let time = |t: Option<i64>| t.map(format_millis).unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<12} {:>10} {:>8} {:>7} {:>8} {:<23} {:<23} {:>10}  {}",
+            table_of(file).unwrap_or_else(|| "-".to_string()),
+            t.keys,
+            t.series.len(),
+            t.labels.len(),
+            t.chunks,
+            time(t.mint),
+            time(t.maxt),
+            format_bytes(std::fs::metadata(file)?.len()),
+            gray(&display_path(file))
+        );
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Deletes {
+    /// the delete_requests boltdb file of the compactor
+    file: PathBuf,
+
+    /// only report this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct DeleteRequest {
+    query: String,
+    // milliseconds
+    created_at: i64,
+    start: i64,
+    end: i64,
+    status: String,
+}
+
+// A delete request is two entries keyed by `{tenant}:{request id}`:
+//   `1\0{tenant}:{request id}` -> status
+//   `2:{tenant}:{request id}\0{created at:x}:{start:x}:{end:x}` -> query
+// other entries (the `3:{tenant}` cache generation numbers) are ignored.
+fn add_delete_entry(requests: &mut BTreeMap<String, DeleteRequest>, key: &[u8], value: &[u8]) {
+    let mut parts = key.splitn(2, |b| *b == 0);
+    let (Some(Ok(hash)), Some(Ok(range_value))) = (parts.next().map(from_utf8), parts.next().map(from_utf8)) else {
+        return;
+    };
+    let value = String::from_utf8_lossy(value).to_string();
+    match hash.split_once(':') {
+        None if hash == "1" => requests.entry(range_value.to_string()).or_default().status = value,
+        Some(("2", id)) => {
+            let times: Vec<_> = range_value.split(':').map(|t| i64::from_str_radix(t, 16)).collect();
+            let [Ok(created_at), Ok(start), Ok(end)] = times[..] else {
+                return;
+            };
+            let r = requests.entry(id.to_string()).or_default();
+            (r.query, r.created_at, r.start, r.end) = (value, created_at, start, end);
+        }
+        _ => {}
+    }
+}
+
+fn deletes(d: Deletes) -> Result<()> {
+    let db = open_index(&native_path(&d.file))?;
+    let tx = db.begin_tx()?;
+    let mut requests = BTreeMap::new();
+    for name in tx.buckets() {
+        let bucket = tx.bucket(&name)?;
+        scan_prefix(&bucket, b"", |k, v| {
+            add_delete_entry(&mut requests, k, v);
+            true
+        })?;
+    }
+    let mut requests: Vec<_> = requests
+        .into_iter()
+        .filter_map(|(id, r)| {
+            let (tenant, id) = id.split_once(':')?;
+            d.tenant.as_ref().is_none_or(|t| t == tenant).then(|| (tenant.to_string(), id.to_string(), r))
+        })
+        .collect();
+    if requests.is_empty() {
+        return Err(IndexError::NotFound(format!("no delete requests in {}", display_path(&d.file))).into());
+    }
+    requests.sort_by_key(|(_, _, r)| r.created_at);
+    println!(
+        "{:<12} {:<20} {:<10} {:<23} {:<23} {:<23}  query",
+        "tenant", "request id", "status", "created at", "start", "end"
+    );
+    for (tenant, id, r) in requests.iter() {
+        let status = match r.status.as_str() {
+            "processed" => green(&format!("{:<10}", r.status)),
+            "" => gray(&format!("{:<10}", "-")),
+            _ => yellow(&format!("{:<10}", r.status)),
+        };
+        // a status entry without its details
+        let time = |t: i64| if r.query.is_empty() { "-".to_string() } else { format_millis(t) };
+        println!(
+            "{:<12} {:<20} {} {:<23} {:<23} {:<23}  {}",
+            tenant,
+            id,
+            status,
+            time(r.created_at),
+            time(r.start),
+            time(r.end),
+            r.query
+        );
+    }
+    println!("{}", gray(&format!("{} delete requests", requests.len())));
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Churn {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// only report this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+}
+
+// `{tenant}:d{day}:{seriesID}` hash keys of chunk entries, label entries
+// start with the row shard and have more parts
+fn parse_series_hash(hash: &str) -> Option<(&str, i64, &str)> {
+    let (rest, series_id) = hash.rsplit_once(':')?;
+    let (tenant, day) = rest.rsplit_once(':')?;
+    let day = day.strip_prefix('d')?.parse().ok()?;
+    if tenant.is_empty() || series_id.is_empty() || series_id.contains(':') {
+        return None;
+    }
+    Some((tenant, day, series_id))
+}
+
+fn churn(c: Churn) -> Result<()> {
+    let mut files = vec![];
+    for p in c.paths.iter() {
+        collect_files(&native_path(p), &mut files)?;
+    }
+    // tenant -> day -> series ids
+    let mut series: BTreeMap<String, BTreeMap<i64, HashSet<String>>> = BTreeMap::new();
+    for file in files.iter() {
+        let db = match open_index(file) {
+            Ok(db) => db,
+            Err(err) => {
+                println!("{} {}", yellow(&display_path(file)), gray(&format!("skipped: {err}")));
+                continue;
+            }
+        };
+        let tx = db.begin_tx()?;
+        // "index" for uncompacted files, one bucket per tenant once compacted
+        for name in tx.buckets() {
+            let bucket = tx.bucket(&name)?;
+            scan_prefix(&bucket, b"", |k, _| {
+                let hash = k.split(|b| *b == 0).next().unwrap_or_default();
+                if let Some((tenant, day, id)) = from_utf8(hash).ok().and_then(parse_series_hash) {
+                    if c.tenant.as_ref().map(|t| t == tenant).unwrap_or(true) {
+                        series
+                            .entry(tenant.to_string())
+                            .or_default()
+                            .entry(day)
+                            .or_default()
+                            .insert(id.to_string());
+                    }
+                }
+                true
+            })?;
+        }
+    }
+    if series.is_empty() {
+        return Err(IndexError::NotFound("no chunk index entries found".to_string()).into());
+    }
+
+    for (tenant, days) in series.iter() {
+        println!("{}", green(&format!("tenant {tenant}")));
+        println!(
+            "  {:<8} {:<10} {:>10} {:>10} {:>10} {:>10}",
+            "day", "date", "series", "created", "deleted", "persisting"
+        );
+        let mut prev: Option<(i64, &HashSet<String>)> = None;
+        for (day, ids) in days.iter() {
+            let date = format_millis(day * 86_400_000);
+            let date = date.split(' ').next().unwrap_or_default().to_string();
+            let cols = match prev {
+                Some((_, p)) => {
+                    let persisting = ids.intersection(p).count();
+                    (
+                        (ids.len() - persisting).to_string(),
+                        (p.len() - persisting).to_string(),
+                        persisting.to_string(),
+                    )
+                }
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+            };
+            let gap = match prev {
+                Some((d, _)) if d + 1 != *day => yellow(&format!(" (after a gap of {} days)", day - d - 1)),
+                _ => String::new(),
+            };
+            println!(
+                "  {:<8} {:<10} {:>10} {:>10} {:>10} {:>10}{gap}",
+                format!("d{day}"),
+                date,
+                ids.len(),
+                cols.0,
+                cols.1,
+                cols.2
+            );
+            prev = Some((*day, ids));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Collisions {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// only report this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+}
+
+// `{shard}:{tenant}:d{day}:logs:{name}` hash keys of label entries
+fn parse_label_hash(hash: &str) -> Option<(&str, &str)> {
+    let (rest, name) = hash.split_once(":logs:")?;
+    let (shard, rest) = rest.split_once(':')?;
+    let (tenant, day) = rest.rsplit_once(':')?;
+    if shard.parse::<u32>().is_err() || !day.starts_with('d') || tenant.is_empty() || name.is_empty() {
+        return None;
+    }
+    Some((tenant, name))
+}
+
+#[derive(Debug, Default)]
+struct SeriesInfo {
+    labels: BTreeMap<String, String>,
+    // fingerprints of its chunk keys
+    fingerprints: HashSet<u64>,
+    chunks: usize,
+    // the chunks, once per day indexed when spanning several
+    refs: Vec<ChunkRef>,
+}
+
+/// Fingerprints claimed by several series, with the fingerprint of the
+/// chunk keys and the one computed from the labels both counting (loki
+/// may have remapped the former).
+fn find_collisions(series: &BTreeMap<String, SeriesInfo>) -> BTreeMap<u64, Vec<&str>> {
+    let mut by_fp: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+    for (id, info) in series.iter() {
+        let mut fps = info.fingerprints.clone();
+        if !info.labels.is_empty() {
+            fps.insert(fingerprint(&info.labels));
+        }
+        for fp in fps {
+            by_fp.entry(fp).or_default().push(id);
+        }
+    }
+    by_fp.retain(|_, ids| ids.len() > 1);
+    by_fp
+}
+
+// tenant -> series id -> labels and chunk fingerprints, from the label and
+// chunk entries of every bucket of the files under `paths`
+fn read_series(paths: &[PathBuf], tenant: Option<&str>) -> Result<BTreeMap<String, BTreeMap<String, SeriesInfo>>> {
+    let mut files = vec![];
+    for p in paths.iter() {
+        collect_files(&native_path(p), &mut files)?;
+    }
+    let mut tenants: BTreeMap<String, BTreeMap<String, SeriesInfo>> = BTreeMap::new();
+    let wanted = |t: &str| tenant.map(|tenant| t == tenant).unwrap_or(true);
+    for file in files.iter() {
+        let db = match open_index(file) {
+            Ok(db) => db,
+            Err(err) => {
+                println!("{} {}", yellow(&display_path(file)), gray(&format!("skipped: {err}")));
+                continue;
+            }
+        };
+        let tx = db.begin_tx()?;
+        for name in tx.buckets() {
+            let bucket = tx.bucket(&name)?;
+            scan_prefix(&bucket, b"", |k, v| {
+                let mut parts = k.splitn(2, |b| *b == 0);
+                let (Some(Ok(hash)), Some(Ok(range_value))) =
+                    (parts.next().map(from_utf8), parts.next().map(from_utf8))
+                else {
+                    return true;
+                };
+                if let Some((tenant, _, id)) = parse_series_hash(hash).filter(|h| wanted(h.0)) {
+                    let Ok(chunk) = parse_chunk_time_range_value(range_value) else {
+                        return true;
+                    };
+                    let info = tenants.entry(tenant.to_string()).or_default().entry(id.to_string()).or_default();
+                    info.chunks += 1;
+                    if let Ok(r) = ChunkRef::parse_external_key(&chunk) {
+                        info.fingerprints.insert(r.fingerprint);
+                        info.refs.push(r);
+                    }
+                } else if let Some((tenant, label)) = parse_label_hash(hash).filter(|h| wanted(h.0)) {
+                    let Ok(id) = parse_chunk_time_range_value(range_value) else {
+                        return true;
+                    };
+                    tenants
+                        .entry(tenant.to_string())
+                        .or_default()
+                        .entry(id)
+                        .or_default()
+                        .labels
+                        .insert(label.to_string(), String::from_utf8_lossy(v).to_string());
+                }
+                true
+            })?;
+        }
+    }
+    if tenants.is_empty() {
+        return Err(IndexError::NotFound("no index entries found".to_string()).into());
+    }
+    Ok(tenants)
+}
+
+// series ids are hashes of the label sets, so different ids of one
+// fingerprint are streams loki may have merged
+fn collisions(c: Collisions) -> Result<()> {
+    let tenants = read_series(&c.paths, c.tenant.as_deref())?;
+
+    let mut total = 0;
+    for (tenant, series) in tenants.iter() {
+        let found = find_collisions(series);
+        let summary = format!("tenant {tenant}: {} series, {} colliding fingerprints", series.len(), found.len());
+        if found.is_empty() {
+            println!("{}", green(&summary));
+            continue;
+        }
+        println!("{}", red(&summary));
+        for (fp, ids) in found.iter() {
+            println!("  fingerprint {}", yellow(&format!("{fp:016x}")));
+            for id in ids {
+                let info = &series[*id];
+                let labels = if info.labels.is_empty() {
+                    gray("(labels not in the index)")
+                } else {
+                    format_labels(&info.labels)
+                };
+                println!("    {labels} {}", gray(&format!("series {id}, {} chunks", info.chunks)));
+            }
+        }
+        total += found.len();
+    }
+    if total > 0 {
+        return Err(anyhow::format_err!("{total} colliding fingerprints"));
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct SeriesList {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// only list this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+
+    /// only list the series matching all of these, like a=1 or b!~"x.*"
+    #[arg(short, long, num_args = 1..)]
+    query: Vec<LabelMatcher>,
+}
+
+// a label a series lacks has the value "", as in loki
+fn series_matches(labels: &BTreeMap<String, String>, query: &[LabelMatcher]) -> bool {
+    query.iter().all(|m| m.matches(labels.get(&m.key).map_or("", String::as_str)))
+}
+
+// the label sets come from the label entries (the series -> label names
+// entries of v11 have no values), the fingerprint from the chunk keys or,
+// without chunks in the files, from the labels
+fn series(s: SeriesList) -> Result<()> {
+    let tenants = read_series(&s.paths, s.tenant.as_deref())?;
+    let mut total = 0;
+    for (tenant, series) in tenants.iter() {
+        let mut found: Vec<_> = series.iter().filter(|(_, info)| series_matches(&info.labels, &s.query)).collect();
+        found.sort_by_key(|(id, info)| (format_labels(&info.labels), *id));
+        println!("{}", green(&format!("tenant {tenant}: {} of {} series", found.len(), series.len())));
+        for (id, info) in found.iter() {
+            let mut fps: Vec<_> = info.fingerprints.iter().map(|fp| format!("{fp:016x}")).collect();
+            fps.sort();
+            let fingerprint = match fps.is_empty() {
+                true if info.labels.is_empty() => "-".to_string(),
+                true => format!("{:016x} (from labels)", fingerprint(&info.labels)),
+                false => fps.join(","),
+            };
+            let labels = match info.labels.is_empty() {
+                true => gray("(labels not in the index)"),
+                false => format_labels(&info.labels),
+            };
+            println!("  {labels} {}", gray(&format!("fingerprint {fingerprint}, series {id}, {} chunks", info.chunks)));
+        }
+        total += found.len();
+    }
+    if total == 0 {
+        println!("{}", yellow("no series matched"));
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Cardinality {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// only report this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+
+    /// only print the first n label names of each tenant
+    #[arg(short = 'n', long)]
+    top: Option<usize>,
+}
+
+// label name -> (distinct values, series having it), the most values first
+fn label_cardinality(series: &BTreeMap<String, SeriesInfo>) -> Vec<(&str, usize, usize)> {
+    let mut labels: BTreeMap<&str, (HashSet<&str>, usize)> = BTreeMap::new();
+    for info in series.values() {
+        for (name, value) in info.labels.iter() {
+            let (values, n) = labels.entry(name).or_default();
+            values.insert(value);
+            *n += 1;
+        }
+    }
+    let mut out: Vec<_> = labels.into_iter().map(|(name, (values, n))| (name, values.len(), n)).collect();
+    out.sort_by_key(|(name, values, n)| (std::cmp::Reverse((*values, *n)), *name));
+    out
+}
+
+fn cardinality(c: Cardinality) -> Result<()> {
+    let tenants = read_series(&c.paths, c.tenant.as_deref())?;
+    for (tenant, series) in tenants.iter() {
+        let labels = label_cardinality(series);
+        println!("{}", green(&format!("tenant {tenant}: {} series, {} label names", series.len(), labels.len())));
+        if labels.is_empty() {
+            println!("  {}", gray("(labels not in the index)"));
+            continue;
+        }
+        println!("  {:<30} {:>8} {:>8}", "label", "values", "series");
+        for (name, values, n) in labels.iter().take(c.top.unwrap_or(usize::MAX)) {
+            println!("  {name:<30} {values:>8} {n:>8}");
+        }
+    }
+    Ok(())
+}
+
+#[derive(Parser, Debug)]
+struct Retention {
+    /// boltdb files or directories of them (daily tables, searched recursively)
+    #[arg(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// only report this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+
+    /// retention period of every stream, like 30d or 744h
+    #[arg(long, value_parser = parse_duration)]
+    retention: Duration,
+
+    /// retention period of the streams matching all of the comma separated
+    /// matchers, like app=b:7d or app=~x.*,env!=prod:14d; the shortest one
+    /// of the matching rules applies, as for loki's rules of one priority
+    #[arg(long)]
+    stream_retention: Vec<StreamRetention>,
+
+    /// schema of the index, decides the form of the chunk keys
+    #[arg(long, value_enum, default_value = "v11")]
+    schema: Schema,
+
+    /// size the expired chunks in this store, a chunks directory of a
+    /// filesystem object store or s3://bucket/prefix
+    #[arg(long)]
+    store: Option<String>,
+
+    #[command(flatten)]
+    s3: S3Opts,
+}
+
+#[derive(Debug, Clone)]
+struct StreamRetention {
+    matchers: Vec<LabelMatcher>,
+    period: Duration,
+}
+
+impl FromStr for StreamRetention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((selector, period)) = s.rsplit_once(':') else {
+            return Err(anyhow::format_err!("invalid format, expect matchers and a period like app=b:7d"));
+        };
+        Ok(StreamRetention {
+            matchers: split_matchers(selector).into_iter().map(str::parse).collect::<Result<_>>()?,
+            period: parse_duration(period)?,
+        })
+    }
+}
+
+impl fmt::Display for StreamRetention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let matchers: Vec<_> = self.matchers.iter().map(|m| m.to_string()).collect();
+        write!(f, "{{{}}} {}", matchers.join(", "), humantime::format_duration(self.period))
+    }
+}
+
+// the comma separated matchers of `selector`, only a comma followed by a
+// label name and an operator starts a new one, so that regexes like
+// a{1,2} stay whole
+fn split_matchers(selector: &str) -> Vec<&str> {
+    let next = Regex::new(r",[a-zA-Z_][a-zA-Z0-9_]*(?:=|!=|=~|!~)").unwrap();
+    let mut out = vec![];
+    let mut start = 0;
+    for m in next.find_iter(selector) {
+        out.push(&selector[start..m.start()]);
+        start = m.start() + 1;
+    }
+    out.push(&selector[start..]);
+    out
+}
+
+// the rule of the series with `labels`, None for the global retention. As
+// in loki's RetentionPeriodFor, a missing label has the value "", so
+// env!=prod applies to series without env.
+fn retention_rule<'a>(labels: &BTreeMap<String, String>, rules: &'a [StreamRetention]) -> Option<&'a StreamRetention> {
+    let value = |name: &str| labels.get(name).map_or("", String::as_str);
+    rules
+        .iter()
+        .filter(|r| r.matchers.iter().all(|m| m.matches(value(&m.key))))
+        .min_by_key(|r| r.period)
+}
+
+// the distinct chunks of `refs` ending before `cutoff` (milliseconds), and
+// how many distinct chunks there are
+fn expired_chunks(refs: &[ChunkRef], cutoff: i64) -> (Vec<&ChunkRef>, usize) {
+    let mut seen = HashSet::new();
+    let distinct: Vec<_> = refs.iter().filter(|r| seen.insert((r.fingerprint, r.from, r.to, r.checksum))).collect();
+    let total = distinct.len();
+    (distinct.into_iter().filter(|r| r.to < cutoff).collect(), total)
+}
+
+impl ChunkSource {
+    // total size of the chunks `keys` and how many of them are missing
+    fn sizes(&self, keys: &[String]) -> Result<(u64, usize)> {
+        let (mut bytes, mut missing) = (0, 0);
+        match self {
+            ChunkSource::Fs(root) => {
+                for key in keys {
+                    match fs_chunk_path(root, key).and_then(|p| Ok(std::fs::metadata(p)?.len())) {
+                        Ok(n) => bytes += n,
+                        Err(_) => missing += 1,
+                    }
+                }
+            }
+            ChunkSource::S3(client, prefix) => {
+                // a listing per tenant rather than a request per chunk
+                let tenants: HashSet<_> = keys.iter().filter_map(|k| k.split_once('/')).map(|k| k.0).collect();
+                let mut sizes = std::collections::HashMap::new();
+                for tenant in tenants {
+                    for o in client.list(&format!("{prefix}{tenant}/"))? {
+                        sizes.insert(o.key, o.size);
+                    }
+                }
+                for key in keys {
+                    match sizes.get(&format!("{prefix}{key}")) {
+                        Some(n) => bytes += n,
+                        None => missing += 1,
+                    }
+                }
+            }
+        }
+        Ok((bytes, missing))
+    }
+}
+
+fn retention(r: Retention) -> Result<()> {
+    let tenants = read_series(&r.paths, r.tenant.as_deref())?;
+    let now = chrono::Utc::now().timestamp_millis();
+    println!(
+        "{}",
+        gray(&format!("retention {} as of {}, {} stream rules", humantime::format_duration(r.retention), format_millis(now), r.stream_retention.len()))
+    );
+    for rule in r.stream_retention.iter() {
+        println!("  {}", gray(&rule.to_string()));
+    }
+    let source = r.store.as_deref().map(|s| ChunkSource::new(s, &r.s3)).transpose()?;
+    for (tenant, series) in tenants.iter() {
+        let (mut all, mut expired, mut whole, mut touched) = (HashSet::new(), vec![], 0, 0);
+        let mut found: Vec<_> = series.iter().map(|(id, info)| (format_labels(&info.labels), id, info)).collect();
+        found.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        println!("{}", green(&format!("tenant {tenant}: {} series", series.len())));
+        for (labels, id, info) in found {
+            let rule = retention_rule(&info.labels, &r.stream_retention);
+            let period = rule.map_or(r.retention, |rule| rule.period);
+            let (chunks, n) = expired_chunks(&info.refs, now - period.as_millis() as i64);
+            all.extend(info.refs.iter().map(|c| r.schema.chunk_key(c)));
+            if chunks.is_empty() {
+                continue;
+            }
+            touched += 1;
+            let what = match chunks.len() == n {
+                true => {
+                    whole += 1;
+                    format!("all {n} chunks")
+                }
+                false => format!("{} of {n} chunks", chunks.len()),
+            };
+            let latest = chunks.iter().map(|c| c.to).max().unwrap_or_default();
+            let by = rule.map_or_else(|| "the retention".to_string(), |rule| rule.to_string());
+            let labels = if info.labels.is_empty() { format!("series {id}") } else { labels };
+            println!("  {labels} {}", gray(&format!("{what} up to {}, by {by}", format_millis(latest))));
+            expired.extend(chunks.into_iter().map(|c| r.schema.chunk_key(c)));
+        }
+        expired.sort();
+        expired.dedup();
+        let mut summary = format!(
+            "  {} of {} series lose chunks ({whole} entirely), {} of {} chunks expired",
+            touched,
+            series.len(),
+            expired.len(),
+            all.len()
+        );
+        if let Some(source) = source.as_ref() {
+            let (bytes, missing) = source.sizes(&expired)?;
+            summary.push_str(&format!(", {}", format_bytes(bytes)));
+            if missing > 0 {
+                summary.push_str(&format!(" ({missing} chunks not in the store)"));
+            }
+        }
+        println!("{}", yellow(&summary));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_collisions() {
+        assert_eq!(parse_label_hash("03:fake:d19000:logs:app"), Some(("fake", "app")));
+        assert_eq!(parse_label_hash("fake:d19000:abc"), None);
+
+        let labels = |v: &str| BTreeMap::from([("app".to_string(), v.to_string())]);
+        let mut series = BTreeMap::new();
+        series.insert("a".to_string(), SeriesInfo { labels: labels("x"), fingerprints: HashSet::from([1]), chunks: 1, ..Default::default() });
+        series.insert("b".to_string(), SeriesInfo { labels: labels("y"), fingerprints: HashSet::from([1]), chunks: 1, ..Default::default() });
+        series.insert("c".to_string(), SeriesInfo { labels: labels("z"), fingerprints: HashSet::from([2]), chunks: 1, ..Default::default() });
+        let found = find_collisions(&series);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[&1], ["a", "b"]);
+    }
+
+    #[test]
+    fn test_regex_matcher() {
+        let m: LabelMatcher = "app=~lf|loki.*".parse().unwrap();
+        assert_eq!(m.to_string(), r#"app=~"lf|loki.*""#);
+        let buckets = make_buckets("fake", DAY, NaiveDateTime::from_timestamp_opt(0, 0).unwrap(), NaiveDateTime::from_timestamp_opt(60, 0).unwrap()).unwrap();
+        let queries = calc_queries(Schema::V11, 1, &buckets, &m);
+        assert_eq!(queries[0].hash_value, "00:fake:d0:logs:app");
+        assert!(queries[0].range_value_prefix.is_empty());
+
+        let entry = |value: &str| Entry {
+            table_name: "index_0".to_string(),
+            hash_value: queries[0].hash_value.clone(),
+            range_value: String::new(),
+            value: value.to_string(),
+        };
+        let entries = [entry("lf"), entry("loki-read"), entry("xlf"), entry("lfx")];
+        let kept: Vec<_> = filter_entries(&entries, &queries[0]).into_iter().map(|e| e.value).collect();
+        assert_eq!(kept, ["lf", "loki-read"]);
+        assert!("app=~(".parse::<LabelMatcher>().is_err());
+    }
+
+    #[test]
+    fn test_table_of() {
+        assert_eq!(table_of(Path::new("cache/index_19500/fake/compactor-1.gz")).as_deref(), Some("index_19500"));
+        assert_eq!(table_of(Path::new("active/index_19501")).as_deref(), Some("index_19501"));
+        assert_eq!(table_of(Path::new("index_x/db")), None);
+    }
+
+    #[test]
+    fn test_schema_chunk_keys() {
+        let r = ChunkRef { user_id: "fake".to_string(), fingerprint: 0xaa, from: 1, to: 2, checksum: 3 };
+        assert_eq!(Schema::V11.chunk_key(&r), "fake/aa:1:2:3");
+        assert_eq!(Schema::V13.chunk_key(&r), "fake/aa/1:2:3");
+
+        let v12 = "00000002\x00\x00fake/aa/1:2:3\x003\x00";
+        assert_eq!(Schema::V12.parse_range_value(v12).unwrap(), "fake/aa/1:2:3");
+        assert!(Schema::V11.parse_range_value(v12).is_err());
+        // series ids are the same in every schema
+        let series = "hash\x00series\x00\x008\x00";
+        assert_eq!(Schema::V11.parse_range_value(series).unwrap(), "series");
+        assert_eq!(Schema::V12.parse_range_value(series).unwrap(), "series");
+    }
+
+    #[test]
+    fn test_weekly_tables() {
+        let day = |d: i64| NaiveDateTime::from_timestamp_opt(d * 86400 + 3600, 0).unwrap();
+        let buckets = make_buckets("fake", DAY * 7, day(19000), day(19002)).unwrap();
+        let tables: Vec<_> = buckets.iter().map(|b| (b.table_name.as_str(), b.hash_key.as_str())).collect();
+        assert_eq!(tables, [("index_2714", "fake:d19000"), ("index_2714", "fake:d19001"), ("index_2714", "fake:d19002")]);
+        assert_eq!(make_buckets("fake", DAY, day(19000), day(19000)).unwrap()[0].table_name, "index_19000");
+        assert!(make_buckets("fake", Duration::ZERO, day(0), day(0)).is_err());
+    }
+
+    #[test]
+    fn test_negative_matchers() {
+        let m: LabelMatcher = "app!=lf".parse().unwrap();
+        assert!(matches!(m.op, MatchType::NotEqual));
+        assert_eq!((m.key.as_str(), m.value.as_str()), ("app", "lf"));
+        assert!(m.excludes("lf") && !m.excludes("loki"));
+
+        let m: LabelMatcher = "app!~lo.*".parse().unwrap();
+        assert_eq!(m.to_string(), r#"app!~"lo.*""#);
+        assert!(m.excludes("loki") && !m.excludes("lf"));
+        // only the first operator counts
+        let m: LabelMatcher = "a=b!=c".parse().unwrap();
+        assert!(matches!(m.op, MatchType::Equal));
+        assert_eq!(m.value, "b!=c");
+        assert!(!m.excludes("b!=c"));
+    }
+
+    #[test]
+    fn test_select_series() {
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<HashSet<_>>();
+        let (app, env, not_empty): (LabelMatcher, LabelMatcher, LabelMatcher) =
+            ("app=~a.*".parse().unwrap(), "env!=dev".parse().unwrap(), "env!=".parse().unwrap());
+        // s3 has no env label, it matches env!=dev
+        let found = || {
+            vec![
+                (&app, ids(&["s1", "s2", "s3"]), ids(&[])),
+                (&env, ids(&["s1", "s2", "s4"]), ids(&["s2"])),
+            ]
+        };
+        assert_eq!(select_series(found()), ids(&["s1", "s3"]));
+        let mut with_env = found();
+        with_env.push((&not_empty, ids(&["s1", "s2", "s4"]), ids(&[])));
+        assert_eq!(select_series(with_env), ids(&["s1"]));
+    }
+
+    #[test]
+    fn test_table_stats() {
+        let mut t = TableStats::default();
+        t.add(b"03:fake:d19000:logs:app\x00aGFzaA\x00s1\x00\x008\x00");
+        t.add(b"fake:d19000:s1\x0000000010\x00\x00fake/aa/10:20:3\x003\x00");
+        t.add(b"fake:d19000:s1\x0000000010\x00\x00fake/aa/5:18:4\x003\x00");
+        t.add(b"s1\x00\x00\x00\x009\x00");
+        assert_eq!((t.keys, t.series.len(), t.labels.len(), t.chunks), (4, 1, 1, 2));
+        assert_eq!((t.mint, t.maxt), (Some(5), Some(0x20)));
+    }
+
+    #[test]
+    fn test_retention_rules() -> Result<()> {
+        let rules: Vec<StreamRetention> = vec!["app=b:7d".parse()?, "app=~b|c,env!=prod:14d".parse()?];
+        let labels = |app: &str, env: &str| BTreeMap::from([("app".to_string(), app.to_string()), ("env".to_string(), env.to_string())]);
+        let period = |l| retention_rule(&l, &rules).map(|r| r.period.as_secs() / 86400);
+        assert_eq!(period(labels("b", "dev")), Some(7));
+        assert_eq!(period(labels("c", "dev")), Some(14));
+        assert_eq!(period(labels("c", "prod")), None);
+        assert!("app=b".parse::<StreamRetention>().is_err());
+        // no env label at all
+        let no_env = BTreeMap::from([("app".to_string(), "c".to_string())]);
+        assert_eq!(retention_rule(&no_env, &rules).map(|r| r.period.as_secs() / 86400), Some(14));
+        // a comma of a regex
+        let rule: StreamRetention = "app=~a{1,2},env=dev:3d".parse()?;
+        assert_eq!(rule.matchers.len(), 2);
+        assert!(rule.matchers[0].matches("aa"));
+
+        let refs = ["fake/1:10:20:1", "fake/1:10:20:1", "fake/1:30:40:2"].map(ChunkRef::parse_external_key);
+        let refs: Vec<_> = refs.into_iter().collect::<Result<_, _>>()?;
+        let (expired, total) = expired_chunks(&refs, 0x30);
+        assert_eq!((expired.len(), total), (1, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_refs() -> Result<()> {
+        let r = ChunkRef::parse_external_key("fake/b15f21094593a8c1/1a:2b:3")?;
+        let path = std::env::temp_dir().join(format!("lf-test-refs-{}.csv", std::process::id()));
+        write_refs(&path, Schema::V12, [&r].into_iter())?;
+        let csv = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(csv.lines().nth(1), Some("fake,b15f21094593a8c1,26,43,3,fake/b15f21094593a8c1/1a:2b:3"));
+        let json = serde_json::to_value([RefRecord::new(Schema::V11, &r)])?;
+        assert_eq!(json[0]["key"], "fake/b15f21094593a8c1:1a:2b:3");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup_refs() -> Result<()> {
+        let (a, b) = (PathBuf::from("index_19000/ingester-0"), PathBuf::from("index_19000/ingester-1"));
+        let key = |k: &str| ChunkRef::parse_external_key(k);
+        let found = vec![
+            (key("fake/b15f21094593a8c1/1a:2b:3")?, &a),
+            (key("fake/b15f21094593a8c1/1a:2b:4")?, &a),
+            (key("fake/b15f21094593a8c1/1a:2b:3")?, &b),
+            (key("fake/b15f21094593a8c1/1a:2b:3")?, &b),
+            (key("other/b15f21094593a8c1/1a:2b:3")?, &b),
+        ];
+        let refs = dedup_refs(found);
+        let refs: Vec<_> = refs.iter().map(|(r, files)| (Schema::V12.chunk_key(r), files.clone())).collect();
+        assert_eq!(
+            refs,
+            [
+                ("fake/b15f21094593a8c1/1a:2b:3".to_string(), vec![&a, &b]),
+                ("fake/b15f21094593a8c1/1a:2b:4".to_string(), vec![&a]),
+                ("other/b15f21094593a8c1/1a:2b:3".to_string(), vec![&b]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_cardinality() {
+        let info = |pairs: &[(&str, &str)]| SeriesInfo {
+            labels: pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Default::default()
+        };
+        let series = BTreeMap::from([
+            ("a".to_string(), info(&[("app", "x"), ("pod", "p1")])),
+            ("b".to_string(), info(&[("app", "x"), ("pod", "p2")])),
+            ("c".to_string(), info(&[("app", "y"), ("env", "dev")])),
+        ]);
+        assert_eq!(label_cardinality(&series), vec![("app", 2, 3), ("pod", 2, 2), ("env", 1, 1)]);
+    }
+
+    #[test]
+    fn test_open_gzipped_index() -> Result<()> {
+        use std::io::Write;
+
+        let dir = std::env::temp_dir().join(format!("lf-test-gz-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (plain, gzipped) = (dir.join("index_19000"), dir.join("index_19000.gz"));
+        {
+            let mut db = DBBuilder::new(&plain).build()?;
+            let mut tx = db.begin_rw_tx()?;
+            tx.create_bucket(b"index")?.put(b"fake:d19000:s1\x00", vec![])?;
+            tx.commit()?;
+        }
+        let mut gz = flate2::write::GzEncoder::new(std::fs::File::create(&gzipped)?, flate2::Compression::default());
+        gz.write_all(&std::fs::read(&plain)?)?;
+        gz.finish()?;
+
+        let db = open_index(&gzipped)?;
+        let temp = db._temp.as_ref().map(|t| t.0.clone()).unwrap();
+        let mut t = TableStats::default();
+        {
+            let tx = db.begin_tx()?;
+            let bucket = tx.bucket(b"index")?;
+            scan_prefix(&bucket, b"", |k, _| {
+                t.add(k);
+                true
+            })?;
+        }
+        assert_eq!(t.series.len(), 1);
+        drop(db);
+        assert!(!temp.exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_delete_entry() {
+        let mut requests = BTreeMap::new();
+        add_delete_entry(&mut requests, b"1\x00fake:abcd", b"received");
+        add_delete_entry(&mut requests, b"2:fake:abcd\x0018a:3e8:7d0", br#"{app="x"}"#);
+        add_delete_entry(&mut requests, b"3:fake\x00", b"1697");
+        assert_eq!(requests.len(), 1);
+        let r = &requests["fake:abcd"];
+        assert_eq!((r.created_at, r.start, r.end), (0x18a, 1000, 2000));
+        assert_eq!((r.query.as_str(), r.status.as_str()), (r#"{app="x"}"#, "received"));
+    }
+
+    #[test]
+    fn test_dump_record() {
+        let r = dump_record(b"00:fake:d19000:logs:app\x00aGFzaA\x00s1\x00\x008\x00", b"x");
+        assert_eq!(r.hash_value, Part::Text("00:fake:d19000:logs:app".to_string()));
+        assert_eq!(r.range_value.len(), 5);
+        assert_eq!(r.range_value[1], Part::Text("s1".to_string()));
+        assert_eq!(r.range_value[4], Part::Text(String::new()));
+        assert_eq!(serde_json::to_string(&Part::new(b"\xff\x01")).unwrap(), r#"{"base64":"/wE="}"#);
+        assert_eq!(Part::new(b"a\tb").to_string(), "base64:YQli");
+        assert!(dump_record(b"meta", b"").range_value.is_empty());
+    }
+
+    #[test]
+    fn test_series_matches() {
+        let labels = BTreeMap::from([("app".to_string(), "lf".to_string()), ("env".to_string(), "dev".to_string())]);
+        let query = |q: &[&str]| q.iter().map(|m| m.parse().unwrap()).collect::<Vec<LabelMatcher>>();
+        assert!(series_matches(&labels, &[]));
+        assert!(series_matches(&labels, &query(&["app=lf", "env!~prod.*"])));
+        assert!(!series_matches(&labels, &query(&["app=lf", "env!=dev"])));
+        // no region label at all, its value is ""
+        assert!(series_matches(&labels, &query(&["region!=eu"])));
+        assert!(!series_matches(&labels, &query(&["region=~.+"])));
+    }
+}
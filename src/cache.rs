@@ -0,0 +1,237 @@
+// Pruning of local directories of downloaded chunks and index files. Files
+// are ranked by their last access (atime, or mtime when later, since
+// relatime and noatime mounts keep atime behind), the ones older than
+// --max-age go first, then the least recently used until the directory
+// fits in --max-size. Every removed file is appended to an index file at
+// the top of the directory, which is never pruned itself.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+
+use crate::{
+    common::{format_bytes, gray, green, parse_bytes, parse_duration, yellow},
+    confirm::confirm,
+};
+
+const INDEX_FILE: &str = ".lf-pruned.jsonl";
+
+/// local chunk and index cache maintenance
+#[derive(Parser, Debug)]
+pub struct Cache {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// remove the least recently used files of a directory by age and size,
+    /// recording them in .lf-pruned.jsonl
+    Prune(Prune),
+}
+
+#[derive(Parser, Debug)]
+struct Prune {
+    /// cache directory
+    dir: PathBuf,
+
+    /// keep at most this much, like 50G
+    #[arg(long, value_parser = parse_bytes)]
+    max_size: Option<u64>,
+
+    /// remove files not accessed for this long, like 30d
+    #[arg(long, value_parser = parse_duration)]
+    max_age: Option<Duration>,
+
+    /// only print what would be removed
+    #[arg(long)]
+    dry_run: bool,
+
+    /// remove without asking
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    path: PathBuf,
+    bytes: u64,
+    accessed: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Reason {
+    Age,
+    Size,
+}
+
+#[derive(Debug, Serialize)]
+struct Removed<'a> {
+    path: &'a Path,
+    bytes: u64,
+    accessed: DateTime<Utc>,
+    removed: DateTime<Utc>,
+    reason: Reason,
+}
+
+// regular files under `dir`, symlinks are neither followed nor removed
+fn walk(dir: &Path, index: &Path, out: &mut Vec<CachedFile>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let meta = std::fs::symlink_metadata(&path)?;
+        if meta.is_dir() {
+            walk(&path, index, out)?;
+        } else if meta.is_file() && path != index {
+            let modified = meta.modified()?;
+            let accessed = meta.accessed().map(|a| a.max(modified)).unwrap_or(modified);
+            out.push(CachedFile { path, bytes: meta.len(), accessed });
+        }
+    }
+    Ok(())
+}
+
+/// Files to remove, as indexes into `files` (sorted by last access,
+/// oldest first) with the reason.
+fn plan(files: &[CachedFile], now: SystemTime, max_size: Option<u64>, max_age: Option<Duration>) -> Vec<(usize, Reason)> {
+    let mut removed = vec![];
+    let mut kept: u64 = files.iter().map(|f| f.bytes).sum();
+    for (i, f) in files.iter().enumerate() {
+        let age = now.duration_since(f.accessed).unwrap_or_default();
+        let reason = if max_age.map(|m| age > m).unwrap_or(false) {
+            Reason::Age
+        } else if max_size.map(|m| kept > m).unwrap_or(false) {
+            Reason::Size
+        } else {
+            break;
+        };
+        kept -= f.bytes;
+        removed.push((i, reason));
+    }
+    removed
+}
+
+// directories left empty under `dir`, deepest first
+fn remove_empty_dirs(dir: &Path) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if std::fs::symlink_metadata(&path)?.is_dir() {
+            remove_empty_dirs(&path)?;
+            if std::fs::read_dir(&path)?.next().is_none() {
+                std::fs::remove_dir(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn prune(p: Prune) -> anyhow::Result<()> {
+    if p.max_size.is_none() && p.max_age.is_none() {
+        return Err(anyhow::format_err!("nothing to do, give --max-size or --max-age"));
+    }
+    if !p.dir.is_dir() {
+        return Err(anyhow::format_err!("{} is not a directory", p.dir.display()));
+    }
+    if p.dir.canonicalize()?.parent().is_none() {
+        return Err(anyhow::format_err!("refuse to prune the root directory"));
+    }
+    let index = p.dir.join(INDEX_FILE);
+    let mut files = vec![];
+    walk(&p.dir, &index, &mut files)?;
+    files.sort_by_key(|f| f.accessed);
+    let total: u64 = files.iter().map(|f| f.bytes).sum();
+    let now = SystemTime::now();
+    let removals = plan(&files, now, p.max_size, p.max_age);
+    if !p.dry_run && !removals.is_empty() {
+        let bytes: u64 = removals.iter().map(|(i, _)| files[*i].bytes).sum();
+        let mut impact = vec![format!(
+            "{} of {} files ({} of {}) under {}, the least recently used first:",
+            removals.len(),
+            files.len(),
+            format_bytes(bytes),
+            format_bytes(total),
+            p.dir.display()
+        )];
+        impact.extend(removals.iter().take(5).map(|(i, _)| format!("  {}", files[*i].path.display())));
+        if removals.len() > 5 {
+            impact.push(format!("  ... and {} more", removals.len() - 5));
+        }
+        confirm("remove cached files", &impact, p.yes)?;
+    }
+
+    let mut log = match p.dry_run {
+        true => None,
+        false => Some(OpenOptions::new().create(true).append(true).open(&index)?),
+    };
+    let (mut count, mut bytes) = (0, 0);
+    for (i, reason) in removals {
+        let f = &files[i];
+        let accessed = DateTime::<Utc>::from(f.accessed);
+        let desc = format!("{} {} {}", f.path.display(), format_bytes(f.bytes), gray(&accessed.format("%Y-%m-%d %H:%M").to_string()));
+        let Some(log) = log.as_mut() else {
+            println!("would remove {desc}");
+            count += 1;
+            bytes += f.bytes;
+            continue;
+        };
+        if let Err(err) = std::fs::remove_file(&f.path) {
+            println!("{} {desc}: {err}", yellow("not removed"));
+            continue;
+        }
+        let record = Removed { path: &f.path, bytes: f.bytes, accessed, removed: now.into(), reason };
+        writeln!(log, "{}", serde_json::to_string(&record)?)?;
+        count += 1;
+        bytes += f.bytes;
+    }
+    if !p.dry_run {
+        remove_empty_dirs(&p.dir)?;
+    }
+    println!(
+        "{}",
+        green(&format!(
+            "{} {count} of {} files ({} of {}), {} kept",
+            if p.dry_run { "would remove" } else { "removed" },
+            files.len(),
+            format_bytes(bytes),
+            format_bytes(total),
+            format_bytes(total - bytes)
+        ))
+    );
+    if !p.dry_run && count > 0 {
+        println!("{}", gray(&format!("removed files are listed in {}", index.display())));
+    }
+    Ok(())
+}
+
+pub fn cache(c: Cache) -> anyhow::Result<()> {
+    match c.cmd {
+        SubCommand::Prune(p) => prune(p),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100 * 86400);
+        let day = |d: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(d * 86400);
+        let files: Vec<_> = [(10, 1), (50, 2), (90, 3), (99, 4)]
+            .iter()
+            .map(|(d, n)| CachedFile { path: PathBuf::from(format!("{n}")), bytes: 100, accessed: day(*d) })
+            .collect();
+        let age = Some(Duration::from_secs(30 * 86400));
+        assert_eq!(plan(&files, now, None, age), [(0, Reason::Age), (1, Reason::Age)]);
+        assert_eq!(plan(&files, now, Some(150), age), [(0, Reason::Age), (1, Reason::Age), (2, Reason::Size)]);
+        assert_eq!(plan(&files, now, Some(400), None), []);
+        assert_eq!(plan(&files, now, Some(0), None).len(), 4);
+    }
+}
@@ -0,0 +1,297 @@
+// loki-canary in a box: push one tagged entry per interval and poll them
+// back with range queries. An entry is received when a query first returns
+// it, its latency is the time from the push to that query, so it is only
+// as precise as --query-interval. Entries not seen within --timeout count
+// as missing.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use clap::Parser;
+use tiny_http::{Header, Response, Server};
+use tracing::debug;
+
+use crate::{
+    common::{gray, green, parse_duration, red, yellow, HttpOpts, KeyValue},
+    push::{send_streams, Stream},
+    tail::poll,
+};
+
+// seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// continuously push entries and query them back, reporting ingest to
+/// queryable latency and missing entries
+#[derive(Parser, Debug)]
+pub struct Canary {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    /// Labels of the canary stream
+    #[clap(short, long, num_args = 0.., default_value = "service_name=lf-canary")]
+    labels: Vec<KeyValue>,
+
+    /// Time between two pushed entries
+    #[clap(long, default_value = "10s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// Time between two queries looking for the pending entries
+    #[clap(long, default_value = "1s", value_parser = parse_duration)]
+    query_interval: Duration,
+
+    /// Entries not queryable after this long are counted as missing
+    #[clap(long, default_value = "1m", value_parser = parse_duration)]
+    timeout: Duration,
+
+    /// Time between two report lines
+    #[clap(long, default_value = "1m", value_parser = parse_duration)]
+    report_interval: Duration,
+
+    /// Stop after pushing this many entries and waiting for them, 0 runs
+    /// forever. Missing entries make the command fail.
+    #[clap(long, default_value = "0")]
+    pushes: u64,
+
+    /// Serve prometheus metrics on this address, like :9101
+    #[clap(long)]
+    metrics_listen: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    pushed: u64,
+    push_errors: u64,
+    received: u64,
+    missing: u64,
+    query_errors: u64,
+    // cumulative, one per LATENCY_BUCKETS
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+impl Metrics {
+    fn observe(&mut self, secs: f64) {
+        self.received += 1;
+        self.latency_sum += secs;
+        for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+            if secs <= *le {
+                self.latency_buckets[i] += 1;
+            }
+        }
+    }
+
+    // prometheus text exposition format
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("pushed", "entries pushed", self.pushed),
+            ("push_errors", "failed pushes", self.push_errors),
+            ("received", "entries queried back", self.received),
+            ("missing", "entries not queryable within the timeout", self.missing),
+            ("query_errors", "failed queries", self.query_errors),
+        ];
+        for (name, help, v) in counters {
+            out.push_str(&format!(
+                "# HELP lf_canary_{name}_total Number of {help}.\n# TYPE lf_canary_{name}_total counter\nlf_canary_{name}_total {v}\n"
+            ));
+        }
+        out.push_str("# HELP lf_canary_latency_seconds Time from push to the first query returning the entry.\n");
+        out.push_str("# TYPE lf_canary_latency_seconds histogram\n");
+        for (le, n) in LATENCY_BUCKETS.iter().zip(self.latency_buckets.iter()) {
+            out.push_str(&format!("lf_canary_latency_seconds_bucket{{le=\"{le}\"}} {n}\n"));
+        }
+        out.push_str(&format!("lf_canary_latency_seconds_bucket{{le=\"+Inf\"}} {}\n", self.received));
+        out.push_str(&format!("lf_canary_latency_seconds_sum {}\n", self.latency_sum));
+        out.push_str(&format!("lf_canary_latency_seconds_count {}\n", self.received));
+        out
+    }
+}
+
+// what happened since the last report line
+#[derive(Debug, Default)]
+struct Window {
+    pushed: u64,
+    missing: u64,
+    latencies: Vec<f64>,
+}
+
+impl Window {
+    fn report(&mut self, total: &Metrics, pending: usize) {
+        self.latencies.sort_by(|a, b| a.total_cmp(b));
+        let pct = |p: f64| {
+            let l = &self.latencies;
+            if l.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{:.2}s", l[((l.len() - 1) as f64 * p).round() as usize])
+            }
+        };
+        let missing = if self.missing > 0 {
+            red(&format!("missing {}", self.missing))
+        } else {
+            green("missing 0")
+        };
+        let rate = if total.pushed > 0 {
+            total.missing as f64 * 100.0 / total.pushed as f64
+        } else {
+            0.0
+        };
+        println!(
+            "{} pushed {} received {} {missing} pending {pending} | latency p50 {} p90 {} p99 {} max {} | {}",
+            gray(&Local::now().format("%H:%M:%S").to_string()),
+            self.pushed,
+            self.latencies.len(),
+            pct(0.5),
+            pct(0.9),
+            pct(0.99),
+            pct(1.0),
+            gray(&format!(
+                "total {} pushed, {} missing ({rate:.2}%), {} push errors, {} query errors",
+                total.pushed, total.missing, total.push_errors, total.query_errors
+            )),
+        );
+        *self = Window::default();
+    }
+}
+
+fn serve_metrics(listen: &str, metrics: Arc<Mutex<Metrics>>) -> anyhow::Result<()> {
+    let addr = if listen.starts_with(':') {
+        format!("0.0.0.0{listen}")
+    } else {
+        listen.to_string()
+    };
+    let server = Server::http(&addr).map_err(|e| anyhow::format_err!("listen {addr}: {e}"))?;
+    println!("{}", gray(&format!("metrics on http://{addr}/metrics")));
+    thread::spawn(move || {
+        for req in server.incoming_requests() {
+            let body = metrics.lock().unwrap().render();
+            let resp = Response::from_string(body).with_header(
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap(),
+            );
+            if let Err(err) = req.respond(resp) {
+                debug!("metrics: {err}");
+            }
+        }
+    });
+    Ok(())
+}
+
+// `seq` of a line pushed by this run
+fn parse_seq(line: &str, run: &str) -> Option<u64> {
+    line.strip_prefix(&format!("lf-canary run={run} seq="))?.parse().ok()
+}
+
+pub fn canary(c: Canary) -> anyhow::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let stream: std::collections::HashMap<String, String> = c.labels.iter().map(|x| x.into()).collect();
+    let run = format!("{:x}", Local::now().timestamp_nanos() as u64 ^ std::process::id() as u64);
+    let selector = c
+        .labels
+        .iter()
+        .map(|kv| format!("{}={:?}", kv.key, kv.value))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let query = format!("{{{selector}}} |= \"lf-canary run={run} \"");
+    if c.http.print_curl {
+        let values = vec![(Local::now().timestamp_nanos().to_string(), format!("lf-canary run={run} seq=0"))];
+        return send_streams(&client, &c.http, None, vec![Stream { stream, values }]);
+    }
+
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    if let Some(listen) = c.metrics_listen.as_ref() {
+        serve_metrics(listen, metrics.clone())?;
+    }
+    println!("{}", gray(&format!("canary run {run}, querying {query}")));
+
+    // seq -> (entry timestamp, pushed at)
+    let mut pending: BTreeMap<u64, (i64, Instant)> = BTreeMap::new();
+    let mut window = Window::default();
+    let mut seq = 0;
+    let mut next_push = Instant::now();
+    let mut next_report = Instant::now() + c.report_interval;
+    loop {
+        let done_pushing = c.pushes > 0 && seq >= c.pushes;
+        if !done_pushing && Instant::now() >= next_push {
+            let ts = Local::now().timestamp_nanos();
+            let values = vec![(ts.to_string(), format!("lf-canary run={run} seq={seq}"))];
+            let pushed_at = Instant::now();
+            match send_streams(&client, &c.http, None, vec![Stream { stream: stream.clone(), values }]) {
+                Ok(()) => {
+                    pending.insert(seq, (ts, pushed_at));
+                    window.pushed += 1;
+                    metrics.lock().unwrap().pushed += 1;
+                }
+                Err(err) => {
+                    eprintln!("{} {err}", red("push:"));
+                    metrics.lock().unwrap().push_errors += 1;
+                }
+            }
+            seq += 1;
+            next_push += c.interval;
+        }
+
+        if let Some((oldest, _)) = pending.values().next().copied() {
+            let now = Local::now().timestamp_nanos();
+            match poll(&c.http, &query, oldest, now + 1, pending.len().max(100) as u32) {
+                Ok(lines) => {
+                    let mut m = metrics.lock().unwrap();
+                    for (_, line) in lines {
+                        let Some((_, pushed_at)) = parse_seq(&line, &run).and_then(|s| pending.remove(&s)) else {
+                            continue;
+                        };
+                        let secs = pushed_at.elapsed().as_secs_f64();
+                        m.observe(secs);
+                        window.latencies.push(secs);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {err}", red("query:"));
+                    metrics.lock().unwrap().query_errors += 1;
+                }
+            }
+        }
+
+        let expired: Vec<u64> = pending
+            .iter()
+            .filter(|(_, (_, pushed_at))| pushed_at.elapsed() > c.timeout)
+            .map(|(s, _)| *s)
+            .collect();
+        for s in expired {
+            pending.remove(&s);
+            println!("{}", yellow(&format!("entry seq={s} not queryable after {}s", c.timeout.as_secs())));
+            window.missing += 1;
+            metrics.lock().unwrap().missing += 1;
+        }
+
+        let finished = done_pushing && pending.is_empty();
+        if finished || Instant::now() >= next_report {
+            window.report(&metrics.lock().unwrap(), pending.len());
+            next_report = Instant::now() + c.report_interval;
+        }
+        if finished {
+            break;
+        }
+        let wake = if done_pushing {
+            c.query_interval
+        } else {
+            c.query_interval.min(next_push.saturating_duration_since(Instant::now()))
+        };
+        thread::sleep(wake);
+    }
+
+    let m = metrics.lock().unwrap();
+    if m.missing > 0 || m.push_errors > 0 {
+        return Err(anyhow::format_err!(
+            "{} of {} entries missing, {} failed pushes",
+            m.missing,
+            m.pushed,
+            m.push_errors
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+// Loki versions serve different apis (volume came with 2.9, patterns and
+// detected fields with 3.0, ...). Rather than keeping a table of versions,
+// the version is read from buildinfo and every api is probed once: a 404
+// means the endpoint does not serve it. Results are cached per endpoint
+// for a day in $XDG_CACHE_HOME/lf/capabilities.json.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use chrono::Utc;
+use reqwest::{blocking::Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    common::{gray, green, red, refine_loki_request, yellow, HttpOpts},
+    error::ApiError,
+    timing,
+};
+
+const TTL_SECS: i64 = 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    Volume,
+    Patterns,
+    DetectedFields,
+    Otlp,
+    TenantLimits,
+}
+
+impl Api {
+    pub const ALL: [Api; 5] = [Api::Volume, Api::Patterns, Api::DetectedFields, Api::Otlp, Api::TenantLimits];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Api::Volume => "volume",
+            Api::Patterns => "patterns",
+            Api::DetectedFields => "detected_fields",
+            Api::Otlp => "otlp",
+            Api::TenantLimits => "tenant_limits",
+        }
+    }
+
+    pub fn path(self) -> &'static str {
+        match self {
+            Api::Volume => "/loki/api/v1/index/volume",
+            Api::Patterns => "/loki/api/v1/patterns",
+            Api::DetectedFields => "/loki/api/v1/detected_fields",
+            Api::Otlp => "/otlp/v1/logs",
+            Api::TenantLimits => "/config/tenant/v1/limits",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Capabilities {
+    version: Option<String>,
+    // unix seconds
+    probed_at: i64,
+    // api name -> served, apis that could not be told (401/403) are absent
+    apis: BTreeMap<String, bool>,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(dir.join("lf").join("capabilities.json"))
+}
+
+fn load() -> BTreeMap<String, Capabilities> {
+    cache_path()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+// the cache is only an optimisation, failing to write it is not an error
+fn save(cache: &BTreeMap<String, Capabilities>) {
+    let Some(path) = cache_path() else {
+        return;
+    };
+    let write = || -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_vec_pretty(cache)?)?;
+        Ok(())
+    };
+    if let Err(err) = write() {
+        debug!("write {}: {err}", path.display());
+    }
+}
+
+fn get(http: &HttpOpts, client: &Client, path: &str) -> anyhow::Result<reqwest::blocking::Response> {
+    let req = client.get(format!("{}{}", http.endpoint, path));
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), http.tenant.clone());
+    timing::send(http.sign(req)?)
+}
+
+fn probe_version(http: &HttpOpts, client: &Client) -> anyhow::Result<Option<String>> {
+    let resp = get(http, client, "/loki/api/v1/status/buildinfo")?;
+    if !resp.status().is_success() {
+        debug!("buildinfo: {}", resp.status());
+        return Ok(None);
+    }
+    let obj: serde_json::Value = serde_json::from_str(&timing::text(resp)?).unwrap_or_default();
+    Ok(obj["version"].as_str().map(|v| v.to_string()))
+}
+
+// requests lack required parameters on purpose, anything else than 404
+// (mostly 400 or 405) shows the route exists
+fn probe_api(http: &HttpOpts, client: &Client, api: Api) -> anyhow::Result<Option<bool>> {
+    let status = get(http, client, api.path())?.status();
+    debug!("probe {}: {status}", api.name());
+    Ok(match status {
+        StatusCode::NOT_FOUND => Some(false),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => None,
+        _ => Some(true),
+    })
+}
+
+// capabilities of the endpoint with at least `apis` probed
+fn lookup(http: &HttpOpts, apis: &[Api], refresh: bool) -> anyhow::Result<Capabilities> {
+    let mut cache = load();
+    let now = Utc::now().timestamp();
+    let client = Client::new();
+    let mut dirty = false;
+    let mut caps = match cache.get(http.endpoint.primary()) {
+        Some(c) if !refresh && now - c.probed_at < TTL_SECS => c.clone(),
+        _ => {
+            dirty = true;
+            Capabilities {
+                version: probe_version(http, &client)?,
+                probed_at: now,
+                apis: BTreeMap::new(),
+            }
+        }
+    };
+    for api in apis {
+        if caps.apis.contains_key(api.name()) {
+            continue;
+        }
+        if let Some(served) = probe_api(http, &client, *api)? {
+            caps.apis.insert(api.name().to_string(), served);
+            dirty = true;
+        }
+    }
+    if dirty {
+        cache.insert(http.endpoint.to_string(), caps.clone());
+        save(&cache);
+    }
+    Ok(caps)
+}
+
+/// Fail with `ApiError::Unsupported` when the endpoint is known not to
+/// serve `api`. Nothing is probed with --print-curl.
+pub(crate) fn require(http: &HttpOpts, api: Api) -> anyhow::Result<()> {
+    if http.print_curl {
+        return Ok(());
+    }
+    let caps = lookup(http, &[api], false)?;
+    if caps.apis.get(api.name()) == Some(&false) {
+        return Err(ApiError::Unsupported {
+            api: api.name(),
+            version: caps.version,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Print the version and the apis served by the endpoint.
+pub(crate) fn show(http: &HttpOpts, refresh: bool) -> anyhow::Result<()> {
+    let caps = lookup(http, &Api::ALL, refresh)?;
+    println!(
+        "{} {}",
+        gray(&format!("{} loki", http.endpoint)),
+        caps.version.as_deref().unwrap_or("(unknown version)")
+    );
+    for api in Api::ALL {
+        let state = match caps.apis.get(api.name()) {
+            Some(true) => green(&format!("{:<11}", "supported")),
+            Some(false) => red(&format!("{:<11}", "unsupported")),
+            None => yellow(&format!("{:<11}", "unknown")),
+        };
+        println!("{:>16}  {}  {}", api.name(), state, gray(api.path()));
+    }
+    Ok(())
+}
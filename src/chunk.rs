@@ -0,0 +1,206 @@
+// `lf chunk`: tools working on whole chunk files rather than their output,
+// like pushing the entries of chunks to another loki again, or scrubbing
+// their lines before they are shared (`lf anonymize`).
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Cursor},
+    path::Path,
+    time::Duration,
+};
+
+use clap::Parser;
+
+use crate::{
+    anonymize::{anonymize, Anonymize},
+    common::{gray, green, parse_duration, yellow, HttpOpts},
+    decode::{fetch_input, input_paths, open_chunk, read_stdin},
+    error::DecodeError,
+    platform::{display_path, native_path},
+    push::{now_nanos, send_streams, LabelRewriteOpts, Stream},
+    s3::S3Opts,
+    ty::ChunkHead,
+};
+
+/// chunk file tools
+#[derive(Parser, Debug)]
+pub struct ChunkCmd {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// push the entries of chunks to a loki, with the labels and tenant of
+    /// their head
+    Replay(Box<ReplayCommand>),
+
+    /// hash or redact the lines of a chunk file so it can be shared, see
+    /// lf anonymize
+    Scrub(Anonymize),
+}
+
+#[derive(Parser, Debug)]
+struct ReplayCommand {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    /// chunk file, "-" for stdin, an s3://bucket/key object or a directory
+    /// of chunks
+    #[clap(short, long)]
+    input: String,
+
+    #[command(flatten)]
+    s3: S3Opts,
+
+    /// Number of entries per push
+    #[clap(long, default_value = "1000")]
+    batch_size: usize,
+
+    /// move every entry this much later
+    #[clap(long, value_parser = parse_duration, conflicts_with = "shift_to_now")]
+    shift: Option<Duration>,
+
+    /// move the entries so that the newest chunk ends now, keeping their
+    /// spacing (loki rejects entries older than its reject_old_samples_max_age)
+    #[clap(long)]
+    shift_to_now: bool,
+
+    #[command(flatten)]
+    rewrite: LabelRewriteOpts,
+}
+
+pub fn chunk(c: ChunkCmd) -> anyhow::Result<()> {
+    match c.cmd {
+        SubCommand::Replay(r) => replay(*r),
+        SubCommand::Scrub(a) => anonymize(a),
+    }
+}
+
+// tenant -> labels -> (ts, line)
+type Pending = BTreeMap<String, BTreeMap<BTreeMap<String, String>, Vec<(String, String)>>>;
+
+fn replay(r: ReplayCommand) -> anyhow::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let paths = input_paths(&r.input)?;
+    let load = |path: &Path| -> anyhow::Result<Vec<u8>> {
+        if r.input == "-" {
+            return read_stdin();
+        }
+        match fetch_input(&r.s3, path)? {
+            Some(bs) => Ok(bs),
+            None => Ok(std::fs::read(native_path(path))?),
+        }
+    };
+
+    // nanoseconds added to every timestamp. --shift-to-now only reads the
+    // heads of the files of a directory, a single chunk (like stdin or an
+    // s3 object) is loaded once and kept for the replay
+    let mut loaded = None;
+    let shift = match (r.shift, r.shift_to_now) {
+        (Some(d), _) => d.as_nanos() as i64,
+        (None, true) => {
+            let mut through = f64::MIN;
+            for path in paths.iter() {
+                let head = match paths.len() {
+                    1 => ChunkHead::read_from(&mut Cursor::new(loaded.insert(load(path)?))),
+                    _ => ChunkHead::read_from(&mut BufReader::new(File::open(native_path(path))?)),
+                };
+                through = through.max(head.map_err(DecodeError::from)?.through);
+            }
+            now_nanos() - (through * 1e9) as i64
+        }
+        (None, false) => 0,
+    };
+
+    let mut pending = Pending::new();
+    let mut pending_count = 0;
+    let (mut pushed, mut failed, mut metadata_dropped, mut relabel_dropped) = (0, 0, 0, 0);
+    let flush = |pending: &mut Pending| -> anyhow::Result<()> {
+        for (tenant, streams) in std::mem::take(pending) {
+            let streams = streams
+                .into_iter()
+                .map(|(labels, values)| Stream {
+                    stream: labels.into_iter().collect(),
+                    values,
+                })
+                .collect();
+            // an explicit --tenant wins over the tenant of the chunk head
+            let tenant = if r.http.tenant.is_some() { None } else { Some(tenant.as_str()) };
+            send_streams(&client, &r.http, tenant, streams)?;
+        }
+        Ok(())
+    };
+
+    for path in paths.iter() {
+        let bs = match loaded.take() {
+            Some(bs) => Ok(bs),
+            None => load(path),
+        };
+        let (header, blocks) = match bs.and_then(|bs| open_chunk(Box::new(Cursor::new(bs)), None, true, false)) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("{} {}", yellow(&display_path(path)), err);
+                failed += 1;
+                continue;
+            }
+        };
+        let entries: usize = blocks.meta.block_metas.iter().map(|m| m.num_entries).sum();
+        let metric = header.metric.into_iter().filter(|(k, _)| k != "__name__").collect();
+        let labels = match r.rewrite.apply(metric)? {
+            Some(l) => l,
+            None => {
+                relabel_dropped += entries;
+                continue;
+            }
+        };
+        // a chunk is pushed whole or not at all
+        let blocks = match blocks.collect::<Result<Vec<_>, _>>() {
+            Ok(blocks) => blocks,
+            Err(err) => {
+                eprintln!("{} {}", yellow(&display_path(path)), DecodeError::from(err));
+                failed += 1;
+                continue;
+            }
+        };
+        println!("{} {entries} entries", gray(&display_path(path)));
+        for block in blocks {
+            for e in block.entries {
+                if !e.structured_metadata.is_empty() {
+                    metadata_dropped += 1;
+                }
+                let ts = e.time.timestamp_nanos() + shift;
+                pending
+                    .entry(header.user_id.clone())
+                    .or_default()
+                    .entry(labels.clone())
+                    .or_default()
+                    .push((ts.to_string(), e.line));
+                pending_count += 1;
+            }
+            if pending_count >= r.batch_size {
+                flush(&mut pending)?;
+                pushed += pending_count;
+                pending_count = 0;
+            }
+        }
+    }
+    flush(&mut pending)?;
+    pushed += pending_count;
+
+    println!("{}", green(&format!("{pushed} entries pushed")));
+    if shift != 0 {
+        println!("{}", gray(&format!("timestamps moved by {}s", shift / 1_000_000_000)));
+    }
+    if relabel_dropped > 0 {
+        println!("{}", gray(&format!("{relabel_dropped} entries dropped by relabel rules")));
+    }
+    if metadata_dropped > 0 {
+        println!("{}", yellow(&format!("{metadata_dropped} entries pushed without their structured metadata")));
+    }
+    if failed > 0 {
+        return Err(anyhow::format_err!("{failed} chunks failed to decode"));
+    }
+    Ok(())
+}
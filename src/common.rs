@@ -1,8 +1,17 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use clap::Args;
-use reqwest::blocking::RequestBuilder;
-use std::{str::FromStr, time::Duration};
-use humantime::parse_duration;
+use reqwest::{
+    blocking::{Request, RequestBuilder, Response},
+    Method,
+};
+use serde::Serialize;
+use std::{fmt, str::FromStr, sync::{mpsc, OnceLock}, thread, time::Duration};
+
+use crate::{
+    platform,
+    sigv4::{self, Credentials},
+    timing,
+};
 
 #[derive(Debug, Clone)]
 pub struct KeyValue {
@@ -27,12 +36,53 @@ impl FromStr for KeyValue {
     }
 }
 
+impl fmt::Display for KeyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
 impl From<&KeyValue> for (String, String) {
     fn from(kv: &KeyValue) -> Self {
         (kv.key.clone(), kv.value.clone())
     }
 }
 
+// loki/pkg/logproto/types.go ChunkRef
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkRef {
+    pub user_id: String,
+    pub fingerprint: u64,
+    pub from: i64,
+    pub to: i64,
+    pub checksum: u32,
+}
+
+impl ChunkRef {
+    /// Parse a chunk external key, either the v11 form
+    /// `tenant/fp:from:through:checksum` or the v12+ form
+    /// `tenant/fp/from:through:checksum`. All numbers are hex.
+    pub fn parse_external_key(key: &str) -> anyhow::Result<ChunkRef> {
+        let invalid = || anyhow::format_err!("invalid chunk key: {key}");
+        let (user_id, rest) = key.split_once('/').ok_or_else(invalid)?;
+        let (fingerprint, rest) = match rest.split_once('/') {
+            Some((fp, rest)) => (fp, rest),
+            None => rest.split_once(':').ok_or_else(invalid)?,
+        };
+        let parts = rest.split(':').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        Ok(ChunkRef {
+            user_id: user_id.to_string(),
+            fingerprint: u64::from_str_radix(fingerprint, 16)?,
+            from: i64::from_str_radix(parts[0], 16)?,
+            to: i64::from_str_radix(parts[1], 16)?,
+            checksum: u32::from_str_radix(parts[2], 16)?,
+        })
+    }
+}
+
 pub(crate) fn refine_loki_request(
     req: RequestBuilder,
     headers: Vec<KeyValue>,
@@ -53,6 +103,120 @@ pub(crate) fn refine_loki_request(
     req
 }
 
+/// Parse a human readable byte size like `512MB`, `1.5GiB` or `100`.
+pub fn parse_bytes(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let idx = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(idx);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| anyhow::format_err!("invalid size: {s}"))?;
+    let mul: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "ki" | "kib" => 1 << 10,
+        "mi" | "mib" => 1 << 20,
+        "gi" | "gib" => 1 << 30,
+        "ti" | "tib" => 1 << 40,
+        other => return Err(anyhow::format_err!("unknown size unit: {other}")),
+    };
+    Ok((num * mul as f64) as u64)
+}
+
+/// Format bytes with a binary unit suffix, e.g. `1.50 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut v = bytes as f64;
+    let mut i = 0;
+    while v >= 1024.0 && i < units.len() - 1 {
+        v /= 1024.0;
+        i += 1;
+    }
+    if i == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", v, units[i])
+    }
+}
+
+/// Format a unix timestamp in nanoseconds as UTC with `digits` (at most 9)
+/// fractional digits, e.g. `2024-01-02 03:04:05.678`.
+pub(crate) fn format_nanos(ns: i64, digits: usize) -> String {
+    NaiveDateTime::from_timestamp_opt(ns.div_euclid(1_000_000_000), ns.rem_euclid(1_000_000_000) as u32)
+        .map(|d| {
+            let s = d.format("%Y-%m-%d %H:%M:%S%.9f").to_string();
+            s[..s.len() - 9 + digits.min(9)].trim_end_matches('.').to_string()
+        })
+        .unwrap_or_else(|| ns.to_string())
+}
+
+/// Format a unix timestamp in milliseconds as UTC, e.g.
+/// `2024-01-02 03:04:05.678`.
+pub(crate) fn format_millis(ms: i64) -> String {
+    NaiveDateTime::from_timestamp_opt(ms.div_euclid(1000), (ms.rem_euclid(1000) * 1_000_000) as u32)
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .unwrap_or_else(|| ms.to_string())
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    ["authorization", "cookie", "token", "secret", "key", "password"]
+        .iter()
+        .any(|s| name.contains(s))
+}
+
+/// Render the request as an equivalent curl command line. Values of
+/// credential-like headers are masked unless `show_secrets` is set.
+pub(crate) fn to_curl(req: &Request, show_secrets: bool) -> String {
+    let mut parts = vec!["curl".to_string()];
+    if req.method() != Method::GET {
+        parts.push(format!("-X {}", req.method()));
+    }
+    for (k, v) in req.headers() {
+        let value = if !show_secrets && is_secret_header(k.as_str()) {
+            "***".to_string()
+        } else {
+            String::from_utf8_lossy(v.as_bytes()).to_string()
+        };
+        parts.push(format!("-H {}", shell_quote(&format!("{}: {}", k, value))));
+    }
+    if let Some(body) = req.body().and_then(|b| b.as_bytes()) {
+        match std::str::from_utf8(body) {
+            Ok(text) => parts.push(format!("--data-binary {}", shell_quote(text))),
+            Err(_) => parts.push(format!("--data-binary @- # {} binary bytes", body.len())),
+        }
+    }
+    parts.push(shell_quote(req.url().as_str()));
+    parts.join(" \\\n  ")
+}
+
+/// With `--print-curl` print the request instead of sending it,
+/// returns whether the request was printed.
+pub(crate) fn maybe_print_curl(
+    req: &RequestBuilder,
+    print_curl: bool,
+    show_secrets: bool,
+) -> anyhow::Result<bool> {
+    if !print_curl {
+        return Ok(false);
+    }
+    let req = req
+        .try_clone()
+        .ok_or_else(|| anyhow::format_err!("request is not printable"))?
+        .build()?;
+    println!("{}", to_curl(&req, show_secrets));
+    Ok(true)
+}
+
 #[allow(dead_code)]
 pub(crate) fn red(s: &str) -> String {
     true_color(s, 255, 0, 0)
@@ -71,19 +235,26 @@ pub(crate) fn yellow(s: &str) -> String {
 }
 
 #[allow(dead_code)]
-pub(crate) fn gray(s: &str) -> String {
+pub fn gray(s: &str) -> String {
     true_color(s, 128, 128, 128)
 }
 
+// stdout is a terminal showing ANSI colors, which windows consoles only
+// do once enabled
+fn colors_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| atty::is(atty::Stream::Stdout) && platform::enable_ansi())
+}
+
 fn true_color(s: &str, r: u8, g: u8, b: u8) -> String {
-    if atty::is(atty::Stream::Stdout) {
+    if colors_enabled() {
         // should have detect 256 color supports properly
         return format!("\x1b[38;2;{};{};{};1m{}\x1b[0m", r, g, b, s);
     }
     s.to_string()
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Args, Clone)]
 pub struct HttpOpts {
     /// Headers to send, used for basic authentication, etc
     #[clap(long, num_args = 0..)]
@@ -93,23 +264,294 @@ pub struct HttpOpts {
     #[clap(short, long, env = "LF_BASIC_AUTH")]
     pub basic_auth: Option<KeyValue>,
 
-    /// Tenant id
+    /// Tenant id. query and push also take a comma separated list or
+    /// @file (one tenant per line) and run once per tenant
     #[clap(short, long, env = "LF_TENANT")]
     pub tenant: Option<String>,
 
-    /// Loki endpoint
+    /// Loki endpoint. query and query-misc also take a comma separated
+    /// list, tried in order on connection errors and 5xx responses
     #[clap(
         short,
         long,
         default_value = "http://127.0.0.1:3100",
         env = "LF_ENDPOINT"
     )]
-    pub endpoint: String,
+    pub endpoint: Endpoints,
+
+    /// Print an equivalent curl command instead of sending the request
+    #[clap(long)]
+    pub print_curl: bool,
+
+    /// Do not mask credentials in --print-curl output
+    #[clap(long)]
+    pub show_secrets: bool,
+
+    /// Sign requests with AWS SigV4 for gateways requiring it, like
+    /// region=us-east-1,service=execute-api. Credentials are read from
+    /// AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN.
+    /// Excludes --basic-auth, both use the Authorization header
+    #[clap(long, env = "LF_SIGV4")]
+    pub sigv4: Option<SigV4Opts>,
+
+    /// query and query-misc send a duplicate request when there is no
+    /// answer after this long, to the next endpoint if any, and take
+    /// whichever answers first
+    #[clap(long, env = "LF_HEDGE_AFTER", value_parser = parse_duration)]
+    pub hedge_after: Option<Duration>,
+}
+
+/// Ordered Loki endpoints, the first one is the primary. Formatting shows
+/// the primary, which is what commands without failover talk to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints(Vec<String>);
+
+impl Endpoints {
+    pub fn primary(&self) -> &str {
+        &self.0[0]
+    }
+
+    pub fn all(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl FromStr for Endpoints {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let endpoints: Vec<_> = s
+            .split(',')
+            .map(|e| e.trim().trim_end_matches('/'))
+            .filter(|e| !e.is_empty())
+            .map(|e| e.to_string())
+            .collect();
+        if endpoints.is_empty() {
+            return Err(anyhow::format_err!("no endpoint given"));
+        }
+        Ok(Endpoints(endpoints))
+    }
+}
+
+impl fmt::Display for Endpoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.primary())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SigV4Opts {
+    pub region: String,
+    pub service: String,
+}
+
+impl FromStr for SigV4Opts {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut region = None;
+        let mut service = "execute-api".to_string();
+        for kv in s.split(',').filter(|kv| !kv.is_empty()) {
+            let kv: KeyValue = kv.parse()?;
+            match kv.key.trim() {
+                "region" => region = Some(kv.value.trim().to_string()),
+                "service" => service = kv.value.trim().to_string(),
+                k => return Err(anyhow::format_err!("unknown sigv4 option {k}, expect region or service")),
+            }
+        }
+        let region = region.ok_or_else(|| anyhow::format_err!("sigv4 needs a region"))?;
+        Ok(SigV4Opts { region, service })
+    }
+}
+
+impl HttpOpts {
+    /// Add the SigV4 headers when --sigv4 is given, to be called once the
+    /// request is complete (the body is part of the signature).
+    pub(crate) fn sign(&self, req: RequestBuilder) -> anyhow::Result<RequestBuilder> {
+        let Some(opts) = self.sigv4.as_ref() else {
+            return Ok(req);
+        };
+        // the signature goes in Authorization, a second one would be sent
+        // along rather than replaced
+        if self.basic_auth.is_some() || self.headers.iter().any(|h| h.key.trim().eq_ignore_ascii_case("authorization")) {
+            return Err(anyhow::format_err!("--sigv4 sets the Authorization header, it can not be used with --basic-auth or an Authorization header"));
+        }
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let creds = match (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key), Some(secret_key)) => Credentials {
+                access_key,
+                secret_key,
+                session_token: env("AWS_SESSION_TOKEN"),
+            },
+            _ => return Err(anyhow::format_err!("--sigv4 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")),
+        };
+        // the blocking builder can not be taken apart, so a built copy is
+        // signed and the headers it gained are set on the builder
+        let mut built = req
+            .try_clone()
+            .ok_or_else(|| anyhow::format_err!("request can not be signed"))?
+            .build()?;
+        let before = built.headers().clone();
+        sigv4::sign(&mut built, &creds, &opts.region, &opts.service, Utc::now())?;
+        let mut req = req;
+        for (name, value) in built.headers().iter() {
+            if before.get(name) != Some(value) {
+                req = req.header(name, value);
+            }
+        }
+        Ok(req)
+    }
+
+    /// Send the request `build` makes for an endpoint, moving on to the
+    /// next endpoint on connection errors and 5xx responses. The last
+    /// endpoint's answer is returned whatever it is. With several endpoints
+    /// (or a hedge) the one that served the response is reported on stderr.
+    pub(crate) fn send_failover<F>(&self, mut build: F) -> anyhow::Result<Response>
+    where
+        F: FnMut(&str) -> RequestBuilder,
+    {
+        let endpoints = self.endpoint.all();
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            let last = i + 1 == endpoints.len();
+            let req = self.sign(build(endpoint))?;
+            let (result, served_by) = match self.hedge_after {
+                Some(after) => {
+                    let hedge_to = endpoints.get(i + 1).unwrap_or(endpoint);
+                    hedged((req, endpoint), (self.sign(build(hedge_to))?, hedge_to), after)
+                }
+                None => (timing::send(req), endpoint.clone()),
+            };
+            let failure = match result {
+                Ok(resp) if last || !resp.status().is_server_error() => {
+                    if endpoints.len() > 1 || self.hedge_after.is_some() {
+                        eprintln!("{}", gray(&format!("served by {served_by}")));
+                    }
+                    return Ok(resp);
+                }
+                Ok(resp) => resp.status().to_string(),
+                Err(err) => match connection_failure(&err) {
+                    Some(failure) if !last => failure.to_string(),
+                    _ => return Err(err),
+                },
+            };
+            eprintln!("{}", yellow(&format!("{endpoint}: {failure}, trying {}", endpoints[i + 1])));
+        }
+        unreachable!("endpoints are never empty")
+    }
+
+    /// Expand `--tenant` into the tenants to run for, `[None]` when no
+    /// tenant is given.
+    pub fn tenants(&self) -> anyhow::Result<Vec<Option<String>>> {
+        let Some(tenant) = self.tenant.as_ref() else {
+            return Ok(vec![None]);
+        };
+        let list = match tenant.strip_prefix('@') {
+            Some(file) => std::fs::read_to_string(file)
+                .map_err(|e| anyhow::format_err!("read tenant file {file}: {e}"))?,
+            None => tenant.replace(',', "\n"),
+        };
+        let tenants: Vec<_> = list
+            .lines()
+            .map(|t| t.trim())
+            .filter(|t| !t.is_empty() && !t.starts_with('#'))
+            .map(|t| Some(t.to_string()))
+            .collect();
+        if tenants.is_empty() {
+            return Err(anyhow::format_err!("no tenant found in {tenant}"));
+        }
+        Ok(tenants)
+    }
+}
+
+type Answer = (anyhow::Result<Response>, String);
+
+fn send_in_background(tx: &mpsc::Sender<Answer>, req: RequestBuilder, endpoint: &str) {
+    let (tx, endpoint) = (tx.clone(), endpoint.to_string());
+    thread::spawn(move || {
+        // the receiver is gone once the other request won
+        let _ = tx.send((timing::send(req), endpoint));
+    });
+}
+
+// Send `first`, and `second` too when `first` is not answered after
+// `after`. The first good answer wins, the other request is left to finish
+// in the background. Returns the endpoint that answered.
+fn hedged(first: (RequestBuilder, &str), second: (RequestBuilder, &str), after: Duration) -> Answer {
+    let (tx, rx) = mpsc::channel();
+    send_in_background(&tx, first.0, first.1);
+    if let Ok(answer) = rx.recv_timeout(after) {
+        return answer;
+    }
+    eprintln!(
+        "{}",
+        yellow(&format!(
+            "no answer from {} after {}, hedging to {}",
+            first.1,
+            humantime::format_duration(after),
+            second.1
+        ))
+    );
+    send_in_background(&tx, second.0, second.1);
+    drop(tx);
+    let Ok(answer) = rx.recv() else {
+        return (Err(anyhow::format_err!("hedged requests sent no answer")), first.1.to_string());
+    };
+    if matches!(&answer.0, Ok(resp) if !resp.status().is_server_error()) {
+        return answer;
+    }
+    rx.recv().unwrap_or(answer)
+}
+
+// errors worth trying another endpoint for
+fn connection_failure(err: &anyhow::Error) -> Option<&'static str> {
+    let err = err.downcast_ref::<reqwest::Error>()?;
+    if err.is_timeout() {
+        Some("timed out")
+    } else if err.is_connect() {
+        Some("connection failed")
+    } else {
+        None
+    }
+}
+
+/// Run `f` once per tenant of `http`, with a header per tenant when there
+/// are several. Failures don't stop the remaining tenants.
+pub(crate) fn for_each_tenant<F: FnMut(Option<String>) -> anyhow::Result<()>>(
+    http: &HttpOpts,
+    mut f: F,
+) -> anyhow::Result<()> {
+    let tenants = http.tenants()?;
+    if tenants.len() == 1 {
+        return f(tenants.into_iter().next().unwrap());
+    }
+    let mut failed = vec![];
+    for tenant in tenants.into_iter().flatten() {
+        println!("{}", yellow(&format!("=== tenant {tenant} ===")));
+        if let Err(err) = f(Some(tenant.clone())) {
+            eprintln!("{}", red(&err.to_string()));
+            failed.push(tenant);
+        }
+    }
+    if !failed.is_empty() {
+        return Err(anyhow::format_err!("failed for tenants: {}", failed.join(", ")));
+    }
+    Ok(())
+}
+
+/// Durations as humantime reads them ("1h30m", "2w", ...) where months
+/// ("1mo", "3months") are 30 days, used by every duration flag.
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let months = regex::Regex::new(r"(\d+)\s*(?:months?|mo|M)\b").unwrap();
+    // too large numbers are left for humantime to complain about
+    let s = months.replace_all(s, |caps: &regex::Captures| {
+        format!("{}d", caps[1].parse::<u64>().unwrap_or(u64::MAX).saturating_mul(30))
+    });
+    Ok(humantime::parse_duration(&s)?)
 }
 
 #[derive(Debug, Args)]
 pub struct TimeRangeOpts {
-    /// The start time for the query. Defaults to one hour ago.
+    /// The start time for the query. Defaults to --default-range before end.
     #[clap(long)]
     pub start: Option<NaiveDateTime>,
 
@@ -125,7 +567,20 @@ pub struct TimeRangeOpts {
 
     /// Shorthand to specify duration (working with start or end).
     /// The interval is [start, start + duration] or [end - duration, end]
-    /// depending on whether start or end you have been specified.
+    /// depending on whether start or end you have been specified,
+    /// alone it is the same as --since.
     #[clap(short, long, value_parser=parse_duration)]
     pub duration: Option<Duration>,
+
+    /// Range used when no time option is given, ending now
+    #[clap(long, env = "LF_DEFAULT_RANGE", default_value = "1h", value_parser=parse_duration)]
+    pub default_range: Duration,
+}
+
+impl TimeRangeOpts {
+    /// No time option given, commands filtering on time read this as
+    /// "everything" rather than the default range.
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.end.is_none() && self.since.is_none() && self.duration.is_none()
+    }
 }
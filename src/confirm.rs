@@ -0,0 +1,88 @@
+// Guardrail of the operations deleting or overwriting data: the impact is
+// shown first, then the operation needs a "y" on the terminal or --yes,
+// and every confirmed operation leaves a json line in the audit log,
+// $LF_AUDIT_LOG or $XDG_STATE_HOME/lf/audit.log.
+
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::common::{gray, yellow};
+
+#[derive(Debug, Serialize)]
+struct AuditLine<'a> {
+    time: String,
+    user: String,
+    args: Vec<String>,
+    action: &'a str,
+    impact: &'a [String],
+    // "--yes" or "prompt"
+    confirmed_by: &'a str,
+}
+
+fn audit_path() -> Option<PathBuf> {
+    if let Some(p) = std::env::var_os("LF_AUDIT_LOG") {
+        return Some(PathBuf::from(p));
+    }
+    let dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("state")))?;
+    Some(dir.join("lf").join("audit.log"))
+}
+
+fn audit(line: &AuditLine) -> anyhow::Result<()> {
+    let path = audit_path().ok_or_else(|| anyhow::format_err!("no audit log, set LF_AUDIT_LOG or HOME"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| anyhow::format_err!("audit log {}: {e}", path.display()))?;
+    writeln!(f, "{}", serde_json::to_string(line)?)?;
+    Ok(())
+}
+
+/// Show what `action` is going to do and wait for a confirmation, unless
+/// `yes`. Fails when declined, or when there is no terminal to ask on.
+/// The confirmed operation is recorded in the audit log before it runs,
+/// an operation that can't be audited does not run.
+pub(crate) fn confirm(action: &str, impact: &[String], yes: bool) -> anyhow::Result<()> {
+    eprintln!("{}", yellow(&format!("about to {action}:")));
+    for line in impact {
+        eprintln!("  {line}");
+    }
+    let confirmed_by = if yes {
+        "--yes"
+    } else {
+        if !atty::is(atty::Stream::Stdin) {
+            return Err(anyhow::format_err!("not confirmed, stdin is not a terminal, pass --yes"));
+        }
+        eprint!("proceed? [y/N] ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().lock().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+            return Err(anyhow::format_err!("not confirmed, nothing done"));
+        }
+        "prompt"
+    };
+    audit(&AuditLine {
+        time: Utc::now().to_rfc3339(),
+        user: std::env::var("USER").unwrap_or_default(),
+        args: std::env::args().collect(),
+        action,
+        impact,
+        confirmed_by,
+    })?;
+    if let Some(path) = audit_path() {
+        eprintln!("{}", gray(&format!("recorded in {}", path.display())));
+    }
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use reqwest::{blocking::Client, StatusCode};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    common::{format_nanos, gray, green, refine_loki_request, yellow, HttpOpts, KeyValue, TimeRangeOpts},
+    error::ApiError,
+    push::{send_streams, LabelRewriteOpts, Stream},
+    query::get_duration,
+    timing,
+};
+
+/// copy the result of a log query from one loki to another
+#[derive(Parser, Debug)]
+pub struct Copy {
+    /// Loki to read from
+    #[clap(long, env = "LF_ENDPOINT")]
+    from_endpoint: String,
+
+    /// Tenant to read from
+    #[clap(long, env = "LF_TENANT")]
+    from_tenant: Option<String>,
+
+    /// Basic auth for the source loki
+    #[clap(long, env = "LF_BASIC_AUTH")]
+    from_basic_auth: Option<KeyValue>,
+
+    /// Loki to push to
+    #[clap(long)]
+    to_endpoint: String,
+
+    /// Tenant to push to, defaults to the source tenant
+    #[clap(long)]
+    to_tenant: Option<String>,
+
+    /// Basic auth for the destination loki
+    #[clap(long)]
+    to_basic_auth: Option<KeyValue>,
+
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// The LogQL log query selecting what to copy
+    #[clap(short, long)]
+    query: String,
+
+    /// Entries fetched (and pushed) per request
+    #[clap(long, default_value = "1000")]
+    batch_size: u32,
+
+    /// Max entries pushed per second
+    #[clap(long)]
+    rate: Option<u32>,
+
+    /// Set (or overwrite) a label on every copied stream
+    #[clap(long, num_args = 0..)]
+    set_label: Vec<KeyValue>,
+
+    #[command(flatten)]
+    rewrite: LabelRewriteOpts,
+
+    /// File keeping the progress, an interrupted copy started again with
+    /// the same file continues where it stopped
+    #[clap(long)]
+    resume: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryRangeRequest<'a> {
+    start: i64,
+    end: i64,
+    limit: u32,
+    direction: &'a str,
+    query: &'a str,
+}
+
+type Labels = BTreeMap<String, String>;
+
+fn fetch(client: &Client, c: &Copy, start: i64, end: i64) -> anyhow::Result<Vec<(Labels, i64, String)>> {
+    let req = client.get(format!("{}/loki/api/v1/query_range", c.from_endpoint));
+    let req = refine_loki_request(req, vec![], c.from_basic_auth.clone(), c.from_tenant.clone());
+    let req = req
+        .query(&QueryRangeRequest {
+            start,
+            end,
+            limit: c.batch_size,
+            direction: "forward",
+            query: &c.query,
+        });
+    let resp = timing::send(req)?;
+    if resp.status() != StatusCode::OK {
+        return Err(ApiError::status("query", resp).into());
+    }
+    let obj: serde_json::Value = serde_json::from_str(&timing::text(resp)?)?;
+    if obj["data"]["resultType"] != "streams" {
+        return Err(ApiError::UnexpectedResponse("only log queries can be copied".to_string()).into());
+    }
+    let mut entries = vec![];
+    for r in obj["data"]["result"].as_array().into_iter().flatten() {
+        let labels: Labels = r["stream"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+            .collect();
+        for value in r["values"].as_array().into_iter().flatten() {
+            let ts = value[0].as_str().and_then(|s| s.parse().ok()).unwrap_or_default();
+            let line = value[1].as_str().unwrap_or_default().to_string();
+            entries.push((labels.clone(), ts, line));
+        }
+    }
+    entries.sort_by_key(|e| e.1);
+    Ok(entries)
+}
+
+// None when the relabel rules drop the stream
+fn rewrite(c: &Copy, labels: Labels) -> anyhow::Result<Option<Labels>> {
+    let Some(mut labels) = c.rewrite.apply(labels)? else {
+        return Ok(None);
+    };
+    for kv in c.set_label.iter() {
+        labels.insert(kv.key.clone(), kv.value.clone());
+    }
+    Ok(Some(labels))
+}
+
+pub fn copy(c: Copy) -> anyhow::Result<()> {
+    let (from, through) = get_duration(&c.time_range)?;
+    let (start, end) = (from.timestamp_nanos(), through.timestamp_nanos());
+    let mut cursor = start;
+    if let Some(path) = c.resume.as_ref().filter(|p| p.exists()) {
+        let saved: i64 = std::fs::read_to_string(path)?.trim().parse()?;
+        if saved > cursor && saved <= end {
+            println!("{}", gray(&format!("resuming from {}", format_nanos(saved, 3))));
+            cursor = saved;
+        }
+    }
+
+    let client = Client::new();
+    let to = HttpOpts {
+        headers: vec![],
+        basic_auth: c.to_basic_auth.clone(),
+        tenant: c.to_tenant.clone().or_else(|| c.from_tenant.clone()),
+        endpoint: c.to_endpoint.parse()?,
+        print_curl: false,
+        show_secrets: false,
+        sigv4: None,
+        hedge_after: None,
+    };
+
+    let started = Instant::now();
+    let mut copied: u64 = 0;
+    // entries at exactly `cursor` that have been pushed already, the next
+    // query starts at `cursor` again since more lines may share that timestamp
+    let mut seen: HashSet<(Labels, String)> = HashSet::new();
+    loop {
+        let entries = fetch(&client, &c, cursor, end)?;
+        let full_page = entries.len() >= c.batch_size as usize;
+        // the next query would start at the same timestamp and get the same page
+        if full_page && entries.iter().all(|e| e.1 == cursor) {
+            return Err(anyhow::format_err!(
+                "{} or more entries at {}, copy again with a larger --batch-size",
+                c.batch_size,
+                format_nanos(cursor, 9)
+            ));
+        }
+        let entries: Vec<_> = entries
+            .into_iter()
+            .filter(|(labels, ts, line)| {
+                *ts >= cursor && *ts <= end && !(*ts == cursor && seen.contains(&(labels.clone(), line.clone())))
+            })
+            .collect();
+        if entries.is_empty() {
+            break;
+        }
+
+        let last = entries.last().map(|e| e.1).unwrap_or(cursor);
+        if last != cursor {
+            seen.clear();
+        }
+        let mut streams: BTreeMap<Labels, Vec<(String, String)>> = BTreeMap::new();
+        let count = entries.len();
+        for (labels, ts, line) in entries {
+            if ts == last {
+                seen.insert((labels.clone(), line.clone()));
+            }
+            if let Some(labels) = rewrite(&c, labels)? {
+                streams.entry(labels).or_default().push((ts.to_string(), line));
+            }
+        }
+        let streams: Vec<_> = streams
+            .into_iter()
+            .map(|(labels, values)| Stream {
+                stream: labels.into_iter().collect(),
+                values,
+            })
+            .collect();
+        if !streams.is_empty() {
+            send_streams(&client, &to, None, streams)?;
+        }
+        copied += count as u64;
+        cursor = last;
+        if let Some(path) = c.resume.as_ref() {
+            std::fs::write(path, cursor.to_string())?;
+        }
+        println!("{} entries copied, up to {}", green(&copied.to_string()), gray(&format_nanos(cursor, 3)));
+
+        if let Some(rate) = c.rate.filter(|r| *r > 0) {
+            let expected = Duration::from_secs_f64(copied as f64 / rate as f64);
+            if let Some(wait) = expected.checked_sub(started.elapsed()) {
+                debug!("pacing, sleep {wait:?}");
+                thread::sleep(wait);
+            }
+        }
+        if !full_page {
+            break;
+        }
+    }
+    if copied == 0 {
+        println!("{}", yellow("nothing to copy"));
+    }
+    Ok(())
+}
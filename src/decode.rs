@@ -1,20 +1,64 @@
-use std::{io::{Read, Seek, Cursor}, path::Path};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
 
-use binread::BinReaderExt;
-use clap::Parser;
+use chrono::NaiveDateTime;
+use clap::{Parser, ValueEnum};
+use regex::Regex;
+use serde::{
+    ser::{Error, SerializeSeq, SerializeStruct},
+    Serialize, Serializer,
+};
+use tracing::info;
 
-use crate::ty::Chunk;
+use crate::{
+    common::{blue, gray, green, red, yellow, KeyValue, TimeRangeOpts},
+    error::DecodeError,
+    grep::{collect_files, grep_chunk, grep_chunk_bytes, highlight, optional_range},
+    parquet::{write_parquet, Column},
+    platform::{display_path, native_path},
+    proxy::format_labels,
+    repair::salvage,
+    s3::{parse_s3_url, S3Client, S3Opts},
+    split::StreamFiles,
+    timefmt::{self, Zone},
+    validate::check_strict,
+    sqlite::EntryDb,
+    timing,
+    ty::{identify, Chunk, ChunkData, ChunkHead, ChunkStream, UnorderedBlock, UnorderedBlockEntry},
+};
 
 /// decode proto struct from input
 #[derive(Parser, Debug)]
 pub struct Decode {
-    /// input file (binary input)
+    /// input file (binary input), "-" for a chunk piped to stdin, an
+    /// s3://bucket/key chunk object, or a directory of chunks decoded one
+    /// by one into an ndjson stream of chunks (or --output-dir)
     #[clap(short, long)]
     pub input: String,
 
-    /// output file (json output)
-    #[clap(short, long, default_value="out.json")]
-    pub output: String,
+    /// chunks directory of a filesystem object store (storage_config
+    /// filesystem), the input is then a chunk key like
+    /// tenant/fp:from:through:checksum, found under its encoded name
+    #[clap(long, value_name = "DIR")]
+    pub store_root: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub s3: S3Opts,
+
+    /// output file, out.json by default (out.db with --format sqlite)
+    #[clap(short, long)]
+    pub output: Option<String>,
+
+    /// with a directory input, write one file per chunk in this directory
+    /// instead, at the same relative path plus .json (or .parquet)
+    #[clap(long, conflicts_with_all = ["split_by_stream", "grep_fast"])]
+    pub output_dir: Option<PathBuf>,
 
     /// disable pretty output
     #[clap(short, long)]
@@ -23,27 +67,961 @@ pub struct Decode {
     /// just parse, do not output
     #[clap(long)]
     pub noout: bool,
+
+    /// print the entry counts, sizes, compression ratio and time range of
+    /// every block and the line lengths of the chunk instead
+    #[clap(long, conflicts_with_all = ["split_by_stream", "grep_fast", "format", "noout", "meta_only", "output_dir"])]
+    pub stats: bool,
+
+    /// print the entries older than one before them (unordered writes),
+    /// within a block or across blocks, instead (or after --stats)
+    #[clap(long, conflicts_with_all = ["split_by_stream", "grep_fast", "format", "noout", "meta_only", "output_dir"])]
+    pub order_report: bool,
+
+    /// only output the header and block metas (time ranges, sizes and
+    /// entry counts) of the chunks, no block is decompressed
+    #[clap(long, conflicts_with_all = ["split_by_stream", "grep_fast", "format", "noout"])]
+    pub meta_only: bool,
+
+    /// output format, parquet writes one row per entry with timestamp, line,
+    /// block, structured_metadata (format v4) and one column per label,
+    /// logfmt one record per entry with
+    /// ts, stream_labels, line and the structured metadata, ndjson one
+    /// json object per entry with ts, line, block and labels, csv one row
+    /// per entry with ts, labels and line, sqlite the entries and labels
+    /// tables of the --output database (appended to, see sqlite.rs)
+    #[clap(long, value_enum, default_value = "json")]
+    pub format: OutputFormat,
+
+    /// time zone of the decoded timestamps of entries and block metas:
+    /// local, utc or a zone like Europe/Berlin (naive utc when neither this
+    /// nor --ts-format is given, parquet and sqlite keep unix nanoseconds)
+    #[clap(long, value_name = "ZONE")]
+    pub tz: Option<Zone>,
+
+    /// strftime format of the decoded timestamps, like "%Y-%m-%d
+    /// %H:%M:%S%.3f %Z" (rfc3339 when only --tz is given)
+    #[clap(long, value_name = "STRFTIME")]
+    pub ts_format: Option<String>,
+
+    /// field delimiter of the csv output
+    #[clap(long, default_value_t = ',', value_name = "CHAR")]
+    pub delimiter: char,
+
+    /// leave the header row out of the csv output
+    #[clap(long)]
+    pub no_header: bool,
+
+    /// write one file per label set into this directory instead, the input
+    /// may then also be a directory of chunks
+    #[clap(long)]
+    pub split_by_stream: Option<PathBuf>,
+
+    /// print the lines matching this regex instead of decoding everything,
+    /// blocks outside of the time range are not decompressed
+    #[clap(long, value_name = "PATTERN")]
+    pub grep_fast: Option<String>,
+
+    /// stop after this many matching lines (with --grep-fast)
+    #[clap(long, requires = "grep_fast")]
+    pub max_matches: Option<usize>,
+
+    /// only decode the entries in this time range, blocks out of it are
+    /// not decompressed (and left empty in the json output)
+    #[command(flatten)]
+    pub time_range: TimeRangeOpts,
+
+    /// only keep entries whose structured metadata has this pair (format
+    /// v4 chunks, repeat to require several)
+    #[clap(long, value_name = "NAME=VALUE")]
+    pub metadata: Vec<KeyValue>,
+
+    /// only decode this block of the chunk, counted from 0 like the block
+    /// metas (the others are left empty in the json output)
+    #[clap(long, value_name = "N", conflicts_with = "grep_fast")]
+    pub block: Option<usize>,
+
+    /// only keep the entries whose line matches this regex
+    #[clap(long, value_name = "REGEX", conflicts_with = "grep_fast")]
+    pub grep: Option<String>,
+
+    /// only keep the entries whose line contains this string
+    #[clap(long, value_name = "STRING", conflicts_with = "grep_fast")]
+    pub contains: Option<String>,
+
+    /// cut the emitted lines to at most N bytes, marking the cut ones with
+    /// a trailing "…"
+    #[clap(long, value_name = "N", conflicts_with_all = ["stats", "order_report"])]
+    pub max_line_len: Option<usize>,
+
+    /// only output the first N entries of each chunk (after the time range
+    /// and entry filters), no block is decoded past them
+    #[clap(long, value_name = "N", conflicts_with_all = ["tail", "stats", "order_report", "split_by_stream", "grep_fast"])]
+    pub head: Option<usize>,
+
+    /// only output the last N entries of each chunk (after the time range
+    /// and entry filters), the blocks before them are not decoded
+    #[clap(long, value_name = "N", conflicts_with_all = ["stats", "order_report", "split_by_stream", "grep_fast"])]
+    pub tail: Option<usize>,
+
+    /// skip the checksum verification of the blocks and block metas
+    /// (--grep-fast never verifies them)
+    #[clap(long)]
+    pub no_verify: bool,
+
+    /// fail rather than warn when the head encoding disagrees with the
+    /// chunk data, and check the structure of the chunk first: its length
+    /// fields, that the blocks neither overlap nor run into the block
+    /// metas, the uncompressed block sizes and the trailer, every problem
+    /// is printed with its byte position
+    #[clap(long, conflicts_with = "best_effort")]
+    pub strict: bool,
+
+    /// decode what is left of a truncated or damaged chunk: blocks failing
+    /// to decompress are skipped with a warning, a block cut short keeps
+    /// the entries before the cut
+    #[clap(long, conflicts_with = "grep_fast")]
+    pub best_effort: bool,
+}
+
+impl Decode {
+    /// The --output file, or the default of the output format.
+    pub fn output(&self) -> &str {
+        match (&self.output, &self.format) {
+            (Some(output), _) => output,
+            (None, OutputFormat::Sqlite) => "out.db",
+            (None, _) => "out.json",
+        }
+    }
 }
 
-fn decode_chunk<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Chunk> {
-    match reader.read_le() {
-        Ok(chunk) => Ok(chunk),
-        Err(error) => {
-            match error {
-                binread::Error::Custom { pos: _, err: _ } => {
-                    let err_msg = error.custom_err::<anyhow::Error>().unwrap();
-                    Err(anyhow::format_err!("{err_msg:?}"))
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Parquet,
+    Logfmt,
+    Ndjson,
+    Csv,
+    Sqlite,
+}
+
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The chunk piped to stdin, held in memory: the block metas are found
+/// from the trailer at the end of a chunk, a pipe can't seek there.
+pub fn read_stdin() -> anyhow::Result<Vec<u8>> {
+    if atty::is(atty::Stream::Stdin) {
+        return Err(anyhow::format_err!("no chunk piped to stdin, it is a terminal"));
+    }
+    let mut bs = vec![];
+    std::io::stdin().lock().read_to_end(&mut bs)?;
+    if bs.is_empty() {
+        return Err(anyhow::format_err!("no chunk piped to stdin, it is empty"));
+    }
+    Ok(bs)
+}
+
+/// The chunk bytes of `file` when they don't come from a local file:
+/// "-" reads stdin, `s3://bucket/key` downloads the object.
+pub(crate) fn fetch_input(s3: &S3Opts, file: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    let name = file.to_string_lossy();
+    if name == "-" {
+        return read_stdin().map(Some);
+    }
+    if !name.starts_with("s3://") {
+        return Ok(None);
+    }
+    let (bucket, key) = parse_s3_url(&name)?;
+    if key.is_empty() || key.ends_with('/') {
+        return Err(anyhow::format_err!("{name} is not a chunk object, expect s3://bucket/tenant/fingerprint/..."));
+    }
+    let client = S3Client::new(s3, &bucket)?;
+    timing::time("download", || client.get(&key)).map(Some)
+}
+
+/// The inputs of `input`: the chunks under a directory, or a single local
+/// chunk, stdin or s3 object.
+pub fn input_paths(input: &str) -> anyhow::Result<Vec<PathBuf>> {
+    if input == "-" || input.starts_with("s3://") {
+        return Ok(vec![PathBuf::from(input)]);
+    }
+    let mut paths = vec![];
+    collect_files(&native_path(input), &mut paths)?;
+    Ok(paths)
+}
+
+/// The header of a chunk and its blocks, decoded as they are taken from
+/// the stream. A head encoding disagreeing with the data is a warning, or
+/// an error when `strict`.
+pub fn open_chunk(
+    mut reader: Box<dyn ReadSeek>,
+    range: Option<(i64, i64)>,
+    verify: bool,
+    strict: bool,
+) -> anyhow::Result<(ChunkHead, ChunkStream<Box<dyn ReadSeek>>)> {
+    let header = match ChunkHead::read_from(&mut reader) {
+        Ok(header) => header,
+        Err(e) => {
+            // tell what the file is instead of the parse error if we know it
+            let mut prefix = Vec::with_capacity(64);
+            reader.seek(SeekFrom::Start(0))?;
+            (&mut reader).take(64).read_to_end(&mut prefix)?;
+            return Err(identify(&prefix).unwrap_or_else(|| DecodeError::from(e)).into());
+        }
+    };
+    eprintln!("{header:?}");
+    let stream = match ChunkStream::new(reader, range, verify) {
+        Ok(stream) => stream,
+        Err(e) => {
+            header.check_log_chunk()?;
+            return Err(DecodeError::from(e).into());
+        }
+    };
+    if let Some(msg) = header.encoding_mismatch(stream.format, &stream.ty) {
+        if strict {
+            return Err(DecodeError::EncodingMismatch(msg).into());
+        }
+        eprintln!("{} {msg}", red("warning:"));
+    }
+    info!("{:?}", stream.meta);
+    Ok((header, stream))
+}
+
+/// Parse the chunk file at `path` whole, see `Chunk::from_reader`.
+pub fn decode_file(path: impl AsRef<Path>) -> anyhow::Result<Chunk> {
+    let mut reader = BufReader::new(File::open(native_path(path.as_ref()))?);
+    Ok(Chunk::from_reader(&mut reader)?)
+}
+
+/// `open_chunk` of `file` ("-" for stdin, or an s3 url) with the time
+/// range, --block, --no-verify, --best-effort and --strict of `d`. The
+/// whole chunk is read first for the last two.
+pub fn open_decoded(d: &Decode, file: &Path) -> anyhow::Result<(ChunkHead, ChunkStream<Box<dyn ReadSeek>>)> {
+    let reader: Box<dyn ReadSeek> = match (fetch_input(&d.s3, file)?, d.best_effort || d.strict) {
+        (Some(bs), false) => Box::new(Cursor::new(bs)),
+        (None, false) => Box::new(BufReader::new(File::open(native_path(file))?)),
+        (bs, true) => {
+            let bs = match bs {
+                Some(bs) => bs,
+                None => std::fs::read(native_path(file))?,
+            };
+            match d.best_effort {
+                true => Box::new(Cursor::new(salvage(&bs)?)),
+                false => {
+                    check_strict(&bs)?;
+                    Box::new(Cursor::new(bs))
                 }
-                err => {
-                    Err(anyhow::format_err!("{err}"))
+            }
+        }
+    };
+    let (header, mut stream) = open_chunk(reader, optional_range(&d.time_range)?, !d.no_verify, d.strict)?;
+    if let Some(i) = d.block {
+        if i >= stream.meta.num_blocks {
+            return Err(anyhow::format_err!("no block {i}, the chunk has {} blocks", stream.meta.num_blocks));
+        }
+        stream.select_block(i);
+    }
+    Ok((header, stream))
+}
+
+/// Write the entries of a chunk as parquet rows.
+pub fn write_chunk_parquet<W: Write>(chunk: &Chunk, w: W) -> anyhow::Result<()> {
+    let (mut ts, mut lines, mut blocks, mut metadata) = (vec![], vec![], vec![], vec![]);
+    for (i, b) in chunk.data.blocks.iter().enumerate() {
+        for e in b.entries.iter() {
+            ts.push(e.time.timestamp_nanos());
+            lines.push(e.line.clone());
+            blocks.push(i as i32);
+            let pairs: BTreeMap<_, _> = e.structured_metadata.iter().map(|(k, v)| (k, v)).collect();
+            metadata.push(serde_json::to_string(&pairs)?);
+        }
+    }
+    let rows = ts.len();
+    let labels: BTreeMap<_, _> = chunk.header.metric.iter().filter(|(k, _)| *k != "__name__").collect();
+    let mut columns = vec![
+        Column::Timestamp("timestamp".to_string(), ts),
+        Column::Str("line".to_string(), lines),
+        Column::Int32("block".to_string(), blocks),
+    ];
+    // format v4, the metadata of an entry as a json object of its pairs
+    if chunk.data.format >= 4 {
+        columns.push(Column::Str("structured_metadata".to_string(), metadata));
+    }
+    for (name, value) in labels {
+        let name = if ["timestamp", "line", "block", "structured_metadata"].contains(&name.as_str()) {
+            format!("label_{name}")
+        } else {
+            name.clone()
+        };
+        columns.push(Column::Str(name, vec![value.clone(); rows]));
+    }
+    write_parquet(w, &columns)
+}
+
+// serialized like a Chunk (with the path of the file first when decoding
+// a directory), blocks are decoded while they are written out
+#[derive(Serialize)]
+#[serde(bound = "")]
+struct StreamedChunk<'a, R: Read + Seek> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    header: &'a ChunkHead,
+    data: StreamedData<'a, R>,
+}
+
+struct StreamedData<'a, R> {
+    stream: RefCell<ChunkStream<R>>,
+    filter: &'a EntryFilter<'a>,
+    // whether an entry carried structured metadata, see EntryFilter::block
+    seen_metadata: Cell<bool>,
+    // --meta-only, leave the blocks out
+    meta_only: bool,
+}
+
+struct StreamedBlocks<'a, 'b, R>(RefCell<&'b mut ChunkStream<R>>, &'b StreamedData<'a, R>);
+
+impl<R: Read + Seek> Serialize for StreamedData<'_, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut stream = self.stream.borrow_mut();
+        let mut s = serializer.serialize_struct("ChunkData", 5)?;
+        s.serialize_field("format", &stream.format)?;
+        s.serialize_field("ty", &stream.ty)?;
+        match stream.symbols.is_empty() {
+            true => s.skip_field("symbols")?,
+            false => s.serialize_field("symbols", &stream.symbols)?,
+        }
+        match self.meta_only {
+            true => s.skip_field("blocks")?,
+            false => s.serialize_field("blocks", &StreamedBlocks(RefCell::new(&mut stream), self))?,
+        }
+        s.serialize_field("meta", &stream.meta)?;
+        s.end()
+    }
+}
+
+impl<R: Read + Seek> Serialize for StreamedBlocks<'_, '_, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut stream = self.0.borrow_mut();
+        let mut seq = serializer.serialize_seq(Some(stream.meta.num_blocks))?;
+        while let Some(block) = timing::time("decompress", || stream.next()) {
+            let mut block = block.map_err(|e| S::Error::custom(DecodeError::from(e)))?;
+            if self.1.filter.block(&mut block) {
+                self.1.seen_metadata.set(true);
+            }
+            seq.serialize_element(&block)?;
+            // --head, the blocks after it are left out
+            if self.1.filter.done() {
+                break;
+            }
+        }
+        seq.end()
+    }
+}
+
+/// Decode the chunk `file` as json, writing the blocks out one at a time
+/// so that only one of them is in memory. A chunk failing to decode half
+/// way leaves the json written so far incomplete. `path` is added to the
+/// json, for the lines of ndjson output.
+pub fn write_chunk_json<W: Write>(d: &Decode, file: &Path, path: Option<String>, w: W) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, stream) = filter.open(d, file)?;
+    let chunk = StreamedChunk {
+        path,
+        header: &header,
+        data: StreamedData {
+            stream: RefCell::new(stream),
+            filter: &filter,
+            seen_metadata: Cell::new(false),
+            meta_only: d.meta_only,
+        },
+    };
+    match d.compact || chunk.path.is_some() {
+        true => serde_json::to_writer(w, &chunk)?,
+        false => serde_json::to_writer_pretty(w, &chunk)?,
+    }
+    if !d.meta_only {
+        filter.note_metadata(chunk.data.seen_metadata.get());
+    }
+    Ok(())
+}
+
+// a logfmt value, quoted (json style) when it has spaces, '=' or '"'
+fn logfmt_value(s: &str) -> Cow<'_, str> {
+    match s.is_empty() || s.chars().any(|c| c <= ' ' || c == '=' || c == '"' || c == '\u{7f}') {
+        true => Cow::Owned(serde_json::to_string(s).unwrap_or_default()),
+        false => Cow::Borrowed(s),
+    }
+}
+
+/// Decode the chunk `file` as logfmt records, one line per entry like
+/// `ts=2022-08-31T11:51:49.123456789Z stream_labels="{app=\"x\"}" line=...`
+/// followed by the structured metadata pairs, block by block.
+pub fn write_chunk_logfmt<W: Write>(d: &Decode, file: &Path, mut w: W) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, blocks) = filter.open(d, file)?;
+    let labels = format_labels(header.metric.iter().filter(|(k, _)| *k != "__name__").collect::<BTreeMap<_, _>>());
+    let labels = logfmt_value(&labels);
+    let mut seen_metadata = false;
+    for block in blocks {
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        seen_metadata |= filter.block(&mut block);
+        for e in block.entries.iter() {
+            let ts = timefmt::format(&e.time, "%Y-%m-%dT%H:%M:%S%.9fZ");
+            write!(w, "ts={ts} stream_labels={labels} line={}", logfmt_value(&e.line))?;
+            for (name, value) in e.structured_metadata.iter() {
+                write!(w, " {name}={}", logfmt_value(value))?;
+            }
+            writeln!(w)?;
+        }
+        if filter.done() {
+            break;
+        }
+    }
+    filter.note_metadata(seen_metadata);
+    Ok(())
+}
+
+// a csv field, quoted (quotes doubled) when it has the delimiter, a quote
+// or a line break
+pub(crate) fn csv_field(s: &str, delimiter: char) -> Cow<'_, str> {
+    match s.contains([delimiter, '"', '\n', '\r']) {
+        true => Cow::Owned(format!("\"{}\"", s.replace('"', "\"\""))),
+        false => Cow::Borrowed(s),
+    }
+}
+
+fn write_csv_header<W: Write>(d: &Decode, mut w: W) -> std::io::Result<()> {
+    writeln!(w, "ts{0}labels{0}line", d.delimiter)
+}
+
+/// Decode the chunk `file` as csv rows of ts, labels and line, block by
+/// block, after the header row when `header` is set.
+pub fn write_chunk_csv<W: Write>(d: &Decode, file: &Path, mut w: W, header: bool) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (head, blocks) = filter.open(d, file)?;
+    let sep = d.delimiter;
+    let labels = format_labels(head.metric.iter().filter(|(k, _)| *k != "__name__").collect::<BTreeMap<_, _>>());
+    let labels = csv_field(&labels, sep);
+    if header {
+        write_csv_header(d, &mut w)?;
+    }
+    let mut seen_metadata = false;
+    for block in blocks {
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        seen_metadata |= filter.block(&mut block);
+        for e in block.entries.iter() {
+            let ts = timefmt::format(&e.time, "%Y-%m-%dT%H:%M:%S%.9fZ");
+            writeln!(w, "{ts}{sep}{labels}{sep}{}", csv_field(&e.line, sep))?;
+        }
+        if filter.done() {
+            break;
+        }
+    }
+    filter.note_metadata(seen_metadata);
+    Ok(())
+}
+
+// a line of --format ndjson
+#[derive(Serialize)]
+struct NdjsonEntry<'a> {
+    #[serde(serialize_with = "timefmt::serialize")]
+    ts: &'a NaiveDateTime,
+    line: &'a str,
+    block: usize,
+    labels: &'a BTreeMap<&'a String, &'a String>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    structured_metadata: &'a [(String, String)],
+}
+
+/// Decode the chunk `file` as ndjson, one object per entry like
+/// `{"ts":"2022-08-31T11:51:49.123456789","line":"...","block":0,"labels":{...}}`,
+/// block by block.
+pub fn write_chunk_ndjson<W: Write>(d: &Decode, file: &Path, mut w: W) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, blocks) = filter.open(d, file)?;
+    let labels: BTreeMap<_, _> = header.metric.iter().filter(|(k, _)| *k != "__name__").collect();
+    let mut seen_metadata = false;
+    for (i, block) in blocks.enumerate() {
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        seen_metadata |= filter.block(&mut block);
+        for e in block.entries.iter() {
+            let entry = NdjsonEntry {
+                ts: &e.time,
+                line: &e.line,
+                block: i,
+                labels: &labels,
+                structured_metadata: &e.structured_metadata,
+            };
+            serde_json::to_writer(&mut w, &entry)?;
+            writeln!(w)?;
+        }
+        if filter.done() {
+            break;
+        }
+    }
+    filter.note_metadata(seen_metadata);
+    Ok(())
+}
+
+/// Decode the chunk `file` into the entries and labels tables of `db`, as
+/// chunk `id`.
+pub fn write_chunk_sqlite(d: &Decode, file: &Path, id: &str, db: &mut EntryDb) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, blocks) = filter.open(d, file)?;
+    let labels: BTreeMap<_, _> = header.metric.iter().filter(|(k, _)| *k != "__name__").collect();
+    let format = blocks.format;
+    let rows = db.chunk(id, &labels)?;
+    let mut seen_metadata = false;
+    for (i, block) in blocks.enumerate() {
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        seen_metadata |= filter.block(&mut block);
+        for e in block.entries.iter() {
+            let metadata = match format >= 4 {
+                true => {
+                    let pairs: BTreeMap<_, _> = e.structured_metadata.iter().map(|(k, v)| (k, v)).collect();
+                    Some(serde_json::to_string(&pairs)?)
+                }
+                false => None,
+            };
+            rows.insert(i, e.time.timestamp_nanos(), &e.line, metadata.as_deref())?;
+        }
+        if filter.done() {
+            break;
+        }
+    }
+    rows.commit()?;
+    filter.note_metadata(seen_metadata);
+    Ok(())
+}
+
+/// Decode the chunk `file` in the `--format` of `d`. Parquet columns are
+/// written all at once, so the whole chunk is decoded first.
+pub fn write_chunk<W: Write>(d: &Decode, file: &Path, w: W) -> anyhow::Result<()> {
+    if d.meta_only {
+        return write_chunk_json(d, file, None, w);
+    }
+    match d.format {
+        OutputFormat::Parquet => {
+            let filter = EntryFilter::new(d)?;
+            let (header, mut stream) = filter.open(d, file)?;
+            let (mut blocks, mut seen_metadata) = (vec![], false);
+            while !filter.done() {
+                let Some(block) = timing::time("decompress", || stream.next()) else {
+                    break;
+                };
+                let mut block = block.map_err(DecodeError::from)?;
+                seen_metadata |= filter.block(&mut block);
+                blocks.push(block);
+            }
+            filter.note_metadata(seen_metadata);
+            let data = ChunkData {
+                format: stream.format,
+                ty: stream.ty,
+                symbols: stream.symbols,
+                blocks,
+                meta: stream.meta,
+            };
+            write_chunk_parquet(&Chunk { header, data }, w)
+        }
+        OutputFormat::Json => write_chunk_json(d, file, None, w),
+        OutputFormat::Logfmt => write_chunk_logfmt(d, file, w),
+        OutputFormat::Ndjson => write_chunk_ndjson(d, file, w),
+        OutputFormat::Csv => write_chunk_csv(d, file, w, !d.no_header),
+        OutputFormat::Sqlite => Err(anyhow::format_err!("sqlite output goes to a database file, not a stream")),
+    }
+}
+
+/// Decode every chunk under the `input` directory, into a file each under
+/// `--output-dir` or as the lines of one ndjson (or logfmt) output, one
+/// line per chunk, or per entry with --format ndjson. Chunks
+/// failing to decode are reported and skipped, the command fails once all
+/// are done.
+pub fn decode_dir(d: &Decode) -> anyhow::Result<()> {
+    let root = native_path(&d.input);
+    let mut paths = vec![];
+    collect_files(&root, &mut paths)?;
+    // bad time options fail once here rather than for every chunk
+    optional_range(&d.time_range)?;
+    let mut stream: Option<Box<dyn Write>> = match (&d.output_dir, d.noout) {
+        (None, false) if d.format == OutputFormat::Parquet => {
+            return Err(anyhow::format_err!("parquet output of a directory needs --output-dir"));
+        }
+        (None, false) if d.format == OutputFormat::Sqlite => None,
+        (None, false) if d.output() == "-" => Some(Box::new(BufWriter::new(std::io::stdout().lock()))),
+        (None, false) => Some(Box::new(BufWriter::new(File::create(native_path(d.output()))?))),
+        _ => None,
+    };
+    // one csv header row for the rows of every chunk
+    if let Some(w) = stream.as_mut().filter(|_| d.format == OutputFormat::Csv && !d.no_header) {
+        write_csv_header(d, w)?;
+    }
+    // every chunk into the one --output database
+    let mut db = match (&d.output_dir, d.noout) {
+        (None, false) if d.format == OutputFormat::Sqlite => Some(sqlite_output(d)?),
+        _ => None,
+    };
+    let (mut decoded, mut failed) = (0, 0);
+    let mut line = vec![];
+    for path in paths.iter() {
+        let rel = path.strip_prefix(&root).unwrap_or(path);
+        line.clear();
+        // the output of a chunk is only written once the whole chunk
+        // decoded, so that chunks failing half way leave no broken lines
+        let result = match stream.as_mut() {
+            Some(w) => match d.format {
+                OutputFormat::Logfmt => write_chunk_logfmt(d, path, &mut line),
+                OutputFormat::Ndjson => write_chunk_ndjson(d, path, &mut line),
+                OutputFormat::Csv => write_chunk_csv(d, path, &mut line, false),
+                _ => write_chunk_json(d, path, Some(rel.display().to_string()), &mut line).map(|_| line.push(b'\n')),
+            }
+            .and_then(|_| Ok(w.write_all(&line)?)),
+            None => match db.as_mut() {
+                Some(db) => write_chunk_sqlite(d, path, &rel.display().to_string(), db),
+                None => decode_dir_chunk(d, path, rel),
+            },
+        };
+        match result {
+            Ok(()) => decoded += 1,
+            Err(err) => {
+                eprintln!("{} {}", yellow(&display_path(path)), err);
+                failed += 1;
+            }
+        }
+    }
+    if let Some(mut w) = stream {
+        w.flush()?;
+    }
+    eprintln!(
+        "{}",
+        green(&format!("{decoded} of {} chunks decoded, {failed} failed", paths.len()))
+    );
+    if failed > 0 {
+        return Err(anyhow::format_err!("{failed} chunks failed to decode"));
+    }
+    Ok(())
+}
+
+// a chunk of decode_dir into its own file under --output-dir, or nowhere
+fn decode_dir_chunk(d: &Decode, path: &Path, rel: &Path) -> anyhow::Result<()> {
+    let Some(dir) = d.output_dir.as_ref().filter(|_| !d.noout) else {
+        return check_chunk(d, path);
+    };
+    let ext = match d.format {
+        OutputFormat::Json => "json",
+        OutputFormat::Parquet => "parquet",
+        OutputFormat::Logfmt => "logfmt",
+        OutputFormat::Ndjson => "ndjson",
+        OutputFormat::Csv => "csv",
+        OutputFormat::Sqlite => "db",
+    };
+    let mut out = native_path(dir).join(rel).into_os_string();
+    out.push(format!(".{ext}"));
+    let out = PathBuf::from(out);
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if d.format == OutputFormat::Sqlite {
+        return write_chunk_sqlite(d, path, &rel.display().to_string(), &mut EntryDb::open(&out)?);
+    }
+    write_chunk(d, path, BufWriter::new(File::create(&out)?))
+}
+
+/// The --output database of --format sqlite.
+pub fn sqlite_output(d: &Decode) -> anyhow::Result<EntryDb> {
+    if d.output() == "-" {
+        return Err(anyhow::format_err!("sqlite output needs an --output database file"));
+    }
+    EntryDb::open(&native_path(d.output()))
+}
+
+/// Decode `input` (a chunk, or a directory of them) into one file per
+/// stream under `dir`, keeping the entries of the `--start`/`--end` range.
+pub fn split_by_stream(d: &Decode, dir: PathBuf) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let paths = input_paths(&d.input)?;
+    let mut out = StreamFiles::new(dir)?;
+    for path in paths {
+        let (header, blocks) = match open_decoded(d, &path) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("{} {}", yellow(&display_path(&path)), err);
+                continue;
+            }
+        };
+        let labels: BTreeMap<String, String> = header.metric.into_iter().collect();
+        for block in blocks {
+            let mut block = match block {
+                Ok(b) => b,
+                Err(err) => {
+                    eprintln!("{} {}", yellow(&display_path(&path)), DecodeError::from(err));
+                    break;
+                }
+            };
+            filter.block(&mut block);
+            for e in block.entries.iter() {
+                out.write(&labels, e.time.timestamp_nanos(), &e.line)?;
+            }
+        }
+    }
+    out.finish()
+}
+
+/// Print the lines of a chunk matching `pattern`, only decompressing the
+/// blocks overlapping the time range and stopping at `--max-matches`.
+pub fn grep_fast(d: &Decode, pattern: &str) -> anyhow::Result<()> {
+    let re = Regex::new(pattern)?;
+    let range = optional_range(&d.time_range)?;
+    let m = match fetch_input(&d.s3, Path::new(&d.input))? {
+        Some(bs) => timing::time("decompress", || grep_chunk_bytes(&bs, &re, range, &d.metadata, d.max_matches)),
+        None => timing::time("decompress", || {
+            grep_chunk(&native_path(&d.input), &re, range, &d.metadata, d.max_matches)
+        }),
+    }?;
+    println!("{}", green(&m.labels));
+    for e in m.lines.iter() {
+        let date_str = timefmt::format(&e.time, "%Y-%m-%d %H:%M:%S%.3f");
+        let mut line = e.line.clone();
+        if let Some(max) = d.max_line_len {
+            truncate_line(&mut line, max);
+        }
+        println!("{} {} {}", gray(&date_str), blue("|"), highlight(&re, &line));
+    }
+    eprintln!(
+        "{}",
+        gray(&format!(
+            "{} matching lines, {} blocks skipped by time range, {} not searched",
+            m.lines.len(),
+            m.skipped_blocks,
+            m.unsearched_blocks
+        ))
+    );
+    Ok(())
+}
+
+/// Decode the chunk `file` block by block without keeping anything, to
+/// check that it decodes.
+pub fn check_chunk(d: &Decode, file: &Path) -> anyhow::Result<()> {
+    let (_, blocks) = open_decoded(d, file)?;
+    for block in blocks {
+        timing::time("decompress", || block).map_err(DecodeError::from)?;
+    }
+    Ok(())
+}
+
+/// Cut a line to at most `max` bytes on a char boundary, appending "…"
+/// when anything was cut.
+fn truncate_line(line: &mut String, max: usize) {
+    if line.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line.truncate(end);
+    line.push('…');
+}
+
+/// What the entries of a decoded chunk are filtered by: --metadata,
+/// --grep and --contains, then --head or --tail, and then cut to
+/// --max-line-len. Applied to every block as it is decoded, a filter is
+/// made for each chunk.
+pub struct EntryFilter<'a> {
+    metadata: &'a [KeyValue],
+    grep: Option<Regex>,
+    contains: Option<&'a str>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    // entries still to output with --head, or to drop before the last ones
+    // with --tail
+    left: Cell<usize>,
+    max_line_len: Option<usize>,
+}
+
+impl<'a> EntryFilter<'a> {
+    pub fn new(d: &'a Decode) -> anyhow::Result<Self> {
+        Ok(EntryFilter {
+            metadata: &d.metadata,
+            grep: d.grep.as_deref().map(Regex::new).transpose()?,
+            contains: d.contains.as_deref(),
+            head: d.head,
+            tail: d.tail,
+            left: Cell::new(d.head.unwrap_or(0)),
+            max_line_len: d.max_line_len,
+        })
+    }
+
+    /// `open_decoded`, with the blocks before the --tail entries skipped:
+    /// the blocks are decoded from the last one back until they hold
+    /// enough entries, those are decoded again when read.
+    pub fn open(&self, d: &Decode, file: &Path) -> anyhow::Result<(ChunkHead, ChunkStream<Box<dyn ReadSeek>>)> {
+        let (header, mut stream) = open_decoded(d, file)?;
+        if let Some(n) = self.tail {
+            let mut kept = 0;
+            for i in (0..stream.meta.num_blocks).rev() {
+                if kept >= n {
+                    stream.skip_blocks(i + 1);
+                    break;
                 }
+                let block = timing::time("decompress", || stream.block(i)).map_err(DecodeError::from)?;
+                kept += block.entries.iter().filter(|e| self.keep(e)).count();
+            }
+            self.left.set(kept.saturating_sub(n));
+        }
+        Ok((header, stream))
+    }
+
+    /// Whether --head has all its entries, the remaining blocks need no
+    /// decoding.
+    pub fn done(&self) -> bool {
+        self.head.is_some() && self.left.get() == 0
+    }
+
+    fn keep(&self, e: &UnorderedBlockEntry) -> bool {
+        self.contains.is_none_or(|s| e.line.contains(s))
+            && self.grep.as_ref().is_none_or(|re| re.is_match(&e.line))
+            && e.has_metadata(self.metadata)
+    }
+
+    /// Drop the entries of a block not passing the filter, true when some
+    /// entry carries structured metadata at all.
+    pub fn block(&self, block: &mut UnorderedBlock) -> bool {
+        let seen = !self.metadata.is_empty() && block.entries.iter().any(|e| !e.structured_metadata.is_empty());
+        if !self.metadata.is_empty() || self.grep.is_some() || self.contains.is_some() {
+            block.entries.retain(|e| self.keep(e));
+        }
+        if self.head.is_some() {
+            block.entries.truncate(self.left.get());
+            self.left.set(self.left.get() - block.entries.len());
+        }
+        if self.tail.is_some() {
+            let drop = self.left.get().min(block.entries.len());
+            block.entries.drain(..drop);
+            self.left.set(self.left.get() - drop);
+        }
+        if let Some(max) = self.max_line_len {
+            for e in block.entries.iter_mut() {
+                truncate_line(&mut e.line, max);
             }
         }
+        seen
+    }
+
+    /// Drop the entries of a whole chunk not passing the filter.
+    pub fn chunk(&self, chunk: &mut Chunk) {
+        let mut seen = false;
+        for b in chunk.data.blocks.iter_mut() {
+            seen |= self.block(b);
+        }
+        self.note_metadata(seen);
+    }
+
+    // --metadata can't match anything when no entry carries any
+    fn note_metadata(&self, seen: bool) {
+        if !self.metadata.is_empty() && !seen {
+            eprintln!("{}", gray("no entry of this chunk carries structured metadata"));
+        }
     }
 }
 
-pub fn decode_file<P: AsRef<Path>>(file: P) -> anyhow::Result<Chunk> {
-    let bs = std::fs::read(file).unwrap();
-    let mut cursor = Cursor::new(bs);
-    decode_chunk(&mut cursor)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("plain text", ','), "plain text");
+        assert_eq!(csv_field("a,b", ','), "\"a,b\"");
+        assert_eq!(csv_field("a,b", '\t'), "a,b");
+        assert_eq!(csv_field("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines", ';'), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_truncate_line() {
+        let mut s = "short".to_string();
+        truncate_line(&mut s, 5);
+        assert_eq!(s, "short");
+        let mut s = "a longer line".to_string();
+        truncate_line(&mut s, 8);
+        assert_eq!(s, "a longer…");
+        // never cut inside a multibyte char
+        let mut s = "héllo".to_string();
+        truncate_line(&mut s, 2);
+        assert_eq!(s, "h…");
+    }
+
+    fn block(lines: &[&str]) -> UnorderedBlock {
+        let time = NaiveDateTime::default();
+        let entries = lines.iter().map(|l| UnorderedBlockEntry { time, line: l.to_string(), structured_metadata: vec![] });
+        UnorderedBlock { entries: entries.collect() }
+    }
+
+    fn lines(b: &UnorderedBlock) -> Vec<&str> {
+        b.entries.iter().map(|e| e.line.as_str()).collect()
+    }
+
+    #[test]
+    fn test_head_tail() {
+        let d = Decode::try_parse_from(["decode", "-i", "x", "--head", "3", "--contains", "a"]).unwrap();
+        let filter = EntryFilter::new(&d).unwrap();
+        let (mut b1, mut b2) = (block(&["a1", "b", "a2"]), block(&["a3", "a4"]));
+        filter.block(&mut b1);
+        assert!(!filter.done());
+        filter.block(&mut b2);
+        assert!(filter.done());
+        assert_eq!((lines(&b1), lines(&b2)), (vec!["a1", "a2"], vec!["a3"]));
+
+        // EntryFilter::open leaves the entries before the last ones to drop
+        let d = Decode::try_parse_from(["decode", "-i", "x", "--tail", "2"]).unwrap();
+        let filter = EntryFilter::new(&d).unwrap();
+        filter.left.set(3);
+        let (mut b1, mut b2) = (block(&["1", "2"]), block(&["3", "4", "5"]));
+        filter.block(&mut b1);
+        filter.block(&mut b2);
+        assert_eq!((lines(&b1), lines(&b2)), (vec![], vec!["4", "5"]));
+    }
+
+    #[test]
+    fn test_decode_dir_logfmt() -> anyhow::Result<()> {
+        use crate::encode::{encode_chunk, encode_memchunk, make_head, ChunkEncoding, EncodeEntry};
+        use crate::ty::Chunk;
+
+        let dir = std::env::temp_dir().join(format!("lf-test-decode-dir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("chunks"))?;
+        let entries: Vec<_> = (0..100)
+            .map(|i| EncodeEntry { ts: 1_661_946_709_000_000_000 + i * 1_000_000_000, line: format!("line {i}") })
+            .collect();
+        let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+        let head = make_head("fake", &labels, 1_661_946_709_000, 1_661_946_808_000);
+        let mut memchunk = encode_memchunk(&entries, ChunkEncoding::Snappy, 256)?;
+        let good = encode_chunk(&head, &memchunk)?;
+        std::fs::write(dir.join("chunks/a"), &good)?;
+        // the last block fails its crc, after the others decoded
+        let chunk = Chunk::from_reader(&mut Cursor::new(&good))?;
+        assert!(chunk.data.meta.num_blocks > 1);
+        memchunk[chunk.data.meta.block_metas.last().unwrap().offset as usize] ^= 0xff;
+        std::fs::write(dir.join("chunks/b"), encode_chunk(&head, &memchunk)?)?;
+
+        let out = dir.join("out.logfmt");
+        let input = dir.join("chunks");
+        let args = ["decode", "-i", input.to_str().unwrap(), "-o", out.to_str().unwrap(), "--format", "logfmt"];
+        let d = Decode::try_parse_from(args)?;
+        assert!(decode_dir(&d).is_err());
+        let written = std::fs::read_to_string(&out)?;
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(written.lines().count(), 100);
+        assert!(written.ends_with("line=\"line 99\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_logfmt_value() {
+        assert_eq!(logfmt_value("fizzbuzz"), "fizzbuzz");
+        assert_eq!(logfmt_value(""), r#""""#);
+        assert_eq!(logfmt_value("a=b c"), r#""a=b c""#);
+        assert_eq!(logfmt_value("say \"hi\"\n"), r#""say \"hi\"\n""#);
+    }
 }
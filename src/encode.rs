@@ -0,0 +1,439 @@
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use chrono::{DateTime, NaiveDateTime};
+use clap::{Parser, ValueEnum};
+use flate2::{
+    write::{DeflateEncoder, GzEncoder},
+    Compression,
+};
+use integer_encoding::VarInt;
+
+use serde::Deserialize;
+
+use crate::{
+    common::{format_bytes, gray, green, yellow, KeyValue},
+    confirm::confirm,
+    error::DecodeError,
+    platform::{display_path, native_path},
+    ty::{ChunkHead, EncType, CHUNK_MAGIC, LOG_CHUNK_ENCODING},
+};
+
+
+/// Block encodings we are able to produce
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ChunkEncoding {
+    None,
+    Gzip,
+    Snappy,
+    Flate,
+    Zstd,
+}
+
+impl From<ChunkEncoding> for EncType {
+    fn from(e: ChunkEncoding) -> Self {
+        match e {
+            ChunkEncoding::None => EncType::EncNone,
+            ChunkEncoding::Gzip => EncType::EncGZIP,
+            ChunkEncoding::Snappy => EncType::EncSnappy,
+            ChunkEncoding::Flate => EncType::EncFlate,
+            ChunkEncoding::Zstd => EncType::EncZstd,
+        }
+    }
+}
+
+impl TryFrom<&EncType> for ChunkEncoding {
+    type Error = DecodeError;
+
+    fn try_from(e: &EncType) -> Result<Self, Self::Error> {
+        Ok(match e {
+            EncType::EncNone => ChunkEncoding::None,
+            EncType::EncGZIP => ChunkEncoding::Gzip,
+            EncType::EncSnappy => ChunkEncoding::Snappy,
+            EncType::EncFlate => ChunkEncoding::Flate,
+            EncType::EncZstd => ChunkEncoding::Zstd,
+            e => return Err(DecodeError::Unsupported(format!("writing {e:?} encoding"))),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodeEntry {
+    // nanoseconds
+    pub ts: i64,
+    pub line: String,
+}
+
+/// Stream fingerprint as computed by loki (prometheus labels.Hash),
+/// `__name__` is not part of the stream labels.
+pub fn fingerprint(labels: &BTreeMap<String, String>) -> u64 {
+    let mut b = vec![];
+    for (k, v) in labels.iter().filter(|(k, _)| k.as_str() != "__name__") {
+        b.extend_from_slice(k.as_bytes());
+        b.push(0xff);
+        b.extend_from_slice(v.as_bytes());
+        b.push(0xff);
+    }
+    xxhash_rust::xxh64::xxh64(&b, 0)
+}
+
+pub fn compress(data: &[u8], enc: ChunkEncoding) -> anyhow::Result<Vec<u8>> {
+    Ok(match enc {
+        ChunkEncoding::None => data.to_vec(),
+        ChunkEncoding::Gzip => {
+            let mut w = GzEncoder::new(vec![], Compression::default());
+            w.write_all(data)?;
+            w.finish()?
+        }
+        ChunkEncoding::Snappy => {
+            let mut w = snap::write::FrameEncoder::new(vec![]);
+            w.write_all(data)?;
+            w.into_inner()
+                .map_err(|e| anyhow::format_err!("snappy: {e}"))?
+        }
+        ChunkEncoding::Flate => {
+            let mut w = DeflateEncoder::new(vec![], Compression::default());
+            w.write_all(data)?;
+            w.finish()?
+        }
+        ChunkEncoding::Zstd => zstd::encode_all(data, 0)?,
+    })
+}
+
+/// Serialize entries of a block the way loki's head blocks do
+/// (varint ts, uvarint len, line). Entries are sorted by time first.
+fn serialise_block(entries: &mut [EncodeEntry]) -> Vec<u8> {
+    entries.sort_by_key(|e| e.ts);
+    let mut buf = vec![];
+    for e in entries.iter() {
+        buf.extend_from_slice(&e.ts.encode_var_vec());
+        buf.extend_from_slice(&(e.line.len() as u64).encode_var_vec());
+        buf.extend_from_slice(e.line.as_bytes());
+    }
+    buf
+}
+
+/// Build the memchunk bytes (chunk format v3): blocks are cut in the given
+/// entry order whenever `block_size` uncompressed bytes are reached.
+pub fn encode_memchunk(
+    entries: &[EncodeEntry],
+    enc: ChunkEncoding,
+    block_size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    encode_blocks(&mut cut_blocks(entries, block_size), enc)
+}
+
+// entries in blocks of `block_size` uncompressed bytes
+fn cut_blocks(entries: &[EncodeEntry], block_size: usize) -> Vec<Vec<EncodeEntry>> {
+    let mut blocks = vec![];
+    let mut block = vec![];
+    let mut size = 0;
+    for e in entries {
+        size += e.line.len();
+        block.push(e.clone());
+        if size >= block_size {
+            blocks.push(std::mem::take(&mut block));
+            size = 0;
+        }
+    }
+    blocks.push(block);
+    blocks
+}
+
+/// Build the memchunk bytes (chunk format v3) of the given blocks, empty
+/// ones are left out.
+pub fn encode_blocks(blocks: &mut [Vec<EncodeEntry>], enc: ChunkEncoding) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![];
+    out.extend_from_slice(&CHUNK_MAGIC.to_be_bytes());
+    out.push(3);
+    out.push(EncType::from(enc) as u8);
+
+    let mut metas = vec![];
+    let mut num_blocks = 0;
+    for block in blocks.iter_mut().filter(|b| !b.is_empty()) {
+        let raw = serialise_block(block);
+        let compressed = compress(&raw, enc)?;
+        let offset = out.len();
+        out.extend_from_slice(&compressed);
+        out.extend_from_slice(&crc32c::crc32c(&compressed).to_be_bytes());
+
+        metas.extend_from_slice(&(block.len() as u64).encode_var_vec());
+        metas.extend_from_slice(&block[0].ts.encode_var_vec());
+        metas.extend_from_slice(&block[block.len() - 1].ts.encode_var_vec());
+        metas.extend_from_slice(&(offset as u64).encode_var_vec());
+        metas.extend_from_slice(&(raw.len() as u64).encode_var_vec());
+        metas.extend_from_slice(&(compressed.len() as u64).encode_var_vec());
+        num_blocks += 1;
+    }
+
+    let meta_offset = out.len();
+    let mut meta = (num_blocks as u64).encode_var_vec();
+    meta.extend_from_slice(&metas);
+    out.extend_from_slice(&meta);
+    out.extend_from_slice(&crc32c::crc32c(&meta).to_be_bytes());
+    out.extend_from_slice(&(meta_offset as u64).to_be_bytes());
+    Ok(out)
+}
+
+/// Wrap memchunk bytes with the storage envelope: length prefixed snappy
+/// compressed json head, then length prefixed data.
+pub fn encode_chunk(head: &ChunkHead, memchunk: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut w = snap::write::FrameEncoder::new(vec![]);
+    serde_json::to_writer(&mut w, head)?;
+    w.write_all(b"\n")?;
+    let head_bs = w
+        .into_inner()
+        .map_err(|e| anyhow::format_err!("snappy: {e}"))?;
+
+    let mut out = ((head_bs.len() + 4) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(&head_bs);
+    out.extend_from_slice(&(memchunk.len() as u32).to_be_bytes());
+    out.extend_from_slice(memchunk);
+    Ok(out)
+}
+
+/// Head for a log chunk of the given stream, `from`/`through` in milliseconds.
+pub fn make_head(
+    user_id: &str,
+    labels: &BTreeMap<String, String>,
+    from: i64,
+    through: i64,
+) -> ChunkHead {
+    let mut metric: std::collections::HashMap<String, String> =
+        labels.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    metric.insert("__name__".to_string(), "logs".to_string());
+    ChunkHead {
+        fingerprint: fingerprint(labels),
+        user_id: user_id.to_string(),
+        from: from as f64 / 1000.0,
+        through: through as f64 / 1000.0,
+        metric,
+        encoding: LOG_CHUNK_ENCODING,
+    }
+}
+
+/// encode the json of `lf decode`, or ndjson entries, into a chunk file
+#[derive(Parser, Debug)]
+pub struct Encode {
+    /// json of a chunk written by `lf decode`, or ndjson of entries like
+    /// {"ts": <nanoseconds or rfc3339>, "line": "..."}; "-" for stdin
+    #[clap(short, long, default_value = "-")]
+    input: PathBuf,
+
+    /// output chunk file
+    #[clap(short, long)]
+    output: PathBuf,
+
+    /// block encoding, by default the one of the decoded chunk (gzip for
+    /// ndjson entries)
+    #[clap(long, value_enum)]
+    encoding: Option<ChunkEncoding>,
+
+    /// uncompressed size at which a block of ndjson entries is cut, the
+    /// blocks of a decoded chunk are kept as they are
+    #[clap(long, default_value = "262144")]
+    block_size: usize,
+
+    /// stream labels of ndjson entries
+    #[clap(short, long, num_args = 0..)]
+    labels: Vec<KeyValue>,
+
+    /// tenant of ndjson entries
+    #[clap(short, long, default_value = "fake")]
+    tenant: String,
+
+    /// overwrite the output file if it already exists without asking
+    #[clap(short, long, alias = "force")]
+    yes: bool,
+}
+
+// the json of `lf decode`, only what goes back into a chunk
+#[derive(Deserialize)]
+struct DecodedChunk {
+    header: ChunkHead,
+    data: DecodedData,
+}
+
+#[derive(Deserialize)]
+struct DecodedData {
+    ty: EncType,
+    blocks: Vec<DecodedBlock>,
+}
+
+#[derive(Deserialize)]
+struct DecodedBlock {
+    entries: Vec<InputEntry>,
+}
+
+// an entry of a decoded block, or a line of ndjson input
+#[derive(Deserialize)]
+struct InputEntry {
+    #[serde(alias = "time")]
+    ts: InputTime,
+    line: String,
+    #[serde(default)]
+    structured_metadata: Vec<(String, String)>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InputTime {
+    Nanos(i64),
+    Text(String),
+}
+
+impl InputTime {
+    fn nanos(&self) -> anyhow::Result<i64> {
+        match self {
+            InputTime::Nanos(ns) => Ok(*ns),
+            InputTime::Text(s) => DateTime::parse_from_rfc3339(s)
+                .map(|t| t.timestamp_nanos())
+                // lf decode writes utc times without offset
+                .or_else(|_| s.parse::<NaiveDateTime>().map(|t| t.timestamp_nanos()))
+                .map_err(|_| anyhow::format_err!("invalid time {s:?}")),
+        }
+    }
+}
+
+fn encode_entries(entries: &[InputEntry], dropped_metadata: &mut usize) -> anyhow::Result<Vec<EncodeEntry>> {
+    entries
+        .iter()
+        .map(|e| {
+            if !e.structured_metadata.is_empty() {
+                *dropped_metadata += 1;
+            }
+            Ok(EncodeEntry { ts: e.ts.nanos()?, line: e.line.clone() })
+        })
+        .collect()
+}
+
+pub fn encode(e: Encode) -> anyhow::Result<()> {
+    let input = match e.input.as_os_str() == "-" {
+        true => std::io::read_to_string(std::io::stdin())?,
+        false => std::fs::read_to_string(native_path(&e.input))?,
+    };
+    let mut values = serde_json::Deserializer::from_str(&input).into_iter::<serde_json::Value>();
+    let first = values.next().transpose()?.ok_or_else(|| anyhow::format_err!("no chunk or entries in the input"))?;
+
+    let mut dropped_metadata = 0;
+    let (user_id, labels, enc, mut blocks) = if first.get("header").is_some() {
+        if values.next().is_some() {
+            return Err(anyhow::format_err!("more than one decoded chunk in the input"));
+        }
+        let chunk: DecodedChunk = serde_json::from_value(first)?;
+        let labels: BTreeMap<_, _> = chunk.header.metric.into_iter().filter(|(k, _)| k != "__name__").collect();
+        let enc = match e.encoding {
+            Some(enc) => enc,
+            None => ChunkEncoding::try_from(&chunk.data.ty)?,
+        };
+        let blocks = chunk
+            .data
+            .blocks
+            .iter()
+            .map(|b| encode_entries(&b.entries, &mut dropped_metadata))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        (chunk.header.user_id, labels, enc, blocks)
+    } else {
+        let mut entries = vec![serde_json::from_value::<InputEntry>(first)?];
+        for v in values {
+            entries.push(serde_json::from_value(v?)?);
+        }
+        let blocks = cut_blocks(&encode_entries(&entries, &mut dropped_metadata)?, e.block_size);
+        let labels: BTreeMap<String, String> = e.labels.iter().map(|kv| kv.into()).collect();
+        (e.tenant.clone(), labels, e.encoding.unwrap_or(ChunkEncoding::Gzip), blocks)
+    };
+    if dropped_metadata > 0 {
+        eprintln!(
+            "{}",
+            yellow(&format!("structured metadata of {dropped_metadata} entries dropped, chunks are written in format v3"))
+        );
+    }
+
+    let ts = || blocks.iter().flatten().map(|e| e.ts);
+    let (from, through) = match (ts().min(), ts().max()) {
+        (Some(from), Some(through)) => (from / 1_000_000, through / 1_000_000),
+        _ => return Err(anyhow::format_err!("no entries to encode")),
+    };
+    let count = ts().count();
+    let memchunk = encode_blocks(&mut blocks, enc)?;
+    let head = make_head(&user_id, &labels, from, through);
+    let chunk = encode_chunk(&head, &memchunk)?;
+
+    let output = native_path(&e.output);
+    if let Ok(existing) = std::fs::metadata(&output) {
+        confirm(
+            &format!("overwrite {}", display_path(&output)),
+            &[format!(
+                "{} ({}) is replaced by the encoded chunk ({})",
+                display_path(&output),
+                format_bytes(existing.len()),
+                format_bytes(chunk.len() as u64)
+            )],
+            e.yes,
+        )?;
+    }
+    std::fs::write(&output, &chunk)?;
+    println!(
+        "{} {} ({} bytes, {} entries)",
+        green(&display_path(&output)),
+        gray(&format!("{:?}", labels)),
+        chunk.len(),
+        count
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, io::Cursor};
+
+    use binread::BinRead;
+
+    use crate::ty::{decompress_range, Chunk};
+
+    use super::{encode_chunk, encode_memchunk, make_head, ChunkEncoding, EncodeEntry, InputTime};
+
+    #[test]
+    fn test_input_time() {
+        let ns = 1_661_946_709_123_456_789;
+        assert_eq!(InputTime::Nanos(ns).nanos().unwrap(), ns);
+        assert_eq!(InputTime::Text("2022-08-31T11:51:49.123456789Z".into()).nanos().unwrap(), ns);
+        assert_eq!(InputTime::Text("2022-08-31T13:51:49.123456789+02:00".into()).nanos().unwrap(), ns);
+        assert_eq!(InputTime::Text("2022-08-31T11:51:49.123456789".into()).nanos().unwrap(), ns);
+        assert!(InputTime::Text("yesterday".into()).nanos().is_err());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() -> anyhow::Result<()> {
+        let entries: Vec<_> = (0..100)
+            .map(|i| EncodeEntry {
+                ts: 1_661_946_709_000_000_000 + i * 1_000_000_000,
+                line: format!("line {i}"),
+            })
+            .collect();
+        for encoding in [ChunkEncoding::Snappy, ChunkEncoding::Flate, ChunkEncoding::None] {
+            let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+            let memchunk = encode_memchunk(&entries, encoding, 256)?;
+            let head = make_head("fake", &labels, 1_661_946_709_000, 1_661_946_808_000);
+            let bs = encode_chunk(&head, &memchunk)?;
+
+            let chunk = Chunk::read(&mut Cursor::new(bs))?;
+            assert_eq!(chunk.header.user_id, "fake");
+            assert!(chunk.data.meta.num_blocks > 1);
+            let lines: Vec<_> = chunk
+                .data
+                .blocks
+                .iter()
+                .flat_map(|b| b.entries.iter().map(|e| e.line.clone()))
+                .collect();
+            assert_eq!(lines.len(), 100);
+            assert_eq!(lines[42], "line 42");
+
+            // the streaming reader of decode --from/--to
+            let block = &memchunk[chunk.data.meta.block_metas[0].offset as usize..];
+            let block = &block[..chunk.data.meta.block_metas[0].compressed_size];
+            let range = decompress_range(block, &encoding.into(), 3, (0, i64::MAX), None)?;
+            assert_eq!(range.entries[2].line, "line 2");
+        }
+        Ok(())
+    }
+}
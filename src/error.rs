@@ -0,0 +1,204 @@
+// Typed errors for the failure classes a caller may want to tell apart.
+// They travel inside anyhow::Error like any other error, `code` finds the
+// machine readable code of the first typed error in the chain.
+
+use std::fmt;
+
+use reqwest::{blocking::Response, StatusCode};
+
+use crate::timing;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// input ends before `what` could be read
+    Truncated(&'static str),
+    /// the snappy/json chunk head is not readable
+    InvalidHead(String),
+    /// encoding or format this tool can not read
+    Unsupported(String),
+    /// checksum mismatch, damaged block metas or blocks
+    Corrupt(String),
+    /// the input is something else than a log chunk, and what it looks like
+    NotLogChunk(String),
+    /// the head encoding disagrees with the chunk data (--strict)
+    EncodingMismatch(String),
+    /// reading or decompressing failed
+    Io(std::io::Error),
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    /// index key or range value that does not follow the schema
+    InvalidKey(String),
+    /// the index does not reference what was looked for
+    NotFound(String),
+}
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// a non 2xx response
+    Status {
+        what: &'static str,
+        status: StatusCode,
+        body: String,
+    },
+    /// a 2xx response with something else than expected
+    UnexpectedResponse(String),
+    /// the loki behind the endpoint does not serve this api
+    Unsupported {
+        api: &'static str,
+        version: Option<String>,
+    },
+}
+
+impl DecodeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DecodeError::Truncated(_) => "decode.truncated",
+            DecodeError::InvalidHead(_) => "decode.invalid_head",
+            DecodeError::Unsupported(_) => "decode.unsupported",
+            DecodeError::Corrupt(_) => "decode.corrupt",
+            DecodeError::NotLogChunk(_) => "decode.not_log_chunk",
+            DecodeError::EncodingMismatch(_) => "decode.encoding_mismatch",
+            DecodeError::Io(_) => "decode.io",
+        }
+    }
+}
+
+impl From<binread::Error> for DecodeError {
+    fn from(err: binread::Error) -> Self {
+        match err {
+            binread::Error::Custom { err, pos } => match err.downcast::<DecodeError>() {
+                Ok(e) => *e,
+                Err(_) => DecodeError::Corrupt(format!("custom error at {pos}")),
+            },
+            binread::Error::BadMagic { pos, .. } => {
+                DecodeError::Unsupported(format!("unexpected magic or format at {pos}"))
+            }
+            binread::Error::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                DecodeError::Truncated("chunk data")
+            }
+            binread::Error::Io(e) => DecodeError::Io(e),
+            err => DecodeError::Corrupt(err.to_string()),
+        }
+    }
+}
+
+impl IndexError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IndexError::InvalidKey(_) => "index.invalid_key",
+            IndexError::NotFound(_) => "index.not_found",
+        }
+    }
+}
+
+impl ApiError {
+    /// Turn a failed response into an error, `what` names the request
+    /// ("query", "push", ...).
+    pub fn status(what: &'static str, resp: Response) -> ApiError {
+        let status = resp.status();
+        let body = timing::text(resp).unwrap_or_default();
+        ApiError::Status { what, status, body }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::Status { status, .. } => match status.as_u16() {
+                401 | 403 => "api.unauthorized",
+                404 => "api.not_found",
+                429 => "api.rate_limited",
+                400..=499 => "api.bad_request",
+                _ => "api.server_error",
+            },
+            ApiError::UnexpectedResponse(_) => "api.unexpected_response",
+            ApiError::Unsupported { .. } => "api.unsupported",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated(what) => write!(f, "truncated input, missing {what}"),
+            DecodeError::InvalidHead(msg) => write!(f, "chunk head is not readable: {msg}"),
+            DecodeError::Unsupported(msg) => write!(f, "not supported: {msg}"),
+            DecodeError::Corrupt(msg) => write!(f, "corrupt chunk: {msg}"),
+            DecodeError::NotLogChunk(what) => write!(f, "not a loki log chunk, {what}"),
+            DecodeError::EncodingMismatch(msg) => write!(f, "encoding mismatch: {msg}"),
+            DecodeError::Io(e) => write!(f, "decoding failed: {e}"),
+        }
+    }
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexError::InvalidKey(msg) => write!(f, "invalid index entry: {msg}"),
+            IndexError::NotFound(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Status { what, status, body } => write!(f, "{what} failed, {status}: {body}"),
+            ApiError::UnexpectedResponse(msg) => write!(f, "unexpected response: {msg}"),
+            ApiError::Unsupported { api, version: Some(v) } => {
+                write!(f, "the {api} api is not supported by loki {v}")
+            }
+            ApiError::Unsupported { api, version: None } => {
+                write!(f, "the {api} api is not supported by this endpoint")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl std::error::Error for IndexError {}
+impl std::error::Error for ApiError {}
+
+/// The code of the first typed error in the chain of `err`, transport
+/// errors of requests are "api.transport".
+pub fn code(err: &anyhow::Error) -> Option<&'static str> {
+    err.chain().find_map(|e| {
+        if let Some(e) = e.downcast_ref::<DecodeError>() {
+            Some(e.code())
+        } else if let Some(e) = e.downcast_ref::<IndexError>() {
+            Some(e.code())
+        } else if let Some(e) = e.downcast_ref::<ApiError>() {
+            Some(e.code())
+        } else if e.is::<reqwest::Error>() {
+            Some("api.transport")
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let eof = binread::Error::Io(std::io::ErrorKind::UnexpectedEof.into());
+        let err = anyhow::Error::from(DecodeError::from(eof)).context("decoding x.chunk");
+        assert_eq!(code(&err), Some("decode.truncated"));
+        let err = ApiError::Status {
+            what: "push",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+        };
+        assert_eq!(err.code(), "api.rate_limited");
+        assert_eq!(code(&anyhow::format_err!("plain")), None);
+    }
+}
@@ -0,0 +1,205 @@
+use chrono::NaiveDateTime;
+use clap::Parser;
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    common::{
+        format_bytes, gray, green, maybe_print_curl, parse_bytes, red, refine_loki_request, yellow,
+        HttpOpts, TimeRangeOpts,
+    },
+    capability::{self, Api},
+    error::ApiError,
+    query::get_duration,
+    timing,
+};
+
+/// estimate how much data a query would touch (index stats api)
+#[derive(Parser, Debug)]
+pub struct Estimate {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// The LogQL stream selector to estimate
+    #[clap(short, long)]
+    query: String,
+
+    /// Budget of bytes the query may touch, e.g. 10GB
+    #[clap(long, value_parser = parse_bytes)]
+    max_bytes: Option<u64>,
+
+    /// Budget of chunks the query may touch
+    #[clap(long)]
+    max_chunks: Option<u64>,
+
+    /// Budget of entries (lines) the query may touch
+    #[clap(long)]
+    max_entries: Option<u64>,
+
+    /// Budget of streams the query may touch
+    #[clap(long)]
+    max_streams: Option<u64>,
+
+    /// Also show the top N streams by volume (index volume api), 0 to disable
+    #[clap(long, default_value = "0")]
+    top: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct VolumeRequest {
+    query: String,
+    start: i64,
+    end: i64,
+    limit: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexStatsRequest {
+    query: String,
+    start: i64,
+    end: i64,
+}
+
+// loki/pkg/logproto/indexgateway.proto IndexStatsResponse
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub(crate) struct IndexStats {
+    #[serde(default)]
+    pub streams: u64,
+    #[serde(default)]
+    pub chunks: u64,
+    #[serde(default)]
+    pub bytes: u64,
+    #[serde(default)]
+    pub entries: u64,
+}
+
+pub(crate) fn stats_request(
+    client: &Client,
+    http: &HttpOpts,
+    tenant: Option<String>,
+    query: &str,
+    from: NaiveDateTime,
+    through: NaiveDateTime,
+) -> RequestBuilder {
+    let req = client.get(format!("{}/loki/api/v1/index/stats", http.endpoint));
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), tenant);
+    req.query(&IndexStatsRequest {
+        query: query.to_string(),
+        start: from.timestamp_nanos(),
+        end: through.timestamp_nanos(),
+    })
+}
+
+pub(crate) fn volume_request(
+    client: &Client,
+    http: &HttpOpts,
+    tenant: Option<String>,
+    query: &str,
+    from: NaiveDateTime,
+    through: NaiveDateTime,
+    limit: u32,
+) -> RequestBuilder {
+    let req = client.get(format!("{}{}", http.endpoint, Api::Volume.path()));
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), tenant);
+    req.query(&VolumeRequest {
+        query: query.to_string(),
+        start: from.timestamp_nanos(),
+        end: through.timestamp_nanos(),
+        limit,
+    })
+}
+
+/// Streams of a volume response with their bytes, labels rendered as
+/// `name = value, ...`.
+pub(crate) fn volume_streams(obj: &serde_json::Value) -> Vec<(String, u64)> {
+    let mut streams = vec![];
+    for r in obj["data"]["result"].as_array().into_iter().flatten() {
+        let labels = r["metric"]
+            .as_object()
+            .map(|m| {
+                m.iter()
+                    .map(|(k, v)| format!("{} = {}", k, v.as_str().unwrap_or_default()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        let bytes = r["value"][1]
+            .as_str()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or_default();
+        streams.push((labels, bytes));
+    }
+    streams
+}
+
+pub fn estimate(e: Estimate) -> anyhow::Result<()> {
+    debug!("{e:?}");
+    let (from, through) = get_duration(&e.time_range)?;
+    let client = reqwest::blocking::Client::new();
+    let req = stats_request(&client, &e.http, e.http.tenant.clone(), &e.query, from, through);
+    let req = e.http.sign(req)?;
+    if maybe_print_curl(&req, e.http.print_curl, e.http.show_secrets)? {
+        return Ok(());
+    }
+    let resp = timing::send(req)?;
+    if resp.status() != StatusCode::OK {
+        return Err(ApiError::status("index stats", resp).into());
+    }
+    let stats: IndexStats = serde_json::from_str(&timing::text(resp)?)?;
+    debug!("{stats:?}");
+
+    println!("{}", gray(&format!("{} from {} to {}", e.query, from, through)));
+    let checks = [
+        ("streams", stats.streams, e.max_streams),
+        ("chunks", stats.chunks, e.max_chunks),
+        ("entries", stats.entries, e.max_entries),
+        ("bytes", stats.bytes, e.max_bytes),
+    ];
+    let display = |name, v| if name == "bytes" { format_bytes(v) } else { v.to_string() };
+    let mut exceeded = vec![];
+    for (name, value, budget) in checks {
+        match budget {
+            Some(b) if value > b => {
+                println!("{:>8}: {} (budget {})", name, red(&display(name, value)), display(name, b));
+                exceeded.push(name);
+            }
+            Some(b) => println!("{:>8}: {} (budget {})", name, green(&display(name, value)), display(name, b)),
+            None => println!("{:>8}: {}", name, display(name, value)),
+        }
+    }
+    // the volume api is optional, its absence is only reported
+    let volume = e.top > 0
+        && match capability::require(&e.http, Api::Volume) {
+            Ok(()) => true,
+            Err(err) => {
+                println!("{}", yellow(&err.to_string()));
+                false
+            }
+        };
+    if volume {
+        let req = volume_request(&client, &e.http, e.http.tenant.clone(), &e.query, from, through, e.top);
+        let resp = timing::send(e.http.sign(req)?)?;
+        if resp.status() != StatusCode::OK {
+            println!("{}", yellow(&format!("volume api unavailable: {}", resp.status())));
+        } else {
+            let obj: serde_json::Value = serde_json::from_str(&timing::text(resp)?)?;
+            println!("\n{}", gray("top streams by volume:"));
+            for (labels, bytes) in volume_streams(&obj) {
+                println!("{:>12}  {}", format_bytes(bytes), green(&labels));
+            }
+        }
+    }
+
+    if !exceeded.is_empty() {
+        println!("{}", yellow("query exceeds the given budget, consider narrowing it down"));
+        return Err(anyhow::format_err!("budget exceeded: {}", exceeded.join(", ")));
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::Context;
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|p| is_executable(p))
+}
+
+#[cfg(unix)]
+fn is_executable(p: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    p.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(p: &Path) -> bool {
+    p.is_file()
+}
+
+/// Run `lf-<name>` from PATH like git and cargo do for unknown
+/// subcommands. The plugin inherits the environment, so the LF_*
+/// variables (LF_ENDPOINT, LF_TENANT, LF_BASIC_AUTH, ...) reach it as
+/// they are, and LF_BIN points back to this executable.
+pub fn run(args: Vec<String>) -> anyhow::Result<()> {
+    let (name, rest) = args
+        .split_first()
+        .ok_or_else(|| anyhow::format_err!("missing subcommand"))?;
+    let bin = format!("lf-{name}");
+    let path = find_in_path(&bin)
+        .ok_or_else(|| anyhow::format_err!("unknown subcommand '{name}' (no {bin} in PATH)"))?;
+    let mut cmd = Command::new(&path);
+    cmd.args(rest);
+    if let Ok(exe) = env::current_exe() {
+        cmd.env("LF_BIN", exe);
+    }
+    let status = cmd
+        .status()
+        .with_context(|| format!("failed to run {}", path.display()))?;
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    Ok(())
+}
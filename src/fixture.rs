@@ -0,0 +1,174 @@
+// --record DIR saves every response next to the request that produced it,
+// --replay DIR serves them back without any network. Requests are keyed on
+// method and url without the time parameters (start, end, time), so
+// relative ranges like --since 1h replay fine; repeated requests with the
+// same key (polling, paging) are told apart by their order.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use reqwest::blocking::{Request, RequestBuilder, Response};
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+static MODE: Mutex<Option<Mode>> = Mutex::new(None);
+// requests seen per key, to number repeated ones
+static SEEN: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+
+pub fn record(dir: PathBuf) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&dir)?;
+    *MODE.lock().unwrap() = Some(Mode::Record(dir));
+    Ok(())
+}
+
+pub fn replay(dir: PathBuf) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Err(anyhow::format_err!("fixture directory {} not found", dir.display()));
+    }
+    *MODE.lock().unwrap() = Some(Mode::Replay(dir));
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    method: String,
+    url: String,
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    // utf-8 bodies are kept readable, anything else is base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    body_base64: Option<String>,
+}
+
+// method and url with the time parameters dropped
+fn request_key(req: &Request) -> String {
+    let mut url = req.url().clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| !matches!(k.as_ref(), "start" | "end" | "time"))
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.set_query(None);
+    if !pairs.is_empty() {
+        url.query_pairs_mut().extend_pairs(pairs);
+    }
+    format!("{} {}", req.method(), url)
+}
+
+// file of the n-th request with this key
+fn fixture_path(dir: &Path, key: &str, n: usize) -> PathBuf {
+    let hash = digest(&SHA256, key.as_bytes());
+    let name: String = hash.as_ref()[..8].iter().map(|b| format!("{b:02x}")).collect();
+    dir.join(format!("{name}-{n}.json"))
+}
+
+fn next_index(key: &str) -> usize {
+    let mut seen = SEEN.lock().unwrap();
+    let n = seen.get_or_insert_with(HashMap::new).entry(key.to_string()).or_default();
+    *n += 1;
+    *n
+}
+
+fn to_response(f: Fixture) -> anyhow::Result<Response> {
+    let body = match (f.body, f.body_base64) {
+        (Some(s), _) => s.into_bytes(),
+        (None, Some(b)) => base64::decode(b)?,
+        (None, None) => vec![],
+    };
+    let mut builder = http::Response::builder().status(f.status);
+    for (name, value) in f.headers.iter() {
+        builder = builder.header(name, value);
+    }
+    Ok(Response::from(builder.body(body)?))
+}
+
+/// Send the request, or in replay mode answer it from the fixtures. In
+/// record mode the response is written out before being handed back.
+pub(crate) fn send<F>(req: RequestBuilder, send: F) -> anyhow::Result<Response>
+where
+    F: FnOnce(RequestBuilder) -> reqwest::Result<Response>,
+{
+    // the lock is not held while sending, requests may run in parallel
+    let mode = match MODE.lock().unwrap().as_ref() {
+        None => None,
+        Some(Mode::Record(dir)) => Some((dir.clone(), true)),
+        Some(Mode::Replay(dir)) => Some((dir.clone(), false)),
+    };
+    let Some((dir, recording)) = mode else {
+        return Ok(send(req)?);
+    };
+    let built = req
+        .try_clone()
+        .ok_or_else(|| anyhow::format_err!("request can not be recorded"))?
+        .build()?;
+    let key = request_key(&built);
+    let n = next_index(&key);
+    if !recording {
+        // a key replayed more often than recorded gets its last answer
+        let path = (1..=n)
+            .rev()
+            .map(|i| fixture_path(&dir, &key, i))
+            .find(|p| p.exists())
+            .ok_or_else(|| anyhow::format_err!("no recorded response for {key}"))?;
+        debug!("replay {key} from {}", path.display());
+        return to_response(serde_json::from_slice(&std::fs::read(path)?)?);
+    }
+    let resp = send(req)?;
+    let status = resp.status().as_u16();
+    let headers: Vec<(String, String)> = resp
+        .headers()
+        .iter()
+        .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+        .collect();
+    let bytes = resp.bytes()?.to_vec();
+    let (body, body_base64) = match String::from_utf8(bytes) {
+        Ok(s) => (Some(s), None),
+        Err(e) => (None, Some(base64::encode(e.as_bytes()))),
+    };
+    let fixture = Fixture {
+        method: built.method().to_string(),
+        url: built.url().to_string(),
+        status,
+        headers,
+        body,
+        body_base64,
+    };
+    let path = fixture_path(&dir, &key, n);
+    debug!("record {key} to {}", path.display());
+    std::fs::write(path, serde_json::to_vec_pretty(&fixture)?)?;
+    to_response(fixture)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_request_key() {
+        let client = reqwest::blocking::Client::new();
+        let req = |start: &str| {
+            client
+                .get("http://loki:3100/loki/api/v1/query_range")
+                .query(&[("query", "{app=\"x\"}"), ("start", start), ("limit", "10")])
+                .build()
+                .unwrap()
+        };
+        assert_eq!(request_key(&req("1")), request_key(&req("2")));
+        assert_eq!(
+            request_key(&req("1")),
+            "GET http://loki:3100/loki/api/v1/query_range?query=%7Bapp%3D%22x%22%7D&limit=10"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::Parser;
+
+use crate::{
+    common::{gray, green, KeyValue, TimeRangeOpts},
+    encode::{encode_chunk, encode_memchunk, make_head, ChunkEncoding, EncodeEntry},
+    platform::chunk_file_name,
+    query::get_duration,
+};
+
+/// generate synthetic chunk files for testing
+#[derive(Parser, Debug)]
+pub struct GenChunk {
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// number of streams, one chunk is written per stream
+    #[clap(long, default_value = "3")]
+    streams: usize,
+
+    /// number of entries per stream
+    #[clap(long, default_value = "1000")]
+    entries: usize,
+
+    /// block encoding
+    #[clap(long, default_value = "gzip", value_enum)]
+    encoding: ChunkEncoding,
+
+    /// length of every generated line
+    #[clap(long, default_value = "100")]
+    line_size: usize,
+
+    /// uncompressed size at which a block is cut
+    #[clap(long, default_value = "262144")]
+    block_size: usize,
+
+    /// fraction of entries (0~1) arriving out of order, which makes
+    /// block time ranges overlap like unordered writes do
+    #[clap(long, default_value = "0")]
+    unordered_ratio: f64,
+
+    /// tenant name
+    #[clap(short, long, default_value = "fake")]
+    tenant: String,
+
+    /// additional labels for every stream
+    #[clap(short, long, num_args = 0..)]
+    labels: Vec<KeyValue>,
+
+    /// seed of the generator, same seed gives the same chunks
+    #[clap(long, default_value = "0")]
+    seed: u64,
+
+    /// output directory
+    #[clap(short, long)]
+    out: PathBuf,
+}
+
+// xorshift64*, good enough for fixtures and keeps output reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed ^ 0x9E3779B97F4A7C15 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    fn ratio(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+const WORDS: &[&str] = &[
+    "level=info", "level=warn", "level=error", "msg=request", "msg=done", "status=200",
+    "status=500", "path=/api/v1", "user=alice", "user=bob", "latency=12ms", "retry=true",
+];
+
+fn gen_line(rng: &mut Rng, seq: usize, size: usize) -> String {
+    let mut line = format!("seq={seq}");
+    while line.len() < size {
+        line.push(' ');
+        line.push_str(WORDS[(rng.next() % WORDS.len() as u64) as usize]);
+    }
+    line.truncate(size.max(1));
+    line
+}
+
+pub fn gen_chunk(g: GenChunk) -> anyhow::Result<()> {
+    let (start, end) = get_duration(&g.time_range)?;
+    let (start, end) = (start.timestamp_nanos(), end.timestamp_nanos());
+    let step = (end - start) / g.entries.max(1) as i64;
+    let dir = g.out.join(&g.tenant);
+    std::fs::create_dir_all(&dir)?;
+
+    let mut rng = Rng::new(g.seed);
+    for s in 0..g.streams {
+        let mut labels: BTreeMap<String, String> =
+            g.labels.iter().map(|kv| kv.into()).collect();
+        labels.insert("app".to_string(), "lf-gen".to_string());
+        labels.insert("stream".to_string(), s.to_string());
+
+        let mut entries: Vec<EncodeEntry> = (0..g.entries)
+            .map(|i| EncodeEntry {
+                ts: start + i as i64 * step,
+                line: gen_line(&mut rng, i, g.line_size),
+            })
+            .collect();
+        // swap some entries with a later neighbour so they arrive late
+        for i in 0..entries.len() {
+            if rng.ratio() < g.unordered_ratio {
+                let j = i + (rng.next() % 64) as usize;
+                if j < entries.len() {
+                    entries.swap(i, j);
+                }
+            }
+        }
+
+        let from = entries.iter().map(|e| e.ts).min().unwrap_or(start) / 1_000_000;
+        let through = entries.iter().map(|e| e.ts).max().unwrap_or(end) / 1_000_000;
+        let memchunk = encode_memchunk(&entries, g.encoding, g.block_size)?;
+        let head = make_head(&g.tenant, &labels, from, through);
+        let chunk = encode_chunk(&head, &memchunk)?;
+        let checksum = crc32c::crc32c(&chunk);
+        let name = format!("{:x}:{:x}:{:x}:{:x}", head.fingerprint, from, through, checksum);
+        let path = dir.join(chunk_file_name(&name));
+        std::fs::write(&path, &chunk)?;
+        println!(
+            "{} {} ({} bytes)",
+            green(&path.display().to_string()),
+            gray(&format!("{:?}", labels)),
+            chunk.len()
+        );
+    }
+    Ok(())
+}
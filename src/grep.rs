@@ -0,0 +1,280 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use binread::BinReaderExt;
+use clap::Parser;
+use num_traits::FromPrimitive;
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    common::{blue, gray, green, red, yellow, KeyValue, TimeRangeOpts},
+    error::DecodeError,
+    proxy::format_labels,
+    query::optional_duration,
+    repair::parse_raw_meta,
+    ty::{
+        blocks_in_range, decompress, decompress_range, identify, parse_symbols, ChunkHead, EncType, UnorderedBlockEntry,
+        CHUNK_MAGIC,
+    },
+};
+
+/// search lines of every chunk under a directory
+#[derive(Parser, Debug)]
+pub struct Grep {
+    /// regular expression to look for
+    pattern: String,
+
+    /// chunk files or directories (searched recursively)
+    #[clap(required = true, num_args = 1..)]
+    paths: Vec<PathBuf>,
+
+    /// Only look at lines in this time range, blocks outside of it are not
+    /// decoded. Without any time option every block is searched.
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// case insensitive match
+    #[clap(short, long)]
+    ignore_case: bool,
+
+    /// only print the number of matching lines per chunk
+    #[clap(short, long)]
+    count: bool,
+
+    /// number of decoding threads, defaults to the number of cpus
+    #[clap(short, long)]
+    jobs: Option<usize>,
+}
+
+pub(crate) struct Matched {
+    pub labels: String,
+    pub metric: BTreeMap<String, String>,
+    pub lines: Vec<UnorderedBlockEntry>,
+    pub skipped_blocks: usize,
+    // blocks left undecoded because `max` matches were found
+    pub unsearched_blocks: usize,
+}
+
+/// The time range in nanoseconds, None when no time option is given.
+pub(crate) fn optional_range(t: &TimeRangeOpts) -> anyhow::Result<Option<(i64, i64)>> {
+    let range = optional_duration(t)?;
+    Ok(range.map(|(from, to)| (from.timestamp_nanos(), to.timestamp_nanos())))
+}
+
+pub(crate) fn collect_files(path: &Path, out: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if path.is_file() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for p in entries {
+        collect_files(&p, out)?;
+    }
+    Ok(())
+}
+
+fn be_u32(bs: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bs.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Decode only the blocks of a chunk overlapping `range` (nanoseconds)
+/// and keep the lines in it matching `re` and carrying the structured
+/// `metadata` pairs, stopping after `max` lines.
+pub(crate) fn grep_chunk(
+    path: &Path,
+    re: &Regex,
+    range: Option<(i64, i64)>,
+    metadata: &[KeyValue],
+    max: Option<usize>,
+) -> anyhow::Result<Matched> {
+    grep_chunk_bytes(&std::fs::read(path)?, re, range, metadata, max)
+}
+
+/// `grep_chunk` over the bytes of a chunk object.
+pub(crate) fn grep_chunk_bytes(
+    bs: &[u8],
+    re: &Regex,
+    range: Option<(i64, i64)>,
+    metadata: &[KeyValue],
+    max: Option<usize>,
+) -> anyhow::Result<Matched> {
+    let head_len = be_u32(bs, 0).ok_or(DecodeError::Truncated("head length"))? as usize;
+    if head_len < 4 || head_len + 4 > bs.len() {
+        let e = identify(bs).unwrap_or_else(|| DecodeError::InvalidHead(format!("invalid head length: {head_len}")));
+        return Err(e.into());
+    }
+    let head: ChunkHead = Cursor::new(&bs[4..head_len])
+        .read_le()
+        .map_err(|e| identify(bs).unwrap_or_else(|| DecodeError::from(e)))?;
+    let labels: BTreeMap<_, _> = head.metric.iter().filter(|(k, _)| *k != "__name__").collect();
+
+    let chunk = &bs[head_len + 4..];
+    if chunk.len() < 14 {
+        return Err(DecodeError::Truncated("chunk data").into());
+    }
+    if be_u32(chunk, 0) != Some(CHUNK_MAGIC) {
+        head.check_log_chunk()?;
+        return Err(DecodeError::InvalidHead("chunk data doesn't start with the memchunk magic".to_string()).into());
+    }
+    let format = chunk[4];
+    let enc = if format > 1 { chunk[5] } else { EncType::EncGZIP as u8 };
+    let enc = EncType::from_u8(enc).ok_or_else(|| DecodeError::Unsupported(format!("encoding {enc}")))?;
+    if let Some(msg) = head.encoding_mismatch(format, &enc) {
+        eprintln!("{} {msg}", red("warning:"));
+    }
+    // the metas offset is the last 8 bytes of the trailer for every format
+    let meta_offset = u64::from_be_bytes(chunk[chunk.len() - 8..].try_into()?) as usize;
+    let meta = parse_raw_meta(chunk, meta_offset, format)
+        .ok_or_else(|| DecodeError::Corrupt("unable to parse block metas".to_string()))?;
+    // v4 trailer starts with the length and offset of the symbols
+    let symbols = match format {
+        4 => {
+            let trailer = chunk.len().checked_sub(32).ok_or(DecodeError::Truncated("chunk trailer"))?;
+            let len = u64::from_be_bytes(chunk[trailer..trailer + 8].try_into()?) as usize;
+            let offset = u64::from_be_bytes(chunk[trailer + 8..trailer + 16].try_into()?) as usize;
+            let section = chunk
+                .get(offset..offset.saturating_add(len))
+                .ok_or_else(|| DecodeError::Corrupt(format!("symbols at {offset} out of bounds")))?;
+            Some(parse_symbols(section, &enc).map_err(DecodeError::from)?)
+        }
+        _ => None,
+    };
+
+    let mut lines = vec![];
+    let mut skipped_blocks = 0;
+    let mut unsearched_blocks = 0;
+    let wanted = match range {
+        Some((from, to)) => {
+            let spans: Vec<_> = meta.blocks.iter().map(|b| (b.mint, b.maxt)).collect();
+            blocks_in_range(&spans, from, to)
+        }
+        None => 0..meta.blocks.len(),
+    };
+    skipped_blocks += meta.blocks.len() - wanted.len();
+    for i in wanted.clone() {
+        let b = &meta.blocks[i];
+        if max.map(|m| lines.len() >= m).unwrap_or(false) {
+            unsearched_blocks = wanted.end - i;
+            break;
+        }
+        if let Some((from, to)) = range {
+            if b.maxt < from || b.mint > to {
+                skipped_blocks += 1;
+                continue;
+            }
+        }
+        let data = chunk
+            .get(b.offset..b.offset + b.len)
+            .ok_or_else(|| DecodeError::Corrupt(format!("block at {} out of bounds", b.offset)))?;
+        let block = match range {
+            Some(range) => decompress_range(data, &enc, b.entries, range, symbols.as_deref()),
+            None => decompress(data, &enc, b.entries, symbols.as_deref()),
+        }
+        .map_err(DecodeError::from)?;
+        lines.extend(
+            block
+                .entries
+                .into_iter()
+                .filter(|e| e.has_metadata(metadata) && re.is_match(&e.line)),
+        );
+    }
+    if let Some(m) = max {
+        lines.truncate(m);
+    }
+    Ok(Matched {
+        metric: labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        labels: format_labels(labels),
+        lines,
+        skipped_blocks,
+        unsearched_blocks,
+    })
+}
+
+pub(crate) fn highlight(re: &Regex, line: &str) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for m in re.find_iter(line) {
+        out.push_str(&line[last..m.start()]);
+        out.push_str(&red(m.as_str()));
+        last = m.end();
+    }
+    out.push_str(&line[last..]);
+    out
+}
+
+pub fn grep(g: Grep) -> anyhow::Result<()> {
+    let re = RegexBuilder::new(&g.pattern)
+        .case_insensitive(g.ignore_case)
+        .build()?;
+    let range = optional_range(&g.time_range)?;
+
+    let mut files = vec![];
+    for p in g.paths.iter() {
+        collect_files(p, &mut files)?;
+    }
+    let jobs = g
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+        .max(1);
+
+    let next = AtomicUsize::new(0);
+    let total = AtomicUsize::new(0);
+    let skipped = AtomicUsize::new(0);
+    // hold the lock while printing so output of a chunk stays together
+    let stdout = Mutex::new(());
+    thread::scope(|s| {
+        for _ in 0..jobs.min(files.len().max(1)) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = files.get(i) else {
+                    return;
+                };
+                let path_str = path.display().to_string();
+                let matched = match grep_chunk(path, &re, range, &[], None) {
+                    Ok(m) => m,
+                    Err(err) => {
+                        let _lock = stdout.lock().unwrap();
+                        eprintln!("{} {}", yellow(&path_str), red(&err.to_string()));
+                        continue;
+                    }
+                };
+                total.fetch_add(matched.lines.len(), Ordering::Relaxed);
+                skipped.fetch_add(matched.skipped_blocks, Ordering::Relaxed);
+                if matched.lines.is_empty() {
+                    continue;
+                }
+                let _lock = stdout.lock().unwrap();
+                if g.count {
+                    println!("{} {} {}", blue(&path_str), green(&matched.labels), matched.lines.len());
+                    continue;
+                }
+                println!("{} {}", blue(&path_str), green(&matched.labels));
+                for e in matched.lines {
+                    let date_str = e.time.format("%Y-%m-%d %H:%M:%S").to_string();
+                    println!("  {} {} {}", gray(&date_str), blue("|"), highlight(&re, &e.line));
+                }
+            });
+        }
+    });
+    eprintln!(
+        "{}",
+        gray(&format!(
+            "{} matching lines in {} chunks, {} blocks skipped by time range",
+            total.into_inner(),
+            files.len(),
+            skipped.into_inner()
+        ))
+    );
+    Ok(())
+}
@@ -0,0 +1,142 @@
+// Standalone html pages for investigation reports: inline css, no scripts
+// and no external assets, so a single file can be attached to a ticket
+// and opened anywhere.
+
+use std::path::Path;
+
+const STYLE: &str = "body{font-family:-apple-system,Segoe UI,Helvetica,Arial,sans-serif;margin:2em;color:#222}
+h1{font-size:1.5em}h2{font-size:1.2em;margin-top:2em;border-bottom:1px solid #ddd}
+.note{color:#777}table{border-collapse:collapse;margin:.5em 0;font-size:.9em}
+th,td{border:1px solid #ddd;padding:.2em .6em;text-align:left;vertical-align:top}
+th{background:#f4f4f4}td.num{text-align:right;font-variant-numeric:tabular-nums}
+td.line{font-family:monospace;white-space:pre-wrap;word-break:break-all}
+.hist{display:flex;align-items:flex-end;height:120px;gap:1px;border-bottom:1px solid #999}
+.hist div{flex:1;background:#4a7bd0;min-height:1px}.axis{display:flex;justify-content:space-between;color:#777;font-size:.8em}
+.bar{background:#4a7bd0;height:.9em}.problems li{color:#b00020}";
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// A table cell, numbers are right aligned and log lines keep their
+/// whitespace.
+pub(crate) enum Cell {
+    Text(String),
+    Num(String),
+    Line(String),
+}
+
+pub(crate) struct Page {
+    title: String,
+    body: String,
+}
+
+impl Page {
+    pub fn new(title: &str) -> Page {
+        Page {
+            title: title.to_string(),
+            body: String::new(),
+        }
+    }
+
+    pub fn heading(&mut self, text: &str) {
+        self.body.push_str(&format!("<h2>{}</h2>\n", escape(text)));
+    }
+
+    pub fn note(&mut self, text: &str) {
+        self.body.push_str(&format!("<p class=\"note\">{}</p>\n", escape(text)));
+    }
+
+    pub fn table(&mut self, header: &[&str], rows: Vec<Vec<Cell>>) {
+        self.body.push_str("<table>\n<tr>");
+        for h in header {
+            self.body.push_str(&format!("<th>{}</th>", escape(h)));
+        }
+        self.body.push_str("</tr>\n");
+        for row in rows {
+            self.body.push_str("<tr>");
+            for cell in row {
+                let (class, text) = match cell {
+                    Cell::Text(t) => ("", t),
+                    Cell::Num(t) => (" class=\"num\"", t),
+                    Cell::Line(t) => (" class=\"line\"", t),
+                };
+                self.body.push_str(&format!("<td{class}>{}</td>", escape(&text)));
+            }
+            self.body.push_str("</tr>\n");
+        }
+        self.body.push_str("</table>\n");
+    }
+
+    /// Vertical bars over consecutive buckets (time), labelled by the first
+    /// and last bucket.
+    pub fn histogram(&mut self, buckets: &[(String, f64)]) {
+        let (Some(first), Some(last)) = (buckets.first(), buckets.last()) else {
+            return;
+        };
+        let max = buckets.iter().map(|b| b.1).fold(0.0, f64::max);
+        self.body.push_str("<div class=\"hist\">");
+        for (label, v) in buckets {
+            let pct = if max > 0.0 { v / max * 100.0 } else { 0.0 };
+            self.body.push_str(&format!(
+                "<div style=\"height:{pct:.1}%\" title=\"{}: {v}\"></div>",
+                escape(label)
+            ));
+        }
+        self.body.push_str(&format!(
+            "</div>\n<div class=\"axis\"><span>{}</span><span>max {max}</span><span>{}</span></div>\n",
+            escape(&first.0),
+            escape(&last.0)
+        ));
+    }
+
+    /// Horizontal bars, one row per (label, value, shown value).
+    pub fn bars(&mut self, rows: &[(String, f64, String)]) {
+        let max = rows.iter().map(|r| r.1).fold(0.0, f64::max);
+        self.body.push_str("<table>\n");
+        for (label, v, shown) in rows {
+            let pct = if max > 0.0 { v / max * 100.0 } else { 0.0 };
+            self.body.push_str(&format!(
+                "<tr><td>{}</td><td class=\"num\">{}</td><td style=\"width:300px\"><div class=\"bar\" style=\"width:{pct:.1}%\"></div></td></tr>\n",
+                escape(label),
+                escape(shown)
+            ));
+        }
+        self.body.push_str("</table>\n");
+    }
+
+    pub fn problems(&mut self, items: &[String]) {
+        if items.is_empty() {
+            return;
+        }
+        self.body.push_str("<ul class=\"problems\">\n");
+        for item in items {
+            self.body.push_str(&format!("<li>{}</li>\n", escape(item)));
+        }
+        self.body.push_str("</ul>\n");
+    }
+
+    pub fn render(&self) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title><style>{STYLE}</style></head>\n<body>\n<h1>{title}</h1>\n{}<p class=\"note\">generated by lf at {}</p>\n</body></html>\n",
+            self.body,
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+            title = escape(&self.title),
+        )
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.render()).map_err(|e| anyhow::format_err!("write {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,62 @@
+//! Loki chunk and index tooling, the library behind the `lf` command.
+//!
+//! Chunks can be parsed without the command line:
+//!
+//! ```no_run
+//! let chunk = lf::decode::decode_file("chunk.bin")?;
+//! println!("{} blocks", chunk.data.meta.num_blocks);
+//! for entry in chunk.data.blocks.iter().flat_map(|b| b.entries.iter()) {
+//!     println!("{} {}", entry.time, entry.line);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! [`ty::Chunk::from_bytes`] parses a chunk already in memory, and
+//! [`ty::ChunkStream`] decodes the blocks of a large chunk one at a time.
+//! The other modules are the subcommands of `lf`.
+
+pub mod ty;
+pub mod common;
+pub mod decode;
+pub mod push;
+pub mod query;
+pub mod bolt;
+pub mod s3;
+pub mod sigv4;
+pub mod store;
+pub mod estimate;
+pub mod repair;
+pub mod encode;
+pub mod gen;
+pub mod tail;
+pub mod proto;
+pub mod proxy;
+pub mod wal;
+pub mod grep;
+pub mod copy;
+pub mod external;
+pub mod timing;
+pub mod timefmt;
+pub mod xcheck;
+pub mod parquet;
+pub mod split;
+pub mod sqlite;
+pub mod clickhouse;
+pub mod error;
+pub mod topk;
+pub mod bench;
+pub mod capability;
+pub mod fixture;
+pub mod report;
+pub mod relabel;
+pub mod anonymize;
+pub mod canary;
+pub mod html;
+pub mod cache;
+pub mod trace;
+pub mod confirm;
+pub mod platform;
+pub mod stats;
+pub mod chunk;
+pub mod validate;
+pub mod tsdb;
@@ -1,15 +1,12 @@
-use std::{io::{stdout, Write, BufWriter}, fs::File};
+use std::{io::{stdout, Write, BufWriter}, fs::File, path::PathBuf, time::Instant};
 
-use clap::Parser;
-use decode::decode_file;
-use tracing::{debug, info};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use tracing::debug;
 
-mod ty;
-mod common;
-mod decode;
-mod push;
-mod query;
-mod bolt;
+use lf::{
+    anonymize, bench, bolt, cache, canary, chunk, common, copy, decode, encode, error, estimate, external, fixture,
+    gen, grep, platform, proxy, push, query, repair, report, stats, store, tail, timefmt, timing, trace, tsdb, wal, xcheck,
+};
 
 #[derive(Parser, Debug)]
 #[clap(version = "1.0")]
@@ -17,6 +14,23 @@ mod bolt;
 struct Opts {
     #[clap(subcommand)]
     command: SubCommand,
+
+    /// Print a per phase timing breakdown (to stderr) when done
+    #[clap(long, global = true)]
+    timing: bool,
+
+    /// Save every http response to this directory
+    #[clap(long, global = true, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Answer http requests from responses saved with --record, offline
+    #[clap(long, global = true)]
+    replay: Option<PathBuf>,
+
+    /// Send traces of the command, its phases and http requests to this
+    /// OTLP/HTTP endpoint, like http://localhost:4318
+    #[clap(long, global = true, env = "LF_OTEL_ENDPOINT")]
+    otel_endpoint: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -31,7 +45,7 @@ enum SubCommand {
 
     /// query loki
     #[clap(aliases=&["q"])]
-    Query(query::Query),
+    Query(Box<query::Query>),
 
     /// query loki for miscellaneous stats
     #[clap(aliases=&["qm"])]
@@ -40,37 +54,158 @@ enum SubCommand {
 
     #[clap(aliases=&["b", "boltdb"])]
     Bolt(bolt::Bolt),
+
+    /// tsdb index inspection
+    Tsdb(tsdb::Tsdb),
+
+    /// object store inspection
+    #[clap(aliases=&["s"])]
+    Store(store::Store),
+
+    /// estimate the cost of a query before running it
+    #[clap(aliases=&["e", "est"])]
+    Estimate(estimate::Estimate),
+
+    /// encode decoded json or ndjson entries into a chunk file
+    #[clap(aliases=&["enc"])]
+    Encode(encode::Encode),
+
+    /// repair checksums and trailer of a chunk file
+    Repair(repair::Repair),
+
+    /// generate synthetic chunks
+    #[clap(aliases=&["gen"])]
+    GenChunk(gen::GenChunk),
+
+    /// follow new lines of one or more queries
+    #[clap(aliases=&["t", "follow"])]
+    Tail(tail::Tail),
+
+    /// relay pushes to loki and print them
+    Proxy(proxy::Proxy),
+
+    /// inspect and replay ingester WALs
+    Wal(wal::Wal),
+
+    /// replay chunk files into a loki, or scrub them
+    Chunk(chunk::ChunkCmd),
+
+    /// search chunk files for lines matching a regex
+    #[clap(aliases=&["g"])]
+    Grep(grep::Grep),
+
+    /// copy query results from one loki to another
+    #[clap(aliases=&["cp"])]
+    Copy(copy::Copy),
+
+    /// cross check a chunk against the index
+    Xcheck(xcheck::Xcheck),
+
+    /// benchmark chunk encodings
+    Bench(bench::Bench),
+
+    /// usage reports for capacity reviews
+    Report(report::Report),
+
+    /// hash or mask sensitive data in a chunk, e.g. for bug reports
+    Anonymize(anonymize::Anonymize),
+
+    /// push entries and query them back, measuring ingest latency
+    Canary(canary::Canary),
+
+    /// local chunk and index cache maintenance
+    Cache(cache::Cache),
+
+    /// anything else runs lf-<name> from PATH
+    #[clap(external_subcommand)]
+    External(Vec<String>),
 }
 
 fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    let opts = Opts::parse();
-    match opts.command {
-        SubCommand::Decode(d) => {
+    let matches = Opts::command().get_matches();
+    let opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if opts.timing {
+        timing::enable();
+    }
+    if let Some(dir) = opts.record {
+        fixture::record(dir)?;
+    }
+    if let Some(dir) = opts.replay {
+        fixture::replay(dir)?;
+    }
+    if let Some(endpoint) = opts.otel_endpoint.as_ref() {
+        trace::enable(endpoint);
+    }
+    let started = Instant::now();
+    let result = {
+        let mut root = trace::root(format!("lf {}", matches.subcommand_name().unwrap_or_default()));
+        let result = run(opts.command);
+        if let Err(err) = result.as_ref() {
+            root.error(err);
+        }
+        result
+    };
+    timing::report(started.elapsed());
+    trace::export();
+    if let Err(err) = result.as_ref() {
+        if let Some(code) = error::code(err) {
+            eprintln!("{}", common::gray(&format!("error code: {code}")));
+        }
+    }
+    result
+}
+
+fn run(command: SubCommand) -> anyhow::Result<()> {
+    match command {
+        SubCommand::Decode(mut d) => {
             debug!("{d:?}");
-            let chunk = decode_file(d.input)?;
-            if d.noout {
+            timefmt::set(d.tz.clone(), d.ts_format.clone())?;
+            if let Some(root) = d.store_root.as_ref() {
+                let path = store::fs_chunk_path(&platform::native_path(root), &d.input)?;
+                d.input = path.to_string_lossy().into_owned();
+            }
+            if let Some(dir) = d.split_by_stream.clone() {
+                return decode::split_by_stream(&d, dir);
+            }
+            if let Some(pattern) = d.grep_fast.as_ref() {
+                return decode::grep_fast(&d, pattern);
+            }
+            if d.stats || d.order_report {
+                for path in decode::input_paths(&d.input)? {
+                    if d.stats {
+                        stats::chunk_stats(&d, &path)?;
+                    }
+                    if d.order_report {
+                        stats::order_report(&d, &path)?;
+                    }
+                }
                 return Ok(());
             }
-            info!("{:?}", chunk.data.meta);
-            let writer: Box<dyn Write> = if d.output == "-" {
+            if platform::native_path(&d.input).is_dir() {
+                return decode::decode_dir(&d);
+            }
+            let input = platform::native_path(&d.input);
+            if d.noout {
+                return decode::check_chunk(&d, &input);
+            }
+            if d.format == decode::OutputFormat::Sqlite {
+                let mut db = decode::sqlite_output(&d)?;
+                return timing::time("serialize", || decode::write_chunk_sqlite(&d, &input, &d.input, &mut db));
+            }
+            let writer: Box<dyn Write> = if d.output() == "-" {
                 Box::new(BufWriter::new(stdout().lock()))
             } else {
-                Box::new(BufWriter::new(File::create(d.output)?))
+                Box::new(BufWriter::new(File::create(d.output())?))
             };
-            if d.compact {
-                serde_json::to_writer(writer, &chunk)?;
-            } else {
-                serde_json::to_writer_pretty(writer, &chunk)?;
-            }
-            Ok(())
+            timing::time("serialize", || decode::write_chunk(&d, &input, writer))
         },
         SubCommand::Push(p) => {
             push::push(p)?;
             Ok(())
         },
         SubCommand::Query(q) => {
-            query::query(q)?;
+            query::query(*q)?;
             Ok(())
         },
         SubCommand::QueryMisc(q) => {
@@ -81,5 +216,81 @@ fn main() -> anyhow::Result<()> {
             bolt::inspect(b)?;
             Ok(())
         },
+        SubCommand::Tsdb(t) => {
+            tsdb::inspect(t)?;
+            Ok(())
+        },
+        SubCommand::Store(s) => {
+            store::store(s)?;
+            Ok(())
+        },
+        SubCommand::Estimate(e) => {
+            estimate::estimate(e)?;
+            Ok(())
+        },
+        SubCommand::Encode(e) => {
+            encode::encode(e)?;
+            Ok(())
+        },
+        SubCommand::Repair(r) => {
+            repair::repair(r)?;
+            Ok(())
+        },
+        SubCommand::GenChunk(g) => {
+            gen::gen_chunk(g)?;
+            Ok(())
+        },
+        SubCommand::Tail(t) => {
+            tail::tail(t)?;
+            Ok(())
+        },
+        SubCommand::Proxy(p) => {
+            proxy::proxy(p)?;
+            Ok(())
+        },
+        SubCommand::Wal(w) => {
+            wal::wal(w)?;
+            Ok(())
+        },
+        SubCommand::Chunk(c) => {
+            chunk::chunk(c)?;
+            Ok(())
+        },
+        SubCommand::Grep(g) => {
+            grep::grep(g)?;
+            Ok(())
+        },
+        SubCommand::Copy(c) => {
+            copy::copy(c)?;
+            Ok(())
+        },
+        SubCommand::Xcheck(x) => {
+            xcheck::xcheck(x)?;
+            Ok(())
+        },
+        SubCommand::Bench(b) => {
+            bench::run(b)?;
+            Ok(())
+        },
+        SubCommand::Report(r) => {
+            report::run(r)?;
+            Ok(())
+        },
+        SubCommand::Anonymize(a) => {
+            anonymize::anonymize(a)?;
+            Ok(())
+        },
+        SubCommand::Canary(c) => {
+            canary::canary(c)?;
+            Ok(())
+        },
+        SubCommand::Cache(c) => {
+            cache::cache(c)?;
+            Ok(())
+        },
+        SubCommand::External(args) => {
+            external::run(args)?;
+            Ok(())
+        },
     }
 }
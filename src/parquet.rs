@@ -0,0 +1,457 @@
+// A minimal parquet writer: a single row group, required columns only,
+// PLAIN encoded snappy pages. Enough for tools like DataFusion, Polars
+// or duckdb to read, without pulling the arrow stack in.
+//
+// parquet-format/src/main/thrift/parquet.thrift
+
+use std::io::Write;
+
+const MAGIC: &[u8] = b"PAR1";
+
+// thrift compact protocol types
+const T_I32: u8 = 5;
+const T_I64: u8 = 6;
+const T_BINARY: u8 = 8;
+const T_LIST: u8 = 9;
+const T_STRUCT: u8 = 12;
+
+// parquet Type
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+
+const REQUIRED: i32 = 0;
+const CONVERTED_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_SNAPPY: i32 = 1;
+
+pub enum Column {
+    /// nanosecond timestamps
+    Timestamp(String, Vec<i64>),
+    Int32(String, Vec<i32>),
+    Str(String, Vec<String>),
+}
+
+impl Column {
+    fn name(&self) -> &str {
+        match self {
+            Column::Timestamp(n, _) | Column::Int32(n, _) | Column::Str(n, _) => n,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Column::Timestamp(_, v) => v.len(),
+            Column::Int32(_, v) => v.len(),
+            Column::Str(_, v) => v.len(),
+        }
+    }
+
+    fn physical_type(&self) -> i32 {
+        match self {
+            Column::Timestamp(..) => INT64,
+            Column::Int32(..) => INT32,
+            Column::Str(..) => BYTE_ARRAY,
+        }
+    }
+
+    fn plain(&self) -> Vec<u8> {
+        let mut out = vec![];
+        match self {
+            Column::Timestamp(_, v) => v.iter().for_each(|x| out.extend_from_slice(&x.to_le_bytes())),
+            Column::Int32(_, v) => v.iter().for_each(|x| out.extend_from_slice(&x.to_le_bytes())),
+            Column::Str(_, v) => v.iter().for_each(|s| {
+                out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                out.extend_from_slice(s.as_bytes());
+            }),
+        }
+        out
+    }
+}
+
+#[derive(Default)]
+struct Compact {
+    buf: Vec<u8>,
+    last_field: Vec<i16>,
+}
+
+impl Compact {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn zigzag(&mut self, v: i64) {
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, ty: u8) {
+        let last = self.last_field.last_mut().unwrap();
+        let delta = id - *last;
+        *last = id;
+        if delta > 0 && delta <= 15 {
+            self.buf.push(((delta as u8) << 4) | ty);
+        } else {
+            self.buf.push(ty);
+            self.zigzag(id as i64);
+        }
+    }
+
+    fn begin(&mut self) {
+        self.last_field.push(0);
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last_field.pop();
+    }
+
+    fn i32(&mut self, id: i16, v: i32) {
+        self.field(id, T_I32);
+        self.zigzag(v as i64);
+    }
+
+    fn i64(&mut self, id: i16, v: i64) {
+        self.field(id, T_I64);
+        self.zigzag(v);
+    }
+
+    fn binary(&mut self, id: i16, v: &[u8]) {
+        self.field(id, T_BINARY);
+        self.raw_binary(v);
+    }
+
+    fn raw_binary(&mut self, v: &[u8]) {
+        self.varint(v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+
+    fn list(&mut self, id: i16, elem: u8, size: usize) {
+        self.field(id, T_LIST);
+        if size < 15 {
+            self.buf.push(((size as u8) << 4) | elem);
+        } else {
+            self.buf.push(0xf0 | elem);
+            self.varint(size as u64);
+        }
+    }
+
+    fn struct_field(&mut self, id: i16) {
+        self.field(id, T_STRUCT);
+        self.begin();
+    }
+}
+
+struct ChunkMeta {
+    ty: i32,
+    name: String,
+    num_values: i64,
+    uncompressed: i64,
+    compressed: i64,
+    offset: i64,
+}
+
+fn page_header(num_values: usize, uncompressed: usize, compressed: usize) -> Vec<u8> {
+    let mut c = Compact::default();
+    c.begin();
+    // DATA_PAGE
+    c.i32(1, 0);
+    c.i32(2, uncompressed as i32);
+    c.i32(3, compressed as i32);
+    c.struct_field(5);
+    c.i32(1, num_values as i32);
+    c.i32(2, ENCODING_PLAIN);
+    c.i32(3, ENCODING_RLE);
+    c.i32(4, ENCODING_RLE);
+    c.end();
+    c.end();
+    c.buf
+}
+
+fn file_metadata(columns: &[Column], chunks: &[ChunkMeta], rows: usize) -> Vec<u8> {
+    let mut c = Compact::default();
+    c.begin();
+    c.i32(1, 1);
+
+    c.list(2, T_STRUCT, columns.len() + 1);
+    c.begin();
+    c.binary(4, b"schema");
+    c.i32(5, columns.len() as i32);
+    c.end();
+    for col in columns {
+        c.begin();
+        c.i32(1, col.physical_type());
+        c.i32(3, REQUIRED);
+        c.binary(4, col.name().as_bytes());
+        match col {
+            Column::Str(..) => {
+                c.i32(6, CONVERTED_UTF8);
+                // LogicalType.STRING
+                c.struct_field(10);
+                c.struct_field(1);
+                c.end();
+                c.end();
+            }
+            Column::Timestamp(..) => {
+                // LogicalType.TIMESTAMP { isAdjustedToUTC: true, unit: NANOS }
+                c.struct_field(10);
+                c.struct_field(8);
+                c.field(1, 1);
+                c.struct_field(2);
+                c.struct_field(3);
+                c.end();
+                c.end();
+                c.end();
+                c.end();
+            }
+            Column::Int32(..) => {}
+        }
+        c.end();
+    }
+
+    c.i64(3, rows as i64);
+
+    c.list(4, T_STRUCT, 1);
+    c.begin();
+    c.list(1, T_STRUCT, chunks.len());
+    for m in chunks {
+        c.begin();
+        c.i64(2, m.offset);
+        c.struct_field(3);
+        c.i32(1, m.ty);
+        c.list(2, T_I32, 1);
+        c.zigzag(ENCODING_PLAIN as i64);
+        c.list(3, T_BINARY, 1);
+        c.raw_binary(m.name.as_bytes());
+        c.i32(4, CODEC_SNAPPY);
+        c.i64(5, m.num_values);
+        c.i64(6, m.uncompressed);
+        c.i64(7, m.compressed);
+        c.i64(9, m.offset);
+        c.end();
+        c.end();
+    }
+    c.i64(2, chunks.iter().map(|m| m.uncompressed).sum());
+    c.i64(3, rows as i64);
+    c.end();
+
+    c.binary(6, b"lf");
+    c.end();
+    c.buf
+}
+
+/// Write `columns` (all of the same length) as a parquet file.
+pub fn write_parquet<W: Write>(mut w: W, columns: &[Column]) -> anyhow::Result<()> {
+    let rows = columns.first().map(|c| c.len()).unwrap_or_default();
+    if columns.iter().any(|c| c.len() != rows) {
+        return Err(anyhow::format_err!("columns differ in length"));
+    }
+    w.write_all(MAGIC)?;
+    let mut offset = MAGIC.len();
+    let mut chunks = vec![];
+    for col in columns {
+        let data = col.plain();
+        let compressed = snap::raw::Encoder::new().compress_vec(&data)?;
+        let header = page_header(col.len(), data.len(), compressed.len());
+        w.write_all(&header)?;
+        w.write_all(&compressed)?;
+        chunks.push(ChunkMeta {
+            ty: col.physical_type(),
+            name: col.name().to_string(),
+            num_values: col.len() as i64,
+            uncompressed: (header.len() + data.len()) as i64,
+            compressed: (header.len() + compressed.len()) as i64,
+            offset: offset as i64,
+        });
+        offset += header.len() + compressed.len();
+    }
+    let meta = file_metadata(columns, &chunks, rows);
+    w.write_all(&meta)?;
+    w.write_all(&(meta.len() as u32).to_le_bytes())?;
+    w.write_all(MAGIC)?;
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Bool(bool),
+        Int(i64),
+        Binary(Vec<u8>),
+        List(Vec<Value>),
+        Struct(BTreeMap<i16, Value>),
+    }
+
+    impl Value {
+        fn int(&self) -> i64 {
+            match self {
+                Value::Int(v) => *v,
+                v => panic!("not an int: {v:?}"),
+            }
+        }
+
+        fn list(&self) -> &[Value] {
+            match self {
+                Value::List(v) => v,
+                v => panic!("not a list: {v:?}"),
+            }
+        }
+
+        fn get(&self, id: i16) -> &Value {
+            match self {
+                Value::Struct(m) => &m[&id],
+                v => panic!("not a struct: {v:?}"),
+            }
+        }
+
+        fn str(&self, id: i16) -> &str {
+            match self.get(id) {
+                Value::Binary(b) => std::str::from_utf8(b).unwrap(),
+                v => panic!("not a binary: {v:?}"),
+            }
+        }
+    }
+
+    // just enough of a thrift compact reader for what the writer emits
+    struct Reader<'a> {
+        buf: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.pos += 1;
+            self.buf[self.pos - 1]
+        }
+
+        fn varint(&mut self) -> u64 {
+            let (mut v, mut shift) = (0, 0);
+            loop {
+                let b = self.byte();
+                v |= ((b & 0x7f) as u64) << shift;
+                if b < 0x80 {
+                    return v;
+                }
+                shift += 7;
+            }
+        }
+
+        fn zigzag(&mut self) -> i64 {
+            let v = self.varint();
+            ((v >> 1) as i64) ^ -((v & 1) as i64)
+        }
+
+        fn value(&mut self, ty: u8) -> Value {
+            match ty {
+                T_I32 | T_I64 => Value::Int(self.zigzag()),
+                T_BINARY => {
+                    let len = self.varint() as usize;
+                    self.pos += len;
+                    Value::Binary(self.buf[self.pos - len..self.pos].to_vec())
+                }
+                T_LIST => {
+                    let b = self.byte();
+                    let size = match b >> 4 {
+                        15 => self.varint() as usize,
+                        n => n as usize,
+                    };
+                    Value::List((0..size).map(|_| self.value(b & 0x0f)).collect())
+                }
+                T_STRUCT => self.structure(),
+                ty => panic!("unexpected type {ty}"),
+            }
+        }
+
+        fn structure(&mut self) -> Value {
+            let (mut fields, mut last) = (BTreeMap::new(), 0);
+            loop {
+                let b = self.byte();
+                if b == 0 {
+                    return Value::Struct(fields);
+                }
+                let id = match b >> 4 {
+                    0 => self.zigzag() as i16,
+                    delta => last + delta as i16,
+                };
+                last = id;
+                let v = match b & 0x0f {
+                    1 => Value::Bool(true),
+                    2 => Value::Bool(false),
+                    ty => self.value(ty),
+                };
+                fields.insert(id, v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_parquet() {
+        let columns = vec![
+            Column::Timestamp("timestamp".to_string(), vec![1, 2, 1_700_000_000_000_000_000]),
+            Column::Str("line".to_string(), vec!["a".to_string(), "".to_string(), "ccc".to_string()]),
+            Column::Int32("block".to_string(), vec![0, 0, -1]),
+        ];
+        let mut out = vec![];
+        write_parquet(&mut out, &columns).unwrap();
+
+        assert_eq!(&out[..4], MAGIC);
+        assert_eq!(&out[out.len() - 4..], MAGIC);
+        let meta_len = u32::from_le_bytes(out[out.len() - 8..out.len() - 4].try_into().unwrap()) as usize;
+        let meta_start = out.len() - 8 - meta_len;
+        let mut r = Reader { buf: &out[..out.len() - 8], pos: meta_start };
+        let meta = r.structure();
+        assert_eq!(r.pos, out.len() - 8);
+
+        assert_eq!(meta.get(1).int(), 1);
+        assert_eq!(meta.get(3).int(), 3);
+        let schema = meta.get(2).list();
+        assert_eq!(schema.len(), 4);
+        assert_eq!(schema[0].get(5).int(), 3);
+        let names: Vec<_> = schema[1..].iter().map(|s| s.str(4)).collect();
+        assert_eq!(names, ["timestamp", "line", "block"]);
+        let types: Vec<_> = schema[1..].iter().map(|s| s.get(1).int()).collect();
+        assert_eq!(types, [INT64 as i64, BYTE_ARRAY as i64, INT32 as i64]);
+
+        let groups = meta.get(4).list();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].get(3).int(), 3);
+        let chunks = groups[0].get(1).list();
+        assert_eq!(chunks.len(), 3);
+        let mut end = MAGIC.len();
+        for (chunk, col) in chunks.iter().zip(&columns) {
+            let cm = chunk.get(3);
+            assert_eq!(cm.get(1).int(), col.physical_type() as i64);
+            assert_eq!(cm.get(3).list()[0], Value::Binary(col.name().as_bytes().to_vec()));
+            assert_eq!(cm.get(5).int(), 3);
+            let offset = cm.get(9).int() as usize;
+            assert_eq!(offset, end);
+
+            // the page header, then the snappy compressed PLAIN values
+            let mut r = Reader { buf: &out, pos: offset };
+            let header = r.structure();
+            assert_eq!(header.get(5).get(1).int(), 3);
+            let compressed = header.get(3).int() as usize;
+            assert_eq!(cm.get(7).int() as usize, r.pos - offset + compressed);
+            let data = snap::raw::Decoder::new().decompress_vec(&out[r.pos..r.pos + compressed]).unwrap();
+            assert_eq!(data.len(), header.get(2).int() as usize);
+            assert_eq!(data, col.plain());
+            end = r.pos + compressed;
+        }
+        assert_eq!(end, meta_start);
+    }
+
+    #[test]
+    fn test_write_parquet_uneven() {
+        let columns = vec![Column::Int32("a".to_string(), vec![1]), Column::Int32("b".to_string(), vec![])];
+        assert!(write_parquet(vec![], &columns).is_err());
+    }
+}
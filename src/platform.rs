@@ -0,0 +1,79 @@
+// What differs between the systems lf runs on. Windows consoles show the
+// ANSI colors of the output as escape garbage until asked to interpret
+// them, and windows paths can't hold the ':' of chunk keys: on ntfs a
+// name like `fp:from:through:checksum` silently writes an alternate data
+// stream of a file named `fp` instead.
+
+use std::path::{Path, PathBuf};
+
+/// Turn on ANSI escape handling of the console, true when stdout shows
+/// colors. Nothing to do outside of windows.
+#[cfg(windows)]
+pub fn enable_ansi() -> bool {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_ERROR_HANDLE,
+        STD_OUTPUT_HANDLE,
+    };
+
+    let enable = |std| unsafe {
+        let handle = GetStdHandle(std);
+        let mut mode = 0;
+        GetConsoleMode(handle, &mut mode) != 0
+            && SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    };
+    // stderr may well be redirected while stdout is not
+    enable(STD_ERROR_HANDLE);
+    enable(STD_OUTPUT_HANDLE)
+}
+
+#[cfg(not(windows))]
+pub fn enable_ansi() -> bool {
+    true
+}
+
+/// File name of a chunk, named after (the end of) its key. ':' becomes
+/// %3A on windows, like the object store clients escape it.
+pub fn chunk_file_name(key: &str) -> String {
+    match cfg!(windows) {
+        true => key.replace(':', "%3A"),
+        false => key.to_string(),
+    }
+}
+
+/// `path` with the `\\?\` prefix of long windows paths removed, for
+/// display: `\\?\C:\logs` is shown as `C:\logs`, `\\?\UNC\srv\share` as
+/// `\\srv\share`. Other paths are left alone.
+pub fn display_path(path: &Path) -> String {
+    let s = path.display().to_string();
+    if let Some(unc) = s.strip_prefix(r"\\?\UNC\") {
+        return format!(r"\\{unc}");
+    }
+    match s.strip_prefix(r"\\?\") {
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => rest.to_string(),
+        _ => s,
+    }
+}
+
+/// `path` as given on the command line, with '/' turned into '\' on
+/// windows when it has the `\\?\` prefix, which disables the usual
+/// translation of '/' by windows itself.
+pub fn native_path(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    match path.to_str() {
+        Some(s) if cfg!(windows) && s.starts_with(r"\\?\") => PathBuf::from(s.replace('/', r"\")),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_path() {
+        assert_eq!(display_path(Path::new(r"\\?\C:\logs\chunk")), r"C:\logs\chunk");
+        assert_eq!(display_path(Path::new(r"\\?\UNC\srv\share\x")), r"\\srv\share\x");
+        assert_eq!(display_path(Path::new(r"\\?\Volume{1}\x")), r"\\?\Volume{1}\x");
+        assert_eq!(display_path(Path::new("/tmp/chunk")), "/tmp/chunk");
+    }
+}
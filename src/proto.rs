@@ -0,0 +1,114 @@
+// Hand written prost messages, mirroring the upstream proto definitions
+// closely enough to encode and decode the wire format.
+
+// loki/pkg/push/push.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PushRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub streams: Vec<StreamAdapter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamAdapter {
+    #[prost(string, tag = "1")]
+    pub labels: String,
+    #[prost(message, repeated, tag = "2")]
+    pub entries: Vec<EntryAdapter>,
+    #[prost(uint64, tag = "3")]
+    pub hash: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EntryAdapter {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<Timestamp>,
+    #[prost(string, tag = "2")]
+    pub line: String,
+    #[prost(message, repeated, tag = "3")]
+    pub structured_metadata: Vec<LabelPairAdapter>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LabelPairAdapter {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+// google/protobuf/timestamp.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(int32, tag = "2")]
+    pub nanos: i32,
+}
+
+// prometheus/prompb/remote.proto, types.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    // milliseconds
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+// loki/pkg/ingester/checkpoint.proto
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CheckpointSeries {
+    #[prost(string, tag = "1")]
+    pub user_id: String,
+    #[prost(uint64, tag = "2")]
+    pub fingerprint: u64,
+    #[prost(message, repeated, tag = "3")]
+    pub labels: Vec<LabelPairAdapter>,
+    #[prost(message, repeated, tag = "4")]
+    pub chunks: Vec<CheckpointChunk>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CheckpointChunk {
+    #[prost(message, optional, tag = "1")]
+    pub from: Option<Timestamp>,
+    #[prost(message, optional, tag = "2")]
+    pub to: Option<Timestamp>,
+    // unset until the chunk is flushed to the store
+    #[prost(message, optional, tag = "3")]
+    pub flushed_at: Option<Timestamp>,
+    #[prost(message, optional, tag = "4")]
+    pub last_updated: Option<Timestamp>,
+    #[prost(bool, tag = "5")]
+    pub closed: bool,
+    #[prost(bool, tag = "6")]
+    pub synced: bool,
+    // the cut blocks, as a memchunk
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: Vec<u8>,
+    // the head block, in its checkpoint form
+    #[prost(bytes = "vec", tag = "8")]
+    pub head: Vec<u8>,
+}
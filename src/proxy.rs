@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use chrono::NaiveDateTime;
+use clap::Parser;
+use flate2::read::GzDecoder;
+use prost::Message;
+use serde::Deserialize;
+use tiny_http::{Header, Response, Server};
+use tracing::debug;
+
+use crate::{
+    common::{blue, gray, green, red, yellow},
+    proto,
+};
+
+/// relay loki pushes to an upstream while printing what is being sent
+#[derive(Parser, Debug)]
+pub struct Proxy {
+    /// address to listen on, like :3101 or 127.0.0.1:3101
+    #[clap(short, long, default_value = ":3101")]
+    listen: String,
+
+    /// upstream loki to forward requests to
+    #[clap(short, long, default_value = "http://127.0.0.1:3100")]
+    upstream: String,
+
+    /// only print one of every N entries (every push is still forwarded)
+    #[clap(long, default_value = "1")]
+    sample: u64,
+
+    /// do not print entries, only a summary line per push
+    #[clap(long)]
+    quiet: bool,
+
+    /// number of worker threads
+    #[clap(long, default_value = "4")]
+    workers: usize,
+}
+
+/// A decoded push, format independent
+#[derive(Debug, Default)]
+pub struct PushedStream {
+    pub labels: String,
+    pub entries: Vec<PushedEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct PushedEntry {
+    // nanoseconds
+    pub ts: i64,
+    pub line: String,
+    pub metadata: Vec<(String, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonPush {
+    streams: Vec<JsonStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonStream {
+    stream: BTreeMap<String, String>,
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+pub fn format_labels<'a, I: IntoIterator<Item = (&'a String, &'a String)>>(labels: I) -> String {
+    let inner = labels
+        .into_iter()
+        .map(|(k, v)| format!("{}={:?}", k, v))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{{{inner}}}")
+}
+
+/// Decode the body of a push request, either the snappy compressed
+/// protobuf or the json flavour (optionally gzipped).
+pub fn decode_push(
+    content_type: &str,
+    content_encoding: &str,
+    body: &[u8],
+) -> anyhow::Result<Vec<PushedStream>> {
+    let body = if content_encoding.eq_ignore_ascii_case("gzip") {
+        let mut s = vec![];
+        GzDecoder::new(body).read_to_end(&mut s)?;
+        s
+    } else {
+        body.to_vec()
+    };
+    if content_type.starts_with("application/json") {
+        let push: JsonPush = serde_json::from_slice(&body)?;
+        return Ok(push
+            .streams
+            .into_iter()
+            .map(|s| PushedStream {
+                labels: format_labels(s.stream.iter()),
+                entries: s
+                    .values
+                    .into_iter()
+                    .map(|v| {
+                        let ts = v.first().and_then(|t| t.as_str()).and_then(|t| t.parse().ok());
+                        let line = v.get(1).and_then(|l| l.as_str()).unwrap_or_default();
+                        let metadata = v
+                            .get(2)
+                            .and_then(|m| m.as_object())
+                            .map(|m| {
+                                m.iter()
+                                    .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        PushedEntry {
+                            ts: ts.unwrap_or_default(),
+                            line: line.to_string(),
+                            metadata,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect());
+    }
+    let raw = snap::raw::Decoder::new().decompress_vec(&body)?;
+    let push = proto::PushRequest::decode(raw.as_slice())?;
+    Ok(push
+        .streams
+        .into_iter()
+        .map(|s| PushedStream {
+            labels: s.labels,
+            entries: s
+                .entries
+                .into_iter()
+                .map(|e| {
+                    let ts = e
+                        .timestamp
+                        .map(|t| t.seconds * 1_000_000_000 + t.nanos as i64)
+                        .unwrap_or_default();
+                    let metadata = e
+                        .structured_metadata
+                        .into_iter()
+                        .map(|l| (l.name, l.value))
+                        .collect();
+                    PushedEntry {
+                        ts,
+                        line: e.line,
+                        metadata,
+                    }
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+fn header<'a>(req: &'a tiny_http::Request, name: &str) -> &'a str {
+    req.headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+        .unwrap_or_default()
+}
+
+fn print_push(tenant: &str, streams: &[PushedStream], p: &Proxy, counter: &AtomicU64) {
+    let total: usize = streams.iter().map(|s| s.entries.len()).sum();
+    println!(
+        "{} tenant={} streams={} entries={}",
+        yellow("push"),
+        if tenant.is_empty() { "-" } else { tenant },
+        streams.len(),
+        total
+    );
+    if p.quiet {
+        return;
+    }
+    for s in streams {
+        println!("  {}", green(&s.labels));
+        for PushedEntry { ts, line, metadata } in s.entries.iter() {
+            if !counter.fetch_add(1, Ordering::Relaxed).is_multiple_of(p.sample.max(1)) {
+                continue;
+            }
+            let date = NaiveDateTime::from_timestamp_opt(
+                ts.div_euclid(1_000_000_000),
+                ts.rem_euclid(1_000_000_000) as u32,
+            )
+            .unwrap_or_default();
+            let date_str = date.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+            if metadata.is_empty() {
+                println!("  {} {} {line}", gray(&date_str), blue("|"));
+            } else {
+                println!("  {} {} {line} {}", gray(&date_str), blue("|"), gray(&format!("{metadata:?}")));
+            }
+        }
+    }
+}
+
+fn handle(
+    mut req: tiny_http::Request,
+    p: &Proxy,
+    client: &reqwest::blocking::Client,
+    counter: &AtomicU64,
+) -> anyhow::Result<()> {
+    let mut body = vec![];
+    req.as_reader().read_to_end(&mut body)?;
+    let url = req.url().to_string();
+    debug!("{} {} ({} bytes)", req.method(), url, body.len());
+
+    if url.starts_with("/loki/api/v1/push") || url.starts_with("/api/prom/push") {
+        match decode_push(header(&req, "Content-Type"), header(&req, "Content-Encoding"), &body) {
+            Ok(streams) => print_push(header(&req, "X-Scope-OrgID"), &streams, p, counter),
+            Err(err) => println!("{} {}", red("undecodable push:"), err),
+        }
+    }
+
+    let method = reqwest::Method::from_bytes(req.method().as_str().as_bytes())?;
+    let mut upstream = client.request(method, format!("{}{}", p.upstream.trim_end_matches('/'), url));
+    for h in req.headers() {
+        let name = h.field.as_str().as_str();
+        if name.eq_ignore_ascii_case("host") || name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
+        upstream = upstream.header(name, h.value.as_str());
+    }
+    let response = match upstream.body(body).send() {
+        Ok(resp) => {
+            let status = resp.status().as_u16();
+            let content_type = resp
+                .headers()
+                .get("Content-Type")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+            let bytes = resp.bytes()?.to_vec();
+            if status >= 300 {
+                println!("{} {}", red(&format!("upstream returns {status}:")), String::from_utf8_lossy(&bytes));
+            }
+            let mut r = Response::from_data(bytes).with_status_code(status);
+            if let Some(ct) = content_type {
+                if let Ok(h) = Header::from_bytes("Content-Type", ct) {
+                    r = r.with_header(h);
+                }
+            }
+            r
+        }
+        Err(err) => {
+            println!("{} {}", red("upstream error:"), err);
+            Response::from_string(err.to_string()).with_status_code(502)
+        }
+    };
+    req.respond(response)?;
+    Ok(())
+}
+
+pub fn proxy(p: Proxy) -> anyhow::Result<()> {
+    let addr = if p.listen.starts_with(':') {
+        format!("0.0.0.0{}", p.listen)
+    } else {
+        p.listen.clone()
+    };
+    let server = Arc::new(Server::http(&addr).map_err(|e| anyhow::format_err!("listen {addr}: {e}"))?);
+    println!("{}", gray(&format!("listening on {addr}, forwarding to {}", p.upstream)));
+    let p = Arc::new(p);
+    let counter = Arc::new(AtomicU64::new(0));
+    let workers: Vec<_> = (0..p.workers.max(1))
+        .map(|_| {
+            let server = server.clone();
+            let p = p.clone();
+            let counter = counter.clone();
+            thread::spawn(move || {
+                let client = reqwest::blocking::Client::new();
+                for req in server.incoming_requests() {
+                    if let Err(err) = handle(req, &p, &client, &counter) {
+                        println!("{} {}", red("error:"), err);
+                    }
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        let _ = w.join();
+    }
+    Ok(())
+}
@@ -1,11 +1,24 @@
-use std::{collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{stdin, BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use clap::Parser;
-use serde::Serialize;
+use reqwest::blocking::Client;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
 
-use crate::common::{KeyValue, refine_loki_request, HttpOpts};
+use crate::{
+    common::{gray, green, yellow, KeyValue, for_each_tenant, refine_loki_request, HttpOpts, maybe_print_curl, parse_duration},
+    error::ApiError,
+    relabel::{self, Relabel},
+    timing,
+};
 
-/// push a single message (for now, meant for debugging only)
+/// push a single message, or the lines of files
 #[derive(Parser, Debug)]
 pub struct Push {
     #[command(flatten)]
@@ -16,45 +29,302 @@ pub struct Push {
     labels: Vec<KeyValue>,
 
     /// Content to push
-    #[clap(short, long)]
-    content: String,
+    #[clap(short, long, required_unless_present = "file")]
+    content: Option<String>,
 
+    /// Push every line of these files ("-" for stdin) instead. Lines like
+    /// "<ts in nanoseconds>\t<line>" keep their timestamp, others get the
+    /// current time.
+    #[clap(short, long, conflicts_with = "content")]
+    file: Vec<PathBuf>,
+
+    /// Lines per push request (with --file)
+    #[clap(long, default_value = "1000")]
+    batch_size: usize,
+
+    /// File recording the acknowledged batches, pushing again with the
+    /// same state skips the lines already pushed
+    #[clap(long, requires = "file")]
+    state: Option<PathBuf>,
+
+    /// Skip (ts, line) pairs already sent within this window, like 5m
+    #[clap(long, requires = "file", value_parser = parse_duration)]
+    dedup_window: Option<Duration>,
+
+    #[command(flatten)]
+    rewrite: LabelRewriteOpts,
 }
 
-#[derive(Debug, Serialize)]
-struct PushRequest {
-    streams: Vec<Stream>
+/// Label rewriting applied to pushed streams, so high cardinality labels
+/// can be stripped before they reach loki. Relabel rules run first, then
+/// drop and keep match the resulting names, renames are applied last.
+#[derive(Parser, Debug, Clone, Default)]
+pub struct LabelRewriteOpts {
+    /// Remove a label from every pushed stream (repeat or comma separate)
+    #[clap(long, value_delimiter = ',')]
+    pub drop_label: Vec<String>,
+
+    /// Keep only these labels on every pushed stream (repeat or comma separate)
+    #[clap(long, value_delimiter = ',')]
+    pub keep_label: Vec<String>,
+
+    /// Rename a label, like --rename-label pod=instance
+    #[clap(long)]
+    pub rename_label: Vec<KeyValue>,
+
+    /// Prometheus relabel_configs (a yaml list, or a mapping with a
+    /// relabel_configs key). Labels starting with __ like __path__ are
+    /// visible to the rules and removed afterwards
+    #[clap(long, value_parser = relabel::load)]
+    pub relabel_config: Option<Relabel>,
+}
+
+impl LabelRewriteOpts {
+    /// The rewritten labels, None when a relabel rule drops the stream.
+    pub fn apply(&self, labels: BTreeMap<String, String>) -> anyhow::Result<Option<BTreeMap<String, String>>> {
+        let labels = match self.relabel_config.as_ref() {
+            Some(r) => match r.apply(labels) {
+                Some(l) => l,
+                None => return Ok(None),
+            },
+            None => labels,
+        };
+        let mut out: BTreeMap<String, String> = labels
+            .into_iter()
+            .filter(|(k, _)| !k.starts_with("__"))
+            .filter(|(k, _)| !self.drop_label.contains(k))
+            .filter(|(k, _)| self.keep_label.is_empty() || self.keep_label.contains(k))
+            .collect();
+        for kv in self.rename_label.iter() {
+            if let Some(v) = out.remove(&kv.key) {
+                out.insert(kv.value.clone(), v);
+            }
+        }
+        if out.is_empty() {
+            return Err(anyhow::format_err!("no labels left on a stream after rewriting"));
+        }
+        Ok(Some(out))
+    }
 }
 
 #[derive(Debug, Serialize)]
-struct Stream {
-    stream: HashMap<String, String>,
-    values: Vec<(String, String)>,
+pub struct PushRequest {
+    pub streams: Vec<Stream>
+}
+
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Stream {
+    pub stream: HashMap<String, String>,
+    pub values: Vec<(String, String)>,
 }
 
 pub fn push(p: Push) -> anyhow::Result<()> {
-    let req = mk_req(&p);
+    if !p.file.is_empty() {
+        if p.http.tenants()?.len() > 1 && p.file.iter().any(|f| f == Path::new("-")) {
+            return Err(anyhow::format_err!("stdin can not be pushed to several tenants"));
+        }
+        return for_each_tenant(&p.http, |tenant| push_files(&p, tenant));
+    }
+    for_each_tenant(&p.http, |tenant| push_tenant(&p, tenant))
+}
+
+/// Progress of a --file push, saved after every acknowledged batch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PushState {
+    // "tenant:path" -> lines pushed
+    #[serde(default)]
+    files: BTreeMap<String, u64>,
+    // (ts, hash of ts and line) sent within the dedup window
+    #[serde(default)]
+    recent: Vec<(i64, u64)>,
+}
+
+struct Dedup {
+    window: i64,
+    seen: HashMap<u64, i64>,
+    max_ts: i64,
+}
+
+impl Dedup {
+    fn key(ts: i64, line: &str) -> u64 {
+        let mut bs = ts.to_be_bytes().to_vec();
+        bs.extend_from_slice(line.as_bytes());
+        u64::from_be_bytes(digest(&SHA256, &bs).as_ref()[..8].try_into().unwrap())
+    }
+
+    /// True if the pair has been seen already, remembers it otherwise.
+    fn seen(&mut self, ts: i64, line: &str) -> bool {
+        if self.seen.insert(Dedup::key(ts, line), ts).is_some() {
+            return true;
+        }
+        if ts > self.max_ts {
+            self.max_ts = ts;
+            if self.seen.len() > 100_000 {
+                self.prune();
+            }
+        }
+        false
+    }
+
+    fn prune(&mut self) {
+        let oldest = self.max_ts - self.window;
+        self.seen.retain(|_, ts| *ts >= oldest);
+    }
+}
+
+// "<ts>\t<line>" or just "<line>"
+fn split_ts(line: &str) -> (Option<i64>, &str) {
+    match line.split_once('\t') {
+        Some((ts, rest)) if !ts.is_empty() && ts.bytes().all(|b| b.is_ascii_digit()) => {
+            (ts.parse().ok(), rest)
+        }
+        _ => (None, line),
+    }
+}
+
+pub(crate) fn now_nanos() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("get timestamp").as_nanos() as i64
+}
+
+fn push_files(p: &Push, tenant: Option<String>) -> anyhow::Result<()> {
+    let mut state: PushState = match p.state.as_ref().filter(|s| s.exists()) {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => PushState::default(),
+    };
+    let mut dedup = p.dedup_window.map(|w| Dedup {
+        window: w.as_nanos() as i64,
+        seen: state.recent.iter().map(|(ts, h)| (*h, *ts)).collect(),
+        max_ts: state.recent.iter().map(|r| r.0).max().unwrap_or(i64::MIN),
+    });
+    let client = Client::new();
+    let (mut pushed, mut duplicates) = (0, 0);
+
+    for path in p.file.iter() {
+        let Some(labels) = mk_labels(p, Some(path))? else {
+            println!("{}", gray(&format!("{}: dropped by relabel rules", path.display())));
+            continue;
+        };
+        let key = format!("{}:{}", tenant.clone().unwrap_or_default(), path.display());
+        let done = state.files.get(&key).copied().unwrap_or(0);
+        if done > 0 {
+            println!("{}", gray(&format!("{}: skipping {done} lines already pushed", path.display())));
+        }
+        let reader: Box<dyn BufRead> = if path == Path::new("-") {
+            Box::new(stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+        let mut values = vec![];
+        let mut last_ts = 0;
+        let mut line_no = 0;
+        let mut lines = reader.lines();
+        loop {
+            let line = lines.next().transpose()?;
+            if let Some(line) = line.as_ref() {
+                line_no += 1;
+                if line_no <= done {
+                    continue;
+                }
+                let (ts, line) = split_ts(line);
+                // keep lines without a timestamp in order
+                let ts = ts.unwrap_or_else(|| now_nanos().max(last_ts + 1));
+                last_ts = ts;
+                if dedup.as_mut().map(|d| d.seen(ts, line)).unwrap_or(false) {
+                    duplicates += 1;
+                } else {
+                    values.push((ts.to_string(), line.to_string()));
+                }
+                if values.len() < p.batch_size {
+                    continue;
+                }
+            }
+            if !values.is_empty() {
+                pushed += values.len();
+                let stream = Stream { stream: labels.clone(), values: std::mem::take(&mut values) };
+                send_streams(&client, &p.http, tenant.as_deref(), vec![stream])?;
+            }
+            if let Some(state_path) = p.state.as_ref().filter(|_| !p.http.print_curl) {
+                state.files.insert(key.clone(), line_no);
+                if let Some(d) = dedup.as_mut() {
+                    d.prune();
+                    state.recent = d.seen.iter().map(|(h, ts)| (*ts, *h)).collect();
+                }
+                std::fs::write(state_path, serde_json::to_string(&state)?)?;
+            }
+            if line.is_none() {
+                break;
+            }
+        }
+    }
+    println!("{}", green(&format!("{pushed} lines pushed")));
+    if duplicates > 0 {
+        println!("{}", yellow(&format!("{duplicates} duplicate lines skipped")));
+    }
+    Ok(())
+}
+
+fn push_tenant(p: &Push, tenant: Option<String>) -> anyhow::Result<()> {
+    let req = mk_req(p)?;
     let payload = serde_json::to_string(&req)?;
     let client = reqwest::blocking::Client::new();
     let req = client.post(format!("{}/loki/api/v1/push", p.http.endpoint))
         .header("Content-Type", "application/json");
-    let req = refine_loki_request(req, p.http.headers, p.http.basic_auth, p.http.tenant);
-    let resp = req.body(payload).send()?;
-    println!("{}\n{}", resp.status(), resp.text()?);
+    let req = refine_loki_request(req, p.http.headers.clone(), p.http.basic_auth.clone(), tenant);
+    let req = p.http.sign(req.body(payload))?;
+    if maybe_print_curl(&req, p.http.print_curl, p.http.show_secrets)? {
+        return Ok(());
+    }
+    let resp = timing::send(req)?;
+    println!("{}\n{}", resp.status(), timing::text(resp)?);
+    Ok(())
+}
+
+/// Push a batch of streams, `tenant` overrides the tenant of `http` if given.
+/// Non 2xx responses are turned into errors.
+pub(crate) fn send_streams(
+    client: &Client,
+    http: &HttpOpts,
+    tenant: Option<&str>,
+    streams: Vec<Stream>,
+) -> anyhow::Result<()> {
+    let payload = serde_json::to_string(&PushRequest { streams })?;
+    let req = client.post(format!("{}/loki/api/v1/push", http.endpoint))
+        .header("Content-Type", "application/json");
+    let tenant = tenant.map(|t| t.to_string()).or_else(|| http.tenant.clone());
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), tenant);
+    let req = http.sign(req.body(payload))?;
+    if maybe_print_curl(&req, http.print_curl, http.show_secrets)? {
+        return Ok(());
+    }
+    let resp = timing::send(req)?;
+    if !resp.status().is_success() {
+        return Err(ApiError::status("push", resp).into());
+    }
     Ok(())
 }
 
-fn mk_req(push: &Push) -> PushRequest {
+// labels of the stream, with __path__ of the pushed file for relabel
+// rules; None when the rules drop the stream
+fn mk_labels(push: &Push, path: Option<&Path>) -> anyhow::Result<Option<HashMap<String, String>>> {
     let labels = if push.labels.is_empty() {
         vec![KeyValue{ key: "prog".to_string(), value: "lf".to_string() }]
     } else {
         push.labels.clone()
     };
-    let stream: HashMap<String, String> = labels.iter().map(|x| x.into()).collect();
-    let now = SystemTime::now();
-    let ts = now.duration_since(UNIX_EPOCH).expect("get timestamp").as_nanos() as i64;
-    let values = vec![(ts.to_string(), push.content.clone())];
-    PushRequest {
-        streams: vec![Stream{ stream, values }]
+    let mut labels: BTreeMap<String, String> = labels.iter().map(|x| x.into()).collect();
+    if let Some(path) = path {
+        labels.insert("__path__".to_string(), path.display().to_string());
     }
+    let stream = push.rewrite.apply(labels)?;
+    Ok(stream.map(|s| s.into_iter().collect()))
+}
+
+fn mk_req(push: &Push) -> anyhow::Result<PushRequest> {
+    let stream = mk_labels(push, None)?
+        .ok_or_else(|| anyhow::format_err!("the stream is dropped by the relabel rules"))?;
+    let ts = now_nanos();
+    let values = vec![(ts.to_string(), push.content.clone().unwrap_or_default())];
+    Ok(PushRequest {
+        streams: vec![Stream{ stream, values }]
+    })
 }
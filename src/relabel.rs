@@ -0,0 +1,215 @@
+// Prometheus relabeling (prometheus/model/relabel/relabel.go), so the
+// relabel_configs of a promtail scrape config can be reused as they are.
+
+use std::collections::BTreeMap;
+
+use md5::{Digest, Md5};
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    #[default]
+    Replace,
+    Keep,
+    Drop,
+    KeepEqual,
+    DropEqual,
+    HashMod,
+    LabelMap,
+    LabelDrop,
+    LabelKeep,
+    Lowercase,
+    Uppercase,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    #[serde(default)]
+    source_labels: Vec<String>,
+    separator: Option<String>,
+    target_label: Option<String>,
+    regex: Option<String>,
+    modulus: Option<u64>,
+    replacement: Option<String>,
+    #[serde(default)]
+    action: Action,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    source_labels: Vec<String>,
+    separator: String,
+    target_label: String,
+    // anchored at both ends like prometheus does
+    regex: Regex,
+    modulus: u64,
+    replacement: String,
+    action: Action,
+}
+
+#[derive(Debug, Clone)]
+pub struct Relabel {
+    rules: Vec<Rule>,
+}
+
+impl TryFrom<RawRule> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(r: RawRule) -> Result<Self, Self::Error> {
+        let regex = r.regex.unwrap_or_else(|| "(.*)".to_string());
+        let rule = Rule {
+            source_labels: r.source_labels,
+            separator: r.separator.unwrap_or_else(|| ";".to_string()),
+            target_label: r.target_label.unwrap_or_default(),
+            regex: Regex::new(&format!("^(?:{regex})$"))?,
+            modulus: r.modulus.unwrap_or_default(),
+            replacement: r.replacement.unwrap_or_else(|| "$1".to_string()),
+            action: r.action,
+        };
+        let needs_target = matches!(
+            rule.action,
+            Action::Replace | Action::HashMod | Action::Lowercase | Action::Uppercase | Action::KeepEqual | Action::DropEqual
+        );
+        if needs_target && rule.target_label.is_empty() {
+            return Err(anyhow::format_err!("relabel action {:?} needs a target_label", rule.action));
+        }
+        if rule.action == Action::HashMod && rule.modulus == 0 {
+            return Err(anyhow::format_err!("relabel action hashmod needs a modulus"));
+        }
+        Ok(rule)
+    }
+}
+
+/// Read a relabel config file, used as clap value parser.
+pub fn load(path: &str) -> anyhow::Result<Relabel> {
+    let content = std::fs::read_to_string(path).map_err(|e| anyhow::format_err!("read {path}: {e}"))?;
+    Relabel::parse(&content)
+}
+
+fn valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Relabel {
+    pub fn parse(yaml: &str) -> anyhow::Result<Relabel> {
+        // a list of rules, or a mapping holding them under relabel_configs
+        let mut value: serde_yaml::Value = serde_yaml::from_str(yaml)?;
+        if let Some(rules) = value.get_mut("relabel_configs") {
+            value = std::mem::take(rules);
+        }
+        let raw: Vec<RawRule> = serde_yaml::from_value(value)?;
+        let rules = raw.into_iter().map(Rule::try_from).collect::<anyhow::Result<_>>()?;
+        Ok(Relabel { rules })
+    }
+
+    /// Apply the rules in order, None when a rule drops the stream.
+    pub fn apply(&self, mut labels: BTreeMap<String, String>) -> Option<BTreeMap<String, String>> {
+        for rule in self.rules.iter() {
+            let value = rule
+                .source_labels
+                .iter()
+                .map(|l| labels.get(l).map(|v| v.as_str()).unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join(&rule.separator);
+            match rule.action {
+                Action::Drop if rule.regex.is_match(&value) => return None,
+                Action::Keep if !rule.regex.is_match(&value) => return None,
+                Action::DropEqual if labels.get(&rule.target_label) == Some(&value) => return None,
+                Action::KeepEqual if labels.get(&rule.target_label) != Some(&value) => return None,
+                Action::Replace => {
+                    let Some(caps) = rule.regex.captures(&value) else {
+                        continue;
+                    };
+                    let mut target = String::new();
+                    caps.expand(&rule.target_label, &mut target);
+                    if !valid_label_name(&target) {
+                        continue;
+                    }
+                    let mut v = String::new();
+                    caps.expand(&rule.replacement, &mut v);
+                    if v.is_empty() {
+                        labels.remove(&target);
+                    } else {
+                        labels.insert(target, v);
+                    }
+                }
+                Action::Lowercase => {
+                    labels.insert(rule.target_label.clone(), value.to_lowercase());
+                }
+                Action::Uppercase => {
+                    labels.insert(rule.target_label.clone(), value.to_uppercase());
+                }
+                Action::HashMod => {
+                    let sum = Md5::digest(value.as_bytes());
+                    let n = u64::from_be_bytes(sum[8..].try_into().unwrap()) % rule.modulus;
+                    labels.insert(rule.target_label.clone(), n.to_string());
+                }
+                Action::LabelMap => {
+                    let mapped: Vec<_> = labels
+                        .iter()
+                        .filter_map(|(k, v)| {
+                            let caps = rule.regex.captures(k)?;
+                            let mut name = String::new();
+                            caps.expand(&rule.replacement, &mut name);
+                            Some((name, v.clone()))
+                        })
+                        .collect();
+                    labels.extend(mapped);
+                }
+                Action::LabelDrop => labels.retain(|k, _| !rule.regex.is_match(k)),
+                Action::LabelKeep => labels.retain(|k, _| rule.regex.is_match(k)),
+                Action::Drop | Action::Keep | Action::DropEqual | Action::KeepEqual => {}
+            }
+        }
+        Some(labels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_relabel() {
+        let relabel = Relabel::parse(
+            r#"
+relabel_configs:
+  - source_labels: [__path__]
+    regex: /var/log/(\w+)/.*\.log
+    target_label: service
+  - source_labels: [service]
+    regex: debug
+    action: drop
+  - source_labels: [service, env]
+    separator: "-"
+    target_label: key
+  - regex: env
+    action: labeldrop
+  - source_labels: [key]
+    target_label: shard
+    modulus: 4
+    action: hashmod
+"#,
+        )
+        .unwrap();
+        let labels = |path: &str| {
+            BTreeMap::from([
+                ("__path__".to_string(), path.to_string()),
+                ("env".to_string(), "prod".to_string()),
+            ])
+        };
+        let out = relabel.apply(labels("/var/log/nginx/access.log")).unwrap();
+        assert_eq!(out["service"], "nginx");
+        assert_eq!(out["key"], "nginx-prod");
+        assert!(!out.contains_key("env"));
+        assert!(out["shard"].parse::<u64>().unwrap() < 4);
+        assert!(relabel.apply(labels("/var/log/debug/x.log")).is_none());
+        // no match leaves the target alone
+        assert!(!relabel.apply(labels("/tmp/x.log")).unwrap().contains_key("service"));
+    }
+}
@@ -0,0 +1,586 @@
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use binread::BinReaderExt;
+use clap::Parser;
+use integer_encoding::VarInt;
+use num_traits::FromPrimitive;
+
+use crate::{
+    common::{format_bytes, gray, green, yellow},
+    confirm::confirm,
+    encode::{compress, ChunkEncoding},
+    error::DecodeError,
+    ty::{block_reader, parse_symbols, ChunkHead, EncType},
+};
+
+// loki/pkg/chunkenc/memchunk.go
+const MAGIC: u32 = 0x012EE56A;
+
+/// rewrite block checksums, meta checksum and trailer of a chunk whose
+/// payload is intact, the result is written to a new file
+#[derive(Parser, Debug)]
+pub struct Repair {
+    /// input chunk file
+    input: String,
+
+    /// output file, defaults to <input>.repaired
+    #[clap(short, long)]
+    output: Option<String>,
+
+    /// overwrite the output file if it already exists without asking
+    #[clap(short, long, alias = "force")]
+    yes: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RawBlockMeta {
+    pub entries: usize,
+    pub mint: i64,
+    pub maxt: i64,
+    pub offset: usize,
+    pub len: usize,
+    // chunk format v3, 0 for older formats
+    pub uncompressed: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct RawMeta {
+    pub blocks: Vec<RawBlockMeta>,
+    // length of the encoded metas, without the trailing crc
+    pub len: usize,
+}
+
+fn read_uvarint(bs: &[u8], pos: &mut usize) -> Option<u64> {
+    let (v, n) = u64::decode_var(bs.get(*pos..)?)?;
+    *pos += n;
+    Some(v)
+}
+
+fn read_varint(bs: &[u8], pos: &mut usize) -> Option<i64> {
+    let (v, n) = i64::decode_var(bs.get(*pos..)?)?;
+    *pos += n;
+    Some(v)
+}
+
+/// Parse block metas at `offset` of the memchunk bytes without trusting them.
+pub(crate) fn parse_raw_meta(chunk: &[u8], offset: usize, format: u8) -> Option<RawMeta> {
+    let mut pos = offset;
+    let num_blocks = read_uvarint(chunk, &mut pos)? as usize;
+    // every block meta takes at least 5 bytes
+    if num_blocks == 0 || num_blocks > chunk.len() / 5 {
+        return None;
+    }
+    let mut blocks = Vec::with_capacity(num_blocks);
+    for _ in 0..num_blocks {
+        let entries = read_uvarint(chunk, &mut pos)? as usize;
+        let mint = read_varint(chunk, &mut pos)?;
+        let maxt = read_varint(chunk, &mut pos)?;
+        let offset = read_uvarint(chunk, &mut pos)? as usize;
+        let uncompressed = match format >= 3 {
+            true => read_uvarint(chunk, &mut pos)? as usize,
+            false => 0,
+        };
+        let len = read_uvarint(chunk, &mut pos)? as usize;
+        blocks.push(RawBlockMeta {
+            entries,
+            mint,
+            maxt,
+            offset,
+            len,
+            uncompressed,
+        });
+    }
+    Some(RawMeta {
+        blocks,
+        len: pos - offset,
+    })
+}
+
+// blocks are laid out back to back (each followed by a 4 bytes crc)
+// and the metas start right after the last one
+fn blocks_consistent(meta: &RawMeta, header_len: usize, meta_offset: usize) -> bool {
+    let mut expected = match meta.blocks.first() {
+        Some(b) if b.offset >= header_len => b.offset,
+        _ => return false,
+    };
+    for b in meta.blocks.iter() {
+        if b.offset != expected || b.mint > b.maxt {
+            return false;
+        }
+        expected = b.offset + b.len + 4;
+    }
+    expected == meta_offset
+}
+
+pub(crate) fn be_u32(bs: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bs.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+pub(crate) fn be_u64(bs: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bs.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+pub fn repair(r: Repair) -> anyhow::Result<()> {
+    let output = r
+        .output
+        .clone()
+        .unwrap_or_else(|| format!("{}.repaired", r.input));
+    if Path::new(&output) == Path::new(&r.input) {
+        return Err(anyhow::format_err!("refuse to overwrite the input file"));
+    }
+
+    let bs = std::fs::read(&r.input)?;
+    let mut fixes: Vec<String> = vec![];
+    let result = repair_bytes(&bs, &mut fixes)?;
+
+    if fixes.is_empty() {
+        println!("{}", green("nothing to repair, the chunk looks fine"));
+        return Ok(());
+    }
+    for f in fixes.iter() {
+        println!("{} {}", yellow("fixed:"), f);
+    }
+    if let Ok(existing) = std::fs::metadata(&output) {
+        confirm(
+            &format!("overwrite {output}"),
+            &[format!(
+                "{output} ({}) is replaced by the repaired chunk ({}, {} fixes)",
+                format_bytes(existing.len()),
+                format_bytes(result.len() as u64),
+                fixes.len()
+            )],
+            r.yes,
+        )?;
+    }
+    std::fs::write(&output, &result)?;
+    println!(
+        "{} (new chunk checksum {:x})",
+        green(&format!("written to {output}")),
+        crc32c::crc32c(&result)
+    );
+    Ok(())
+}
+
+/// The chunk `bs` with its checksums, meta offset, trailer and length
+/// fields rewritten to match its blocks, what was fixed added to `fixes`.
+fn repair_bytes(bs: &[u8], fixes: &mut Vec<String>) -> anyhow::Result<Vec<u8>> {
+    // chunk head: 4 bytes length (including itself) + snappy compressed json
+    let head_len = be_u32(bs, 0).ok_or_else(|| anyhow::format_err!("file too short"))? as usize;
+    if head_len < 4 || head_len > bs.len() {
+        return Err(anyhow::format_err!("invalid head length: {head_len}"));
+    }
+    let head: ChunkHead = Cursor::new(&bs[4..head_len])
+        .read_le()
+        .map_err(|e| anyhow::format_err!("chunk head is not readable: {e}"))?;
+    println!("{}", gray(&format!("head ok: {} {:?}", head.user_id, head.metric)));
+
+    let data_len = be_u32(bs, head_len).ok_or_else(|| anyhow::format_err!("missing data"))?;
+    let chunk = &bs[head_len + 4..];
+    if be_u32(chunk, 0) != Some(MAGIC) {
+        return Err(anyhow::format_err!(
+            "chunk magic not found, payload is not a loki chunk"
+        ));
+    }
+    let format = *chunk.get(4).ok_or(DecodeError::Truncated("chunk format"))?;
+    let header_len = if format > 1 { 6 } else { 5 };
+    let trailer_len = if format >= 4 { 32 } else { 8 };
+
+    // first trust the trailer, then fall back to scanning for metas
+    let stored_offset = chunk
+        .len()
+        .checked_sub(8)
+        .and_then(|p| be_u64(chunk, p))
+        .map(|o| o as usize);
+    let mut located = stored_offset.and_then(|o| {
+        parse_raw_meta(chunk, o, format)
+            .filter(|m| blocks_consistent(m, header_len, o))
+            .map(|m| (o, m))
+    });
+    if located.is_none() {
+        fixes.push(format!(
+            "trailer meta offset {:?} (metas located by scanning)",
+            stored_offset
+        ));
+        located = (header_len..chunk.len()).find_map(|o| {
+            parse_raw_meta(chunk, o, format)
+                .filter(|m| blocks_consistent(m, header_len, o))
+                .map(|m| (o, m))
+        });
+    }
+    let (meta_offset, meta) = located.ok_or_else(|| {
+        anyhow::format_err!("unable to locate block metas, payload is probably damaged")
+    })?;
+    println!(
+        "{}",
+        gray(&format!(
+            "format v{format}, {} blocks, metas at {meta_offset}",
+            meta.blocks.len()
+        ))
+    );
+
+    let mut out = chunk[..meta_offset].to_vec();
+
+    // structured metadata section (v4+) sits between the header and the first block
+    let first_block = meta.blocks[0].offset;
+    if format >= 4 && first_block >= header_len + 4 {
+        let crc_pos = first_block - 4;
+        let crc = crc32c::crc32c(&chunk[header_len..crc_pos]);
+        if be_u32(chunk, crc_pos) != Some(crc) {
+            fixes.push("structured metadata section crc".to_string());
+            out[crc_pos..first_block].copy_from_slice(&crc.to_be_bytes());
+        }
+    }
+
+    for (i, b) in meta.blocks.iter().enumerate() {
+        let crc = crc32c::crc32c(&chunk[b.offset..b.offset + b.len]);
+        let crc_pos = b.offset + b.len;
+        if be_u32(chunk, crc_pos) != Some(crc) {
+            fixes.push(format!("block {i} crc at offset {crc_pos}"));
+            out[crc_pos..crc_pos + 4].copy_from_slice(&crc.to_be_bytes());
+        }
+    }
+
+    let metas = &chunk[meta_offset..meta_offset + meta.len];
+    let meta_crc = crc32c::crc32c(metas);
+    if be_u32(chunk, meta_offset + meta.len) != Some(meta_crc) {
+        fixes.push("meta crc".to_string());
+    }
+    out.extend_from_slice(metas);
+    out.extend_from_slice(&meta_crc.to_be_bytes());
+    if format >= 4 {
+        // the length of the symbols, without their crc
+        out.extend_from_slice(&(first_block.saturating_sub(header_len + 4) as u64).to_be_bytes());
+        out.extend_from_slice(&(header_len as u64).to_be_bytes());
+        out.extend_from_slice(&(meta.len as u64).to_be_bytes());
+    }
+    out.extend_from_slice(&(meta_offset as u64).to_be_bytes());
+
+    let expected_len = meta_offset + meta.len + 4 + trailer_len;
+    if chunk.len() != expected_len {
+        fixes.push(format!(
+            "chunk length {} -> {}",
+            chunk.len(),
+            expected_len
+        ));
+    } else if chunk[meta_offset + meta.len + 4..] != out[meta_offset + meta.len + 4..] {
+        fixes.push("trailer".to_string());
+    }
+    if data_len as usize != out.len() {
+        fixes.push(format!("data length field {} -> {}", data_len, out.len()));
+    }
+
+    let mut result = bs[..head_len].to_vec();
+    result.extend_from_slice(&(out.len() as u32).to_be_bytes());
+    result.extend_from_slice(&out);
+    Ok(result)
+}
+
+// a readable block of a damaged chunk, whole or up to the entry it is cut at
+struct Salvaged {
+    // compressed
+    data: Vec<u8>,
+    entries: usize,
+    mint: i64,
+    maxt: i64,
+    raw_len: usize,
+}
+
+// skip the entry at `pos` of raw block bytes (varint ts, uvarint len, line,
+// and the structured metadata section of format v4), returning its time
+fn skip_entry(raw: &[u8], pos: &mut usize, metadata: bool) -> Option<i64> {
+    let ts = read_varint(raw, pos)?;
+    for _ in 0..1 + metadata as usize {
+        let len = read_uvarint(raw, pos)? as usize;
+        *pos = pos.checked_add(len).filter(|&p| p <= raw.len())?;
+    }
+    Some(ts)
+}
+
+// the block `data`, recompressed up to its last complete entry when it is
+// cut off or damaged past some entries
+fn salvage_block(data: &[u8], enc: &EncType, metadata: bool, expected: Option<usize>) -> anyhow::Result<Salvaged> {
+    let mut raw = vec![];
+    // what was decompressed before an error is still in `raw`
+    let cut = block_reader(data, enc)
+        .map_err(DecodeError::from)?
+        .read_to_end(&mut raw)
+        .is_err();
+    let (mut pos, mut end, mut entries, mut mint, mut maxt) = (0, 0, 0, i64::MAX, i64::MIN);
+    while let Some(ts) = skip_entry(&raw, &mut pos, metadata) {
+        (end, entries, mint, maxt) = (pos, entries + 1, mint.min(ts), maxt.max(ts));
+    }
+    if entries == 0 {
+        return Err(anyhow::format_err!("no entry could be decoded"));
+    }
+    let whole = !cut && end == raw.len() && expected.is_none_or(|n| n == entries);
+    let data = match whole {
+        true => data.to_vec(),
+        false => compress(&raw[..end], ChunkEncoding::try_from(enc)?)?,
+    };
+    Ok(Salvaged {
+        data,
+        entries,
+        mint,
+        maxt,
+        raw_len: end,
+    })
+}
+
+// regions followed by their crc32c, back to back from `pos`: the blocks of
+// a chunk whose metas are lost
+fn crc_regions(chunk: &[u8], mut pos: usize) -> Vec<(usize, usize)> {
+    let mut regions = vec![];
+    let region_end = |pos: usize| {
+        let mut crc = 0;
+        (pos..chunk.len().saturating_sub(3)).find(|&end| {
+            let found = end > pos && be_u32(chunk, end) == Some(crc);
+            crc = crc32c::crc32c_append(crc, &chunk[end..end + 1]);
+            found
+        })
+    };
+    while let Some(end) = region_end(pos) {
+        regions.push((pos, end - pos));
+        pos = end + 4;
+    }
+    regions
+}
+
+/// Rebuild the chunk `bs` out of what is still readable of it, for `lf
+/// decode --best-effort`. The blocks are found from the metas, or from
+/// their checksums when the metas are gone with the end of a truncated
+/// chunk. Blocks failing to decompress are skipped, the entries of a block
+/// cut short are kept up to the cut. What is lost is reported on stderr.
+pub(crate) fn salvage(bs: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let head_len = be_u32(bs, 0).ok_or(DecodeError::Truncated("head length"))? as usize;
+    if head_len < 4 || head_len + 4 > bs.len() {
+        return Err(DecodeError::InvalidHead(format!("invalid head length: {head_len}")).into());
+    }
+    let head: ChunkHead = Cursor::new(&bs[4..head_len])
+        .read_le()
+        .map_err(DecodeError::from)?;
+    let chunk = &bs[head_len + 4..];
+    if be_u32(chunk, 0) != Some(MAGIC) {
+        head.check_log_chunk()?;
+        return Err(DecodeError::InvalidHead("chunk magic not found".to_string()).into());
+    }
+    let format = *chunk.get(4).ok_or(DecodeError::Truncated("chunk format"))?;
+    let header_len = if format > 1 { 6 } else { 5 };
+    let enc = match format {
+        1 => EncType::EncGZIP,
+        2..=4 => {
+            let enc = *chunk.get(5).ok_or(DecodeError::Truncated("chunk encoding"))?;
+            EncType::from_u8(enc).ok_or_else(|| DecodeError::Unsupported(format!("encoding {enc}")))?
+        }
+        f => return Err(DecodeError::Unsupported(format!("chunk format v{f}")).into()),
+    };
+    let warn = |msg: String| eprintln!("{} {msg}", yellow("best effort:"));
+
+    // blocks as (offset, length, entries in the metas), and where the
+    // bytes past the last block found start when the metas are lost
+    let stored = chunk.len().checked_sub(8).and_then(|p| be_u64(chunk, p)).map(|o| o as usize);
+    let located = stored.into_iter().chain(header_len..chunk.len()).find_map(|o| {
+        parse_raw_meta(chunk, o, format)
+            .filter(|m| blocks_consistent(m, header_len, o))
+            .map(|m| (o, m))
+    });
+    let (mut blocks, tail): (Vec<_>, _) = match located {
+        Some((_, ref meta)) => (meta.blocks.iter().map(|b| (b.offset, b.len, Some(b.entries))).collect(), None),
+        None => {
+            warn("block metas not found, the chunk is probably truncated, blocks are found by their checksums".to_string());
+            let regions = crc_regions(chunk, header_len);
+            let tail = regions.last().map_or(header_len, |&(o, len)| o + len + 4);
+            (regions.into_iter().map(|(o, len)| (o, len, None)).collect(), Some(tail))
+        }
+    };
+    // format v4: the symbols section comes first, followed by its crc
+    let symbols = match format {
+        4 => {
+            let end = match located {
+                Some(_) => blocks.first().map(|b| b.0.saturating_sub(4)),
+                None if blocks.is_empty() => None,
+                None => Some(header_len + blocks.remove(0).1),
+            };
+            let section = end
+                .and_then(|end| chunk.get(header_len..end))
+                .ok_or(DecodeError::Truncated("symbols section"))?;
+            parse_symbols(section, &enc).map_err(DecodeError::from)?;
+            Some(section)
+        }
+        _ => None,
+    };
+
+    let tail = tail.filter(|&t| t < chunk.len());
+    let mut salvaged = vec![];
+    let total = blocks.len() + tail.is_some() as usize;
+    for (i, (offset, len, expected)) in blocks.into_iter().enumerate() {
+        let data = &chunk[offset..offset + len];
+        if be_u32(chunk, offset + len) != Some(crc32c::crc32c(data)) {
+            warn(format!("block {i} at offset {offset} has a bad checksum"));
+        }
+        match salvage_block(data, &enc, symbols.is_some(), expected) {
+            Ok(b) => {
+                if let Some(n) = expected.filter(|&n| n != b.entries) {
+                    warn(format!("block {i} at offset {offset}: {} of {n} entries recovered", b.entries));
+                }
+                salvaged.push(b);
+            }
+            Err(e) => warn(format!("block {i} at offset {offset} skipped: {e}")),
+        }
+    }
+    if let Some(tail) = tail {
+        match salvage_block(&chunk[tail..], &enc, symbols.is_some(), None) {
+            Ok(b) => {
+                warn(format!("{} entries recovered from the cut off block at offset {tail}", b.entries));
+                salvaged.push(b);
+            }
+            Err(e) => warn(format!("{} bytes at offset {tail} dropped: {e}", chunk.len() - tail)),
+        }
+    }
+    if salvaged.is_empty() {
+        return Err(DecodeError::Corrupt("no block could be recovered".to_string()).into());
+    }
+    if salvaged.len() != total {
+        warn(format!("{} of {total} blocks recovered", salvaged.len()));
+    }
+
+    // the recovered blocks as a well formed chunk of the same format
+    let mut out = chunk[..header_len].to_vec();
+    if let Some(section) = symbols {
+        out.extend_from_slice(section);
+        out.extend_from_slice(&crc32c::crc32c(section).to_be_bytes());
+    }
+    let mut metas = (salvaged.len() as u64).encode_var_vec();
+    for b in salvaged.iter() {
+        metas.extend_from_slice(&(b.entries as u64).encode_var_vec());
+        metas.extend_from_slice(&b.mint.encode_var_vec());
+        metas.extend_from_slice(&b.maxt.encode_var_vec());
+        metas.extend_from_slice(&(out.len() as u64).encode_var_vec());
+        if format >= 3 {
+            metas.extend_from_slice(&(b.raw_len as u64).encode_var_vec());
+        }
+        metas.extend_from_slice(&(b.data.len() as u64).encode_var_vec());
+        out.extend_from_slice(&b.data);
+        out.extend_from_slice(&crc32c::crc32c(&b.data).to_be_bytes());
+    }
+    let meta_offset = out.len();
+    out.extend_from_slice(&metas);
+    out.extend_from_slice(&crc32c::crc32c(&metas).to_be_bytes());
+    if let Some(section) = symbols {
+        out.extend_from_slice(&(section.len() as u64).to_be_bytes());
+        out.extend_from_slice(&(header_len as u64).to_be_bytes());
+        out.extend_from_slice(&(metas.len() as u64).to_be_bytes());
+    }
+    out.extend_from_slice(&(meta_offset as u64).to_be_bytes());
+
+    let mut result = bs[..head_len].to_vec();
+    result.extend_from_slice(&(out.len() as u32).to_be_bytes());
+    result.extend_from_slice(&out);
+    Ok(result)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::collections::BTreeMap;
+
+    use binread::BinRead;
+
+    use super::*;
+    use crate::{
+        encode::{encode_chunk, encode_memchunk, make_head, EncodeEntry},
+        ty::Chunk,
+    };
+
+    #[test]
+    fn test_salvage_truncated() -> anyhow::Result<()> {
+        let entries: Vec<_> = (0..100)
+            .map(|i| EncodeEntry {
+                ts: 1_661_946_709_000_000_000 + i * 1_000_000_000,
+                line: format!("line {i}"),
+            })
+            .collect();
+        let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+        let memchunk = encode_memchunk(&entries, ChunkEncoding::Gzip, 256)?;
+        let head = make_head("fake", &labels, 1_661_946_709_000, 1_661_946_808_000);
+        let bs = encode_chunk(&head, &memchunk)?;
+
+        // an intact chunk comes out with the same entries
+        let chunk = Chunk::read(&mut Cursor::new(salvage(&bs)?))?;
+        assert_eq!(chunk.data.blocks.iter().map(|b| b.entries.len()).sum::<usize>(), 100);
+
+        // cut in the middle: the metas are gone, the first blocks are found
+        // by their checksums
+        let chunk = Chunk::read(&mut Cursor::new(salvage(&bs[..bs.len() / 2])?))?;
+        let lines: Vec<_> = chunk.data.blocks.iter().flat_map(|b| b.entries.iter()).map(|e| e.line.as_str()).collect();
+        assert!(lines.len() > 10 && lines.len() < 100, "{}", lines.len());
+        assert!(lines.iter().enumerate().all(|(i, l)| *l == format!("line {i}")));
+        Ok(())
+    }
+
+    // a format v4 chunk of one block with two entries, the first one with
+    // structured metadata
+    // a format v4 chunk of two entries, the first with structured metadata
+    pub(crate) fn chunk_v4() -> anyhow::Result<Vec<u8>> {
+        let uvarints = |vs: &[u64]| vs.iter().flat_map(|v| v.encode_var_vec()).collect::<Vec<u8>>();
+        let mut symbols = vec![];
+        for s in ["trace_id", "abc"] {
+            symbols.extend(uvarints(&[s.len() as u64]));
+            symbols.extend(s.as_bytes());
+        }
+        let mut section = uvarints(&[2]);
+        section.extend(compress(&symbols, ChunkEncoding::Gzip)?);
+        let mut raw = 1_000_000_000_i64.encode_var_vec();
+        raw.extend(uvarints(&[3]));
+        raw.extend(b"foo");
+        raw.extend(uvarints(&[3, 1, 0, 1]));
+        raw.extend(2_000_000_000_i64.encode_var_vec());
+        raw.extend(uvarints(&[3]));
+        raw.extend(b"bar");
+        raw.extend(uvarints(&[1, 0]));
+        let block = compress(&raw, ChunkEncoding::Gzip)?;
+
+        let mut data = MAGIC.to_be_bytes().to_vec();
+        data.extend([4, 1]);
+        data.extend(&section);
+        data.extend(crc32c::crc32c(&section).to_be_bytes());
+        let block_offset = data.len();
+        data.extend(&block);
+        data.extend(crc32c::crc32c(&block).to_be_bytes());
+        let metas_offset = data.len();
+        let mut metas = uvarints(&[1, 2]);
+        metas.extend(1_000_000_000_i64.encode_var_vec());
+        metas.extend(2_000_000_000_i64.encode_var_vec());
+        metas.extend(uvarints(&[block_offset as u64, raw.len() as u64, block.len() as u64]));
+        data.extend(&metas);
+        data.extend(crc32c::crc32c(&metas).to_be_bytes());
+        // symbols and metas lengths without their crc
+        for v in [section.len(), 6, metas.len(), metas_offset] {
+            data.extend((v as u64).to_be_bytes());
+        }
+        let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+        encode_chunk(&make_head("fake", &labels, 1_000, 2_000), &data)
+    }
+
+    #[test]
+    fn test_repair_v4() -> anyhow::Result<()> {
+        let bs = chunk_v4()?;
+        let mut fixes = vec![];
+        assert_eq!(repair_bytes(&bs, &mut fixes)?, bs);
+        assert!(fixes.is_empty(), "{fixes:?}");
+
+        // a damaged block checksum is rewritten, the result decodes
+        let mut bad = bs.clone();
+        let base = be_u32(&bs, 0).unwrap() as usize + 4;
+        let metas_offset = be_u64(&bs, bs.len() - 8).unwrap() as usize;
+        bad[base + metas_offset - 1] ^= 0xff;
+        let repaired = repair_bytes(&bad, &mut fixes)?;
+        assert!(fixes.len() == 1 && fixes[0].starts_with("block 0 crc"), "{fixes:?}");
+        let chunk = Chunk::read(&mut Cursor::new(repaired))?;
+        let entries = &chunk.data.blocks[0].entries;
+        assert_eq!(entries[0].structured_metadata, [("trace_id".to_string(), "abc".to_string())]);
+        assert_eq!(entries[1].line, "bar");
+        Ok(())
+    }
+}
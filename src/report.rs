@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use clap::{Parser, ValueEnum};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    common::{format_bytes, maybe_print_curl, refine_loki_request, HttpOpts, TimeRangeOpts},
+    capability::{self, Api},
+    error::ApiError,
+    estimate::{stats_request, volume_request, volume_streams, IndexStats},
+    html::{self, Cell},
+    query::get_duration,
+    timing,
+};
+
+// limits worth a look in capacity and chargeback reviews
+const LIMITS: &[&str] = &[
+    "ingestion_rate_mb",
+    "ingestion_burst_size_mb",
+    "per_stream_rate_limit",
+    "max_global_streams_per_user",
+    "max_line_size",
+    "max_entries_limit_per_query",
+    "max_query_series",
+    "max_query_length",
+    "retention_period",
+];
+
+/// usage reports combining several apis
+#[derive(Parser, Debug)]
+pub struct Report {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// per tenant streams, series, chunks, entries, bytes, top streams and
+    /// limits (the latter needs admin access)
+    Tenant(TenantCommand),
+}
+
+#[derive(Parser, Debug)]
+struct TenantCommand {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// Stream selector covering what should be accounted
+    #[clap(short, long, default_value = "{job=~\".+\"}")]
+    query: String,
+
+    /// Number of top streams by volume per tenant, 0 to skip
+    #[clap(long, default_value = "10")]
+    top: u32,
+
+    /// Also fetch the limits of every tenant (/config/tenant/v1/limits)
+    #[clap(long)]
+    limits: bool,
+
+    /// Report format
+    #[clap(long, default_value = "markdown", value_enum)]
+    format: ReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageReport {
+    from: String,
+    through: String,
+    query: String,
+    tenants: Vec<TenantUsage>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TenantUsage {
+    tenant: Option<String>,
+    stats: Option<IndexStats>,
+    series: Option<u64>,
+    top_streams: Vec<StreamVolume>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limits: Option<BTreeMap<String, String>>,
+    // parts of the report that could not be fetched
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamVolume {
+    labels: String,
+    bytes: u64,
+}
+
+pub fn run(r: Report) -> anyhow::Result<()> {
+    match r.cmd {
+        SubCommand::Tenant(t) => tenant_report(t),
+    }
+}
+
+// body of a successful response, None when printed with --print-curl
+fn fetch(http: &HttpOpts, req: RequestBuilder, what: &'static str) -> anyhow::Result<Option<String>> {
+    let req = http.sign(req)?;
+    if maybe_print_curl(&req, http.print_curl, http.show_secrets)? {
+        return Ok(None);
+    }
+    let resp = timing::send(req)?;
+    if !resp.status().is_success() {
+        return Err(ApiError::status(what, resp).into());
+    }
+    Ok(Some(timing::text(resp)?))
+}
+
+// top level `key: value` lines of the limits yaml
+fn parse_limits(yaml: &str) -> BTreeMap<String, String> {
+    yaml.lines()
+        .filter(|l| !l.starts_with(' ') && !l.starts_with('#'))
+        .filter_map(|l| l.split_once(':'))
+        .filter(|(k, _)| LIMITS.contains(&k.trim()))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().trim_matches('"').to_string()))
+        .collect()
+}
+
+fn tenant_usage(
+    t: &TenantCommand,
+    client: &Client,
+    tenant: Option<String>,
+    (from, through): (NaiveDateTime, NaiveDateTime),
+) -> anyhow::Result<TenantUsage> {
+    let http = &t.http;
+    let mut usage = TenantUsage {
+        tenant: tenant.clone(),
+        ..Default::default()
+    };
+
+    let req = stats_request(client, http, tenant.clone(), &t.query, from, through);
+    match fetch(http, req, "index stats") {
+        Ok(Some(body)) => usage.stats = Some(serde_json::from_str(&body)?),
+        Ok(None) => {}
+        Err(err) => usage.errors.push(err.to_string()),
+    }
+
+    let req = client.get(format!("{}/loki/api/v1/series", http.endpoint));
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), tenant.clone());
+    let req = req.query(&[
+        ("match[]", t.query.clone()),
+        ("start", from.timestamp_nanos().to_string()),
+        ("end", through.timestamp_nanos().to_string()),
+    ]);
+    match fetch(http, req, "series") {
+        Ok(Some(body)) => {
+            let obj: serde_json::Value = serde_json::from_str(&body)?;
+            usage.series = obj["data"].as_array().map(|a| a.len() as u64);
+        }
+        Ok(None) => {}
+        Err(err) => usage.errors.push(err.to_string()),
+    }
+
+    if t.top > 0 {
+        let req = volume_request(client, http, tenant.clone(), &t.query, from, through, t.top);
+        match capability::require(http, Api::Volume).and_then(|_| fetch(http, req, "volume")) {
+            Ok(Some(body)) => {
+                let obj: serde_json::Value = serde_json::from_str(&body)?;
+                usage.top_streams = volume_streams(&obj)
+                    .into_iter()
+                    .map(|(labels, bytes)| StreamVolume { labels, bytes })
+                    .collect();
+            }
+            Ok(None) => {}
+            Err(err) => usage.errors.push(err.to_string()),
+        }
+    }
+
+    if t.limits {
+        let req = client.get(format!("{}{}", http.endpoint, Api::TenantLimits.path()));
+        let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), tenant);
+        match capability::require(http, Api::TenantLimits).and_then(|_| fetch(http, req, "tenant limits")) {
+            Ok(Some(body)) => usage.limits = Some(parse_limits(&body)),
+            Ok(None) => {}
+            Err(err) => usage.errors.push(err.to_string()),
+        }
+    }
+    Ok(usage)
+}
+
+fn tenant_report(t: TenantCommand) -> anyhow::Result<()> {
+    debug!("{t:?}");
+    let (from, through) = get_duration(&t.time_range)?;
+    let client = Client::new();
+    let mut tenants = vec![];
+    for tenant in t.http.tenants()? {
+        tenants.push(tenant_usage(&t, &client, tenant, (from, through))?);
+    }
+    if t.http.print_curl {
+        return Ok(());
+    }
+    let report = UsageReport {
+        from: from.to_string(),
+        through: through.to_string(),
+        query: t.query.clone(),
+        tenants,
+    };
+    match t.format {
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        ReportFormat::Markdown => print!("{}", markdown(&report)),
+        ReportFormat::Html => print!("{}", html_page(&report).render()),
+    }
+    Ok(())
+}
+
+// markdown table cells can't hold pipes or newlines
+fn cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+fn markdown(r: &UsageReport) -> String {
+    let opt = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+    let name = |u: &TenantUsage| u.tenant.clone().unwrap_or_else(|| "(default)".to_string());
+    let mut out = String::from("# Tenant usage report\n\n");
+    out.push_str(&format!("{} to {}, selector `{}`\n\n", r.from, r.through, r.query));
+    out.push_str("| tenant | streams | series | chunks | entries | bytes |\n");
+    out.push_str("|---|---:|---:|---:|---:|---:|\n");
+    for u in r.tenants.iter() {
+        let stats = u.stats.as_ref();
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {} | {} |\n",
+            cell(&name(u)),
+            opt(stats.map(|s| s.streams)),
+            opt(u.series),
+            opt(stats.map(|s| s.chunks)),
+            opt(stats.map(|s| s.entries)),
+            stats.map(|s| format_bytes(s.bytes)).unwrap_or_else(|| "-".to_string()),
+        ));
+    }
+    for u in r.tenants.iter() {
+        if u.top_streams.is_empty() && u.limits.is_none() && u.errors.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\n## {}\n", name(u)));
+        if !u.top_streams.is_empty() {
+            out.push_str("\n| bytes | stream |\n|---:|---|\n");
+            for s in u.top_streams.iter() {
+                out.push_str(&format!("| {} | {} |\n", format_bytes(s.bytes), cell(&s.labels)));
+            }
+        }
+        if let Some(limits) = u.limits.as_ref() {
+            out.push_str("\n| limit | value |\n|---|---|\n");
+            for (k, v) in limits {
+                out.push_str(&format!("| {} | {} |\n", k, cell(v)));
+            }
+        }
+        for err in u.errors.iter() {
+            out.push_str(&format!("\n> {}\n", cell(err)));
+        }
+    }
+    out
+}
+
+fn html_page(r: &UsageReport) -> html::Page {
+    let name = |u: &TenantUsage| u.tenant.clone().unwrap_or_else(|| "(default)".to_string());
+    let num = |v: Option<u64>| Cell::Num(v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string()));
+    let mut page = html::Page::new("Tenant usage report");
+    page.note(&format!("{} to {}, selector {}", r.from, r.through, r.query));
+    let rows = r
+        .tenants
+        .iter()
+        .map(|u| {
+            let stats = u.stats.as_ref();
+            vec![
+                Cell::Text(name(u)),
+                num(stats.map(|s| s.streams)),
+                num(u.series),
+                num(stats.map(|s| s.chunks)),
+                num(stats.map(|s| s.entries)),
+                Cell::Num(stats.map(|s| format_bytes(s.bytes)).unwrap_or_else(|| "-".to_string())),
+            ]
+        })
+        .collect();
+    page.table(&["tenant", "streams", "series", "chunks", "entries", "bytes"], rows);
+    page.heading("Bytes per tenant");
+    let bytes: Vec<_> = r
+        .tenants
+        .iter()
+        .filter_map(|u| u.stats.as_ref().map(|s| (name(u), s.bytes as f64, format_bytes(s.bytes))))
+        .collect();
+    page.bars(&bytes);
+    for u in r.tenants.iter() {
+        if u.top_streams.is_empty() && u.limits.is_none() && u.errors.is_empty() {
+            continue;
+        }
+        page.heading(&name(u));
+        page.problems(&u.errors);
+        if !u.top_streams.is_empty() {
+            let streams: Vec<_> = u
+                .top_streams
+                .iter()
+                .map(|s| (s.labels.clone(), s.bytes as f64, format_bytes(s.bytes)))
+                .collect();
+            page.bars(&streams);
+        }
+        if let Some(limits) = u.limits.as_ref() {
+            let rows = limits
+                .iter()
+                .map(|(k, v)| vec![Cell::Text(k.clone()), Cell::Text(v.clone())])
+                .collect();
+            page.table(&["limit", "value"], rows);
+        }
+    }
+    page
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        let yaml = "ingestion_rate_mb: 4\nmax_line_size: \"256KB\"\nshard_streams:\n  enabled: false\nmax_cache_freshness_per_query: 10m\n";
+        let limits = parse_limits(yaml);
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits["ingestion_rate_mb"], "4");
+        assert_eq!(limits["max_line_size"], "256KB");
+    }
+}
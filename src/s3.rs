@@ -0,0 +1,191 @@
+use chrono::Utc;
+use clap::Args;
+use reqwest::{blocking::Client, Method, StatusCode, Url};
+use tracing::debug;
+
+use crate::{
+    sigv4::{self, uri_encode, Credentials},
+    trace,
+};
+
+#[derive(Debug, Args)]
+pub struct S3Opts {
+    /// S3 compatible endpoint, defaults to aws s3 of the given region
+    #[clap(long, env = "LF_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+
+    /// S3 region
+    #[clap(long, default_value = "us-east-1", env = "AWS_REGION")]
+    pub region: String,
+
+    /// S3 access key, requests are sent unsigned if not given
+    #[clap(long, env = "AWS_ACCESS_KEY_ID", hide_env_values = true)]
+    pub access_key: Option<String>,
+
+    /// S3 secret key
+    #[clap(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    pub secret_key: Option<String>,
+
+    /// S3 session token
+    #[clap(long, env = "AWS_SESSION_TOKEN", hide_env_values = true)]
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+}
+
+pub struct S3Client {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    creds: Option<Credentials>,
+}
+
+/// Split `s3://bucket/prefix` into bucket and prefix.
+pub fn parse_s3_url(url: &str) -> anyhow::Result<(String, String)> {
+    let rest = url
+        .strip_prefix("s3://")
+        .ok_or_else(|| anyhow::format_err!("expect s3://bucket/prefix, got {url}"))?;
+    match rest.split_once('/') {
+        Some((bucket, prefix)) => Ok((bucket.to_string(), prefix.to_string())),
+        None => Ok((rest.to_string(), String::default())),
+    }
+}
+
+impl S3Client {
+    pub fn new(opts: &S3Opts, bucket: &str) -> anyhow::Result<S3Client> {
+        let endpoint = match &opts.s3_endpoint {
+            Some(e) => e.trim_end_matches('/').to_string(),
+            None => format!("https://s3.{}.amazonaws.com", opts.region),
+        };
+        let creds = match (&opts.access_key, &opts.secret_key) {
+            (Some(ak), Some(sk)) => Some(Credentials {
+                access_key: ak.clone(),
+                secret_key: sk.clone(),
+                session_token: opts.session_token.clone(),
+            }),
+            (None, None) => None,
+            _ => {
+                return Err(anyhow::format_err!(
+                    "access key and secret key should be given together"
+                ))
+            }
+        };
+        Ok(S3Client {
+            client: Client::new(),
+            endpoint,
+            bucket: bucket.to_string(),
+            region: opts.region.clone(),
+            creds,
+        })
+    }
+
+    // path style addressing, works for both aws and minio-like gateways
+    fn send(&self, method: Method, key: &str, query: &[(&str, &str)]) -> anyhow::Result<Vec<u8>> {
+        let mut url = if key.is_empty() {
+            Url::parse(&format!("{}/{}", self.endpoint, self.bucket))?
+        } else {
+            Url::parse(&format!(
+                "{}/{}/{}",
+                self.endpoint,
+                self.bucket,
+                uri_encode(key, true)
+            ))?
+        };
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        debug!("s3 request: {method} {url}");
+        let mut span = trace::client_span(format!("s3 {method}"));
+        span.attr("url.full", url.as_str());
+        let mut req = self.client.request(method, url).build()?;
+        if let Some(creds) = &self.creds {
+            sigv4::sign(&mut req, creds, &self.region, "s3", Utc::now())?;
+        }
+        let resp = self.client.execute(req)?;
+        let status = resp.status();
+        let body = resp.bytes()?.to_vec();
+        span.attr("http.response.status_code", status.as_u16());
+        span.attr("s3.bytes", body.len());
+        if status != StatusCode::OK {
+            span.error(status);
+            return Err(anyhow::format_err!(
+                "s3 returns {status}: {}",
+                String::from_utf8_lossy(&body)
+            ));
+        }
+        Ok(body)
+    }
+
+    /// Download an object.
+    pub fn get(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        self.send(Method::GET, key, &[])
+    }
+
+    /// List all objects under prefix (ListObjectsV2), following continuation tokens.
+    pub fn list(&self, prefix: &str) -> anyhow::Result<Vec<S3Object>> {
+        let mut objects = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(t) = &token {
+                query.push(("continuation-token", t));
+            }
+            let body = self.send(Method::GET, "", &query)?;
+            let body = String::from_utf8_lossy(&body);
+            for content in xml_blocks(&body, "Contents") {
+                let key = xml_tag(content, "Key").unwrap_or_default();
+                let size = xml_tag(content, "Size")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or_default();
+                objects.push(S3Object {
+                    key: xml_unescape(key),
+                    size,
+                });
+            }
+            if xml_tag(&body, "IsTruncated") != Some("true") {
+                break;
+            }
+            token = xml_tag(&body, "NextContinuationToken").map(xml_unescape);
+            if token.is_none() {
+                break;
+            }
+        }
+        Ok(objects)
+    }
+}
+
+// The list response is simple enough that a couple of string scans will do.
+fn xml_blocks<'a>(s: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    let mut blocks = vec![];
+    let mut rest = s;
+    while let Some(i) = rest.find(&open) {
+        rest = &rest[i + open.len()..];
+        match rest.find(&close) {
+            Some(j) => {
+                blocks.push(&rest[..j]);
+                rest = &rest[j + close.len()..];
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+fn xml_tag<'a>(s: &'a str, tag: &str) -> Option<&'a str> {
+    xml_blocks(s, tag).into_iter().next()
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use reqwest::{blocking::Request, header::HeaderValue};
+use ring::{
+    digest::{digest, SHA256},
+    hmac,
+};
+
+// AWS signature version 4, only the bits we need (header based signing).
+// Reference: https://docs.aws.amazon.com/general/latest/gr/sigv4_signing.html
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+pub fn hex(bs: &[u8]) -> String {
+    bs.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data.as_bytes()).as_ref().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date);
+    let k = hmac_sha256(&k, region);
+    let k = hmac_sha256(&k, service);
+    hmac_sha256(&k, "aws4_request")
+}
+
+// percent encode everything except unreserved characters (and optionally '/')
+pub fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if keep_slash => out.push('/'),
+            b => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn uri_decode(s: &str) -> String {
+    let bs = s.as_bytes();
+    let mut out = Vec::with_capacity(bs.len());
+    let mut i = 0;
+    while i < bs.len() {
+        if bs[i] == b'%' && i + 2 < bs.len() {
+            if let Ok(b) = u8::from_str_radix(&s[i + 1..i + 3], 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bs[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Sign the request in place, adding `x-amz-*` and `Authorization` headers.
+pub fn sign(
+    req: &mut Request,
+    creds: &Credentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload = req.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+    let payload_hash = hex(digest(&SHA256, payload).as_ref());
+
+    let url = req.url().clone();
+    let host = match (url.host_str(), url.port()) {
+        (Some(h), Some(p)) => format!("{}:{}", h, p),
+        (Some(h), None) => h.to_string(),
+        _ => return Err(anyhow::format_err!("url without host: {url}")),
+    };
+
+    let headers = req.headers_mut();
+    headers.insert("host", HeaderValue::from_str(&host)?);
+    headers.insert("x-amz-date", HeaderValue::from_str(&amz_date)?);
+    headers.insert("x-amz-content-sha256", HeaderValue::from_str(&payload_hash)?);
+    if let Some(token) = &creds.session_token {
+        headers.insert("x-amz-security-token", HeaderValue::from_str(token)?);
+    }
+
+    let mut signed: Vec<(String, String)> = headers
+        .iter()
+        .filter(|(k, _)| {
+            let k = k.as_str();
+            k == "host" || k == "content-type" || k.starts_with("x-amz-")
+        })
+        .map(|(k, v)| {
+            (
+                k.as_str().to_string(),
+                v.to_str().unwrap_or_default().trim().to_string(),
+            )
+        })
+        .collect();
+    signed.sort();
+    let canonical_headers: String = signed.iter().map(|(k, v)| format!("{k}:{v}\n")).collect();
+    let signed_headers = signed
+        .iter()
+        .map(|(k, _)| k.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let mut canonical_uri = uri_encode(&uri_decode(url.path()), true);
+    if service != "s3" {
+        // every service except s3 expects the path to be encoded twice
+        canonical_uri = uri_encode(&canonical_uri, true);
+    }
+    let mut query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| (uri_encode(&k, false), uri_encode(&v, false)))
+        .collect();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        req.method().as_str(),
+        canonical_uri,
+        canonical_query,
+        canonical_headers,
+        signed_headers,
+        payload_hash
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(digest(&SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let key = signing_key(&creds.secret_key, &date, region, service);
+    let signature = hex(&hmac_sha256(&key, &string_to_sign));
+    let auth = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        creds.access_key, scope, signed_headers, signature
+    );
+    req.headers_mut()
+        .insert("authorization", HeaderValue::from_str(&auth)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{hex, signing_key, uri_encode};
+
+    #[test]
+    fn test_signing_key() {
+        // example from aws documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("fake/abc:12 3", true), "fake/abc%3A12%203");
+        assert_eq!(uri_encode("fake/abc", false), "fake%2Fabc");
+    }
+}
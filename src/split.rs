@@ -0,0 +1,306 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::NaiveDateTime;
+use clap::Args;
+
+use crate::{
+    common::{format_bytes, gray, parse_bytes},
+    encode::fingerprint,
+    platform::{display_path, native_path},
+};
+
+// files kept open at once, the least recently written one is closed (and
+// appended to when its stream comes back) beyond
+const MAX_OPEN_FILES: usize = 256;
+
+struct StreamFile {
+    path: PathBuf,
+    w: Option<BufWriter<File>>,
+    entries: usize,
+    last_write: u64,
+}
+
+/// One output file per unique label set, each line is `<ts nanos>\t<line>`.
+pub struct StreamFiles {
+    dir: PathBuf,
+    files: HashMap<String, StreamFile>,
+    open: usize,
+    writes: u64,
+}
+
+// `{app="x", pod="a"}` becomes `app=x,pod=a-<fingerprint>.log`, anything
+// that is not safe in a file name is replaced by `_`. The stream
+// fingerprint keeps label sets apart that are alike once replaced or
+// joined, like {app="a,b=c"} and {app="a", b="c"}.
+pub fn stream_file_name(labels: &BTreeMap<String, String>) -> String {
+    let name = labels
+        .iter()
+        .filter(|(k, _)| k.as_str() != "__name__")
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "=,._-".contains(c) { c } else { '_' })
+        .collect();
+    if name.is_empty() {
+        name = "_".to_string();
+    }
+    // keep well below the usual 255 bytes limit
+    name.truncate(190);
+    format!("{name}-{:016x}.log", fingerprint(labels))
+}
+
+impl StreamFiles {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        let dir = native_path(dir);
+        std::fs::create_dir_all(&dir)?;
+        Ok(StreamFiles { dir, files: HashMap::new(), open: 0, writes: 0 })
+    }
+
+    // close the least recently written file
+    fn close_one(&mut self) -> anyhow::Result<()> {
+        let oldest = self.files.values_mut().filter(|f| f.w.is_some()).min_by_key(|f| f.last_write);
+        if let Some(mut w) = oldest.and_then(|f| f.w.take()) {
+            w.flush()?;
+            self.open -= 1;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, labels: &BTreeMap<String, String>, ts: i64, line: &str) -> anyhow::Result<()> {
+        let name = stream_file_name(labels);
+        let reopen = match self.files.get(&name) {
+            Some(f) => f.w.is_none(),
+            None => true,
+        };
+        if reopen {
+            if self.open >= MAX_OPEN_FILES {
+                self.close_one()?;
+            }
+            let path = self.dir.join(&name);
+            // truncated the first time only, appended to once reopened
+            let f = match self.files.contains_key(&name) {
+                true => OpenOptions::new().append(true).open(&path)?,
+                false => File::create(&path)?,
+            };
+            let file = self.files.entry(name.clone()).or_insert(StreamFile { path, w: None, entries: 0, last_write: 0 });
+            file.w = Some(BufWriter::new(f));
+            self.open += 1;
+        }
+        self.writes += 1;
+        let file = self.files.get_mut(&name).unwrap();
+        writeln!(file.w.as_mut().unwrap(), "{ts}\t{line}")?;
+        file.entries += 1;
+        file.last_write = self.writes;
+        Ok(())
+    }
+
+    /// Flush everything and print a line per written file.
+    pub fn finish(self) -> anyhow::Result<()> {
+        let mut files: Vec<_> = self.files.into_values().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        for f in files {
+            if let Some(mut w) = f.w {
+                w.flush()?;
+            }
+            println!("{} {}", display_path(&f.path), gray(&format!("{} entries", f.entries)));
+        }
+        Ok(())
+    }
+}
+
+/// One level of partitioned output, `label=NAME`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionKey(String);
+
+impl FromStr for PartitionKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some(("label", name)) if !name.is_empty() => Ok(PartitionKey(name.to_string())),
+            _ => Err(anyhow::format_err!("invalid partition {s}, expect label=NAME")),
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PartitionOpts {
+    /// Write ndjson entries into a hive style tree under --partition-dir,
+    /// a directory level per given label then a file per day, like
+    /// app=foo/2023-05-01.ndjson. Repeat for nested levels
+    #[clap(long, value_name = "label=NAME", requires = "partition_dir")]
+    pub partition_by: Vec<PartitionKey>,
+
+    /// Root directory of the partitioned output
+    #[clap(long, requires = "partition_by")]
+    pub partition_dir: Option<PathBuf>,
+
+    /// Continue in a new file (2023-05-01.1.ndjson, ...) once one reaches
+    /// this size
+    #[clap(long, default_value = "128MiB", value_parser = parse_bytes)]
+    pub max_file_size: u64,
+}
+
+// hive escapes what would break a path or the key=value split as %XX
+fn partition_value(v: &str) -> String {
+    let mut out = String::with_capacity(v.len());
+    for b in v.bytes() {
+        if b.is_ascii_alphanumeric() || b"._-@+ ".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// Directory (relative to the root) and day of an entry, entries without
+/// one of the labels go to the hive default partition.
+pub fn partition_path(keys: &[PartitionKey], labels: &BTreeMap<String, String>, ts: i64) -> (PathBuf, String) {
+    let mut dir = PathBuf::new();
+    for PartitionKey(name) in keys {
+        let value = match labels.get(name) {
+            Some(v) if !v.is_empty() => partition_value(v),
+            _ => "__HIVE_DEFAULT_PARTITION__".to_string(),
+        };
+        dir.push(format!("{}={value}", partition_value(name)));
+    }
+    let day = NaiveDateTime::from_timestamp_opt(ts.div_euclid(1_000_000_000), 0)
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "invalid-date".to_string());
+    (dir, day)
+}
+
+struct Part {
+    path: PathBuf,
+    w: BufWriter<File>,
+    // files of this partition and day so far
+    rolls: usize,
+    bytes: u64,
+}
+
+/// ndjson records spread over a partitioned directory tree.
+pub struct PartitionedFiles {
+    dir: PathBuf,
+    keys: Vec<PartitionKey>,
+    max_file_size: u64,
+    open: HashMap<(PathBuf, String), Part>,
+    // every file written, with its entries and bytes
+    written: BTreeMap<PathBuf, (usize, u64)>,
+}
+
+impl PartitionedFiles {
+    /// None unless --partition-by is given. `sub` is appended to the root
+    /// directory, e.g. for the tenant.
+    pub fn new(opts: &PartitionOpts, sub: Option<&str>) -> anyhow::Result<Option<Self>> {
+        let Some(dir) = opts.partition_dir.as_ref() else {
+            return Ok(None);
+        };
+        let dir = match sub {
+            Some(sub) => native_path(dir).join(sub),
+            None => native_path(dir),
+        };
+        std::fs::create_dir_all(&dir)?;
+        Ok(Some(PartitionedFiles {
+            dir,
+            keys: opts.partition_by.clone(),
+            max_file_size: opts.max_file_size.max(1),
+            open: HashMap::new(),
+            written: BTreeMap::new(),
+        }))
+    }
+
+    fn file_path(&self, dir: &Path, day: &str, roll: usize) -> PathBuf {
+        match roll {
+            0 => self.dir.join(dir).join(format!("{day}.ndjson")),
+            n => self.dir.join(dir).join(format!("{day}.{n}.ndjson")),
+        }
+    }
+
+    /// Append one record (a json object, without the newline).
+    pub fn write(&mut self, labels: &BTreeMap<String, String>, ts: i64, record: &[u8]) -> anyhow::Result<()> {
+        let key = partition_path(&self.keys, labels, ts);
+        let full = match self.open.get(&key) {
+            Some(part) => part.bytes > 0 && part.bytes + record.len() as u64 + 1 > self.max_file_size,
+            None => true,
+        };
+        if full {
+            let rolls = self.open.get(&key).map(|p| p.rolls).unwrap_or(0);
+            let path = self.file_path(&key.0, &key.1, rolls);
+            std::fs::create_dir_all(path.parent().unwrap_or(&self.dir))?;
+            let w = BufWriter::new(File::create(&path)?);
+            // the replaced file is flushed when dropped, but errors should not go unseen
+            if let Some(mut old) = self.open.insert(key.clone(), Part { path, w, rolls: rolls + 1, bytes: 0 }) {
+                old.w.flush()?;
+            }
+        }
+        let part = self.open.get_mut(&key).unwrap();
+        part.w.write_all(record)?;
+        part.w.write_all(b"\n")?;
+        part.bytes += record.len() as u64 + 1;
+        let stat = self.written.entry(part.path.clone()).or_default();
+        stat.0 += 1;
+        stat.1 += record.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Flush everything and print a line per written file.
+    pub fn finish(self) -> anyhow::Result<()> {
+        for (_, mut part) in self.open {
+            part.w.flush()?;
+        }
+        for (path, (entries, bytes)) in self.written.iter() {
+            println!("{} {}", display_path(path), gray(&format!("{entries} entries, {}", format_bytes(*bytes))));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stream_files() -> anyhow::Result<()> {
+        let labels = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let (joined, apart): (BTreeMap<_, _>, BTreeMap<_, _>) = (labels(&[("app", "a,b=c")]), labels(&[("app", "a"), ("b", "c")]));
+        assert_ne!(stream_file_name(&joined), stream_file_name(&apart));
+        assert_ne!(stream_file_name(&labels(&[("app", "a/b")])), stream_file_name(&labels(&[("app", "a_b")])));
+        assert!(stream_file_name(&joined).starts_with("app=a,b=c-"));
+
+        // more streams than files kept open, each file still gets all of its lines
+        let dir = std::env::temp_dir().join(format!("lf-test-split-{}", std::process::id()));
+        let mut out = StreamFiles::new(dir.clone())?;
+        let n = MAX_OPEN_FILES + 10;
+        for round in 0..2 {
+            for i in 0..n {
+                out.write(&labels(&[("i", &i.to_string())]), round, "x")?;
+            }
+        }
+        assert!(out.open <= MAX_OPEN_FILES);
+        out.finish()?;
+        let first = std::fs::read_to_string(dir.join(stream_file_name(&labels(&[("i", "0")]))))?;
+        assert_eq!(first, "0\tx\n1\tx\n");
+        assert_eq!(std::fs::read_dir(&dir)?.count(), n);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition_path() {
+        let keys: Vec<PartitionKey> = vec!["label=app".parse().unwrap(), "label=ns".parse().unwrap()];
+        let labels = BTreeMap::from([("app".to_string(), "a/b=c".to_string())]);
+        let (dir, day) = partition_path(&keys, &labels, 1_683_000_000_000_000_000);
+        assert_eq!(dir, PathBuf::from("app=a%2Fb%3Dc/ns=__HIVE_DEFAULT_PARTITION__"));
+        assert_eq!(day, "2023-05-02");
+        assert!("app".parse::<PartitionKey>().is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// The database of `lf decode --format sqlite`: one row per entry in
+// `entries`, the labels of each chunk in `labels`, both keyed by a chunk id
+// (its path). The tables are created when missing so that several runs
+// append to the same database, a chunk decoded again replaces its rows.
+//
+//   select l.value, count(*) from entries e join labels l
+//     on l.chunk_id = e.chunk_id and l.name = 'app' group by 1
+
+use std::{collections::BTreeMap, path::Path};
+
+use rusqlite::{params, Connection, Transaction};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS entries (
+    chunk_id TEXT NOT NULL,
+    block INTEGER NOT NULL,
+    -- unix nanoseconds
+    ts INTEGER NOT NULL,
+    line TEXT NOT NULL,
+    -- format v4, a json object of the pairs of the entry
+    structured_metadata TEXT
+);
+CREATE INDEX IF NOT EXISTS entries_chunk_id ON entries (chunk_id);
+CREATE TABLE IF NOT EXISTS labels (
+    chunk_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (chunk_id, name)
+);
+";
+
+pub struct EntryDb {
+    conn: Connection,
+}
+
+impl EntryDb {
+    /// Open (or create) the database at `path`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(EntryDb { conn })
+    }
+
+    /// Start writing the chunk `id`, dropping the rows of an earlier
+    /// decode of it. Nothing is visible until `ChunkRows::commit`.
+    pub fn chunk<'a>(&'a mut self, id: &str, labels: &BTreeMap<&String, &String>) -> anyhow::Result<ChunkRows<'a>> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM entries WHERE chunk_id = ?1", [id])?;
+        tx.execute("DELETE FROM labels WHERE chunk_id = ?1", [id])?;
+        for (name, value) in labels {
+            tx.execute("INSERT INTO labels VALUES (?1, ?2, ?3)", params![id, name, value])?;
+        }
+        Ok(ChunkRows { tx, id: id.to_string() })
+    }
+}
+
+/// The entries of one chunk, written in a single transaction.
+pub struct ChunkRows<'a> {
+    tx: Transaction<'a>,
+    id: String,
+}
+
+impl ChunkRows<'_> {
+    pub fn insert(&self, block: usize, ts: i64, line: &str, metadata: Option<&str>) -> anyhow::Result<()> {
+        let mut stmt = self.tx.prepare_cached("INSERT INTO entries VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        stmt.execute(params![self.id, block as i64, ts, line, metadata])?;
+        Ok(())
+    }
+
+    pub fn commit(self) -> anyhow::Result<()> {
+        Ok(self.tx.commit()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunk_replaced() {
+        let mut db = EntryDb::open(Path::new(":memory:")).unwrap();
+        let (app, x) = ("app".to_string(), "x".to_string());
+        let labels = BTreeMap::from([(&app, &x)]);
+        for lines in [&["a", "b"][..], &["c"]] {
+            let rows = db.chunk("fake/chunk", &labels).unwrap();
+            for (i, line) in lines.iter().enumerate() {
+                rows.insert(0, i as i64, line, None).unwrap();
+            }
+            rows.commit().unwrap();
+        }
+        let count = |sql: &str| db.conn.query_row(sql, [], |r| r.get::<_, i64>(0)).unwrap();
+        assert_eq!(count("SELECT count(*) FROM entries"), 1);
+        assert_eq!(count("SELECT count(*) FROM labels"), 1);
+    }
+}
@@ -0,0 +1,268 @@
+// Statistics of a chunk for `lf decode --stats`: the sizes and time range
+// of every block from the block metas, and the lengths of the decoded lines
+// as an average and a histogram with power of two buckets. `--order-report`
+// looks for entries older than one before them, within a block or across
+// blocks (unordered_writes).
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use crate::{
+    common::{format_bytes, gray, green, yellow},
+    decode::{open_decoded, Decode, EntryFilter},
+    error::DecodeError,
+    proxy::format_labels,
+    timefmt, timing,
+    ty::BlockMeta,
+};
+
+// line length histogram buckets: [0, 16), [16, 32), ... [32K, 64K), 64K+
+const BUCKETS: usize = 14;
+
+#[derive(Debug, Default)]
+struct LineStats {
+    lines: usize,
+    bytes: usize,
+    buckets: [usize; BUCKETS],
+}
+
+impl LineStats {
+    fn add(&mut self, len: usize) {
+        self.lines += 1;
+        self.bytes += len;
+        let bucket = (usize::BITS - (len >> 4).leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+    }
+}
+
+fn bucket_name(i: usize) -> String {
+    let bound = |i: usize| match 16usize << i {
+        b if b >= 1024 => format!("{}K", b / 1024),
+        b => b.to_string(),
+    };
+    match i {
+        0 => "< 16".to_string(),
+        i if i == BUCKETS - 1 => format!(">= {}", bound(i - 1)),
+        i => format!("< {}", bound(i)),
+    }
+}
+
+fn ratio(uncompressed: usize, compressed: usize) -> String {
+    match (uncompressed, compressed) {
+        // format v1 and v2 block metas have no uncompressed size
+        (0, _) | (_, 0) => "-".to_string(),
+        (u, c) => format!("{:.2}", u as f64 / c as f64),
+    }
+}
+
+/// Print the statistics of the chunk `file`, the lines counted are the
+/// ones of the time range, --block and the entry filters of `d`.
+pub fn chunk_stats(d: &Decode, file: &Path) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, mut blocks) = open_decoded(d, file)?;
+    let labels = format_labels(header.metric.iter().filter(|(k, _)| *k != "__name__").collect::<BTreeMap<_, _>>());
+    let meta = blocks.meta.clone();
+    println!("{} {}", green(&labels), gray(&format!("tenant {}", header.user_id)));
+    println!(
+        "{}",
+        gray(&format!("chunk format v{}, {:?}, {} blocks", blocks.format, blocks.ty, meta.num_blocks))
+    );
+    println!(
+        "  {:>5} {:>8} {:>12} {:>12} {:>6}  {:<23}  maxt",
+        "block", "entries", "compressed", "uncompressed", "ratio", "mint"
+    );
+    let time = |t: &chrono::NaiveDateTime| timefmt::format(t, "%Y-%m-%d %H:%M:%S%.3f");
+    let mut lines = LineStats::default();
+    for (i, m) in meta.block_metas.iter().enumerate() {
+        let block = blocks.next().ok_or(DecodeError::Truncated("block"))?;
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        filter.block(&mut block);
+        for e in block.entries.iter() {
+            lines.add(e.line.len());
+        }
+        println!(
+            "  {:>5} {:>8} {:>12} {:>12} {:>6}  {:<23}  {}",
+            i,
+            m.num_entries,
+            format_bytes(m.compressed_size as u64),
+            format_bytes(m.uncompressed_size as u64),
+            ratio(m.uncompressed_size, m.compressed_size),
+            time(&m.mint),
+            time(&m.maxt)
+        );
+    }
+    let sum = |f: fn(&BlockMeta) -> usize| meta.block_metas.iter().map(f).sum::<usize>();
+    let (compressed, uncompressed) = (sum(|m| m.compressed_size), sum(|m| m.uncompressed_size));
+    let mint = meta.block_metas.iter().map(|m| m.mint).min();
+    let maxt = meta.block_metas.iter().map(|m| m.maxt).max();
+    println!(
+        "  {:>5} {:>8} {:>12} {:>12} {:>6}  {:<23}  {}",
+        "total",
+        sum(|m| m.num_entries),
+        format_bytes(compressed as u64),
+        format_bytes(uncompressed as u64),
+        ratio(uncompressed, compressed),
+        mint.as_ref().map(time).unwrap_or_default(),
+        maxt.as_ref().map(time).unwrap_or_default()
+    );
+
+    if lines.lines == 0 {
+        println!("{}", gray("no lines decoded"));
+        return Ok(());
+    }
+    println!(
+        "{}",
+        green(&format!(
+            "{} lines, {} in total, {:.1} bytes on average",
+            lines.lines,
+            format_bytes(lines.bytes as u64),
+            lines.bytes as f64 / lines.lines as f64
+        ))
+    );
+    let max = lines.buckets.iter().copied().max().unwrap_or(1);
+    let first = lines.buckets.iter().position(|&n| n > 0).unwrap_or(0);
+    let last = lines.buckets.iter().rposition(|&n| n > 0).unwrap_or(0);
+    for (i, n) in lines.buckets.iter().enumerate().take(last + 1).skip(first) {
+        let bar = "#".repeat((n * 40).div_ceil(max));
+        println!("  {:>8} {:>8} {}", bucket_name(i), n, green(&bar));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct BlockOrder {
+    entries: usize,
+    // older than an entry before them in the same block
+    within: usize,
+    // in order in their block, older than an entry of an earlier block
+    across: usize,
+    max_skew: i64,
+}
+
+// entries older than the newest one before them, in chunk order
+#[derive(Debug, Default)]
+struct OrderStats {
+    blocks: Vec<BlockOrder>,
+    entries: usize,
+    out_of_order: usize,
+    // runs of consecutive out of order entries
+    spans: usize,
+    max_skew: i64,
+    newest: Option<i64>,
+    block_newest: Option<i64>,
+    in_span: bool,
+}
+
+impl OrderStats {
+    fn start_block(&mut self) {
+        self.blocks.push(BlockOrder::default());
+        self.block_newest = None;
+    }
+
+    fn add(&mut self, ts: i64) {
+        let block = self.blocks.last_mut().expect("start_block first");
+        block.entries += 1;
+        self.entries += 1;
+        let skew = self.newest.map_or(0, |n| n - ts);
+        if skew > 0 {
+            match self.block_newest.is_some_and(|n| n > ts) {
+                true => block.within += 1,
+                false => block.across += 1,
+            }
+            block.max_skew = block.max_skew.max(skew);
+            self.max_skew = self.max_skew.max(skew);
+            self.out_of_order += 1;
+            self.spans += !self.in_span as usize;
+        }
+        self.in_span = skew > 0;
+        self.newest = self.newest.max(Some(ts));
+        self.block_newest = self.block_newest.max(Some(ts));
+    }
+}
+
+fn format_skew(ns: i64) -> String {
+    // humantime down to the millisecond, the nanoseconds are noise here
+    let ms = Duration::from_millis((ns / 1_000_000) as u64);
+    match ms.is_zero() {
+        true => format!("{ns}ns"),
+        false => humantime::format_duration(ms).to_string(),
+    }
+}
+
+/// Print the entries of the chunk `file` which are older than one before
+/// them, within a block or across blocks, with the blocks they are in.
+pub fn order_report(d: &Decode, file: &Path) -> anyhow::Result<()> {
+    let filter = EntryFilter::new(d)?;
+    let (header, blocks) = open_decoded(d, file)?;
+    let labels = format_labels(header.metric.iter().filter(|(k, _)| *k != "__name__").collect::<BTreeMap<_, _>>());
+    println!("{} {}", green(&labels), gray(&format!("tenant {}", header.user_id)));
+    let mut order = OrderStats::default();
+    for block in blocks {
+        let mut block = timing::time("decompress", || block).map_err(DecodeError::from)?;
+        filter.block(&mut block);
+        order.start_block();
+        for e in block.entries.iter() {
+            order.add(e.time.timestamp_nanos());
+        }
+    }
+    if order.out_of_order == 0 {
+        println!("{}", green(&format!("all {} entries in order", order.entries)));
+        return Ok(());
+    }
+    println!(
+        "{}",
+        yellow(&format!(
+            "{} of {} entries out of order in {} spans, max skew {}",
+            order.out_of_order,
+            order.entries,
+            order.spans,
+            format_skew(order.max_skew)
+        ))
+    );
+    println!("  {:>5} {:>8} {:>8} {:>8}  max skew", "block", "entries", "within", "across");
+    for (i, b) in order.blocks.iter().enumerate().filter(|(_, b)| b.within + b.across > 0) {
+        println!(
+            "  {:>5} {:>8} {:>8} {:>8}  {}",
+            i,
+            b.entries,
+            b.within,
+            b.across,
+            format_skew(b.max_skew)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_buckets() {
+        let mut s = LineStats::default();
+        for len in [0, 15, 16, 31, 32, 100, 1 << 20] {
+            s.add(len);
+        }
+        assert_eq!(&s.buckets[..4], &[2, 2, 1, 1]);
+        assert_eq!(s.buckets[BUCKETS - 1], 1);
+        assert_eq!(bucket_name(0), "< 16");
+        assert_eq!(bucket_name(6), "< 1K");
+        assert_eq!(bucket_name(BUCKETS - 1), ">= 64K");
+    }
+
+    #[test]
+    fn test_order_stats() {
+        let mut order = OrderStats::default();
+        order.start_block();
+        for ts in [10, 20, 15, 16, 30] {
+            order.add(ts);
+        }
+        order.start_block();
+        for ts in [25, 40, 41] {
+            order.add(ts);
+        }
+        assert_eq!((order.out_of_order, order.spans, order.max_skew), (3, 2, 5));
+        assert_eq!((order.blocks[0].within, order.blocks[0].across), (2, 0));
+        assert_eq!((order.blocks[1].within, order.blocks[1].across), (0, 1));
+        assert_eq!(format_skew(1_500_000_000), "1s 500ms");
+    }
+}
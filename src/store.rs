@@ -0,0 +1,429 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver},
+        Condvar, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use clap::Parser;
+use regex::Regex;
+use tracing::debug;
+
+use crate::{
+    common::{format_bytes, format_millis, gray, green, parse_bytes, red, ChunkRef, TimeRangeOpts},
+    grep::{grep_chunk_bytes, optional_range},
+    platform::{chunk_file_name, display_path, native_path},
+    query::optional_duration,
+    s3::{parse_s3_url, S3Client, S3Object, S3Opts},
+    split::{PartitionOpts, PartitionedFiles},
+};
+
+/// object store inspection
+#[derive(Parser, Debug)]
+pub struct Store {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// list chunk objects, optionally filtered by tenant and time range
+    #[clap(aliases=&["l", "list"])]
+    Ls(LsCommand),
+
+    /// download, decode and filter chunks into ndjson lines of
+    /// {"ts", "labels", "line"}, in the order chunks finish
+    Export(ExportCommand),
+}
+
+#[derive(Parser, Debug)]
+struct LsCommand {
+    #[command(flatten)]
+    s3: S3Opts,
+
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// object store url, like s3://bucket/prefix
+    url: String,
+
+    /// only list chunks of this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+struct ExportCommand {
+    #[command(flatten)]
+    s3: S3Opts,
+
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+
+    /// object store url, like s3://bucket/prefix
+    url: String,
+
+    /// only export chunks of this tenant
+    #[arg(short, long)]
+    tenant: Option<String>,
+
+    /// only export lines matching this regex
+    #[arg(short, long)]
+    grep: Option<String>,
+
+    /// output file, stdout if not given
+    #[arg(short, long, conflicts_with = "partition_by")]
+    out: Option<PathBuf>,
+
+    #[command(flatten)]
+    partition: PartitionOpts,
+
+    /// number of concurrent downloads
+    #[clap(long, default_value = "8")]
+    download_concurrency: usize,
+
+    /// number of chunks decoded at once
+    #[clap(long, default_value = "4")]
+    decode_concurrency: usize,
+
+    /// bytes held in memory at once: downloaded chunks, then their
+    /// decoded lines until written. Downloads wait when it's used up
+    #[clap(long, default_value = "256MiB", value_parser = parse_bytes)]
+    memory_budget: u64,
+}
+
+pub fn store(s: Store) -> anyhow::Result<()> {
+    match s.cmd {
+        SubCommand::Ls(ls) => list_chunks(ls),
+        SubCommand::Export(e) => export(e),
+    }
+}
+
+// loki/pkg/storage/chunk/client/object_client.go FSEncoder: the filesystem
+// object client writes schema v11 keys (tenant/fp:from:through:checksum) as
+// one base64 name, v12+ keys (tenant/fp/from:through:checksum) keep their
+// directories and only the last part is base64. Chunks copied by hand or
+// written by gen-chunk keep the key as it is.
+fn fs_chunk_candidates(key: &str) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if let Some((dir, name)) = key.rsplit_once('/') {
+        paths.push(PathBuf::from(dir).join(chunk_file_name(name)));
+    }
+    paths.push(PathBuf::from(base64::encode(key)));
+    if let Some((dir, name)) = key.rsplit_once('/').filter(|(dir, _)| dir.contains('/')) {
+        paths.push(PathBuf::from(dir).join(base64::encode(name)));
+    }
+    paths
+}
+
+/// Path of the chunk of external `key` under the `root` directory of a
+/// filesystem object store, the first of the names loki may have used that
+/// exists.
+pub fn fs_chunk_path(root: &Path, key: &str) -> anyhow::Result<PathBuf> {
+    ChunkRef::parse_external_key(key)?;
+    let candidates = fs_chunk_candidates(key);
+    if let Some(path) = candidates.iter().map(|p| root.join(p)).find(|p| p.is_file()) {
+        debug!("chunk {key} at {}", path.display());
+        return Ok(path);
+    }
+    let tried = candidates.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ");
+    Err(anyhow::format_err!("chunk {key} not found under {}, tried {tried}", display_path(root)))
+}
+
+// bucket and the object prefix of the chunks, of one tenant if given
+fn chunk_prefix(url: &str, tenant: Option<&String>) -> anyhow::Result<(String, String)> {
+    let (bucket, mut prefix) = parse_s3_url(url)?;
+    if let Some(tenant) = tenant {
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        prefix.push_str(tenant);
+        prefix.push('/');
+    }
+    Ok((bucket, prefix))
+}
+
+// chunk objects under prefix overlapping `range` (milliseconds), and the
+// number of objects listed
+fn list_chunk_objects(
+    client: &S3Client,
+    prefix: &str,
+    tenant: Option<&String>,
+    range: Option<(i64, i64)>,
+) -> anyhow::Result<(Vec<(S3Object, ChunkRef)>, usize)> {
+    let objects = client.list(prefix)?;
+    let listed = objects.len();
+    let mut chunks = vec![];
+    for obj in objects {
+        // chunk keys are relative to the store prefix, so strip it
+        // before parsing the tenant out of the key
+        let key = obj.key.strip_prefix(prefix).unwrap_or(&obj.key).trim_start_matches('/');
+        let key = match tenant {
+            Some(t) => format!("{t}/{key}"),
+            None => key.to_string(),
+        };
+        let chunk_ref = match ChunkRef::parse_external_key(&key) {
+            Ok(r) => r,
+            Err(err) => {
+                debug!("skip {}: {err}", obj.key);
+                continue;
+            }
+        };
+        if let Some((start, end)) = range {
+            if chunk_ref.to < start || chunk_ref.from > end {
+                continue;
+            }
+        }
+        chunks.push((obj, chunk_ref));
+    }
+    Ok((chunks, listed))
+}
+
+fn list_chunks(ls: LsCommand) -> anyhow::Result<()> {
+    let (bucket, prefix) = chunk_prefix(&ls.url, ls.tenant.as_ref())?;
+    let range = optional_duration(&ls.time_range)?.map(|(start, end)| (start.timestamp_millis(), end.timestamp_millis()));
+
+    let client = S3Client::new(&ls.s3, &bucket)?;
+    let (chunks, listed) = list_chunk_objects(&client, &prefix, ls.tenant.as_ref(), range)?;
+    for (obj, chunk_ref) in chunks.iter() {
+        println!(
+            "{}\t{}\t{} ~ {}",
+            green(&obj.key),
+            obj.size,
+            format_millis(chunk_ref.from),
+            format_millis(chunk_ref.to)
+        );
+    }
+    println!(
+        "{}",
+        gray(&format!("{} chunks matched, {} objects listed", chunks.len(), listed))
+    );
+    Ok(())
+}
+
+// Bytes held by chunks between download and write. A chunk larger than the
+// whole budget still goes through once nothing else is held.
+struct Budget {
+    limit: u64,
+    // used bytes, closed
+    state: Mutex<(u64, bool)>,
+    freed: Condvar,
+}
+
+impl Budget {
+    fn new(limit: u64) -> Budget {
+        Budget {
+            limit,
+            state: Mutex::new((0, false)),
+            freed: Condvar::new(),
+        }
+    }
+
+    // wait for `n` bytes, false once closed
+    fn acquire(&self, n: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && state.0 > 0 && state.0 + n > self.limit {
+            state = self.freed.wait(state).unwrap();
+        }
+        state.0 += n;
+        !state.1
+    }
+
+    // a held chunk changed size (decoded), never waits
+    fn resize(&self, from: u64, to: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = (state.0 + to).saturating_sub(from);
+        self.freed.notify_all();
+    }
+
+    fn release(&self, n: u64) {
+        self.resize(n, 0);
+    }
+
+    // wake and stop everyone waiting, the writer is gone
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.freed.notify_all();
+    }
+}
+
+// a chunk travelling through the stages with the bytes it holds
+struct Job<T> {
+    key: String,
+    held: u64,
+    data: anyhow::Result<T>,
+}
+
+// the matching entries of a chunk
+struct Exported {
+    // one json object per line
+    ndjson: Vec<u8>,
+    // of every line
+    ts: Vec<i64>,
+    labels: BTreeMap<String, String>,
+}
+
+fn export_lines(
+    bs: &[u8],
+    re: &Regex,
+    range: Option<(i64, i64)>,
+) -> anyhow::Result<Exported> {
+    let matched = grep_chunk_bytes(bs, re, range, &[], None)?;
+    let mut out = vec![];
+    let mut ts = vec![];
+    for e in matched.lines.iter() {
+        let obj = serde_json::json!({
+            "ts": e.time.timestamp_nanos(),
+            "labels": matched.labels,
+            "line": e.line,
+        });
+        serde_json::to_writer(&mut out, &obj)?;
+        out.push(b'\n');
+        ts.push(e.time.timestamp_nanos());
+    }
+    Ok(Exported { ndjson: out, ts, labels: matched.metric })
+}
+
+// list -> download -> decode + filter -> write, stages are connected by
+// bounded channels and downloads wait on the memory budget, so a slow
+// writer or decoder holds back the downloads instead of piling up chunks.
+fn export(e: ExportCommand) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let re = Regex::new(e.grep.as_deref().unwrap_or(""))?;
+    let range = optional_range(&e.time_range)?;
+    let (bucket, prefix) = chunk_prefix(&e.url, e.tenant.as_ref())?;
+    let client = S3Client::new(&e.s3, &bucket)?;
+    let chunk_range = range.map(|(from, to)| (from / 1_000_000, to / 1_000_000));
+    let (chunks, listed) = list_chunk_objects(&client, &prefix, e.tenant.as_ref(), chunk_range)?;
+    eprintln!(
+        "{}",
+        gray(&format!("{} chunks to export, {} objects listed", chunks.len(), listed))
+    );
+
+    let mut out: BufWriter<Box<dyn Write>> = BufWriter::new(match &e.out {
+        Some(path) => Box::new(std::fs::File::create(native_path(path))?),
+        None => Box::new(std::io::stdout()),
+    });
+    let mut partitioned = PartitionedFiles::new(&e.partition, None)?;
+    let downloaders = e.download_concurrency.max(1);
+    let decoders = e.decode_concurrency.max(1);
+    let budget = Budget::new(e.memory_budget);
+    let next = AtomicUsize::new(0);
+    let downloaded = AtomicU64::new(0);
+    let (downloaded_tx, downloaded_rx) = sync_channel::<Job<Vec<u8>>>(decoders);
+    let downloaded_rx = Mutex::new(downloaded_rx);
+    let (decoded_tx, decoded_rx) = sync_channel::<Job<Exported>>(decoders);
+
+    let (mut entries, mut written, mut failed) = (0, 0, 0);
+    let result = thread::scope(|s| -> anyhow::Result<()> {
+        for _ in 0..downloaders.min(chunks.len()) {
+            let tx = downloaded_tx.clone();
+            let (budget, next, chunks, client, downloaded) = (&budget, &next, &chunks, &client, &downloaded);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((obj, _)) = chunks.get(i) else {
+                    break;
+                };
+                if !budget.acquire(obj.size) {
+                    break;
+                }
+                let data = client.get(&obj.key);
+                let held = data.as_ref().map(|bs| bs.len() as u64).unwrap_or_default();
+                downloaded.fetch_add(held, Ordering::Relaxed);
+                budget.resize(obj.size, held);
+                let job = Job { key: obj.key.clone(), held, data };
+                if tx.send(job).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(downloaded_tx);
+        for _ in 0..decoders {
+            let tx = decoded_tx.clone();
+            let (budget, rx, re) = (&budget, &downloaded_rx, &re);
+            s.spawn(move || loop {
+                let Ok(job) = rx.lock().unwrap().recv() else {
+                    break;
+                };
+                let data = job.data.and_then(|bs| export_lines(&bs, re, range));
+                let held = data.as_ref().map(|x| x.ndjson.len() as u64).unwrap_or_default();
+                budget.resize(job.held, held);
+                if tx.send(Job { key: job.key, held, data }).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(decoded_tx);
+
+        // takes the receiver so decoders see it gone when writing fails
+        let mut write = |rx: Receiver<Job<Exported>>| -> anyhow::Result<()> {
+            for job in rx.iter() {
+                match job.data {
+                    Ok(x) => {
+                        match partitioned.as_mut() {
+                            // every record is a line, newlines in them are escaped
+                            Some(p) => {
+                                for (record, ts) in x.ndjson.split(|b| *b == b'\n').zip(x.ts.iter()) {
+                                    p.write(&x.labels, *ts, record)?;
+                                }
+                            }
+                            None => out.write_all(&x.ndjson)?,
+                        }
+                        entries += x.ts.len();
+                        written += 1;
+                    }
+                    Err(err) => {
+                        eprintln!("{}", red(&format!("{}: {err}", job.key)));
+                        failed += 1;
+                    }
+                }
+                budget.release(job.held);
+            }
+            out.flush()?;
+            Ok(())
+        };
+        let result = write(decoded_rx);
+        if result.is_err() {
+            // stop downloads and unblock the ones waiting to hand over
+            budget.close();
+            while downloaded_rx.lock().unwrap().recv().is_ok() {}
+        }
+        result
+    });
+    result?;
+    if let Some(p) = partitioned {
+        p.finish()?;
+    }
+    eprintln!(
+        "{}",
+        gray(&format!(
+            "{entries} entries from {written} chunks ({} downloaded) in {:.1?}",
+            format_bytes(downloaded.into_inner()),
+            started.elapsed()
+        ))
+    );
+    if failed > 0 {
+        return Err(anyhow::format_err!("{failed} chunks failed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fs_chunk_candidates() {
+        let v11 = fs_chunk_candidates("fake/6e5ae9d27700e57c:181b03d5300:181b047e7f6:d78a776c");
+        assert_eq!(v11[1], PathBuf::from("ZmFrZS82ZTVhZTlkMjc3MDBlNTdjOjE4MWIwM2Q1MzAwOjE4MWIwNDdlN2Y2OmQ3OGE3NzZj"));
+        assert_eq!(v11.len(), 2);
+        let v12 = fs_chunk_candidates("fake/6e5ae9d27700e57c/181b03d5300:181b047e7f6:d78a776c");
+        assert_eq!(v12[2], PathBuf::from("fake/6e5ae9d27700e57c/MTgxYjAzZDUzMDA6MTgxYjA0N2U3ZjY6ZDc4YTc3NmM="));
+    }
+}
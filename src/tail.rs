@@ -0,0 +1,169 @@
+use std::{
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Duration,
+};
+
+use chrono::{Local, NaiveDateTime};
+use clap::Parser;
+use reqwest::StatusCode;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    common::{blue, gray, green, maybe_print_curl, parse_duration, red, refine_loki_request, yellow, HttpOpts},
+    error::ApiError,
+    timing,
+};
+
+/// follow new lines of one or more queries (polling query_range)
+#[derive(Parser, Debug)]
+pub struct Tail {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    /// LogQL queries to follow, give several to watch them side by side
+    #[clap(short, long, required = true, num_args = 1..)]
+    query: Vec<String>,
+
+    /// Poll interval
+    #[clap(long, default_value = "2s", value_parser = parse_duration)]
+    interval: Duration,
+
+    /// How far back to start following from
+    #[clap(long, default_value = "10s", value_parser = parse_duration)]
+    since: Duration,
+
+    /// Max lines fetched per poll and query
+    #[clap(short, long, default_value = "1000")]
+    limit: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct TailRequest<'a> {
+    start: i64,
+    end: i64,
+    limit: u32,
+    direction: &'a str,
+    query: &'a str,
+}
+
+enum Event {
+    Line { idx: usize, ts: i64, line: String },
+    Error { idx: usize, err: String },
+}
+
+fn prefix(idx: usize) -> String {
+    let p = format!("[{idx}]");
+    match idx % 4 {
+        0 => green(&p),
+        1 => blue(&p),
+        2 => yellow(&p),
+        _ => red(&p),
+    }
+}
+
+/// Lines of a forward range query, oldest first.
+pub(crate) fn poll(http: &HttpOpts, query: &str, start: i64, end: i64, limit: u32) -> anyhow::Result<Vec<(i64, String)>> {
+    let client = reqwest::blocking::Client::new();
+    let req = client.get(format!("{}/loki/api/v1/query_range", http.endpoint));
+    let req = refine_loki_request(req, http.headers.clone(), http.basic_auth.clone(), http.tenant.clone());
+    let req = req.query(&TailRequest {
+        start,
+        end,
+        limit,
+        direction: "forward",
+        query,
+    });
+    let resp = timing::send(http.sign(req)?)?;
+    if resp.status() != StatusCode::OK {
+        return Err(ApiError::status("tail", resp).into());
+    }
+    let obj: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let mut lines = vec![];
+    for r in obj["data"]["result"].as_array().into_iter().flatten() {
+        for value in r["values"].as_array().into_iter().flatten() {
+            let ts = value[0].as_str().and_then(|s| s.parse().ok()).unwrap_or_default();
+            lines.push((ts, value[1].as_str().unwrap_or_default().to_string()));
+        }
+    }
+    lines.sort_by_key(|l| l.0);
+    Ok(lines)
+}
+
+fn follow(idx: usize, http: HttpOpts, query: String, t: (Duration, Duration, u32), tx: Sender<Event>) {
+    let (interval, since, limit) = t;
+    let mut start = Local::now().timestamp_nanos() - since.as_nanos() as i64;
+    loop {
+        let end = Local::now().timestamp_nanos();
+        match poll(&http, &query, start, end, limit) {
+            Ok(lines) => {
+                debug!("[{idx}] {} lines in [{start}, {end}]", lines.len());
+                for (ts, line) in lines {
+                    // the next poll starts right after the newest line we have seen
+                    start = start.max(ts + 1);
+                    if tx.send(Event::Line { idx, ts, line }).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(err) => {
+                if tx.send(Event::Error { idx, err: err.to_string() }).is_err() {
+                    return;
+                }
+            }
+        }
+        thread::sleep(interval);
+    }
+}
+
+pub fn tail(t: Tail) -> anyhow::Result<()> {
+    if t.http.print_curl {
+        let client = reqwest::blocking::Client::new();
+        let end = Local::now().timestamp_nanos();
+        for q in t.query.iter() {
+            let req = client.get(format!("{}/loki/api/v1/query_range", t.http.endpoint));
+            let req = refine_loki_request(req, t.http.headers.clone(), t.http.basic_auth.clone(), t.http.tenant.clone());
+            let req = req.query(&TailRequest {
+                start: end - t.since.as_nanos() as i64,
+                end,
+                limit: t.limit,
+                direction: "forward",
+                query: q,
+            });
+            maybe_print_curl(&t.http.sign(req)?, true, t.http.show_secrets)?;
+        }
+        return Ok(());
+    }
+
+    for (idx, q) in t.query.iter().enumerate() {
+        println!("{} {}", prefix(idx), gray(q));
+    }
+    let (tx, rx) = channel();
+    for (idx, q) in t.query.iter().enumerate() {
+        let tx = tx.clone();
+        let http = t.http.clone();
+        let q = q.clone();
+        let opts = (t.interval, t.since, t.limit);
+        thread::spawn(move || follow(idx, http, q, opts, tx));
+    }
+    drop(tx);
+
+    for event in rx {
+        match event {
+            Event::Line { idx, ts, line } => {
+                let date = NaiveDateTime::from_timestamp_opt(
+                    ts.div_euclid(1_000_000_000),
+                    ts.rem_euclid(1_000_000_000) as u32,
+                )
+                .unwrap_or_default();
+                let date_str = date.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+                println!("{} {} {} {line}", prefix(idx), gray(&date_str), blue("|"));
+            }
+            Event::Error { idx, err } => {
+                eprintln!("{} {}", prefix(idx), red(&err));
+            }
+        }
+    }
+    Ok(())
+}
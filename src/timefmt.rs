@@ -0,0 +1,112 @@
+// How decoded timestamps are written, the --tz and --ts-format of
+// `lf decode`. Process wide like --timing: the entry and block meta
+// serializers of ty.rs have no other way to get at the options. Unset,
+// timestamps are written as naive UTC as before.
+
+use std::{fmt::Write, str::FromStr, sync::OnceLock};
+
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone)]
+pub enum Zone {
+    Utc,
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utc" => Ok(Zone::Utc),
+            "local" => Ok(Zone::Local),
+            _ => s
+                .parse()
+                .map(Zone::Named)
+                .map_err(|_| anyhow::format_err!("unknown time zone {s}, expected local, utc or a zone like Europe/Berlin")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeFormat {
+    zone: Zone,
+    // strftime, rfc3339 when unset
+    format: Option<String>,
+}
+
+static FORMAT: OnceLock<TimeFormat> = OnceLock::new();
+
+/// Write the timestamps in `zone` (utc when unset) and with the strftime
+/// `format` (rfc3339 with the offset when unset) from now on. Nothing
+/// changes when both are unset, a bad format fails here rather than half
+/// way through the output.
+pub fn set(zone: Option<Zone>, format: Option<String>) -> anyhow::Result<()> {
+    if zone.is_none() && format.is_none() {
+        return Ok(());
+    }
+    if let Some(f) = format.as_ref() {
+        let mut s = String::new();
+        write!(s, "{}", Utc.timestamp_nanos(0).format(f)).map_err(|_| anyhow::format_err!("bad --ts-format {f}"))?;
+    }
+    let zone = zone.unwrap_or(Zone::Utc);
+    _ = FORMAT.set(TimeFormat { zone, format });
+    Ok(())
+}
+
+fn in_zone<T: TimeZone>(zone: &T, t: &NaiveDateTime, format: Option<&str>) -> String
+where
+    T::Offset: std::fmt::Display,
+{
+    let t: DateTime<T> = zone.from_utc_datetime(t);
+    match format {
+        Some(f) => t.format(f).to_string(),
+        None => t.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+/// The utc timestamp `t` as set by `set`, or with `default` (strftime of
+/// the naive utc time) when nothing was.
+pub fn format(t: &NaiveDateTime, default: &str) -> String {
+    match FORMAT.get() {
+        None => t.format(default).to_string(),
+        Some(f) => {
+            let format = f.format.as_deref();
+            match &f.zone {
+                Zone::Utc => in_zone(&Utc, t, format),
+                Zone::Local => in_zone(&Local, t, format),
+                Zone::Named(tz) => in_zone(tz, t, format),
+            }
+        }
+    }
+}
+
+/// serde serialize_with of timestamps, the naive utc serialization when
+/// nothing was set.
+pub fn serialize<S: Serializer>(t: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    match FORMAT.get() {
+        None => t.serialize(serializer),
+        Some(_) => serializer.serialize_str(&format(t, "")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_in_zone() {
+        let t = NaiveDateTime::from_timestamp_opt(1661946709, 123_000_000).unwrap();
+        let Ok(Zone::Named(berlin)) = "Europe/Berlin".parse() else {
+            panic!("Europe/Berlin not parsed");
+        };
+        assert_eq!(in_zone(&berlin, &t, None), "2022-08-31T13:51:49.123+02:00");
+        assert_eq!(in_zone(&Utc, &t, None), "2022-08-31T11:51:49.123Z");
+        assert_eq!(in_zone(&berlin, &t, Some("%H:%M %Z")), "13:51 CEST");
+        assert!(matches!("UTC".parse(), Ok(Zone::Utc)));
+        assert!("Mars/Base".parse::<Zone>().is_err());
+    }
+}
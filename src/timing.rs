@@ -0,0 +1,122 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use reqwest::blocking::{RequestBuilder, Response};
+
+use crate::{common::gray, fixture, trace};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// phase name, accumulated duration, count; in first seen order
+static PHASES: Mutex<Vec<(&'static str, Duration, u32)>> = Mutex::new(Vec::new());
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record(phase: &'static str, d: Duration) {
+    if !enabled() {
+        return;
+    }
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|p| p.0 == phase) {
+        Some(p) => {
+            p.1 += d;
+            p.2 += 1;
+        }
+        None => phases.push((phase, d, 1)),
+    }
+}
+
+/// Run `f`, accounting the time it takes to `phase`. It is also a span
+/// when tracing.
+pub fn time<T, F: FnOnce() -> T>(phase: &'static str, f: F) -> T {
+    let _span = trace::span(phase);
+    let start = Instant::now();
+    let r = f();
+    record(phase, start.elapsed());
+    r
+}
+
+// reqwest does not expose its connection phases, so with --timing a
+// separate resolve + tcp connect to the same host is measured first
+fn probe(req: &RequestBuilder) {
+    let Some(url) = req.try_clone().and_then(|r| r.build().ok()).map(|r| r.url().clone()) else {
+        return;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return;
+    };
+    let start = Instant::now();
+    let addrs: Vec<_> = match (host, port).to_socket_addrs() {
+        Ok(a) => a.collect(),
+        Err(_) => return,
+    };
+    record("dns", start.elapsed());
+    if let Some(addr) = addrs.first() {
+        let start = Instant::now();
+        if TcpStream::connect_timeout(addr, Duration::from_secs(10)).is_ok() {
+            record("connect", start.elapsed());
+        }
+    }
+}
+
+/// Send the request, the time until the response headers arrive is
+/// recorded as ttfb (it includes connect and tls of the real request).
+pub fn send(req: RequestBuilder) -> anyhow::Result<Response> {
+    if enabled() {
+        probe(&req);
+    }
+    let (mut span, req) = match req.try_clone().and_then(|r| r.build().ok()) {
+        Some(built) => {
+            let mut span = trace::client_span(format!("{} {}", built.method(), built.url().path()));
+            span.attr("http.request.method", built.method().as_str());
+            span.attr("url.full", built.url().as_str());
+            let req = match span.traceparent() {
+                Some(tp) => req.header("traceparent", tp),
+                None => req,
+            };
+            (span, req)
+        }
+        None => (trace::client_span("http"), req),
+    };
+    let resp = fixture::send(req, |req| time("ttfb", || req.send()));
+    match resp.as_ref() {
+        Ok(r) => {
+            span.attr("http.response.status_code", r.status().as_u16());
+            if r.status().is_server_error() {
+                span.error(r.status());
+            }
+        }
+        Err(err) => span.error(err),
+    }
+    resp
+}
+
+/// Read the response body, recorded as body.
+pub fn text(resp: Response) -> reqwest::Result<String> {
+    time("body", || resp.text())
+}
+
+/// Print the per phase breakdown to stderr.
+pub fn report(total: Duration) {
+    if !enabled() {
+        return;
+    }
+    let phases = PHASES.lock().unwrap();
+    eprintln!("{}", gray("timing:"));
+    for (phase, d, count) in phases.iter() {
+        let times = if *count > 1 { format!(" ({count}x)") } else { String::new() };
+        eprintln!("  {:<12} {:>12.3?}{times}", phase, d);
+    }
+    eprintln!("  {:<12} {:>12.3?}", "total", total);
+}
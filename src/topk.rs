@@ -0,0 +1,152 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::common::{gray, green};
+
+/// `field=pod,k=20,by=bytes`, k defaults to 10 and by to count
+#[derive(Debug, Clone)]
+pub struct TopK {
+    field: String,
+    k: usize,
+    by_bytes: bool,
+}
+
+impl FromStr for TopK {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut field = None;
+        let mut k = 10;
+        let mut by_bytes = false;
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some(("field", v)) => field = Some(v.to_string()),
+                Some(("k", v)) => k = v.parse()?,
+                Some(("by", "count")) => by_bytes = false,
+                Some(("by", "bytes")) => by_bytes = true,
+                // a bare name is the field
+                None if field.is_none() => field = Some(part.to_string()),
+                _ => return Err(anyhow::format_err!("invalid top-k option {part}, expect field=..,k=..,by=count|bytes")),
+            }
+        }
+        let field = field.ok_or_else(|| anyhow::format_err!("top-k needs a field"))?;
+        Ok(TopK { field, k, by_bytes })
+    }
+}
+
+// value of `key` in a logfmt line, quoted values may contain spaces and \"
+fn logfmt_value(line: &str, key: &str) -> Option<String> {
+    let bs = line.as_bytes();
+    let mut i = 0;
+    while i < bs.len() {
+        while i < bs.len() && bs[i] == b' ' {
+            i += 1;
+        }
+        let start = i;
+        while i < bs.len() && bs[i] != b'=' && bs[i] != b' ' {
+            i += 1;
+        }
+        let name = &line[start..i];
+        if i >= bs.len() || bs[i] != b'=' {
+            continue;
+        }
+        i += 1;
+        let value = if i < bs.len() && bs[i] == b'"' {
+            i += 1;
+            let mut v = String::new();
+            while i < bs.len() && bs[i] != b'"' {
+                if bs[i] == b'\\' && i + 1 < bs.len() {
+                    i += 1;
+                }
+                let c = line[i..].chars().next()?;
+                v.push(c);
+                i += c.len_utf8();
+            }
+            i += 1;
+            v
+        } else {
+            let start = i;
+            while i < bs.len() && bs[i] != b' ' {
+                i += 1;
+            }
+            line[start..i].to_string()
+        };
+        if name == key {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn field_value(line: &str, field: &str) -> Option<String> {
+    if line.trim_start().starts_with('{') {
+        if let Ok(serde_json::Value::Object(obj)) = serde_json::from_str(line) {
+            return match obj.get(field)? {
+                serde_json::Value::String(s) => Some(s.clone()),
+                v => Some(v.to_string()),
+            };
+        }
+    }
+    logfmt_value(line, field)
+}
+
+/// Print the top values of the field over the lines of a streams result,
+/// stream labels are used for lines without the field.
+pub fn report(t: &TopK, obj: &serde_json::Value) -> anyhow::Result<()> {
+    if obj["data"]["resultType"] != "streams" {
+        return Err(anyhow::format_err!("--top-k expects a log query"));
+    }
+    // value -> (count, bytes)
+    let mut stats: HashMap<String, (u64, u64)> = HashMap::new();
+    let (mut lines, mut missing) = (0, 0);
+    for r in obj["data"]["result"].as_array().into_iter().flatten() {
+        let label = r["stream"][&t.field].as_str();
+        for v in r["values"].as_array().into_iter().flatten() {
+            let line = v[1].as_str().unwrap_or_default();
+            lines += 1;
+            let Some(value) = field_value(line, &t.field).or_else(|| label.map(|l| l.to_string())) else {
+                missing += 1;
+                continue;
+            };
+            let s = stats.entry(value).or_default();
+            s.0 += 1;
+            s.1 += line.len() as u64;
+        }
+    }
+    let mut top: Vec<_> = stats.into_iter().collect();
+    top.sort_by(|a, b| {
+        let key = |s: &(u64, u64)| if t.by_bytes { (s.1, s.0) } else { s.to_owned() };
+        key(&b.1).cmp(&key(&a.1)).then_with(|| a.0.cmp(&b.0))
+    });
+    let total_bytes: u64 = top.iter().map(|(_, s)| s.1).sum();
+    println!(
+        "{}",
+        gray(&format!("top {} {} by {} over {lines} lines", t.k, t.field, if t.by_bytes { "bytes" } else { "count" }))
+    );
+    println!("{:>10} {:>12} {:>7}  {}", "count", "bytes", "share", t.field);
+    for (value, (count, bytes)) in top.iter().take(t.k) {
+        let share = if t.by_bytes {
+            *bytes as f64 / total_bytes.max(1) as f64
+        } else {
+            *count as f64 / lines.max(1) as f64
+        };
+        println!("{:>10} {:>12} {:>6.1}%  {}", count, bytes, share * 100.0, green(value));
+    }
+    if missing > 0 {
+        println!("{}", gray(&format!("{missing} lines without {}", t.field)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_field_value() {
+        let line = r#"level=info msg="hello \"world\" x" pod=a-1"#;
+        assert_eq!(field_value(line, "msg").as_deref(), Some(r#"hello "world" x"#));
+        assert_eq!(field_value(line, "pod").as_deref(), Some("a-1"));
+        assert_eq!(field_value(line, "none"), None);
+        assert_eq!(field_value(r#"{"pod":"p1","n":3}"#, "n").as_deref(), Some("3"));
+    }
+}
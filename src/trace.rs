@@ -0,0 +1,216 @@
+// OpenTelemetry traces of lf itself: with --otel-endpoint the command, its
+// phases and http requests become spans, sent as OTLP/HTTP json once the
+// command is done (jaeger, tempo and the collector all take it on :4318).
+// Spans nest per thread, spans started on other threads hang below the
+// root span. Requests to loki carry a traceparent header, so the spans of
+// the servers join the same trace.
+
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use reqwest::header::CONTENT_TYPE;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::{json, Value};
+
+use crate::common::{gray, yellow};
+
+type SpanId = [u8; 8];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATE: Mutex<Option<Tracer>> = Mutex::new(None);
+static SPANS: Mutex<Vec<Value>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static CURRENT: Cell<Option<SpanId>> = const { Cell::new(None) };
+}
+
+struct Tracer {
+    endpoint: String,
+    trace_id: [u8; 16],
+    root: Option<SpanId>,
+}
+
+const KIND_INTERNAL: u8 = 1;
+const KIND_CLIENT: u8 = 3;
+
+pub fn enable(endpoint: &str) {
+    *STATE.lock().unwrap() = Some(Tracer {
+        endpoint: endpoint.to_string(),
+        trace_id: random(),
+        root: None,
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut b = [0; N];
+    // only fails when the os has no randomness to give
+    SystemRandom::new().fill(&mut b).expect("random span id");
+    b
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+struct SpanData {
+    name: String,
+    kind: u8,
+    id: SpanId,
+    parent: Option<SpanId>,
+    // span current when this one started, restored when it ends
+    previous: Option<SpanId>,
+    start: u128,
+    attributes: Vec<Value>,
+    error: Option<String>,
+}
+
+/// A span, ended when dropped. Does nothing unless tracing is enabled.
+pub struct Span(Option<SpanData>);
+
+fn start(name: String, kind: u8) -> Span {
+    if !enabled() {
+        return Span(None);
+    }
+    let id = random();
+    let previous = CURRENT.with(|c| c.replace(Some(id)));
+    let parent = previous.or_else(|| STATE.lock().unwrap().as_ref().and_then(|t| t.root));
+    Span(Some(SpanData {
+        name,
+        kind,
+        id,
+        parent,
+        previous,
+        start: now_nanos(),
+        attributes: vec![],
+        error: None,
+    }))
+}
+
+/// Start a span below the current one of this thread.
+pub fn span(name: impl Into<String>) -> Span {
+    start(name.into(), KIND_INTERNAL)
+}
+
+/// Start a span for a request to another service.
+pub fn client_span(name: impl Into<String>) -> Span {
+    start(name.into(), KIND_CLIENT)
+}
+
+/// Start the span of the whole command, the parent of spans without one.
+pub fn root(name: impl Into<String>) -> Span {
+    let span = span(name);
+    if let (Some(data), Some(tracer)) = (span.0.as_ref(), STATE.lock().unwrap().as_mut()) {
+        tracer.root = Some(data.id);
+    }
+    span
+}
+
+impl Span {
+    pub fn attr(&mut self, key: &str, value: impl Into<Value>) {
+        let Some(data) = self.0.as_mut() else {
+            return;
+        };
+        let value = match value.into() {
+            Value::String(s) => json!({ "stringValue": s }),
+            // int64 are strings in otlp json
+            Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+            Value::Number(n) => json!({ "doubleValue": n }),
+            Value::Bool(b) => json!({ "boolValue": b }),
+            other => json!({ "stringValue": other.to_string() }),
+        };
+        data.attributes.push(json!({ "key": key, "value": value }));
+    }
+
+    pub fn error(&mut self, message: impl ToString) {
+        if let Some(data) = self.0.as_mut() {
+            data.error = Some(message.to_string());
+        }
+    }
+
+    /// W3C trace context of this span, to send along with a request.
+    pub fn traceparent(&self) -> Option<String> {
+        let data = self.0.as_ref()?;
+        let trace_id = STATE.lock().unwrap().as_ref()?.trace_id;
+        Some(format!("00-{}-{}-01", hex(&trace_id), hex(&data.id)))
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(data) = self.0.take() else {
+            return;
+        };
+        CURRENT.with(|c| c.set(data.previous));
+        let mut span = json!({
+            "spanId": hex(&data.id),
+            "name": data.name,
+            "kind": data.kind,
+            "startTimeUnixNano": data.start.to_string(),
+            "endTimeUnixNano": now_nanos().to_string(),
+            "attributes": data.attributes,
+            "status": match data.error {
+                Some(message) => json!({ "code": 2, "message": message }),
+                None => json!({}),
+            },
+        });
+        if let Some(parent) = data.parent {
+            span["parentSpanId"] = json!(hex(&parent));
+        }
+        SPANS.lock().unwrap().push(span);
+    }
+}
+
+/// Send the finished spans, failures are reported but don't fail the
+/// command.
+pub fn export() {
+    let Some(Tracer { endpoint, trace_id, .. }) = STATE.lock().unwrap().take() else {
+        return;
+    };
+    let mut spans = std::mem::take(&mut *SPANS.lock().unwrap());
+    for span in spans.iter_mut() {
+        span["traceId"] = json!(hex(&trace_id));
+    }
+    let count = spans.len();
+    let body = json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [
+                { "key": "service.name", "value": { "stringValue": "lf" } },
+            ]},
+            "scopeSpans": [{ "scope": { "name": "lf" }, "spans": spans }],
+        }],
+    });
+    let url = match endpoint.trim_end_matches('/') {
+        e if e.ends_with("/v1/traces") => e.to_string(),
+        e => format!("{e}/v1/traces"),
+    };
+    let result = reqwest::blocking::Client::new()
+        .post(&url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send();
+    match result {
+        Ok(resp) if resp.status().is_success() => {
+            eprintln!("{}", gray(&format!("trace {} sent to {url} ({count} spans)", hex(&trace_id))));
+        }
+        Ok(resp) => eprintln!("{}", yellow(&format!("trace export to {url}: {}", resp.status()))),
+        Err(err) => eprintln!("{}", yellow(&format!("trace export to {url}: {err}"))),
+    }
+}
@@ -0,0 +1,641 @@
+// Loki TSDB index files, the default index since loki 2.8. A prometheus
+// tsdb index (format v2) where each series also carries its fingerprint and
+// the metas of its chunks:
+// loki/pkg/storage/stores/tsdb/index/index.go
+//
+//   [magic 0xBAAAD700][version] symbols, series (16 byte aligned, referenced
+//   by offset / 16), label indices, postings, postings offset table,
+//   fingerprint offsets, then the table of contents
+//
+// Sections are a 4 byte length, the content and its crc32c. Times are
+// milliseconds.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Read,
+};
+
+use anyhow::Result;
+use clap::Parser;
+use flate2::read::GzDecoder;
+use integer_encoding::VarInt;
+
+use crate::{
+    common::{format_millis, gray, green, yellow, ChunkRef, KeyValue, TimeRangeOpts},
+    error::IndexError,
+    platform::native_path,
+    proxy::format_labels,
+    query::optional_duration,
+    ty::identify,
+};
+
+const MAGIC: u32 = 0xBAAAD700;
+
+// label of the series of multi tenant index files (before compaction)
+const TENANT_LABEL: &str = "__loki_tenant__";
+
+/// tsdb index inspection, resolves label matchers to series and chunks
+/// like `lf bolt` does for boltdb
+#[derive(Parser, Debug)]
+pub struct Tsdb {
+    /// tsdb index file (.tsdb, or gzipped as shipped to the object store)
+    file: String,
+
+    /// query label string, every series when left out
+    #[arg(short, long, num_args = 1..)]
+    query: Vec<KeyValue>,
+
+    /// tenant of the chunk keys, by default the __loki_tenant__ label of
+    /// multi tenant files or fake; only the series of this tenant are kept
+    /// in multi tenant files
+    #[arg(short, long)]
+    tenant: Option<String>,
+
+    /// only keep the chunks overlapping this range, every chunk by default
+    #[command(flatten)]
+    time_range: TimeRangeOpts,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Toc {
+    pub symbols: u64,
+    pub series: u64,
+    pub label_indices: u64,
+    pub label_indices_table: u64,
+    pub postings: u64,
+    pub postings_table: u64,
+    // loki only
+    pub fingerprint_offsets: Option<u64>,
+    // mint and maxt of the chunks of the index, loki only
+    pub bounds: Option<(i64, i64)>,
+}
+
+// loki/pkg/storage/stores/tsdb/index/chunk.go ChunkMeta
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkMeta {
+    pub checksum: u32,
+    pub mint: i64,
+    pub maxt: i64,
+    pub kb: u32,
+    pub entries: u32,
+}
+
+#[derive(Debug)]
+pub struct Series {
+    pub id: u64,
+    pub fingerprint: u64,
+    pub labels: BTreeMap<String, String>,
+    pub chunks: Vec<ChunkMeta>,
+}
+
+// a cursor over the index bytes, reads fail past the end
+struct Dec<'a> {
+    b: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Dec<'a> {
+    fn new(b: &'a [u8], pos: usize) -> Self {
+        Dec { b, pos }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bs = self.b.get(self.pos..self.pos + n).ok_or_else(|| {
+            IndexError::InvalidKey(format!("tsdb index ends inside the {n} bytes at {}", self.pos))
+        })?;
+        self.pos += n;
+        Ok(bs)
+    }
+
+    fn be32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn be64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn uvarint(&mut self) -> Result<u64> {
+        let (v, n) = u64::decode_var(self.b.get(self.pos..).unwrap_or_default())
+            .ok_or_else(|| IndexError::InvalidKey(format!("bad uvarint at {}", self.pos)))?;
+        self.pos += n;
+        Ok(v)
+    }
+
+    fn varint(&mut self) -> Result<i64> {
+        let (v, n) = i64::decode_var(self.b.get(self.pos..).unwrap_or_default())
+            .ok_or_else(|| IndexError::InvalidKey(format!("bad varint at {}", self.pos)))?;
+        self.pos += n;
+        Ok(v)
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        let len = self.uvarint()? as usize;
+        Ok(std::str::from_utf8(self.bytes(len)?)?)
+    }
+}
+
+pub struct TsdbIndex {
+    bs: Vec<u8>,
+    pub version: u8,
+    pub toc: Toc,
+    // absolute offset (the reference of format v1) and symbol
+    symbols: Vec<(u64, String)>,
+    // (name, value) -> offset of the postings list
+    postings: BTreeMap<(String, String), u64>,
+}
+
+impl TsdbIndex {
+    /// Parse the index file `bs`, gzipped or not.
+    pub fn parse(bs: Vec<u8>) -> Result<Self> {
+        let bs = match bs.starts_with(&[0x1f, 0x8b]) {
+            true => {
+                let mut out = vec![];
+                GzDecoder::new(bs.as_slice()).read_to_end(&mut out)?;
+                out
+            }
+            false => bs,
+        };
+        if Dec::new(&bs, 0).be32().ok() != Some(MAGIC) {
+            let what = identify(&bs[..bs.len().min(64)]).map(|e| format!(", {e}")).unwrap_or_default();
+            return Err(IndexError::InvalidKey(format!("not a tsdb index file{what}")).into());
+        }
+        let version = bs[4];
+        if !(1..=3).contains(&version) {
+            return Err(IndexError::InvalidKey(format!("unsupported tsdb index format v{version}")).into());
+        }
+        let toc = read_toc(&bs)?;
+        let mut index = TsdbIndex {
+            bs,
+            version,
+            toc,
+            symbols: vec![],
+            postings: BTreeMap::new(),
+        };
+        index.symbols = index.read_symbols()?;
+        index.postings = index.read_postings_table()?;
+        Ok(index)
+    }
+
+    // the content of the length prefixed, crc suffixed section at `offset`
+    fn section(&self, offset: u64, what: &str) -> Result<&[u8]> {
+        let mut d = Dec::new(&self.bs, offset as usize);
+        let len = d.be32()? as usize;
+        let content = d.bytes(len)?;
+        if d.be32()? != crc32c::crc32c(content) {
+            return Err(IndexError::InvalidKey(format!("{what} at {offset}: checksum mismatch")).into());
+        }
+        Ok(content)
+    }
+
+    fn read_symbols(&self) -> Result<Vec<(u64, String)>> {
+        let content = self.section(self.toc.symbols, "symbols")?;
+        // offsets are of the whole file, after the length field
+        let base = self.toc.symbols + 4;
+        let mut d = Dec::new(content, 0);
+        let count = d.be32()?;
+        let mut symbols = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let offset = base + d.pos as u64;
+            symbols.push((offset, d.str()?.to_string()));
+        }
+        Ok(symbols)
+    }
+
+    // prometheus/tsdb/docs/format/index.md "Postings Offset Table"
+    fn read_postings_table(&self) -> Result<BTreeMap<(String, String), u64>> {
+        let content = self.section(self.toc.postings_table, "postings offset table")?;
+        let mut d = Dec::new(content, 0);
+        let count = d.be32()?;
+        let mut table = BTreeMap::new();
+        for _ in 0..count {
+            let n = d.uvarint()?;
+            if n != 2 {
+                return Err(IndexError::InvalidKey(format!("postings table entry of {n} keys")).into());
+            }
+            let name = d.str()?.to_string();
+            let value = d.str()?.to_string();
+            table.insert((name, value), d.uvarint()?);
+        }
+        Ok(table)
+    }
+
+    /// The symbol of reference `r`, its number from format v2 on and its
+    /// offset before.
+    fn symbol(&self, r: u64) -> Result<&str> {
+        let found = match self.version {
+            1 => self.symbols.binary_search_by_key(&r, |s| s.0).ok().map(|i| &self.symbols[i]),
+            _ => self.symbols.get(r as usize),
+        };
+        found
+            .map(|s| s.1.as_str())
+            .ok_or_else(|| IndexError::InvalidKey(format!("no symbol {r}")).into())
+    }
+
+    pub fn num_symbols(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Label names of the postings table, with the number of values.
+    pub fn label_names(&self) -> BTreeMap<&str, usize> {
+        let mut names = BTreeMap::new();
+        for (name, _) in self.postings.keys().filter(|(n, _)| !n.is_empty()) {
+            *names.entry(name.as_str()).or_default() += 1;
+        }
+        names
+    }
+
+    /// Ids of the series with label `name`=`value`, every series for the
+    /// empty pair.
+    pub fn postings(&self, name: &str, value: &str) -> Result<Vec<u64>> {
+        let Some(&offset) = self.postings.get(&(name.to_string(), value.to_string())) else {
+            return Ok(vec![]);
+        };
+        let content = self.section(offset, "postings")?;
+        let mut d = Dec::new(content, 0);
+        let count = d.be32()?;
+        (0..count).map(|_| Ok(d.be32()? as u64)).collect()
+    }
+
+    /// The series `id`, at byte `id * 16` from format v2 on.
+    pub fn series(&self, id: u64) -> Result<Series> {
+        let offset = match self.version {
+            1 => id,
+            _ => id * 16,
+        } as usize;
+        let mut d = Dec::new(&self.bs, offset);
+        let len = d.uvarint()? as usize;
+        let content = d.bytes(len)?;
+        if d.be32()? != crc32c::crc32c(content) {
+            return Err(IndexError::InvalidKey(format!("series {id} at {offset}: checksum mismatch")).into());
+        }
+        let mut d = Dec::new(content, 0);
+        let fingerprint = d.be64()?;
+        let mut labels = BTreeMap::new();
+        for _ in 0..d.uvarint()? {
+            let name = self.symbol(d.uvarint()?)?;
+            let value = self.symbol(d.uvarint()?)?;
+            labels.insert(name.to_string(), value.to_string());
+        }
+        let chunks = match self.version {
+            3 => read_chunks_v3(&mut d),
+            _ => read_chunks(&mut d),
+        }
+        .map_err(|e| e.context(format!("chunks of series {id}")))?;
+        Ok(Series {
+            id,
+            fingerprint,
+            labels,
+            chunks,
+        })
+    }
+}
+
+// the table of contents ends the file: the section offsets (loki adds the
+// fingerprint offsets) and, loki again, the bounds and a checksum of the
+// index, then the crc. Its length is found by the crc.
+fn read_toc(bs: &[u8]) -> Result<Toc> {
+    for (offsets, metadata) in [(7, true), (7, false), (6, false)] {
+        let len = offsets * 8 + if metadata { 20 } else { 0 } + 4;
+        let Some(start) = bs.len().checked_sub(len).filter(|&s| s >= 5) else {
+            continue;
+        };
+        let mut d = Dec::new(bs, start);
+        let content = d.bytes(len - 4)?;
+        if d.be32()? != crc32c::crc32c(content) {
+            continue;
+        }
+        let mut d = Dec::new(content, 0);
+        let mut toc = Toc {
+            symbols: d.be64()?,
+            series: d.be64()?,
+            label_indices: d.be64()?,
+            label_indices_table: d.be64()?,
+            postings: d.be64()?,
+            postings_table: d.be64()?,
+            fingerprint_offsets: None,
+            bounds: None,
+        };
+        if offsets == 7 {
+            toc.fingerprint_offsets = Some(d.be64()?);
+        }
+        if metadata {
+            toc.bounds = Some((d.be64()? as i64, d.be64()? as i64));
+        }
+        return Ok(toc);
+    }
+    Err(IndexError::InvalidKey("tsdb index table of contents not found (bad checksum)".to_string()).into())
+}
+
+// one chunk meta, its mint a delta to the maxt of the one before
+fn read_chunk(d: &mut Dec, prev_maxt: i64) -> Result<ChunkMeta> {
+    let mint = prev_maxt + d.varint()?;
+    let maxt = mint + d.uvarint()? as i64;
+    let kb = d.uvarint()? as u32;
+    let entries = d.uvarint()? as u32;
+    let checksum = d.be32()?;
+    Ok(ChunkMeta {
+        checksum,
+        mint,
+        maxt,
+        kb,
+        entries,
+    })
+}
+
+// formats v1 and v2
+fn read_chunks(d: &mut Dec) -> Result<Vec<ChunkMeta>> {
+    let n = d.uvarint()? as usize;
+    read_chunks_from(d, n, &mut 0)
+}
+
+// loki/pkg/storage/stores/tsdb/index/chunk.go chunkPageMarker
+#[derive(Debug)]
+struct PageMarker {
+    chunks: u64,
+    mint: i64,
+}
+
+// format v3: the chunks come in pages, each with a marker of its bounds,
+// number of chunks, size and entries ahead of the chunks so that pages out
+// of a query range can be skipped
+fn read_chunks_v3(d: &mut Dec) -> Result<Vec<ChunkMeta>> {
+    let n = d.uvarint()? as usize;
+    if n == 0 {
+        return Ok(vec![]);
+    }
+    let mut markers = vec![];
+    for _ in 0..d.uvarint()? {
+        let chunks = d.uvarint()?;
+        let (_kb, _entries, _offset) = (d.be32()?, d.be32()?, d.uvarint()?);
+        let mint = d.varint()?;
+        let _maxt = mint + d.varint()?;
+        markers.push(PageMarker { chunks, mint });
+    }
+    // chunks in each page, from the counts of the markers (or the chunks
+    // left from each page on)
+    let mut sizes: Vec<_> = markers.iter().map(|m| m.chunks as usize).collect();
+    if sizes.iter().sum::<usize>() != n && sizes.first() == Some(&n) {
+        sizes = sizes.iter().zip(sizes.iter().skip(1).chain([&0])).map(|(a, b)| a.saturating_sub(*b)).collect();
+    }
+    if sizes.iter().sum::<usize>() != n {
+        return Err(IndexError::InvalidKey(format!("{n} chunks but the page markers count {}", sizes.iter().sum::<usize>())).into());
+    }
+    // the first chunk of a page is a delta to 0 so that pages can be read on
+    // their own, checked against the bounds of the markers
+    let start = d.pos;
+    for reset in [true, false] {
+        d.pos = start;
+        let mut chunks = Vec::with_capacity(n);
+        let mut prev_maxt = 0;
+        let mut matches = true;
+        for (m, &size) in markers.iter().zip(sizes.iter()) {
+            if reset {
+                prev_maxt = 0;
+            }
+            let page = read_chunks_from(d, size, &mut prev_maxt)?;
+            matches &= page.iter().map(|c| c.mint).min() == Some(m.mint);
+            chunks.extend(page);
+        }
+        if matches {
+            return Ok(chunks);
+        }
+    }
+    Err(IndexError::InvalidKey("chunks do not match their page markers".to_string()).into())
+}
+
+fn read_chunks_from(d: &mut Dec, n: usize, prev_maxt: &mut i64) -> Result<Vec<ChunkMeta>> {
+    let mut chunks = Vec::with_capacity(n);
+    for _ in 0..n {
+        let c = read_chunk(d, *prev_maxt)?;
+        *prev_maxt = c.maxt;
+        chunks.push(c);
+    }
+    Ok(chunks)
+}
+
+// the tenant of a series: --tenant, the tenant label or fake
+fn series_tenant<'a>(t: &'a Tsdb, series: &'a Series) -> &'a str {
+    t.tenant.as_deref().or(series.labels.get(TENANT_LABEL).map(String::as_str)).unwrap_or("fake")
+}
+
+pub fn inspect(t: Tsdb) -> Result<()> {
+    let path = native_path(&t.file);
+    let index = TsdbIndex::parse(std::fs::read(&path)?)?;
+    let range = optional_duration(&t.time_range)?.map(|(s, e)| (s.timestamp_millis(), e.timestamp_millis()));
+
+    println!(
+        "{}",
+        gray(&format!("tsdb index format v{}, {} symbols, {} labels", index.version, index.num_symbols(), index.label_names().len()))
+    );
+    if let Some((from, through)) = index.toc.bounds {
+        println!("{}", gray(&format!("bounds: {} - {}", format_millis(from), format_millis(through))));
+    }
+
+    let mut series_ids: Option<HashSet<u64>> = None;
+    for kv in t.query.iter() {
+        let ids: HashSet<u64> = index.postings(&kv.key, &kv.value)?.into_iter().collect();
+        println!("{}", gray(&format!("{kv}: {} series", ids.len())));
+        series_ids = Some(match series_ids {
+            None => ids,
+            Some(s) => s.intersection(&ids).cloned().collect(),
+        });
+    }
+    let mut ids: Vec<u64> = match series_ids {
+        Some(ids) => ids.into_iter().collect(),
+        None => index.postings("", "")?,
+    };
+    ids.sort();
+
+    let mut chunk_refs = vec![];
+    let mut shown = 0;
+    for id in ids {
+        let series = index.series(id)?;
+        let tenant = series_tenant(&t, &series);
+        if series.labels.get(TENANT_LABEL).is_some_and(|l| l != tenant) {
+            continue;
+        }
+        let chunks: Vec<_> = series
+            .chunks
+            .iter()
+            .filter(|c| range.is_none_or(|(from, to)| c.maxt >= from && c.mint <= to))
+            .collect();
+        let refs: Vec<_> = chunks
+            .iter()
+            .map(|c| ChunkRef {
+                user_id: tenant.to_string(),
+                fingerprint: series.fingerprint,
+                from: c.mint,
+                to: c.maxt,
+                checksum: c.checksum,
+            })
+            .collect();
+        println!(
+            "\n{} {}",
+            green(&format_labels(&series.labels)),
+            gray(&format!(
+                "series {id}, fingerprint {:016x}, {} of {} chunks",
+                series.fingerprint,
+                refs.len(),
+                series.chunks.len()
+            ))
+        );
+        for (r, c) in refs.iter().zip(chunks.iter()) {
+            println!(
+                "  {}/{:x}:{:x}:{:x}:{:x} {}",
+                r.user_id,
+                r.fingerprint,
+                r.from,
+                r.to,
+                r.checksum,
+                gray(&format!("{} - {}, {} KB, {} entries", format_millis(r.from), format_millis(r.to), c.kb, c.entries))
+            );
+        }
+        shown += 1;
+        chunk_refs.extend(refs);
+    }
+    println!();
+    if shown == 0 {
+        println!("{}", yellow("no series matched"));
+    }
+    println!("{}", green(&format!("{shown} series, {} chunks", chunk_refs.len())));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // fingerprint, labels and chunks
+    type TestSeries<'a> = (u64, Vec<(&'a str, &'a str)>, Vec<ChunkMeta>);
+
+    // a tsdb index of the given series (sorted by labels) as loki writes it
+    fn build_index(version: u8, series: &[TestSeries]) -> Vec<u8> {
+        fn section(out: &mut Vec<u8>, content: &[u8]) {
+            out.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            out.extend_from_slice(content);
+            out.extend_from_slice(&crc32c::crc32c(content).to_be_bytes());
+        }
+        fn put_str(b: &mut Vec<u8>, s: &str) {
+            b.extend_from_slice(&(s.len() as u64).encode_var_vec());
+            b.extend_from_slice(s.as_bytes());
+        }
+        fn put_chunks(b: &mut Vec<u8>, chunks: &[ChunkMeta]) {
+            let mut prev = 0;
+            for c in chunks {
+                b.extend_from_slice(&(c.mint - prev).encode_var_vec());
+                b.extend_from_slice(&((c.maxt - c.mint) as u64).encode_var_vec());
+                b.extend_from_slice(&(c.kb as u64).encode_var_vec());
+                b.extend_from_slice(&(c.entries as u64).encode_var_vec());
+                b.extend_from_slice(&c.checksum.to_be_bytes());
+                prev = c.maxt;
+            }
+        }
+        let mut symbols: Vec<&str> = series.iter().flat_map(|s| s.1.iter().flat_map(|(k, v)| [*k, *v])).collect();
+        symbols.push("");
+        symbols.sort();
+        symbols.dedup();
+        let sym = |s: &str| symbols.iter().position(|x| *x == s).unwrap() as u64;
+
+        let mut out = MAGIC.to_be_bytes().to_vec();
+        out.push(version);
+        let symbols_offset = out.len() as u64;
+        let mut content = (symbols.len() as u32).to_be_bytes().to_vec();
+        for s in symbols.iter() {
+            put_str(&mut content, s);
+        }
+        section(&mut out, &content);
+
+        let series_offset = out.len() as u64;
+        let mut postings: BTreeMap<(String, String), Vec<u32>> = BTreeMap::new();
+        for (fp, labels, chunks) in series {
+            while !out.len().is_multiple_of(16) {
+                out.push(0);
+            }
+            let id = (out.len() / 16) as u32;
+            let mut b = fp.to_be_bytes().to_vec();
+            b.extend_from_slice(&(labels.len() as u64).encode_var_vec());
+            for (k, v) in labels {
+                b.extend_from_slice(&sym(k).encode_var_vec());
+                b.extend_from_slice(&sym(v).encode_var_vec());
+                postings.entry((k.to_string(), v.to_string())).or_default().push(id);
+            }
+            postings.entry((String::new(), String::new())).or_default().push(id);
+            b.extend_from_slice(&(chunks.len() as u64).encode_var_vec());
+            if version == 3 {
+                // pages of two chunks
+                let pages: Vec<_> = chunks.chunks(2).collect();
+                b.extend_from_slice(&(pages.len() as u64).encode_var_vec());
+                let mut offset = 0;
+                let mut encoded = vec![];
+                for page in pages.iter() {
+                    b.extend_from_slice(&(page.len() as u64).encode_var_vec());
+                    b.extend_from_slice(&page.iter().map(|c| c.kb).sum::<u32>().to_be_bytes());
+                    b.extend_from_slice(&page.iter().map(|c| c.entries).sum::<u32>().to_be_bytes());
+                    b.extend_from_slice(&(offset as u64).encode_var_vec());
+                    b.extend_from_slice(&page[0].mint.encode_var_vec());
+                    b.extend_from_slice(&(page[page.len() - 1].maxt - page[0].mint).encode_var_vec());
+                    let before = encoded.len();
+                    put_chunks(&mut encoded, page);
+                    offset += encoded.len() - before;
+                }
+                b.extend_from_slice(&encoded);
+            } else {
+                put_chunks(&mut b, chunks);
+            }
+            out.extend_from_slice(&(b.len() as u64).encode_var_vec());
+            out.extend_from_slice(&b);
+            out.extend_from_slice(&crc32c::crc32c(&b).to_be_bytes());
+        }
+
+        let postings_offset = out.len() as u64;
+        let mut table = (postings.len() as u32).to_be_bytes().to_vec();
+        for ((k, v), ids) in postings.iter() {
+            let offset = out.len() as u64;
+            let mut content = (ids.len() as u32).to_be_bytes().to_vec();
+            for id in ids {
+                content.extend_from_slice(&id.to_be_bytes());
+            }
+            section(&mut out, &content);
+            table.extend_from_slice(&2u64.encode_var_vec());
+            put_str(&mut table, k);
+            put_str(&mut table, v);
+            table.extend_from_slice(&offset.encode_var_vec());
+        }
+        let table_offset = out.len() as u64;
+        section(&mut out, &table);
+
+        let mut toc = vec![];
+        for o in [symbols_offset, series_offset, 0, 0, postings_offset, table_offset, 0, 1000, 9000] {
+            toc.extend_from_slice(&o.to_be_bytes());
+        }
+        toc.extend_from_slice(&0u32.to_be_bytes());
+        out.extend_from_slice(&toc);
+        out.extend_from_slice(&crc32c::crc32c(&toc).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn test_tsdb_index() -> Result<()> {
+        let chunk = |mint, maxt, checksum| ChunkMeta { checksum, mint, maxt, kb: 12, entries: 100 };
+        let series = [
+            (0xaa, vec![("app", "a"), ("env", "prod")], vec![chunk(1000, 2000, 1), chunk(1500, 3000, 2), chunk(4000, 5000, 3)]),
+            (0xbb, vec![("app", "b"), ("env", "prod")], vec![chunk(2000, 9000, 4)]),
+        ];
+        for version in [2, 3] {
+            let index = TsdbIndex::parse(build_index(version, &series))?;
+            assert_eq!(index.toc.bounds, Some((1000, 9000)));
+            assert_eq!(index.label_names(), BTreeMap::from([("app", 2), ("env", 1)]));
+            let prod = index.postings("env", "prod")?;
+            assert_eq!(prod.len(), 2);
+            let a = index.postings("app", "a")?;
+            assert_eq!(a.len(), 1);
+            let s = index.series(a[0])?;
+            assert_eq!(s.fingerprint, 0xaa);
+            assert_eq!(s.labels["env"], "prod");
+            assert_eq!(s.chunks, series[0].2, "format v{version}");
+            assert!(index.postings("app", "c")?.is_empty());
+        }
+        Ok(())
+    }
+}
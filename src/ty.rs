@@ -1,11 +1,11 @@
 use std::{
     collections::HashMap,
-    io::{Cursor, Read},
+    io::{Cursor, Read, Seek, SeekFrom},
 };
 
 use binread::{error::magic, BinRead, BinReaderExt, BinResult, Endian};
 use chrono::NaiveDateTime;
-use flate2::read::GzDecoder;
+use flate2::read::{DeflateDecoder, GzDecoder};
 use integer_encoding::VarIntReader;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
@@ -13,6 +13,8 @@ use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
+use crate::{common::KeyValue, error::DecodeError};
+
 #[derive(Debug, Clone, Serialize)]
 pub struct UnorderedBlock {
     pub entries: Vec<UnorderedBlockEntry>,
@@ -21,8 +23,20 @@ pub struct UnorderedBlock {
 // loki/pkg/chunkenc/unordered.go Serialise
 #[derive(Debug, Clone, Serialize)]
 pub struct UnorderedBlockEntry {
+    #[serde(serialize_with = "crate::timefmt::serialize")]
     pub time: NaiveDateTime,
     pub line: String,
+    // chunk format v4, always empty for older formats
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub structured_metadata: Vec<(String, String)>,
+}
+
+impl UnorderedBlockEntry {
+    /// Whether the structured metadata has every one of the pairs.
+    pub fn has_metadata(&self, want: &[KeyValue]) -> bool {
+        want.iter()
+            .all(|kv| self.structured_metadata.iter().any(|(k, v)| *k == kv.key && *v == kv.value))
+    }
 }
 
 impl BinRead for UnorderedBlockEntry {
@@ -34,16 +48,82 @@ impl BinRead for UnorderedBlockEntry {
         _args: Self::Args,
     ) -> binread::BinResult<Self> {
         let ts = reader.read_varint::<i64>()?;
-        let sz = reader.read_varint::<u64>()?;
-        let mut vec = vec![0; sz as usize];
-        reader.read_exact(vec.as_mut())?;
-        Ok(UnorderedBlockEntry {
-            time: NaiveDateTime::from_timestamp_opt(ts / (1e9 as i64), 0).unwrap(),
-            line: String::from_utf8_lossy(&vec).to_string(),
-        })
+        read_entry(reader, ts, None)
     }
 }
 
+/// Symbol table of a format v4 chunk: the names and values of the
+/// structured metadata, referenced by index from the entries.
+/// loki/pkg/chunkenc/symbols.go symbolizerFromEnc
+pub(crate) fn parse_symbols(section: &[u8], enc_type: &EncType) -> BinResult<Vec<String>> {
+    let mut cursor = Cursor::new(section);
+    // the count is not compressed, the symbols are
+    let count = cursor.read_varint::<u64>()? as usize;
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    let decoded = decompress_bytes(&section[cursor.position() as usize..], enc_type)?;
+    let mut cursor = Cursor::new(decoded);
+    let mut symbols = Vec::with_capacity(count.min(section.len()));
+    for _ in 0..count {
+        let len = cursor.read_varint::<u64>()?;
+        let s = read_bytes(&mut cursor, len)?;
+        symbols.push(String::from_utf8_lossy(&s).to_string());
+    }
+    Ok(symbols)
+}
+
+/// Entry timestamps are nanoseconds, every i64 is a valid date.
+pub(crate) fn nanos_to_datetime(ns: i64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp_opt(ns.div_euclid(1_000_000_000), ns.rem_euclid(1_000_000_000) as u32)
+        .unwrap_or_default()
+}
+
+// `len` bytes, without allocating them up front: the lengths read from a
+// damaged (and unverified) block are garbage
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut bs = vec![];
+    reader.take(len).read_to_end(&mut bs)?;
+    if (bs.len() as u64) < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bs)
+}
+
+// the structured metadata following the line of an entry (format v4): the
+// section length, the number of pairs, then name and value symbol indexes
+fn read_structured_metadata<R: Read>(reader: &mut R, symbols: &[String]) -> BinResult<Vec<(String, String)>> {
+    let len = reader.read_varint::<u64>()?;
+    let mut cursor = Cursor::new(read_bytes(reader, len)?);
+    let pairs = cursor.read_varint::<u64>()?;
+    let symbol = |cursor: &mut Cursor<Vec<u8>>| -> BinResult<String> {
+        let i = cursor.read_varint::<u64>()? as usize;
+        symbols.get(i).cloned().ok_or_else(|| binread::Error::Custom {
+            pos: 0,
+            err: Box::new(DecodeError::Corrupt(format!("structured metadata symbol {i} of {}", symbols.len()))),
+        })
+    };
+    (0..pairs)
+        .map(|_| Ok((symbol(&mut cursor)?, symbol(&mut cursor)?)))
+        .collect()
+}
+
+// the line and, with the symbols of a format v4 chunk, the structured
+// metadata of an entry whose timestamp was read
+fn read_entry<R: Read>(reader: &mut R, ts: i64, symbols: Option<&[String]>) -> BinResult<UnorderedBlockEntry> {
+    let sz = reader.read_varint::<u64>()?;
+    let line = read_bytes(reader, sz)?;
+    let structured_metadata = match symbols {
+        Some(symbols) => read_structured_metadata(reader, symbols)?,
+        None => vec![],
+    };
+    Ok(UnorderedBlockEntry {
+        time: nanos_to_datetime(ts),
+        line: String::from_utf8_lossy(&line).to_string(),
+        structured_metadata,
+    })
+}
+
 impl BinRead for UnorderedBlock {
     type Args = usize;
 
@@ -66,35 +146,49 @@ impl BinRead for UnorderedBlock {
 #[derive(Debug, Clone, Serialize)]
 pub struct BlockMeta {
     pub num_entries: usize,
+    #[serde(serialize_with = "crate::timefmt::serialize")]
     pub mint: NaiveDateTime,
+    #[serde(serialize_with = "crate::timefmt::serialize")]
     pub maxt: NaiveDateTime,
     pub offset: u64,
-    // chunk format v3
+    // chunk format v3, 0 for older formats
     pub uncompressed_size: usize,
     pub compressed_size: usize,
+    // mint/maxt above as nanoseconds
+    #[serde(skip)]
+    pub mint_ns: i64,
+    #[serde(skip)]
+    pub maxt_ns: i64,
 }
 
 impl BinRead for BlockMeta {
-    type Args = ();
+    // chunk format
+    type Args = (u8,);
+
+    fn args_default() -> Option<Self::Args> {
+        Some((3,))
+    }
 
     fn read_options<R: std::io::Read + std::io::Seek>(
         reader: &mut R,
         _options: &binread::ReadOptions,
-        _args: Self::Args,
+        (format,): Self::Args,
     ) -> binread::BinResult<Self> {
         let num_entries = reader.read_varint()?;
         let mint = reader.read_varint::<i64>()?;
         let maxt = reader.read_varint::<i64>()?;
         let offset = reader.read_varint()?;
-        let uncompressed_size = reader.read_varint()?;
+        let uncompressed_size = if format >= 3 { reader.read_varint()? } else { 0 };
         let compressed_size = reader.read_varint()?;
         Ok(BlockMeta {
             num_entries,
-            mint: NaiveDateTime::from_timestamp_opt(mint / (1e9 as i64), 0).unwrap(),
-            maxt: NaiveDateTime::from_timestamp_opt(maxt / (1e9 as i64), 0).unwrap(),
+            mint: nanos_to_datetime(mint),
+            maxt: nanos_to_datetime(maxt),
             offset,
             uncompressed_size,
             compressed_size,
+            mint_ns: mint,
+            maxt_ns: maxt,
         })
     }
 }
@@ -103,23 +197,28 @@ impl BinRead for BlockMeta {
 pub struct Meta {
     pub num_blocks: usize,
     pub block_metas: Vec<BlockMeta>,
+    // of the block metas
     pub block_crc: u32,
 }
 
 impl BinRead for Meta {
-    type Args = ();
+    // chunk format
+    type Args = (u8,);
+
+    fn args_default() -> Option<Self::Args> {
+        Some((3,))
+    }
 
     fn read_options<R: std::io::Read + std::io::Seek>(
         reader: &mut R,
         _options: &binread::ReadOptions,
-        _args: Self::Args,
+        (format,): Self::Args,
     ) -> binread::BinResult<Self> {
         let num_blocks = reader.read_varint()?;
         let block_metas = (0..num_blocks)
-            .map(|_| reader.read_le())
+            .map(|_| reader.read_le_args((format,)))
             .collect::<BinResult<_>>()?;
-        let crc32 = reader.read_le()?;
-        //TODO: CRC check
+        let crc32 = reader.read_be()?;
 
         Ok(Meta {
             num_blocks,
@@ -130,7 +229,7 @@ impl BinRead for Meta {
 }
 
 #[repr(u8)]
-#[derive(FromPrimitive, Debug, Clone, Serialize)]
+#[derive(FromPrimitive, Debug, Clone, Serialize, Deserialize)]
 pub enum EncType {
     EncNone,
     EncGZIP,
@@ -146,65 +245,300 @@ pub enum EncType {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct ChunkData {
+    pub format: u8,
     pub ty: EncType,
+    // structured metadata names and values (format v4)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub symbols: Vec<String>,
     pub blocks: Vec<UnorderedBlock>,
     pub meta: Meta,
 }
 
-impl BinRead for ChunkData {
-    // data start offset
-    type Args = ();
+// loki checksums the symbols, every block and the block metas with crc32
+// (castagnoli), stored big endian right after them
+fn check_crc(data: &[u8], expected: u32, pos: u64, what: impl FnOnce() -> String) -> BinResult<()> {
+    let actual = crc32c::crc32c(data);
+    if actual != expected {
+        return Err(binread::Error::Custom {
+            pos,
+            err: Box::new(DecodeError::Corrupt(format!(
+                "{}: checksum {actual:08x}, expected {expected:08x}",
+                what()
+            ))),
+        });
+    }
+    Ok(())
+}
 
-    fn read_options<R: std::io::Read + std::io::Seek>(
-        reader: &mut R,
-        options: &binread::ReadOptions,
-        _args: Self::Args,
-    ) -> binread::BinResult<Self> {
+/// The blocks of a chunk, read and decompressed one at a time: only the
+/// header, symbols and block metas are kept, blocks are seeked to as they
+/// are asked for. `reader` is positioned at the length of the chunk data.
+pub struct ChunkStream<R> {
+    reader: R,
+    // start of the chunk data, block offsets are relative to it
+    base: u64,
+    pub format: u8,
+    pub ty: EncType,
+    pub symbols: Vec<String>,
+    pub meta: Meta,
+    range: Option<(i64, i64)>,
+    wanted: std::ops::Range<usize>,
+    verify: bool,
+    next: usize,
+}
+
+impl<R: Read + Seek> ChunkStream<R> {
+    /// Read all but the blocks, only decoding the entries in `range`
+    /// (nanoseconds) and checking the checksums when `verify`.
+    pub fn new(mut reader: R, range: Option<(i64, i64)>, verify: bool) -> BinResult<Self> {
         // skip length
         _ = reader.read_le::<u32>();
 
         let cur_pos = reader.stream_position()?;
         debug!("cur pos: {cur_pos}");
-        reader.seek(std::io::SeekFrom::End(-8))?;
+        let mut opt = binread::ReadOptions::default();
+        opt.endian = Endian::Big;
+        debug!("finding magic 0x012ee56a");
+        magic(&mut reader, CHUNK_MAGIC, &opt)?;
+        // loki/pkg/chunkenc/memchunk.go newByteChunk: v1 chunks (before
+        // loki 2.0) are always gzip and have no encoding byte, block metas
+        // carry the uncompressed size from v3 (loki 2.3) on. The entries
+        // of the blocks are laid out the same way in every format.
+        let format: u8 = reader.read_le()?;
+        let unsupported = |what: String| binread::Error::Custom {
+            pos: cur_pos + 4,
+            err: Box::new(DecodeError::Unsupported(what)),
+        };
+        let enc_type = match format {
+            1 => EncType::EncGZIP,
+            2..=4 => {
+                let et: u8 = reader.read_le()?;
+                EncType::from_u8(et).ok_or_else(|| unsupported(format!("encoding {et}")))?
+            }
+            f => return Err(unsupported(format!("chunk format v{f}"))),
+        };
+        debug!("chunk format v{format}, {enc_type:?}");
+
+        // v4 trailer: symbols length and offset, metas length and offset
+        let symbols = match format {
+            4 => {
+                reader.seek(SeekFrom::End(-32))?;
+                let len = reader.read_be::<u64>()?;
+                let offset = reader.read_be::<u64>()?;
+                reader.seek(SeekFrom::Start(offset + cur_pos))?;
+                let section = read_bytes(&mut reader, len)?;
+                if verify {
+                    let pos = reader.stream_position()?;
+                    check_crc(&section, reader.read_be()?, pos, || format!("symbols at offset {offset}"))?;
+                }
+                parse_symbols(&section, &enc_type)?
+            }
+            _ => vec![],
+        };
+
+        reader.seek(SeekFrom::End(-8))?;
         let offset = reader.read_be::<u64>()?;
         debug!("offset: {offset}");
-        reader.seek(std::io::SeekFrom::Start(offset + cur_pos))?;
-        let meta: Meta = reader.read_le()?;
+        reader.seek(SeekFrom::Start(offset + cur_pos))?;
+        let meta: Meta = reader.read_le_args((format,))?;
         debug!("meta parsed: {:?}", meta);
-
-        reader.seek(std::io::SeekFrom::Start(cur_pos))?;
-        let mut new_opt = *options;
-        new_opt.endian = Endian::Big;
-        debug!("finding magic 0x012ee56a");
-        magic(reader, 0x012EE56A_u32, &new_opt)?;
-        debug!("finding magic 3");
-        magic(reader, 3_u8, &new_opt)?;
-        let et = reader.read_le()?;
-        let enc_type = EncType::from_u8(et).expect("invalid enc type");
-
-        let mut blocks = vec![];
-        for i in 0..meta.num_blocks {
-            let block_meta = &meta.block_metas[i];
-            reader.seek(std::io::SeekFrom::Start(block_meta.offset + cur_pos))?;
-            let mut vec = vec![0; block_meta.compressed_size];
-
-            debug!("uncompressed size: {}", block_meta.uncompressed_size);
-            reader.read_exact(&mut vec)?;
-            let bs = decompress(&vec, &enc_type, block_meta.num_entries)?;
-            // assert_eq!(bs.line.len(), block_meta.uncompressed_size)
-            blocks.push(bs);
+        if verify {
+            let end = reader.stream_position()? - 4;
+            reader.seek(SeekFrom::Start(offset + cur_pos))?;
+            let metas = read_bytes(&mut reader, end - offset - cur_pos)?;
+            check_crc(&metas, meta.block_crc, end, || format!("block metas at offset {offset}"))?;
         }
 
-        Ok(ChunkData {
+        let wanted = match range {
+            Some((from, to)) => {
+                let spans: Vec<_> = meta.block_metas.iter().map(|m| (m.mint_ns, m.maxt_ns)).collect();
+                blocks_in_range(&spans, from, to)
+            }
+            None => 0..meta.num_blocks,
+        };
+        debug!("decoding blocks {wanted:?} of {}", meta.num_blocks);
+        Ok(ChunkStream {
+            reader,
+            base: cur_pos,
+            format,
             ty: enc_type,
-            blocks,
+            symbols,
             meta,
+            range,
+            wanted,
+            verify,
+            next: 0,
+        })
+    }
+
+    /// Only decode block `i` (of those in the range), the others are left
+    /// empty.
+    pub fn select_block(&mut self, i: usize) {
+        self.wanted = self.wanted.start.max(i)..self.wanted.end.min(i + 1);
+    }
+
+    /// Leave the blocks before `i` empty.
+    pub fn skip_blocks(&mut self, i: usize) {
+        self.wanted.start = self.wanted.start.max(i);
+    }
+
+    /// Decode block `i` on its own, without moving the iterator.
+    pub fn block(&mut self, i: usize) -> BinResult<UnorderedBlock> {
+        self.read_block(i)
+    }
+
+    /// Decode the remaining blocks.
+    pub fn into_data(mut self) -> BinResult<ChunkData> {
+        let blocks = self.by_ref().collect::<BinResult<_>>()?;
+        Ok(ChunkData {
+            format: self.format,
+            ty: self.ty,
+            symbols: self.symbols,
+            blocks,
+            meta: self.meta,
         })
     }
+
+    fn read_block(&mut self, i: usize) -> BinResult<UnorderedBlock> {
+        let block_meta = &self.meta.block_metas[i];
+        let range = self.range;
+        if !self.wanted.contains(&i)
+            || range.is_some_and(|(from, to)| block_meta.maxt_ns < from || block_meta.mint_ns > to)
+        {
+            return Ok(UnorderedBlock { entries: vec![] });
+        }
+        self.reader.seek(SeekFrom::Start(block_meta.offset + self.base))?;
+        debug!("uncompressed size: {}", block_meta.uncompressed_size);
+        let vec = read_bytes(&mut self.reader, block_meta.compressed_size as u64)?;
+        if self.verify {
+            let pos = self.reader.stream_position()?;
+            check_crc(&vec, self.reader.read_be()?, pos, || {
+                format!("block {i} of {} at offset {}", self.meta.num_blocks, block_meta.offset)
+            })?;
+        }
+        let symbols = (self.format >= 4).then_some(self.symbols.as_slice());
+        match range {
+            Some(range) => decompress_range(&vec, &self.ty, block_meta.num_entries, range, symbols),
+            None => decompress(&vec, &self.ty, block_meta.num_entries, symbols),
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for ChunkStream<R> {
+    // blocks out of the range are empty (and not verified)
+    type Item = BinResult<UnorderedBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.meta.num_blocks {
+            return None;
+        }
+        self.next += 1;
+        Some(self.read_block(self.next - 1))
+    }
 }
 
-// decompress chunk data (assumes unordered block)
-fn decompress(vec: &[u8], enc_type: &EncType, num_entries: usize) -> BinResult<UnorderedBlock> {
+impl BinRead for ChunkData {
+    // see ChunkStream::new
+    type Args = (Option<(i64, i64)>, bool);
+
+    fn args_default() -> Option<Self::Args> {
+        Some((None, true))
+    }
+
+    fn read_options<R: std::io::Read + std::io::Seek>(
+        reader: &mut R,
+        _options: &binread::ReadOptions,
+        (range, verify): Self::Args,
+    ) -> binread::BinResult<Self> {
+        ChunkStream::new(reader, range, verify)?.into_data()
+    }
+}
+
+/// Indexes of the blocks that may overlap [from, to] given their (mint,
+/// maxt). Blocks of a chunk are cut in time order, so both bounds only
+/// grow and the first and last block are found by binary search. With
+/// overlapping blocks (out of order writes) every block is a candidate,
+/// callers still check the bounds of each block in the range.
+pub(crate) fn blocks_in_range(spans: &[(i64, i64)], from: i64, to: i64) -> std::ops::Range<usize> {
+    if !spans.windows(2).all(|w| w[0].0 <= w[1].0 && w[0].1 <= w[1].1) {
+        return 0..spans.len();
+    }
+    let first = spans.partition_point(|s| s.1 < from);
+    let last = spans.partition_point(|s| s.0 <= to);
+    first..last.max(first)
+}
+
+// decompress chunk data (assumes unordered block), `symbols` are the ones
+// of a format v4 chunk, its entries carry structured metadata
+pub(crate) fn decompress(
+    vec: &[u8],
+    enc_type: &EncType,
+    num_entries: usize,
+    symbols: Option<&[String]>,
+) -> BinResult<UnorderedBlock> {
+    let decoded = decompress_bytes(vec, enc_type)?;
+    let mut cursor = Cursor::new(decoded);
+    let Some(symbols) = symbols else {
+        return cursor.read_le_args(num_entries);
+    };
+    let mut entries = vec![];
+    for _ in 0..num_entries {
+        let ts = cursor.read_varint::<i64>()?;
+        entries.push(read_entry(&mut cursor, ts, Some(symbols))?);
+    }
+    Ok(UnorderedBlock { entries })
+}
+
+/// The raw entries of a compressed block, decompressed as they are read.
+pub(crate) fn block_reader<'a>(vec: &'a [u8], enc_type: &EncType) -> BinResult<Box<dyn Read + 'a>> {
+    Ok(match enc_type {
+        EncType::EncNone => Box::new(vec),
+        EncType::EncGZIP => Box::new(GzDecoder::new(vec)),
+        EncType::EncFlate => Box::new(DeflateDecoder::new(vec)),
+        EncType::EncSnappy => Box::new(snap::read::FrameDecoder::new(vec)),
+        EncType::EncZstd => Box::new(zstd::Decoder::new(vec)?),
+        e => {
+            return Err(binread::Error::Custom {
+                pos: 0,
+                err: Box::new(DecodeError::Unsupported(format!("{e:?} encoding"))),
+            })
+        }
+    })
+}
+
+/// Decode the entries of a block in [from, to] (nanoseconds). Entries of a
+/// block are sorted, so decompression stops at the first one past `to` and
+/// the lines before `from` are skipped without being copied.
+pub(crate) fn decompress_range(
+    vec: &[u8],
+    enc_type: &EncType,
+    num_entries: usize,
+    (from, to): (i64, i64),
+    symbols: Option<&[String]>,
+) -> BinResult<UnorderedBlock> {
+    let mut reader = block_reader(vec, enc_type)?;
+    let mut entries = vec![];
+    for _ in 0..num_entries {
+        let ts = reader.read_varint::<i64>()?;
+        if ts > to {
+            break;
+        }
+        if ts < from {
+            let sz = reader.read_varint::<u64>()?;
+            std::io::copy(&mut (&mut reader).take(sz), &mut std::io::sink())?;
+            if symbols.is_some() {
+                let sz = reader.read_varint::<u64>()?;
+                std::io::copy(&mut (&mut reader).take(sz), &mut std::io::sink())?;
+            }
+            continue;
+        }
+        entries.push(read_entry(&mut reader, ts, symbols)?);
+    }
+    Ok(UnorderedBlock { entries })
+}
+
+// raw entries of a block
+pub(crate) fn decompress_bytes(vec: &[u8], enc_type: &EncType) -> BinResult<Vec<u8>> {
     // std::fs::write("debug.bin", vec)?;
     debug!(
         "decompress called, vec len: {}, enc type: {:?}",
@@ -213,12 +547,20 @@ fn decompress(vec: &[u8], enc_type: &EncType, num_entries: usize) -> BinResult<U
     );
     // let vec = BufReader::new(vec);
     let decoded = match enc_type {
+        EncType::EncNone => vec.to_vec(),
         EncType::EncGZIP => {
             let mut d = GzDecoder::new(vec);
             let mut s = Vec::new();
             d.read_to_end(&mut s)?;
             s
         }
+        // compress/flate of go, raw deflate without the gzip framing
+        EncType::EncFlate => {
+            let mut d = DeflateDecoder::new(vec);
+            let mut s = Vec::new();
+            d.read_to_end(&mut s)?;
+            s
+        }
         EncType::EncSnappy => {
             let mut decoder = snap::read::FrameDecoder::new(vec);
             let mut s = Vec::new();
@@ -234,14 +576,12 @@ fn decompress(vec: &[u8], enc_type: &EncType, num_entries: usize) -> BinResult<U
         e => {
             return Err(binread::Error::Custom {
                 pos: 0,
-                err: Box::new(anyhow::format_err!("not supported: {e:?}")),
+                err: Box::new(DecodeError::Unsupported(format!("{e:?} encoding"))),
             })
         }
     };
     debug!("real uncompressed size: {}", decoded.len());
-    let mut cursor = Cursor::new(decoded);
-    let unordered_block = cursor.read_le_args(num_entries)?;
-    Ok(unordered_block)
+    Ok(decoded)
 }
 
 // loki/pkg/storage/chunk/chunk.go Chunk
@@ -279,41 +619,141 @@ impl BinRead for ChunkHead {
                 println!("{:?}", err);
                 Err(binread::Error::Custom {
                     pos: 0,
-                    err: Box::new(anyhow::format_err!("header json deserialize: {err:?}")),
+                    err: Box::new(DecodeError::InvalidHead(format!("header json deserialize: {err}"))),
                 })
             }
         }
     }
 }
 
+// loki/pkg/storage/chunk/encoding: the head encoding of log chunks, metric
+// chunks of cortex (and loki before the split) use the ones below it
+pub(crate) const LOG_CHUNK_ENCODING: u8 = 129;
+pub(crate) const CHUNK_MAGIC: u32 = 0x012EE56A;
+
+fn metric_chunk_encoding(encoding: u8) -> Option<&'static str> {
+    Some(match encoding {
+        0 => "Delta",
+        1 => "DoubleDelta",
+        2 => "Varbit",
+        3 => "Bigchunk",
+        4 => "PrometheusXorChunk",
+        _ => return None,
+    })
+}
+
+/// What a file which doesn't start with a chunk head is, going by the
+/// magic numbers of the files found next to chunks.
+pub(crate) fn identify(bs: &[u8]) -> Option<DecodeError> {
+    let be32 = |pos: usize| bs.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+    let what = match () {
+        _ if be32(0) == Some(CHUNK_MAGIC) => "chunk data without the storage head (format and blocks of a memchunk)",
+        _ if be32(0) == Some(0x85BD40DD) => "a prometheus tsdb chunks segment file",
+        _ if be32(0) == Some(0x0130BC91) => "a prometheus head chunks file (chunks_head)",
+        _ if be32(0) == Some(0xBAAAD700) => "a tsdb index file, see lf tsdb",
+        // bolt meta page: 16 bytes of page header, then the magic in host order
+        _ if be32(16).map(u32::swap_bytes) == Some(0xED0CDAED) || be32(16) == Some(0xED0CDAED) => {
+            "a boltdb file (boltdb-shipper index), see lf bolt"
+        }
+        _ if bs.starts_with(&[0x1f, 0x8b]) => "a gzip file, decompress it first",
+        _ if bs.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) => "a zstd file, decompress it first",
+        _ if bs.starts_with(b"\xff\x06\x00\x00sNaPpY") => "a snappy framed file, decompress it first",
+        _ if bs.starts_with(b"{") => "json, lf encode turns the json of lf decode back into a chunk",
+        _ => return None,
+    };
+    Some(DecodeError::NotLogChunk(format!("looks like {what}")))
+}
+
+impl ChunkHead {
+    /// Fail for the heads of metric chunks, for data which is no memchunk
+    /// either.
+    pub(crate) fn check_log_chunk(&self) -> Result<(), DecodeError> {
+        match metric_chunk_encoding(self.encoding) {
+            Some(name) => {
+                let name_label = self.metric.get("__name__").map(String::as_str).unwrap_or_default();
+                Err(DecodeError::NotLogChunk(format!(
+                    "the head says a cortex metric chunk of {name_label:?} ({name} encoding, log chunks have {LOG_CHUNK_ENCODING})"
+                )))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The disagreement of a head whose encoding isn't the log chunk one
+    /// with its data, which still is a memchunk of `format` and `ty`.
+    pub(crate) fn encoding_mismatch(&self, format: u8, ty: &EncType) -> Option<String> {
+        if self.encoding == LOG_CHUNK_ENCODING {
+            return None;
+        }
+        let name = metric_chunk_encoding(self.encoding).map(|n| format!(" ({n})")).unwrap_or_default();
+        Some(format!(
+            "the head says encoding {}{name} but the data is a log chunk (format v{format}, {ty:?}), whose head encoding is {LOG_CHUNK_ENCODING}",
+            self.encoding
+        ))
+    }
+
+    /// Read the length prefixed header of a chunk, leaving `reader` at the
+    /// chunk data.
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> BinResult<Self> {
+        let head_sz = reader.read_be::<u32>()? as usize;
+        let mut cursor = Cursor::new(read_bytes(reader, head_sz.saturating_sub(4) as u64)?);
+        cursor.read_le()
+    }
+}
+
 impl BinRead for Chunk {
-    type Args = ();
+    // see ChunkData
+    type Args = (Option<(i64, i64)>, bool);
+
+    fn args_default() -> Option<Self::Args> {
+        Some((None, true))
+    }
 
     fn read_options<R: std::io::Read + std::io::Seek>(
         reader: &mut R,
         _options: &binread::ReadOptions,
-        _args: Self::Args,
+        args: Self::Args,
     ) -> binread::BinResult<Self> {
-        let head_sz = reader.read_be::<u32>()? as usize;
-        let mut vec = vec![0; head_sz - 4];
-        reader.read_exact(&mut vec)?;
-        let mut cursor = Cursor::new(vec);
-        let header = cursor.read_le()?;
-        println!("{:?}", header);
-        let data = reader.read_le()?;
+        let header = ChunkHead::read_from(reader)?;
+        debug!("{:?}", header);
+        let data = reader.read_le_args(args)?;
         Ok(Chunk { header, data })
     }
 }
 
+impl Chunk {
+    /// Parse a whole chunk, the storage head then every block, verifying
+    /// the checksums. `ChunkStream` decodes the blocks one at a time.
+    pub fn from_reader<R: Read + Seek>(reader: &mut R) -> Result<Self, DecodeError> {
+        let header = ChunkHead::read_from(reader)?;
+        match ChunkData::read(reader) {
+            Ok(data) => Ok(Chunk { header, data }),
+            Err(e) => {
+                header.check_log_chunk()?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// `from_reader` of a chunk in memory, like an object of the store.
+    pub fn from_bytes(bs: &[u8]) -> Result<Self, DecodeError> {
+        Chunk::from_reader(&mut Cursor::new(bs))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
 
     use binread::BinRead;
+    use integer_encoding::VarInt;
 
-    use crate::ty::{ChunkData, ChunkHead, Meta};
+    use crate::{
+        error::DecodeError,
+        ty::{identify, ChunkData, ChunkHead, EncType, Meta},
+    };
 
-    use super::{BlockMeta, UnorderedBlockEntry};
+    use super::{blocks_in_range, BlockMeta, UnorderedBlockEntry};
 
     #[test]
     fn test_parse_unordered_block() -> anyhow::Result<()> {
@@ -324,6 +764,11 @@ mod test {
         let blk: UnorderedBlockEntry = BinRead::read(&mut cursor)?;
         assert_eq!(format!("{:?}", blk.time), "2022-08-31T11:51:49");
         assert_eq!(blk.line, "fizzbuzz");
+
+        let mut bs = 1_661_946_709_123_456_789_i64.encode_var_vec();
+        bs.extend([3, b'f', b'o', b'o']);
+        let blk: UnorderedBlockEntry = BinRead::read(&mut Cursor::new(bs))?;
+        assert_eq!(serde_json::to_string(&blk.time)?, r#""2022-08-31T11:51:49.123456789""#);
         Ok(())
     }
 
@@ -354,7 +799,7 @@ mod test {
         let meta: Meta = BinRead::read(&mut cursor)?;
         assert_eq!(meta.num_blocks, 1);
         assert_eq!(meta.block_metas.len(), 1);
-        assert_eq!(meta.block_crc, 2972222663);
+        assert_eq!(meta.block_crc, 3347327153);
         Ok(())
     }
 
@@ -374,6 +819,87 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_chunk_data_v1() -> anyhow::Result<()> {
+        // the chunk above as format v1: no encoding byte, no uncompressed
+        // size in the block meta
+        let mut cursor = Cursor::new(&[
+            0, 0, 0, 0, 1, 46, 229, 106, 1, 31, 139, 8, 0, 0, 9, 110, 136, 0, 255, 0, 18, 0, 237,
+            255, 128, 200, 152, 153, 191, 238, 181, 144, 46, 8, 102, 105, 122, 122, 98, 117, 122,
+            122, 3, 0, 220, 180, 200, 63, 18, 0, 0, 0, 180, 135, 149, 161, 1, 1, 128, 200, 152,
+            153, 191, 238, 181, 144, 46, 128, 200, 152, 153, 191, 238, 181, 144, 46, 5, 43, 79, 8,
+            98, 223, 0, 0, 0, 0, 0, 0, 0, 52,
+        ]);
+
+        let ch: ChunkData = BinRead::read(&mut cursor)?;
+        assert_eq!(ch.format, 1);
+        assert_eq!(ch.meta.block_metas[0].compressed_size, 43);
+        assert_eq!(ch.blocks[0].entries[0].line, "fizzbuzz");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_chunk_data_v4() -> anyhow::Result<()> {
+        use crate::encode::{compress, ChunkEncoding};
+
+        let uvarints = |vs: &[u64]| vs.iter().flat_map(|v| v.encode_var_vec()).collect::<Vec<u8>>();
+        let ts = |t: i64| t.encode_var_vec();
+        let mut symbols = vec![];
+        for s in ["trace_id", "abc"] {
+            symbols.extend(uvarints(&[s.len() as u64]));
+            symbols.extend(s.as_bytes());
+        }
+        let mut section = uvarints(&[2]);
+        section.extend(compress(&symbols, ChunkEncoding::Gzip)?);
+
+        // entries: ts, line, then the length of the metadata, its number
+        // of pairs and their name and value symbols
+        let mut raw = ts(1_000_000_000);
+        raw.extend(uvarints(&[3]));
+        raw.extend(b"foo");
+        raw.extend(uvarints(&[3, 1, 0, 1]));
+        raw.extend(ts(2_000_000_000));
+        raw.extend(uvarints(&[3]));
+        raw.extend(b"bar");
+        raw.extend(uvarints(&[1, 0]));
+        let block = compress(&raw, ChunkEncoding::Gzip)?;
+
+        let mut chunk = vec![0; 4];
+        chunk.extend(0x012EE56A_u32.to_be_bytes());
+        chunk.extend([4, 1]);
+        let symbols_offset = chunk.len() - 4;
+        chunk.extend(&section);
+        chunk.extend(crc32c::crc32c(&section).to_be_bytes());
+        let block_offset = chunk.len() - 4;
+        chunk.extend(&block);
+        chunk.extend(crc32c::crc32c(&block).to_be_bytes());
+        let metas_offset = chunk.len() - 4;
+        let mut metas = uvarints(&[1, 2]);
+        metas.extend(ts(1_000_000_000));
+        metas.extend(ts(2_000_000_000));
+        metas.extend(uvarints(&[block_offset as u64, raw.len() as u64, block.len() as u64]));
+        chunk.extend(&metas);
+        chunk.extend(crc32c::crc32c(&metas).to_be_bytes());
+        for v in [section.len(), symbols_offset, metas.len() + 4, metas_offset] {
+            chunk.extend((v as u64).to_be_bytes());
+        }
+
+        let ch: ChunkData = BinRead::read(&mut Cursor::new(&chunk))?;
+        assert_eq!(ch.format, 4);
+        assert_eq!(ch.symbols, ["trace_id", "abc"]);
+        let entries = &ch.blocks[0].entries;
+        assert_eq!(entries[0].structured_metadata, [("trace_id".to_string(), "abc".to_string())]);
+        assert_eq!(entries[1].line, "bar");
+        assert!(entries[1].structured_metadata.is_empty());
+
+        // a damaged block checksum fails unless not verified
+        chunk[block_offset + 4 + block.len()] ^= 1;
+        let err = ChunkData::read(&mut Cursor::new(&chunk)).map_err(DecodeError::from).unwrap_err();
+        assert!(err.to_string().contains("block 0 of 1"), "{err}");
+        ChunkData::read_args(&mut Cursor::new(&chunk), (None, false))?;
+        Ok(())
+    }
+
     #[test]
     fn test_parse_chunk_head() -> anyhow::Result<()> {
         let mut cursor = Cursor::new(&[
@@ -394,4 +920,39 @@ mod test {
         assert_eq!(head.metric.len(), 4);
         Ok(())
     }
+
+    #[test]
+    fn test_blocks_in_range() {
+        let spans = [(0, 9), (10, 19), (20, 29), (30, 39)];
+        assert_eq!(blocks_in_range(&spans, 12, 25), 1..3);
+        assert_eq!(blocks_in_range(&spans, 19, 20), 1..3);
+        assert_eq!(blocks_in_range(&spans, 40, 50), 4..4);
+        assert_eq!(blocks_in_range(&spans, -5, -1), 0..0);
+        // out of order writes make blocks overlap, all are candidates
+        assert_eq!(blocks_in_range(&[(0, 30), (10, 19), (20, 29)], 12, 15), 0..3);
+    }
+
+    #[test]
+    fn test_not_log_chunk() {
+        let head = |encoding| ChunkHead {
+            fingerprint: 1,
+            user_id: "fake".to_string(),
+            from: 0.0,
+            through: 0.0,
+            metric: [("__name__".to_string(), "up".to_string())].into(),
+            encoding,
+        };
+        assert!(head(129).check_log_chunk().is_ok());
+        let err = head(3).check_log_chunk().unwrap_err().to_string();
+        assert!(err.contains("\"up\" (Bigchunk encoding"), "{err}");
+        assert_eq!(head(129).encoding_mismatch(3, &EncType::EncSnappy), None);
+        let mismatch = head(3).encoding_mismatch(3, &EncType::EncSnappy).unwrap_or_default();
+        assert!(mismatch.starts_with("the head says encoding 3 (Bigchunk)"), "{mismatch}");
+
+        let is = |bs: &[u8], what: &str| identify(bs).is_some_and(|e| e.to_string().contains(what));
+        assert!(is(&[0x1f, 0x8b, 8, 0], "gzip"));
+        assert!(is(&[1, 0x2e, 0xe5, 0x6a, 3, 1], "without the storage head"));
+        assert!(is(&[0x85, 0xbd, 0x40, 0xdd, 1], "prometheus tsdb chunks"));
+        assert!(identify(&[0, 0, 1, 2, 3]).is_none());
+    }
 }
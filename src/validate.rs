@@ -0,0 +1,217 @@
+// The structural checks of `lf decode --strict`. Decoding trusts the length
+// and offset fields of a chunk as long as the checksums match, here every
+// one of them is compared with where things really are:
+//
+//   [head length][head][data length][magic][format][encoding]
+//   ([symbols][crc], format v4) [block][crc] ... [metas][crc][trailer]
+//
+// Positions are byte offsets in the whole chunk file.
+
+use std::fmt;
+
+use num_traits::FromPrimitive;
+
+use crate::{
+    common::red,
+    error::DecodeError,
+    repair::{be_u32, be_u64, parse_raw_meta, RawBlockMeta},
+    ty::{decompress, decompress_bytes, parse_symbols, EncType, CHUNK_MAGIC},
+};
+
+#[derive(Debug)]
+pub struct Violation {
+    pub pos: usize,
+    pub what: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.pos, self.what)
+    }
+}
+
+/// Every structural problem of the chunk file `bs`, in file order as far
+/// as possible. Problems making the rest unreadable end the list.
+pub fn check_structure(bs: &[u8]) -> Vec<Violation> {
+    let mut out = vec![];
+    let mut add = |pos: usize, what: String| out.push(Violation { pos, what });
+
+    let head_len = be_u32(bs, 0).unwrap_or(0) as usize;
+    if head_len < 4 || head_len + 4 > bs.len() {
+        add(0, format!("head length field says {head_len} bytes, the file has {}", bs.len()));
+        return out;
+    }
+    let base = head_len + 4;
+    let chunk = &bs[base..];
+    let data_len = be_u32(bs, head_len).unwrap_or(0) as usize;
+    if data_len != chunk.len() {
+        add(head_len, format!("data length field says {data_len} bytes, {} follow", chunk.len()));
+    }
+    if be_u32(chunk, 0) != Some(CHUNK_MAGIC) {
+        add(base, "chunk magic not found".to_string());
+        return out;
+    }
+    let format = chunk.get(4).copied().unwrap_or(0);
+    let (header_len, enc) = match format {
+        1 => (5, Some(EncType::EncGZIP)),
+        2..=4 => (6, chunk.get(5).and_then(|&e| EncType::from_u8(e))),
+        _ => (0, None),
+    };
+    let Some(enc) = enc else {
+        add(base + 4, format!("unsupported chunk format v{format} or encoding"));
+        return out;
+    };
+
+    let trailer_len = if format >= 4 { 32 } else { 8 };
+    let Some(trailer) = chunk.len().checked_sub(trailer_len).filter(|&t| t >= header_len) else {
+        add(base + chunk.len(), format!("no room for the {trailer_len} bytes trailer"));
+        return out;
+    };
+    let meta_offset = be_u64(chunk, chunk.len() - 8).unwrap_or(0) as usize;
+    let Some(meta) = parse_raw_meta(chunk, meta_offset, format).filter(|_| (header_len..trailer).contains(&meta_offset))
+    else {
+        add(base + chunk.len() - 8, format!("the trailer points to block metas at offset {meta_offset}, there are none"));
+        return out;
+    };
+    let metas_end = meta_offset + meta.len + 4;
+    if metas_end != trailer {
+        add(
+            base + meta_offset,
+            format!("the block metas and their crc end at offset {metas_end}, the trailer starts at {trailer}"),
+        );
+    }
+
+    // format v4: the symbols section between the chunk header and the blocks
+    let mut first = header_len;
+    let mut symbols = None;
+    if format >= 4 {
+        let field = |i: usize| be_u64(chunk, trailer + 8 * i).unwrap_or(0) as usize;
+        let (symbols_len, symbols_offset, metas_len) = (field(0), field(1), field(2));
+        // loki leaves the crc out of the length, like lf repair does, one
+        // counting the crc in still reads fine and is let through
+        if metas_len != meta.len && metas_len != meta.len + 4 {
+            add(
+                base + trailer + 16,
+                format!("the trailer says {metas_len} bytes of block metas, they take {} without their crc", meta.len),
+            );
+        }
+        if symbols_offset != header_len {
+            add(base + trailer + 8, format!("the trailer says the symbols start at offset {symbols_offset}, not {header_len}"));
+        } else {
+            match chunk.get(symbols_offset..symbols_offset + symbols_len) {
+                Some(section) => {
+                    first = symbols_offset + symbols_len + 4;
+                    match parse_symbols(section, &enc) {
+                        Ok(s) => symbols = Some(s),
+                        Err(e) => add(base + symbols_offset, format!("symbols are not readable: {}", DecodeError::from(e))),
+                    }
+                }
+                None => add(base + trailer, format!("the symbols section of {symbols_len} bytes runs past the end")),
+            }
+        }
+    }
+
+    let mut prev_end = first;
+    for (i, b) in meta.blocks.iter().enumerate() {
+        let end = b.offset + b.len + 4;
+        if b.offset < prev_end {
+            let what = match i {
+                0 => format!("block 0 starts at offset {}, before the end of the chunk header at {first}", b.offset),
+                _ => format!("block {i} at offset {} overlaps the blocks before it, ending at {prev_end}", b.offset),
+            };
+            add(base + b.offset, what);
+        }
+        if end > meta_offset {
+            add(
+                base + b.offset,
+                format!("block {i} at offset {} of {} bytes and its crc ends at {end}, past the block metas at {meta_offset}", b.offset, b.len),
+            );
+            continue;
+        }
+        prev_end = prev_end.max(end);
+        if format >= 3 && (format < 4 || symbols.is_some()) {
+            if let Some(what) = uncompressed_mismatch(i, b, &chunk[b.offset..b.offset + b.len], &enc, symbols.as_deref()) {
+                add(base + b.offset, what);
+            }
+        }
+    }
+    out
+}
+
+// The uncompressed size of the metas is what loki counts, the line bytes
+// (and 8 per structured metadata pair, format v4); some writers put the
+// decompressed length there instead, both are accepted.
+fn uncompressed_mismatch(
+    i: usize,
+    b: &RawBlockMeta,
+    data: &[u8],
+    enc: &EncType,
+    symbols: Option<&[String]>,
+) -> Option<String> {
+    let sizes = decompress_bytes(data, enc).and_then(|raw| {
+        let block = decompress(data, enc, b.entries, symbols)?;
+        let counted = block.entries.iter().map(|e| e.line.len() + 8 * e.structured_metadata.len()).sum::<usize>();
+        Ok((raw.len(), counted))
+    });
+    match sizes {
+        Ok((raw, counted)) if b.uncompressed != raw && b.uncompressed != counted => Some(format!(
+            "block {i} says {} bytes uncompressed, it holds {counted} bytes of lines ({raw} bytes decompressed)",
+            b.uncompressed
+        )),
+        Ok(_) => None,
+        Err(e) => Some(format!("block {i} does not decompress: {}", DecodeError::from(e))),
+    }
+}
+
+/// `check_structure` for --strict: the problems are printed, the chunk
+/// fails when there is any.
+pub fn check_strict(bs: &[u8]) -> Result<(), DecodeError> {
+    let problems = check_structure(bs);
+    for p in problems.iter() {
+        eprintln!("{} {p}", red("structure:"));
+    }
+    match problems.len() {
+        0 => Ok(()),
+        n => Err(DecodeError::Corrupt(format!("{n} structural problems"))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::encode::{encode_chunk, encode_memchunk, make_head, ChunkEncoding, EncodeEntry};
+
+    #[test]
+    fn test_check_structure() -> anyhow::Result<()> {
+        let entries: Vec<_> = (0..50)
+            .map(|i| EncodeEntry {
+                ts: 1_661_946_709_000_000_000 + i * 1_000_000_000,
+                line: format!("line {i}"),
+            })
+            .collect();
+        let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+        let memchunk = encode_memchunk(&entries, ChunkEncoding::Snappy, 128)?;
+        let head = make_head("fake", &labels, 1_661_946_709_000, 1_661_946_758_000);
+        let bs = encode_chunk(&head, &memchunk)?;
+        assert!(check_structure(&bs).is_empty());
+
+        // a data length field off by one
+        let head_len = be_u32(&bs, 0).unwrap() as usize;
+        let mut bad = bs.clone();
+        bad[head_len + 3] ^= 1;
+        let problems = check_structure(&bad);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].pos, head_len);
+
+        // a trailer pointing nowhere
+        let mut bad = bs.clone();
+        let n = bad.len();
+        bad[n - 8..].copy_from_slice(&(n as u64).to_be_bytes());
+        let problems = check_structure(&bad);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].pos, n - 8);
+        Ok(())
+    }
+}
@@ -0,0 +1,841 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use binread::BinRead;
+use clap::Parser;
+use integer_encoding::VarInt;
+use prost::Message;
+use tracing::debug;
+
+use crate::{
+    common::{blue, format_bytes, format_nanos, gray, green, yellow, HttpOpts},
+    proto,
+    push::{send_streams, LabelRewriteOpts, Stream},
+    ty::ChunkData,
+};
+
+// prometheus/tsdb/wlog/wlog.go
+const PAGE_SIZE: usize = 32 * 1024;
+const RECORD_HEADER_SIZE: usize = 7;
+const SNAPPY_MASK: u8 = 1 << 3;
+const ZSTD_MASK: u8 = 1 << 4;
+
+/// ingester WAL inspection
+#[derive(Parser, Debug)]
+pub struct Wal {
+    #[clap(subcommand)]
+    cmd: SubCommand,
+}
+
+#[derive(Parser, Debug)]
+enum SubCommand {
+    /// push the entries found in WAL segments to a loki
+    Replay(Box<ReplayCommand>),
+
+    /// summarise what a WAL holds, per segment and per tenant
+    Stats(StatsCommand),
+}
+
+#[derive(Parser, Debug)]
+struct StatsCommand {
+    /// WAL directory (or a single segment file)
+    dir: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct ReplayCommand {
+    #[command(flatten)]
+    http: HttpOpts,
+
+    /// WAL directory (or a single segment file)
+    dir: PathBuf,
+
+    /// Number of entries per push
+    #[clap(long, default_value = "1000")]
+    batch_size: usize,
+
+    #[command(flatten)]
+    rewrite: LabelRewriteOpts,
+}
+
+/// Labels of a series, keyed by ref
+pub type SeriesLabels = BTreeMap<String, String>;
+
+// tenant -> labels -> values
+type Pending = BTreeMap<String, BTreeMap<SeriesLabels, Vec<(String, String)>>>;
+
+#[derive(Debug, Clone)]
+pub struct WalEntry {
+    // nanoseconds
+    pub ts: i64,
+    pub line: String,
+    pub structured_metadata: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefEntries {
+    pub series_ref: u64,
+    pub entries: Vec<WalEntry>,
+}
+
+/// A series of a checkpoint with the entries of its chunks not flushed
+/// yet, the flushed ones are in the store already.
+#[derive(Debug, Clone)]
+pub struct CheckpointSeries {
+    pub user_id: String,
+    pub fingerprint: u64,
+    pub labels: SeriesLabels,
+    pub entries: Vec<WalEntry>,
+    pub flushed_chunks: usize,
+}
+
+// loki/pkg/ingester/wal/encoding.go
+#[derive(Debug)]
+pub enum WalRecord {
+    Series {
+        user_id: String,
+        series: Vec<(u64, SeriesLabels)>,
+    },
+    Entries {
+        user_id: String,
+        entries: Vec<RefEntries>,
+    },
+    Checkpoint(CheckpointSeries),
+    Unknown(u8),
+}
+
+struct Dec<'a> {
+    b: &'a [u8],
+}
+
+impl<'a> Dec<'a> {
+    fn err(&self) -> anyhow::Error {
+        anyhow::format_err!("record truncated")
+    }
+
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        let (first, rest) = self.b.split_first().ok_or_else(|| self.err())?;
+        self.b = rest;
+        Ok(*first)
+    }
+
+    fn be64(&mut self) -> anyhow::Result<u64> {
+        let bs = self.bytes(8)?;
+        Ok(u64::from_be_bytes(bs.try_into()?))
+    }
+
+    fn uvarint(&mut self) -> anyhow::Result<u64> {
+        let (v, n) = u64::decode_var(self.b).ok_or_else(|| self.err())?;
+        self.b = &self.b[n..];
+        Ok(v)
+    }
+
+    fn varint(&mut self) -> anyhow::Result<i64> {
+        let (v, n) = i64::decode_var(self.b).ok_or_else(|| self.err())?;
+        self.b = &self.b[n..];
+        Ok(v)
+    }
+
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.b.len() < n {
+            return Err(self.err());
+        }
+        let (head, rest) = self.b.split_at(n);
+        self.b = rest;
+        Ok(head)
+    }
+
+    fn uvarint_str(&mut self) -> anyhow::Result<String> {
+        let n = self.uvarint()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(n)?).to_string())
+    }
+}
+
+/// Split a segment into records, reassembling fragments and undoing
+/// record compression. A torn record at the end of the segment (which
+/// happens when the ingester died mid-write) ends the segment.
+pub fn read_segment_records(bs: &[u8]) -> (Vec<Vec<u8>>, Option<anyhow::Error>) {
+    let mut records = vec![];
+    let mut pending: Vec<u8> = vec![];
+    let mut pos = 0;
+    while pos < bs.len() {
+        let page_left = PAGE_SIZE - pos % PAGE_SIZE;
+        if page_left < RECORD_HEADER_SIZE {
+            pos += page_left;
+            continue;
+        }
+        let flag = bs[pos];
+        let ty = flag & 0b111;
+        if ty == 0 {
+            // page terminator, the rest of the page is zero padding
+            pos += page_left;
+            continue;
+        }
+        if pos + RECORD_HEADER_SIZE > bs.len() {
+            return (records, Some(anyhow::format_err!("torn record header at {pos}")));
+        }
+        let len = u16::from_be_bytes([bs[pos + 1], bs[pos + 2]]) as usize;
+        let crc = u32::from_be_bytes(bs[pos + 3..pos + 7].try_into().unwrap());
+        let start = pos + RECORD_HEADER_SIZE;
+        if start + len > bs.len() {
+            return (records, Some(anyhow::format_err!("torn record at {pos}")));
+        }
+        let data = &bs[start..start + len];
+        if crc32c::crc32c(data) != crc {
+            return (records, Some(anyhow::format_err!("record checksum mismatch at {pos}")));
+        }
+        pos = start + len;
+        match ty {
+            // full
+            1 => pending = data.to_vec(),
+            // first
+            2 => {
+                pending = data.to_vec();
+                continue;
+            }
+            // middle
+            3 => {
+                pending.extend_from_slice(data);
+                continue;
+            }
+            // last
+            4 => pending.extend_from_slice(data),
+            other => {
+                return (records, Some(anyhow::format_err!("unknown fragment type {other} at {pos}")));
+            }
+        }
+        let record = if flag & SNAPPY_MASK != 0 {
+            match snap::raw::Decoder::new().decompress_vec(&pending) {
+                Ok(r) => r,
+                Err(err) => return (records, Some(err.into())),
+            }
+        } else if flag & ZSTD_MASK != 0 {
+            let mut out = vec![];
+            let res = zstd::Decoder::new(pending.as_slice()).and_then(|mut d| d.read_to_end(&mut out));
+            if let Err(err) = res {
+                return (records, Some(err.into()));
+            }
+            out
+        } else {
+            std::mem::take(&mut pending)
+        };
+        records.push(record);
+    }
+    (records, None)
+}
+
+pub fn decode_record(rec: &[u8]) -> anyhow::Result<WalRecord> {
+    let mut dec = Dec { b: rec };
+    let ty = dec.byte()?;
+    match ty {
+        // WALRecordSeries
+        1 => {
+            let user_id = dec.uvarint_str()?;
+            let mut series = vec![];
+            // prometheus record.Series, with its own type byte
+            if !dec.b.is_empty() {
+                dec.byte()?;
+            }
+            while !dec.b.is_empty() {
+                let series_ref = dec.be64()?;
+                let n = dec.uvarint()?;
+                let mut labels = SeriesLabels::new();
+                for _ in 0..n {
+                    let name = dec.uvarint_str()?;
+                    let value = dec.uvarint_str()?;
+                    labels.insert(name, value);
+                }
+                series.push((series_ref, labels));
+            }
+            Ok(WalRecord::Series { user_id, series })
+        }
+        // WALRecordEntriesV1/V2/V3
+        2 | 4 | 5 => {
+            let user_id = dec.uvarint_str()?;
+            let mut entries = vec![];
+            if dec.b.is_empty() {
+                return Ok(WalRecord::Entries { user_id, entries });
+            }
+            let base = dec.be64()? as i64;
+            while !dec.b.is_empty() {
+                let series_ref = dec.be64()?;
+                if ty >= 4 {
+                    // per stream entry counter, only used for deduplication in loki
+                    dec.be64()?;
+                }
+                let n = dec.uvarint()?;
+                let mut es = vec![];
+                for _ in 0..n {
+                    let ts = base + dec.varint()?;
+                    let len = dec.uvarint()? as usize;
+                    let line = String::from_utf8_lossy(dec.bytes(len)?).to_string();
+                    let mut structured_metadata = vec![];
+                    if ty >= 5 {
+                        for _ in 0..dec.uvarint()? {
+                            let name = dec.uvarint_str()?;
+                            let value = dec.uvarint_str()?;
+                            structured_metadata.push((name, value));
+                        }
+                    }
+                    es.push(WalEntry {
+                        ts,
+                        line,
+                        structured_metadata,
+                    });
+                }
+                entries.push(RefEntries {
+                    series_ref,
+                    entries: es,
+                });
+            }
+            Ok(WalRecord::Entries { user_id, entries })
+        }
+        // CheckpointRecord, protobuf encoded series with chunks
+        3 => Ok(WalRecord::Checkpoint(decode_checkpoint(dec.b)?)),
+        other => Ok(WalRecord::Unknown(other)),
+    }
+}
+
+fn decode_checkpoint(b: &[u8]) -> anyhow::Result<CheckpointSeries> {
+    let s = proto::CheckpointSeries::decode(b)?;
+    let mut entries = vec![];
+    let mut flushed_chunks = 0;
+    for c in s.chunks.iter() {
+        // a zero time (year 1) when not flushed
+        if c.flushed_at.as_ref().is_some_and(|t| t.seconds > 0) {
+            flushed_chunks += 1;
+            continue;
+        }
+        if !c.data.is_empty() {
+            // read with the length field preceding it in chunk files
+            let mut bs = (c.data.len() as u32).to_be_bytes().to_vec();
+            bs.extend_from_slice(&c.data);
+            let data = ChunkData::read(&mut Cursor::new(bs))
+                .map_err(|e| anyhow::format_err!("checkpoint chunk of {:x} is not readable: {e}", s.fingerprint))?;
+            for e in data.blocks.into_iter().flat_map(|b| b.entries) {
+                entries.push(WalEntry {
+                    ts: e.time.timestamp_nanos(),
+                    line: e.line,
+                    structured_metadata: e.structured_metadata,
+                });
+            }
+        }
+        if !c.head.is_empty() {
+            entries.extend(decode_head_block(&c.head)?);
+        }
+    }
+    Ok(CheckpointSeries {
+        user_id: s.user_id,
+        fingerprint: s.fingerprint,
+        labels: s.labels.into_iter().map(|l| (l.name, l.value)).collect(),
+        entries,
+        flushed_chunks,
+    })
+}
+
+// loki/pkg/chunkenc headBlock / unorderedHeadBlock CheckpointTo: format,
+// number of entries, size, mint, maxt, then the entries. The structured
+// metadata of format 5 are references to the symbols of the chunk, they
+// are skipped.
+fn decode_head_block(b: &[u8]) -> anyhow::Result<Vec<WalEntry>> {
+    let mut dec = Dec { b };
+    let format = dec.byte()?;
+    if !(3..=5).contains(&format) {
+        return Err(anyhow::format_err!("unsupported head block format {format}"));
+    }
+    let n = dec.uvarint()?;
+    dec.uvarint()?;
+    dec.varint()?;
+    dec.varint()?;
+    let mut entries = vec![];
+    for _ in 0..n {
+        let ts = dec.varint()?;
+        let line = dec.uvarint_str()?;
+        if format >= 5 {
+            for _ in 0..dec.uvarint()? * 2 {
+                dec.uvarint()?;
+            }
+        }
+        entries.push(WalEntry {
+            ts,
+            line,
+            structured_metadata: vec![],
+        });
+    }
+    Ok(entries)
+}
+
+/// Segment files of a WAL directory in replay order: the latest
+/// checkpoint first, then the numbered segments.
+pub fn list_segments(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if dir.is_file() {
+        return Ok(vec![dir.to_path_buf()]);
+    }
+    let numbered = |d: &Path| -> anyhow::Result<Vec<(u64, PathBuf)>> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(d)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if let Ok(n) = name.parse::<u64>() {
+                if path.is_file() {
+                    files.push((n, path));
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    };
+
+    let mut checkpoints = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if let Some(n) = name.strip_prefix("checkpoint.").and_then(|n| n.parse::<u64>().ok()) {
+            if path.is_dir() {
+                checkpoints.push((n, path));
+            }
+        }
+    }
+    checkpoints.sort();
+    let mut segments = vec![];
+    if let Some((_, cp)) = checkpoints.last() {
+        segments.extend(numbered(cp)?.into_iter().map(|(_, p)| p));
+    }
+    segments.extend(numbered(dir)?.into_iter().map(|(_, p)| p));
+    Ok(segments)
+}
+
+pub fn wal(w: Wal) -> anyhow::Result<()> {
+    match w.cmd {
+        SubCommand::Replay(r) => replay(*r),
+        SubCommand::Stats(s) => stats(s),
+    }
+}
+
+// the records of a replay grouped into streams until they are pushed
+#[derive(Default)]
+struct ReplayState {
+    series: HashMap<(String, u64), SeriesLabels>,
+    pending: Pending,
+    pending_count: usize,
+    orphans: usize,
+    flushed: usize,
+    metadata_dropped: usize,
+    relabel_dropped: usize,
+}
+
+impl ReplayState {
+    fn push(&mut self, tenant: String, labels: SeriesLabels, entries: Vec<WalEntry>) {
+        let values = self.pending.entry(tenant).or_default().entry(labels).or_default();
+        for e in entries {
+            if !e.structured_metadata.is_empty() {
+                self.metadata_dropped += 1;
+            }
+            values.push((e.ts.to_string(), e.line));
+            self.pending_count += 1;
+        }
+    }
+
+    fn add(&mut self, record: WalRecord, rewrite: &LabelRewriteOpts) -> anyhow::Result<()> {
+        match record {
+            WalRecord::Series { user_id, series } => {
+                for (series_ref, labels) in series {
+                    self.series.insert((user_id.clone(), series_ref), labels);
+                }
+            }
+            WalRecord::Entries { user_id, entries } => {
+                for re in entries {
+                    let Some(labels) = self.series.get(&(user_id.clone(), re.series_ref)) else {
+                        self.orphans += re.entries.len();
+                        continue;
+                    };
+                    match rewrite.apply(labels.clone())? {
+                        Some(labels) => self.push(user_id.clone(), labels, re.entries),
+                        None => self.relabel_dropped += re.entries.len(),
+                    }
+                }
+            }
+            WalRecord::Checkpoint(cp) => {
+                self.flushed += cp.flushed_chunks;
+                // the segments after a checkpoint only have series records
+                // for the streams created since, loki refs are fingerprints
+                self.series.insert((cp.user_id.clone(), cp.fingerprint), cp.labels.clone());
+                match rewrite.apply(cp.labels)? {
+                    Some(labels) => self.push(cp.user_id, labels, cp.entries),
+                    None => self.relabel_dropped += cp.entries.len(),
+                }
+            }
+            WalRecord::Unknown(ty) => debug!("unknown record type {ty}"),
+        }
+        Ok(())
+    }
+}
+
+fn replay(r: ReplayCommand) -> anyhow::Result<()> {
+    let client = reqwest::blocking::Client::new();
+    let segments = list_segments(&r.dir)?;
+    if segments.is_empty() {
+        return Err(anyhow::format_err!("no segments found in {}", r.dir.display()));
+    }
+
+    let mut state = ReplayState::default();
+    let mut pushed = 0;
+
+    let flush = |pending: &mut Pending| -> anyhow::Result<()> {
+        for (tenant, streams) in std::mem::take(pending) {
+            let streams = streams
+                .into_iter()
+                .map(|(labels, values)| Stream {
+                    stream: labels.into_iter().collect(),
+                    values,
+                })
+                .collect();
+            // an explicit --tenant wins over the tenant recorded in the WAL
+            let tenant = if r.http.tenant.is_some() { None } else { Some(tenant.as_str()) };
+            send_streams(&client, &r.http, tenant, streams)?;
+        }
+        Ok(())
+    };
+
+    for seg in segments.iter() {
+        let bs = std::fs::read(seg)?;
+        let (records, err) = read_segment_records(&bs);
+        println!("{} {} records", gray(&seg.display().to_string()), records.len());
+        if let Some(err) = err {
+            println!("{} {}", yellow("segment ends early:"), err);
+        }
+        for rec in records {
+            state.add(decode_record(&rec)?, &r.rewrite)?;
+            if state.pending_count >= r.batch_size {
+                flush(&mut state.pending)?;
+                pushed += state.pending_count;
+                state.pending_count = 0;
+            }
+        }
+    }
+    flush(&mut state.pending)?;
+    pushed += state.pending_count;
+    let ReplayState { orphans, flushed, metadata_dropped, relabel_dropped, .. } = state;
+
+    println!("{}", green(&format!("{pushed} entries pushed")));
+    if relabel_dropped > 0 {
+        println!("{}", gray(&format!("{relabel_dropped} entries dropped by relabel rules")));
+    }
+    if orphans > 0 {
+        println!("{}", yellow(&format!("{orphans} entries skipped, their series record was not found")));
+    }
+    if metadata_dropped > 0 {
+        println!("{}", yellow(&format!("{metadata_dropped} entries pushed without their structured metadata")));
+    }
+    if flushed > 0 {
+        println!("{}", gray(&format!("{flushed} checkpoint chunks skipped, they are flushed to the store")));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct Coverage {
+    records: usize,
+    streams: HashSet<(String, u64)>,
+    entries: usize,
+    bytes: usize,
+    mint: Option<i64>,
+    maxt: Option<i64>,
+}
+
+impl Coverage {
+    fn add(&mut self, key: &(String, u64), e: &WalEntry) {
+        self.streams.insert(key.clone());
+        self.entries += 1;
+        self.bytes += e.line.len();
+        self.mint = Some(self.mint.map_or(e.ts, |t| t.min(e.ts)));
+        self.maxt = Some(self.maxt.map_or(e.ts, |t| t.max(e.ts)));
+    }
+
+    fn range(&self) -> String {
+        match (self.mint, self.maxt) {
+            (Some(mint), Some(maxt)) => format!("{} - {}", format_nanos(mint, 3), format_nanos(maxt, 3)),
+            _ => "-".to_string(),
+        }
+    }
+}
+
+fn stats(s: StatsCommand) -> anyhow::Result<()> {
+    let segments = list_segments(&s.dir)?;
+    if segments.is_empty() {
+        return Err(anyhow::format_err!("no segments found in {}", s.dir.display()));
+    }
+
+    // series known from series records, per tenant
+    let mut series: BTreeMap<String, HashSet<u64>> = BTreeMap::new();
+    let mut tenants: BTreeMap<String, Coverage> = BTreeMap::new();
+    let mut total = Coverage::default();
+    let mut flushed = 0;
+    let mut file_bytes = 0;
+
+    for seg in segments.iter() {
+        let bs = std::fs::read(seg)?;
+        file_bytes += bs.len();
+        let (records, err) = read_segment_records(&bs);
+        let mut cov = Coverage {
+            records: records.len(),
+            ..Default::default()
+        };
+        for rec in records {
+            match decode_record(&rec)? {
+                WalRecord::Series { user_id, series: s } => {
+                    series.entry(user_id).or_default().extend(s.into_iter().map(|(r, _)| r));
+                }
+                WalRecord::Entries { user_id, entries } => {
+                    let tenant = tenants.entry(user_id.clone()).or_default();
+                    for re in entries {
+                        let key = (user_id.clone(), re.series_ref);
+                        for e in re.entries.iter() {
+                            cov.add(&key, e);
+                            tenant.add(&key, e);
+                            total.add(&key, e);
+                        }
+                    }
+                }
+                WalRecord::Checkpoint(cp) => {
+                    flushed += cp.flushed_chunks;
+                    // loki uses the fingerprints as the refs of the series records
+                    let key = (cp.user_id.clone(), cp.fingerprint);
+                    series.entry(cp.user_id.clone()).or_default().insert(cp.fingerprint);
+                    let tenant = tenants.entry(cp.user_id).or_default();
+                    for e in cp.entries.iter() {
+                        cov.add(&key, e);
+                        tenant.add(&key, e);
+                        total.add(&key, e);
+                    }
+                }
+                WalRecord::Unknown(ty) => debug!("unknown record type {ty}"),
+            }
+        }
+        println!(
+            "{} {} records, {} streams, {} entries, {}, {}",
+            blue(&seg.display().to_string()),
+            cov.records,
+            cov.streams.len(),
+            cov.entries,
+            format_bytes(cov.bytes as u64),
+            gray(&cov.range())
+        );
+        if let Some(err) = err {
+            println!("  {} {}", yellow("segment ends early:"), err);
+        }
+    }
+
+    println!();
+    for (tenant, cov) in tenants.iter() {
+        let known = series.get(tenant).map(|s| s.len()).unwrap_or_default();
+        println!(
+            "{} {} series, {} with entries, {} entries, {}, {}",
+            green(tenant),
+            known,
+            cov.streams.len(),
+            cov.entries,
+            format_bytes(cov.bytes as u64),
+            gray(&cov.range())
+        );
+    }
+    println!(
+        "{} segments, {} on disk, {} entries, {} of log lines",
+        segments.len(),
+        format_bytes(file_bytes as u64),
+        total.entries,
+        format_bytes(total.bytes as u64)
+    );
+    // segments are truncated at every checkpoint, what is left with the
+    // unflushed chunks of the checkpoint is an upper bound of the data that
+    // has not been flushed to the store yet
+    println!(
+        "{}",
+        yellow(&format!(
+            "~{} streams / {} unflushed, spanning {}",
+            total.streams.len(),
+            format_bytes(total.bytes as u64),
+            total.range()
+        ))
+    );
+    if flushed > 0 {
+        println!("{}", gray(&format!("{flushed} checkpoint chunks not included, they are flushed to the store")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use integer_encoding::VarInt;
+
+    use prost::Message;
+
+    use super::{decode_record, read_segment_records, ReplayState, SeriesLabels, WalRecord, PAGE_SIZE};
+    use crate::{
+        encode::{encode_memchunk, ChunkEncoding, EncodeEntry},
+        proto,
+        push::LabelRewriteOpts,
+    };
+
+    fn uvarint_str(b: &mut Vec<u8>, s: &str) {
+        b.extend_from_slice(&(s.len() as u64).encode_var_vec());
+        b.extend_from_slice(s.as_bytes());
+    }
+
+    // write records the way prometheus' wlog does, fragmenting at page ends
+    fn write_segment(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = vec![];
+        for rec in records {
+            let mut rest = rec.as_slice();
+            let mut first = true;
+            loop {
+                let left = PAGE_SIZE - out.len() % PAGE_SIZE;
+                if left < 8 {
+                    out.extend(std::iter::repeat_n(0, left));
+                    continue;
+                }
+                let n = rest.len().min(left - 7);
+                let last = n == rest.len();
+                let ty = match (first, last) {
+                    (true, true) => 1,
+                    (true, false) => 2,
+                    (false, false) => 3,
+                    (false, true) => 4,
+                };
+                out.push(ty);
+                out.extend_from_slice(&(n as u16).to_be_bytes());
+                out.extend_from_slice(&crc32c::crc32c(&rest[..n]).to_be_bytes());
+                out.extend_from_slice(&rest[..n]);
+                rest = &rest[n..];
+                first = false;
+                if last {
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    // two entries of series 7 of tenant fake
+    fn entries_record() -> Vec<u8> {
+        let mut entries = vec![4];
+        uvarint_str(&mut entries, "fake");
+        entries.extend_from_slice(&1_000i64.to_be_bytes());
+        entries.extend_from_slice(&7u64.to_be_bytes());
+        entries.extend_from_slice(&3i64.to_be_bytes());
+        entries.extend_from_slice(&2u64.encode_var_vec());
+        for (delta, line) in [(0i64, "a".repeat(40000)), (5, "b".to_string())] {
+            entries.extend_from_slice(&delta.encode_var_vec());
+            uvarint_str(&mut entries, &line);
+        }
+        entries
+    }
+
+    // the checkpoint of series 7 of tenant fake, a flushed chunk and one
+    // with 3 cut entries and 2 in its head
+    fn checkpoint_record() -> anyhow::Result<Vec<u8>> {
+        let cut: Vec<_> = (0..3).map(|i| EncodeEntry { ts: 1_000 + i, line: format!("cut {i}") }).collect();
+        let mut head = vec![4];
+        for v in [2u64, 12] {
+            head.extend_from_slice(&v.encode_var_vec());
+        }
+        for v in [2_000i64, 2_001] {
+            head.extend_from_slice(&v.encode_var_vec());
+        }
+        for (ts, line) in [(2_000i64, "head 0"), (2_001, "head 1")] {
+            head.extend_from_slice(&ts.encode_var_vec());
+            uvarint_str(&mut head, line);
+        }
+        let flushed_at = |seconds| Some(proto::Timestamp { seconds, nanos: 0 });
+        let series = proto::CheckpointSeries {
+            user_id: "fake".to_string(),
+            fingerprint: 7,
+            labels: vec![proto::LabelPairAdapter { name: "app".to_string(), value: "lf".to_string() }],
+            chunks: vec![
+                proto::CheckpointChunk {
+                    flushed_at: flushed_at(1_700_000_000),
+                    data: encode_memchunk(&cut, ChunkEncoding::Snappy, 1024)?,
+                    ..Default::default()
+                },
+                proto::CheckpointChunk {
+                    flushed_at: flushed_at(-62_135_596_800),
+                    data: encode_memchunk(&cut, ChunkEncoding::Snappy, 1024)?,
+                    head,
+                    ..Default::default()
+                },
+            ],
+        };
+        let mut record = vec![3];
+        record.extend(series.encode_to_vec());
+        Ok(record)
+    }
+
+
+    #[test]
+    fn test_decode_segment() -> anyhow::Result<()> {
+        let mut series = vec![1];
+        uvarint_str(&mut series, "fake");
+        series.push(1);
+        series.extend_from_slice(&7u64.to_be_bytes());
+        series.push(1);
+        uvarint_str(&mut series, "app");
+        uvarint_str(&mut series, "lf");
+
+        let seg = write_segment(&[series, entries_record()]);
+        let (records, err) = read_segment_records(&seg);
+        assert!(err.is_none());
+        assert_eq!(records.len(), 2);
+        match decode_record(&records[0])? {
+            WalRecord::Series { user_id, series } => {
+                assert_eq!(user_id, "fake");
+                assert_eq!(series[0].0, 7);
+                assert_eq!(series[0].1["app"], "lf");
+            }
+            r => panic!("unexpected {r:?}"),
+        }
+        match decode_record(&records[1])? {
+            WalRecord::Entries { entries, .. } => {
+                assert_eq!(entries[0].entries.len(), 2);
+                assert_eq!(entries[0].entries[1].ts, 1005);
+                assert_eq!(entries[0].entries[0].line.len(), 40000);
+            }
+            r => panic!("unexpected {r:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_checkpoint() -> anyhow::Result<()> {
+        let record = checkpoint_record()?;
+        let (records, err) = read_segment_records(&write_segment(&[record]));
+        assert!(err.is_none());
+        match decode_record(&records[0])? {
+            WalRecord::Checkpoint(cp) => {
+                assert_eq!((cp.user_id.as_str(), cp.fingerprint, cp.flushed_chunks), ("fake", 7, 1));
+                assert_eq!(cp.labels["app"], "lf");
+                let lines: Vec<_> = cp.entries.iter().map(|e| e.line.as_str()).collect();
+                assert_eq!(lines, ["cut 0", "cut 1", "cut 2", "head 0", "head 1"]);
+                assert_eq!(cp.entries[4].ts, 2_001);
+            }
+            r => panic!("unexpected {r:?}"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_after_checkpoint() -> anyhow::Result<()> {
+        // a segment after the checkpoint, without the series record
+        let rewrite = LabelRewriteOpts { drop_label: vec![], keep_label: vec![], rename_label: vec![], relabel_config: None };
+        let mut state = ReplayState::default();
+        for record in [checkpoint_record()?, entries_record()] {
+            state.add(decode_record(&record)?, &rewrite)?;
+        }
+        assert_eq!((state.orphans, state.flushed, state.pending_count), (0, 1, 7));
+        let labels: SeriesLabels = [("app".to_string(), "lf".to_string())].into();
+        assert_eq!(state.pending["fake"][&labels].len(), 7);
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use base64::{encode_config, STANDARD_NO_PAD};
+use binread::BinReaderExt;
+use clap::Parser;
+use ring::digest::{digest, SHA256};
+
+use crate::{
+    bolt::{open_index, parse_chunk_time_range_value, scan_prefix, Schema},
+    common::{gray, green, red, yellow, ChunkRef},
+    error::{DecodeError, IndexError},
+    platform::{display_path, native_path},
+    trace,
+    ty::ChunkHead,
+};
+
+/// check that the (boltdb) index agrees with a chunk file
+#[derive(Parser, Debug)]
+pub struct Xcheck {
+    /// chunk file
+    #[clap(long)]
+    chunk: PathBuf,
+
+    /// boltdb index file, or a directory of them (searched recursively)
+    #[clap(long)]
+    index: PathBuf,
+
+    /// row shard
+    #[clap(short, long, default_value = "16")]
+    shard: u32,
+
+    /// schema of the index, decides the form of the chunk keys
+    #[clap(long, value_enum, default_value = "v11")]
+    schema: Schema,
+}
+
+// loki/pkg/storage/stores/series/index/schema_util.go labelsString
+fn labels_string(metric: &BTreeMap<&String, &String>) -> String {
+    let name = metric.iter().find(|(k, _)| k.as_str() == "__name__").map(|(_, v)| v.as_str()).unwrap_or("logs");
+    let inner = metric
+        .iter()
+        .filter(|(k, _)| k.as_str() != "__name__")
+        .map(|(k, v)| format!("{k}={v:?}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{name}{{{inner}}}")
+}
+
+fn index_files(path: &Path, out: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    if path.is_file() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for p in entries {
+        index_files(&p, out)?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Findings {
+    // index entries that reference exactly this chunk
+    exact: usize,
+    // same fingerprint, different from/through/checksum
+    mismatched: Vec<String>,
+    // chunk found under a different series id
+    other_series: Vec<String>,
+    // this chunk, with the key of another schema
+    other_schema: Vec<String>,
+    missing_label_entries: Vec<String>,
+}
+
+pub fn xcheck(x: Xcheck) -> anyhow::Result<()> {
+    let bs = std::fs::read(native_path(&x.chunk))?;
+    let head_len = bs
+        .get(..4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+        .filter(|l| *l >= 4 && *l <= bs.len())
+        .ok_or_else(|| DecodeError::InvalidHead("invalid head length".to_string()))?;
+    let head: ChunkHead = Cursor::new(&bs[4..head_len])
+        .read_le()
+        .map_err(DecodeError::from)?;
+    let from = (head.from * 1000.0).round() as i64;
+    let through = (head.through * 1000.0).round() as i64;
+    let checksum = crc32c::crc32c(&bs);
+    let tenant = head.user_id.clone();
+    let chunk_ref = ChunkRef {
+        user_id: tenant.clone(),
+        fingerprint: head.fingerprint,
+        from,
+        to: through,
+        checksum,
+    };
+    let key = x.schema.chunk_key(&chunk_ref);
+
+    let metric: BTreeMap<_, _> = head.metric.iter().collect();
+    let series_id = encode_config(digest(&SHA256, labels_string(&metric).as_bytes()), STANDARD_NO_PAD);
+    let shard = u32::from_be_bytes(series_id.as_bytes()[..4].try_into()?) % x.shard.max(1);
+    println!("{} {}", gray("chunk key:"), key);
+    println!("{} {} {}", gray("series id:"), series_id, gray(&format!("(shard {shard})")));
+
+    let mut files = vec![];
+    index_files(&native_path(&x.index), &mut files)?;
+    let days: Vec<i64> = (from.div_euclid(86_400_000)..=through.div_euclid(86_400_000)).collect();
+    // `tenant/fp` and the separator of the schema
+    let fp_prefix = &key[..format!("{}/{:x}", tenant, head.fingerprint).len() + 1];
+
+    let mut f = Findings::default();
+    for file in files.iter() {
+        let mut span = trace::span("index lookup");
+        span.attr("index.file", display_path(file));
+        let db = match open_index(file) {
+            Ok(db) => db,
+            Err(err) => {
+                println!("{} {}", yellow(&display_path(file)), gray(&format!("skipped: {err}")));
+                continue;
+            }
+        };
+        let tx = db.begin_tx()?;
+        // uncompacted files keep everything in "index", compacted ones per tenant
+        let bucket = match tx.bucket(b"index").or_else(|_| tx.bucket(tenant.as_bytes())) {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+        for day in days.iter() {
+            let mut found = vec![];
+            scan_prefix(&bucket, format!("{tenant}:d{day}:{series_id}\x00").as_bytes(), |k, _| {
+                if let Ok(id) = parse_chunk_time_range_value(&String::from_utf8_lossy(k)) {
+                    found.push(id);
+                }
+                true
+            })?;
+            if found.is_empty() {
+                // look for the fingerprint under any series of that day
+                scan_prefix(&bucket, format!("{tenant}:d{day}:").as_bytes(), |k, _| {
+                    let k = String::from_utf8_lossy(k);
+                    if let Some((sid, range)) = k.split_once('\x00') {
+                        if let Ok(id) = parse_chunk_time_range_value(range) {
+                            if id.starts_with(fp_prefix) {
+                                f.other_series.push(format!("{id} (series {sid})"));
+                            }
+                        }
+                    }
+                    true
+                })?;
+                continue;
+            }
+            for id in found {
+                if id == key {
+                    f.exact += 1;
+                    println!("{} {} {}", green("ok"), display_path(file), gray(&format!("d{day}")));
+                } else if id.starts_with(fp_prefix) {
+                    f.mismatched.push(id);
+                } else if ChunkRef::parse_external_key(&id).is_ok_and(|r| x.schema.chunk_key(&r) == key) {
+                    f.other_schema.push(id);
+                }
+            }
+            for (name, value) in metric.iter().filter(|(k, _)| k.as_str() != "__name__") {
+                let hv = encode_config(digest(&SHA256, value.as_bytes()), STANDARD_NO_PAD);
+                let entry = format!("{shard:02}:{tenant}:d{day}:logs:{name}\x00{hv}\x00{series_id}\x00\x008\x00");
+                if bucket.get(entry.as_bytes()).is_none() {
+                    f.missing_label_entries.push(format!("d{day} {name}={value:?}"));
+                }
+            }
+        }
+    }
+
+    let expected = chunk_ref;
+    for id in f.mismatched.iter() {
+        let r = ChunkRef::parse_external_key(id)?;
+        let mut diffs = vec![];
+        if r.from != expected.from {
+            diffs.push(format!("from {:x} != {:x}", r.from, expected.from));
+        }
+        if r.to != expected.to {
+            diffs.push(format!("through {:x} != {:x}", r.to, expected.to));
+        }
+        if r.checksum != expected.checksum {
+            diffs.push(format!("checksum {:x} != {:x}", r.checksum, expected.checksum));
+        }
+        println!("{} {id} {}", red("mismatch"), gray(&diffs.join(", ")));
+    }
+    for id in f.other_schema.iter() {
+        println!("{} {id}", yellow("key of another schema"));
+    }
+    for id in f.other_series.iter() {
+        println!("{} {id}", yellow("under another series id"));
+    }
+    for e in f.missing_label_entries.iter() {
+        println!("{} {e}", yellow("missing label entry"));
+    }
+
+    if f.exact > 0 && f.missing_label_entries.is_empty() {
+        println!("{}", green("index and chunk agree"));
+        return Ok(());
+    }
+    let verdict = if f.exact > 0 {
+        "chunk is indexed but some label entries are missing, label matchers may not find it"
+    } else if !f.mismatched.is_empty() {
+        "index references a different version of this chunk (from/through/checksum differ)"
+    } else if !f.other_schema.is_empty() {
+        "the chunk is indexed with the key of another schema than the one given (see --schema)"
+    } else if !f.other_series.is_empty() {
+        "chunk is indexed under another series id, the labels of the chunk and the index disagree"
+    } else {
+        "no index entry references this chunk"
+    };
+    Err(IndexError::NotFound(verdict.to_string()).into())
+}
+
+#[cfg(test)]
+mod test {
+    use nut::DBBuilder;
+
+    use super::*;
+    use crate::encode::{encode_chunk, encode_memchunk, make_head, ChunkEncoding, EncodeEntry};
+
+    #[test]
+    fn test_xcheck_schema() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("lf-test-xcheck-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let labels: BTreeMap<_, _> = [("app".to_string(), "lf".to_string())].into();
+        let entries = [EncodeEntry { ts: 1_641_600_000_000_000_000, line: "x".to_string() }];
+        let head = make_head("fake", &labels, 1_641_600_000_000, 1_641_600_000_000);
+        let bs = encode_chunk(&head, &encode_memchunk(&entries, ChunkEncoding::Snappy, 1024)?)?;
+        let chunk = dir.join("chunk");
+        std::fs::write(&chunk, &bs)?;
+
+        // a v12 index of the chunk, day 19000
+        let metric: BTreeMap<_, _> = head.metric.iter().collect();
+        let series_id = encode_config(digest(&SHA256, labels_string(&metric).as_bytes()), STANDARD_NO_PAD);
+        let shard = u32::from_be_bytes(series_id.as_bytes()[..4].try_into()?) % 16;
+        let r = ChunkRef {
+            user_id: "fake".to_string(),
+            fingerprint: head.fingerprint,
+            from: 1_641_600_000_000,
+            to: 1_641_600_000_000,
+            checksum: crc32c::crc32c(&bs),
+        };
+        let hv = encode_config(digest(&SHA256, b"lf"), STANDARD_NO_PAD);
+        let index = dir.join("index_19000");
+        {
+            let mut db = DBBuilder::new(&index).build()?;
+            let mut tx = db.begin_rw_tx()?;
+            {
+                let mut bucket = tx.create_bucket(b"index")?;
+                let label_entry = format!("{shard:02}:fake:d19000:logs:app\x00{hv}\x00{series_id}\x00\x008\x00");
+                bucket.put(label_entry.as_bytes(), b"lf".to_vec())?;
+                let key = Schema::V12.chunk_key(&r);
+                let chunk_entry = format!("fake:d19000:{series_id}\x00{:08x}\x00\x00{key}\x003\x00", r.to / 1000);
+                bucket.put(chunk_entry.as_bytes(), vec![])?;
+            }
+            tx.commit()?;
+        }
+        let check = |schema| xcheck(Xcheck { chunk: chunk.clone(), index: index.clone(), shard: 16, schema });
+        check(Schema::V12)?;
+        let err = check(Schema::V11).unwrap_err();
+        assert!(err.to_string().contains("another schema"), "{err}");
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}